@@ -0,0 +1,40 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+#[path = "../src/log_buffer.rs"]
+#[allow(dead_code)]
+mod log_buffer;
+
+use log_buffer::LogBuffer;
+
+/// A full instance of synthetic log output, with an error every 50 lines.
+fn synthetic_buffer() -> LogBuffer {
+    let mut buffer = LogBuffer::new();
+    buffer.new_instance();
+    for i in 0..10_000 {
+        if i % 50 == 0 {
+            buffer.append(format!("2024-01-01T00:00:{:02} ERROR request {} failed: timeout", i % 60, i));
+        } else {
+            buffer.append(format!("2024-01-01T00:00:{:02} INFO handled request {} in {}ms", i % 60, i, i % 97));
+        }
+    }
+    buffer
+}
+
+fn bench_search(c: &mut Criterion) {
+    let buffer = synthetic_buffer();
+
+    c.bench_function("search no pattern, tail 100", |b| {
+        b.iter(|| buffer.search(None, None, None, None, black_box(Some(100))))
+    });
+
+    c.bench_function("search narrow pattern, context 2", |b| {
+        b.iter(|| buffer.search(None, black_box(Some("ERROR")), Some(2), None, None))
+    });
+
+    c.bench_function("search broad pattern, tail 100", |b| {
+        b.iter(|| buffer.search(None, black_box(Some("request")), None, None, Some(100)))
+    });
+}
+
+criterion_group!(benches, bench_search);
+criterion_main!(benches);
@@ -1,4 +1,5 @@
 use anyhow::{Context, Result};
+use background_process_manager::tui::mcp_client::{RetryPolicy, DEFAULT_REQUEST_TIMEOUT};
 use background_process_manager::tui::{App, EventHandler, McpClient};
 use crossterm::{
     event::KeyCode,
@@ -33,15 +34,49 @@ fn restore_terminal() -> Result<()> {
 async fn main() -> Result<()> {
     // Parse command line arguments
     let args: Vec<String> = std::env::args().collect();
-    let mcp_url = if args.len() > 1 {
-        args[1].clone()
-    } else {
-        "http://localhost:3001/mcp".to_string()
-    };
+    let mut mcp_url = None;
+    let mut timeout = DEFAULT_REQUEST_TIMEOUT;
+    let mut retry = RetryPolicy::default();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--timeout" => {
+                let seconds = rest.next().and_then(|s| s.parse::<f64>().ok()).filter(|s| *s > 0.0);
+                let Some(seconds) = seconds.and_then(|s| Duration::try_from_secs_f64(s).ok()) else {
+                    eprintln!("--timeout takes a finite number of seconds greater than 0");
+                    std::process::exit(1);
+                };
+                timeout = seconds;
+            }
+            "--retries" => {
+                let Some(retries) = rest.next().and_then(|s| s.parse().ok()) else {
+                    eprintln!("--retries takes a number of retries");
+                    std::process::exit(1);
+                };
+                retry.retries = retries;
+            }
+            "--retry-delay-ms" => {
+                let Some(ms) = rest.next().and_then(|s| s.parse().ok()) else {
+                    eprintln!("--retry-delay-ms takes a number of milliseconds");
+                    std::process::exit(1);
+                };
+                retry.delay = Duration::from_millis(ms);
+            }
+            _ if arg.starts_with("--") || mcp_url.is_some() => {
+                eprintln!(
+                    "Usage: {} [--timeout <seconds>] [--retries <n>] [--retry-delay-ms <ms>] [mcp-url]",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            _ => mcp_url = Some(arg.clone()),
+        }
+    }
+    let mcp_url = mcp_url.unwrap_or_else(|| "http://localhost:3001/mcp".to_string());
 
     // Initialize app
     let mut app = App::new(mcp_url.clone());
-    let mut client = McpClient::new(mcp_url);
+    let mut client = McpClient::new(mcp_url, timeout)?.with_retry(retry);
 
     // Initialize MCP connection
     match client.initialize().await {
@@ -64,6 +99,7 @@ async fn main() -> Result<()> {
 
     // Initial status fetch
     let _ = app.update_status(&mut client).await;
+    let _ = app.refresh_activity(&mut client).await;
 
     // Main loop
     let result = run_app(&mut terminal, &mut app, &mut client, &mut events).await;
@@ -97,6 +133,14 @@ async fn run_app(
                 background_process_manager::tui::Event::Tick => {
                     // Auto-refresh status every tick
                     let _ = app.update_status(client).await;
+                    let _ = app.refresh_activity(client).await;
+                    if app.should_show_build_log() {
+                        let _ = app.refresh_logs(client).await;
+                    }
+                }
+                background_process_manager::tui::Event::Key(key) if app.pending_action.is_some() => {
+                    let confirmed = matches!(key.code, KeyCode::Char('y') | KeyCode::Char('Y'));
+                    let _ = app.resolve_pending_action(client, confirmed).await;
                 }
                 background_process_manager::tui::Event::Key(key) => {
                     match key.code {
@@ -115,9 +159,18 @@ async fn run_app(
                         KeyCode::Char('r') => {
                             let _ = app.restart_selected_process(client).await;
                         }
+                        KeyCode::Char('R') => {
+                            app.request_restart_all();
+                        }
+                        KeyCode::Char('X') => {
+                            app.request_stop_all();
+                        }
                         KeyCode::Char('c') => {
                             app.clear_logs();
                         }
+                        KeyCode::Char('t') => {
+                            app.toggle_activity();
+                        }
                         _ => {}
                     }
                 }
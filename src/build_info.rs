@@ -0,0 +1,159 @@
+use chrono::{DateTime, Local};
+use std::hash::{DefaultHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::process::Command;
+
+/// Identifies what a process is running: the binary it was spawned from and
+/// the state of the project's git checkout when it was built, or spawned if
+/// there was no build.
+#[derive(Debug, Clone)]
+pub struct BuildInfo {
+    pub binary_path: Option<PathBuf>,
+    pub binary_mtime: Option<SystemTime>,
+    pub binary_hash: Option<String>,
+    pub git_commit: Option<String>,
+    pub git_dirty: bool,
+}
+
+impl BuildInfo {
+    /// Hashes the binary, off the async threads since it can be large, and
+    /// asks git for the checkout's state.
+    pub async fn capture(project_dir: &Path, binary_path: Option<&Path>) -> Self {
+        let binary_mtime = match binary_path {
+            Some(path) => modified(path).await,
+            None => None,
+        };
+
+        let binary_hash = match binary_path {
+            Some(path) => {
+                let path = path.to_path_buf();
+                tokio::task::spawn_blocking(move || {
+                    let bytes = std::fs::read(path).ok()?;
+                    let mut hasher = DefaultHasher::new();
+                    hasher.write(&bytes);
+                    Some(format!("{:016x}", hasher.finish())[..8].to_string())
+                })
+                .await
+                .ok()
+                .flatten()
+            }
+            None => None,
+        };
+
+        let git_commit = git_output(project_dir, &["rev-parse", "--short", "HEAD"])
+            .await
+            .filter(|s| !s.is_empty());
+        let git_dirty = match git_commit {
+            Some(_) => git_output(project_dir, &["status", "--porcelain"])
+                .await
+                .is_some_and(|s| !s.is_empty()),
+            None => false,
+        };
+
+        Self {
+            binary_path: binary_path.map(Path::to_path_buf),
+            binary_mtime,
+            binary_hash,
+            git_commit,
+            git_dirty,
+        }
+    }
+
+    /// Human-readable version, e.g. "a1b2c3d (dirty), built 14:32:05".
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+
+        if let Some(ref commit) = self.git_commit {
+            if self.git_dirty {
+                parts.push(format!("{} (dirty)", commit));
+            } else {
+                parts.push(commit.clone());
+            }
+        }
+
+        if let Some(ref hash) = self.binary_hash {
+            parts.push(format!("binary {}", hash));
+        }
+
+        if let Some(mtime) = self.binary_mtime {
+            let built: DateTime<Local> = mtime.into();
+            parts.push(format!("built {}", built.format("%H:%M:%S")));
+        }
+
+        if parts.is_empty() {
+            "unknown".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+
+    /// True if the binary on disk is newer than the one that was spawned.
+    pub async fn is_stale(&self) -> bool {
+        let (Some(path), Some(recorded)) = (&self.binary_path, self.binary_mtime) else {
+            return false;
+        };
+
+        modified(path).await.is_some_and(|current| current > recorded)
+    }
+
+    /// Whether this describes `binary_path` as it is on disk now, going by
+    /// its mtime, so it needn't be captured again.
+    pub async fn is_current(&self, binary_path: Option<&Path>) -> bool {
+        if self.binary_path.as_deref() != binary_path {
+            return false;
+        }
+        match binary_path {
+            Some(path) => self.binary_mtime.is_some() && modified(path).await == self.binary_mtime,
+            None => true,
+        }
+    }
+}
+
+async fn modified(path: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.and_then(|m| m.modified()).ok()
+}
+
+async fn git_output(project_dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(project_dir)
+        .output()
+        .await
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_is_current() {
+        let dir = std::env::temp_dir().join(format!("bpm-test-build-info-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join("app");
+        std::fs::write(&binary, "v1").unwrap();
+
+        let info = BuildInfo::capture(&dir, Some(&binary)).await;
+        assert_eq!(info.binary_hash.as_ref().map(String::len), Some(8));
+        assert!(info.is_current(Some(&binary)).await);
+        assert!(!info.is_current(Some(&dir.join("other"))).await);
+        assert!(!info.is_current(None).await);
+        assert!(!info.is_stale().await);
+
+        // A rebuild changes the mtime
+        let file = std::fs::File::options().write(true).open(&binary).unwrap();
+        file.set_modified(info.binary_mtime.unwrap() + Duration::from_secs(10)).unwrap();
+        assert!(!info.is_current(Some(&binary)).await);
+        assert!(info.is_stale().await);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
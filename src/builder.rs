@@ -1,32 +1,363 @@
-use crate::log_buffer::LogBuffer;
+use crate::build_info::BuildInfo;
+use crate::config::Config;
+use crate::direnv::Direnv;
+use crate::line_reader::{capture_stopped, LineReader};
+use crate::log_buffer::{LineMeta, LogBuffer, LogStream};
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
-use tokio::sync::RwLock;
-use tracing::{error, info};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{watch, Mutex, MutexGuard, Notify, RwLock};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// Number of finished builds kept per process.
+const MAX_BUILD_HISTORY: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildOutcome {
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl BuildOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildOutcome::Succeeded => "succeeded",
+            BuildOutcome::Failed => "failed",
+            BuildOutcome::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// A finished build, for the build history.
+#[derive(Debug, Clone)]
+pub struct BuildRecord {
+    pub release: bool,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration: Duration,
+    pub outcome: BuildOutcome,
+    pub warnings: usize,
+    pub errors: usize,
+}
+
+impl BuildRecord {
+    /// One-line summary for the activity feed.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{} build {} after {:.1}s",
+            if self.release { "release" } else { "dev" },
+            self.outcome.as_str(),
+            self.duration.as_secs_f64()
+        );
+        if self.errors > 0 || self.warnings > 0 {
+            summary.push_str(&format!(" ({} errors, {} warnings)", self.errors, self.warnings));
+        }
+        summary
+    }
+
+    /// Structured form for the get_build_history tool.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "profile": if self.release { "release" } else { "dev" },
+            "started_at": self.started_at.to_rfc3339(),
+            "finished_at": self.finished_at.to_rfc3339(),
+            "duration_seconds": self.duration.as_secs_f64(),
+            "outcome": self.outcome.as_str(),
+            "warnings": self.warnings,
+            "errors": self.errors,
+        })
+    }
+}
+
+/// Size of a process's `target/` directory, as last measured.
+#[derive(Debug, Clone, Copy)]
+pub struct TargetSize {
+    pub bytes: u64,
+    pub measured_at: DateTime<Utc>,
+}
+
+impl TargetSize {
+    /// Walks `dir` on a blocking thread, since that can take a while for a
+    /// large target directory.
+    pub async fn measure(dir: PathBuf) -> Self {
+        let bytes = tokio::task::spawn_blocking(move || dir_size(&dir)).await.unwrap_or(0);
+        Self {
+            bytes,
+            measured_at: Utc::now(),
+        }
+    }
+}
+
+/// Total size of the files under `dir`, without following symlinks.
+/// Entries that can't be read count as empty.
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// Formats a byte count the way cargo does, e.g. "2.3GiB".
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, UNITS[unit])
+}
+
+/// Tracks the build running for a process so it can be cancelled from
+/// another task, and the process's recent builds.
+pub struct BuildControl {
+    /// Held for the duration of a build, so builds for a process never overlap.
+    lock: Mutex<()>,
+    in_progress: RwLock<bool>,
+    /// Builds waiting for `lock`, which `cancel` cancels too.
+    waiting: RwLock<usize>,
+    cancel: Notify,
+    pub history: RwLock<VecDeque<BuildRecord>>,
+    /// Crates cargo has started compiling in the current or last build.
+    compiled: Arc<watch::Sender<usize>>,
+}
+
+impl BuildControl {
+    pub fn new() -> Self {
+        Self {
+            lock: Mutex::new(()),
+            in_progress: RwLock::new(false),
+            waiting: RwLock::new(0),
+            cancel: Notify::new(),
+            history: RwLock::new(VecDeque::new()),
+            compiled: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// Follows the number of crates compiled by the build in progress, as
+    /// counted from cargo's "Compiling" lines. Reset when a build starts.
+    pub fn watch_compiled(&self) -> watch::Receiver<usize> {
+        self.compiled.subscribe()
+    }
+
+    async fn record(&self, record: BuildRecord) {
+        let mut history = self.history.write().await;
+        if history.len() >= MAX_BUILD_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(record);
+    }
+
+    /// One-line summary of the durations of the last `n` builds.
+    pub async fn summary(&self, n: usize) -> Option<String> {
+        let history = self.history.read().await;
+        let recent: Vec<Duration> = history.iter().rev().take(n).map(|r| r.duration).collect();
+        if recent.is_empty() {
+            return None;
+        }
+
+        let total: Duration = recent.iter().sum();
+        let max = recent.iter().max().copied().unwrap_or_default();
+        let mut summary = format!(
+            "avg {:.1}s, max {:.1}s over last {}",
+            total.as_secs_f64() / recent.len() as f64,
+            max.as_secs_f64(),
+            recent.len()
+        );
+        if let Some(last) = history.back().filter(|b| b.warnings > 0) {
+            summary.push_str(&format!(", last build produced {} warnings", last.warnings));
+        }
+        Some(summary)
+    }
+
+    pub async fn is_in_progress(&self) -> bool {
+        *self.in_progress.read().await
+    }
+
+    /// Cancels the running build, if any, and the builds waiting for it to
+    /// finish. Returns false if nothing was building or waiting to.
+    pub async fn cancel(&self) -> bool {
+        if !*self.in_progress.read().await && *self.waiting.read().await == 0 {
+            return false;
+        }
+        self.cancel.notify_waiters();
+        true
+    }
+
+    /// Waits for any other build for the process to finish, unless the
+    /// build is cancelled meanwhile, through `cancel` or `BuildControl::cancel`.
+    async fn wait_turn(&self, cancel: Option<&CancellationToken>) -> Result<MutexGuard<'_, ()>> {
+        // Registered before counting as waiting, so no cancel is missed
+        let notified = self.cancel.notified();
+        *self.waiting.write().await += 1;
+        let guard = tokio::select! {
+            guard = self.lock.lock() => Some(guard),
+            _ = notified => None,
+            _ = cancelled(cancel) => None,
+        };
+        *self.waiting.write().await -= 1;
+        guard.context("Build cancelled")
+    }
+}
+
+/// Resolves once `cancel` is cancelled, or never without one.
+async fn cancelled(cancel: Option<&CancellationToken>) {
+    match cancel {
+        Some(cancel) => cancel.cancelled().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// What to build: the crate in `project_dir`, for `target` if cross-compiling.
+pub struct BuildSpec<'a> {
+    pub project_dir: &'a Path,
+    pub target: Option<&'a str>,
+    pub release: bool,
+}
+
+/// A target directory found by `cargo metadata`, valid while the files that
+/// can move it are unchanged.
+struct CachedTargetDir {
+    stamp: Vec<Option<(SystemTime, u64)>>,
+    dir: PathBuf,
+}
+
+/// Modification times and sizes of the files that decide where cargo puts
+/// its output for `project_dir`: manifests and `.cargo/config` files in it
+/// and every directory above it.
+fn target_dir_stamp(project_dir: &Path) -> Vec<Option<(SystemTime, u64)>> {
+    let project_dir = project_dir.canonicalize().unwrap_or_else(|_| project_dir.to_path_buf());
+    project_dir
+        .ancestors()
+        .flat_map(|dir| {
+            [
+                dir.join("Cargo.toml"),
+                dir.join(".cargo/config.toml"),
+                dir.join(".cargo/config"),
+            ]
+        })
+        .map(|path| {
+            let metadata = std::fs::metadata(path).ok()?;
+            Some((metadata.modified().ok()?, metadata.len()))
+        })
+        .collect()
+}
 
 pub struct Builder {
-    project_dir: PathBuf,
-    has_direnv: bool,
+    echo_output: bool,
+    max_line_bytes: usize,
+    locked: bool,
+    offline: bool,
+    /// Target directories by project directory.
+    target_dirs: std::sync::Mutex<HashMap<PathBuf, CachedTargetDir>>,
+    /// What the last successful build of each binary, or each build
+    /// command's directory, was built from, captured right after it.
+    build_infos: std::sync::Mutex<HashMap<PathBuf, BuildInfo>>,
 }
 
 impl Builder {
-    pub fn new(project_dir: PathBuf) -> Self {
-        let has_direnv = project_dir.join(".envrc").exists();
+    pub fn new(config: &Config) -> Self {
         Self {
-            project_dir,
-            has_direnv,
+            echo_output: config.echo_child_output,
+            max_line_bytes: config.max_log_line_bytes,
+            locked: config.build_locked,
+            offline: config.build_offline,
+            target_dirs: std::sync::Mutex::new(HashMap::new()),
+            build_infos: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// What the last successful build of `binary`, or of the build command
+    /// run in `project_dir` if there is no binary, was built from.
+    pub fn build_info(&self, project_dir: &Path, binary: Option<&Path>) -> Option<BuildInfo> {
+        self.build_infos.lock().unwrap().get(binary.unwrap_or(project_dir)).cloned()
+    }
+
+    /// Captures what a successful build was built from. A binary cargo left
+    /// as it was isn't hashed again.
+    async fn record_build_info(&self, project_dir: &Path, binary: Option<&Path>) {
+        if let Some(known) = self.build_info(project_dir, binary) {
+            if binary.is_some() && known.is_current(binary).await {
+                return;
+            }
         }
+        let info = BuildInfo::capture(project_dir, binary).await;
+        self.build_infos.lock().unwrap().insert(binary.unwrap_or(project_dir).to_path_buf(), info);
     }
 
+    /// The directory cargo builds `project_dir` into, which `CARGO_TARGET_DIR`,
+    /// `build.target-dir` or a workspace above it can move from `target/`.
+    /// Asks `cargo metadata` and caches the answer until a manifest or cargo
+    /// config file changes.
+    pub async fn target_dir(&self, project_dir: &Path, direnv: &Direnv) -> Result<PathBuf> {
+        let stamp = target_dir_stamp(project_dir);
+        if let Some(cached) = self.target_dirs.lock().unwrap().get(project_dir) {
+            if cached.stamp == stamp {
+                return Ok(cached.dir.clone());
+            }
+        }
+
+        let mut cmd = direnv.command("cargo").await?;
+        cmd.args(["metadata", "--format-version", "1", "--no-deps"])
+            .current_dir(project_dir)
+            .kill_on_drop(true);
+        let output = cmd.output().await.context("Failed to run cargo metadata")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "cargo metadata failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let metadata: serde_json::Value =
+            serde_json::from_slice(&output.stdout).context("Failed to parse cargo metadata")?;
+        let dir = PathBuf::from(
+            metadata["target_directory"]
+                .as_str()
+                .context("cargo metadata has no target_directory")?,
+        );
+
+        self.target_dirs.lock().unwrap().insert(
+            project_dir.to_path_buf(),
+            CachedTargetDir { stamp, dir: dir.clone() },
+        );
+        Ok(dir)
+    }
+
+    /// Runs `cargo build` in `spec.project_dir`, the root of the process
+    /// being built, and returns the path of the binary. Besides `control`,
+    /// the build can be cancelled through `cancel`, e.g. by the tool call
+    /// that started it, including while it waits for another build.
     pub async fn build_rust(
         &self,
-        release: bool,
+        spec: BuildSpec<'_>,
+        direnv: &Direnv,
         build_logs: Arc<RwLock<LogBuffer>>,
+        control: &BuildControl,
+        cancel: Option<&CancellationToken>,
     ) -> Result<PathBuf> {
+        let BuildSpec { project_dir, target, release } = spec;
+
+        // Wait for any other build for this process to finish first
+        let _guard = control.wait_turn(cancel).await?;
+
         info!(
             "Building Rust project in {} mode",
             if release { "release" } else { "dev" }
@@ -34,70 +365,271 @@ impl Builder {
 
         // Create new build log instance
         build_logs.write().await.new_instance();
+        control.compiled.send_replace(0);
 
-        let mut cmd = if self.has_direnv {
-            let mut c = Command::new("direnv");
-            c.arg("exec").arg(&self.project_dir).arg("cargo").arg("build");
-            c
-        } else {
-            let mut c = Command::new("cargo");
-            c.arg("build");
-            c
-        };
+        let mut cmd = direnv.command("cargo").await?;
+        cmd.arg("build");
 
         if release {
             cmd.arg("--release");
         }
+        if let Some(target) = target {
+            cmd.arg("--target").arg(target);
+        }
+        if self.locked {
+            cmd.arg("--locked");
+        }
+        if self.offline {
+            cmd.arg("--offline");
+        }
+
+        cmd.current_dir(project_dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        // Put cargo in its own process group, so cancelling also stops rustc
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        self.run_build(cmd, "cargo build", release, build_logs, control, cancel).await?;
+
+        info!("Build completed successfully");
+
+        // Find the binary name from Cargo.toml
+        let target_dir = self.target_dir(project_dir, direnv).await?;
+        let binary = Self::find_rust_binary(project_dir, &target_dir, target, release)?;
+        self.record_build_info(project_dir, Some(&binary)).await;
+        Ok(binary)
+    }
+
+    /// Runs an NPM process's `build_command` in `project_dir`, through direnv
+    /// like the process itself. Its output goes to a new build log instance
+    /// and it is recorded in the build history like a dev build.
+    pub async fn build_command(
+        &self,
+        command: &[String],
+        project_dir: &Path,
+        direnv: &Direnv,
+        build_logs: Arc<RwLock<LogBuffer>>,
+        control: &BuildControl,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<()> {
+        let (program, args) = command.split_first().context("build_command is empty")?;
 
-        cmd.current_dir(&self.project_dir)
+        let _guard = control.wait_turn(cancel).await?;
+
+        info!("Running build command: {}", command.join(" "));
+        build_logs.write().await.new_instance();
+        control.compiled.send_replace(0);
+
+        let mut cmd = direnv.command(program).await?;
+        cmd.args(args)
+            .current_dir(project_dir)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
 
-        let mut child = cmd.spawn().context("Failed to spawn cargo build")?;
+        // In its own process group, so cancelling also stops what it started
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        self.run_build(cmd, program, false, build_logs, control, cancel).await?;
+        info!("Build command completed successfully");
+        self.record_build_info(project_dir, None).await;
+        Ok(())
+    }
+
+    /// Runs a build command prepared by the caller, who holds the build
+    /// lock: captures its output into `build_logs`, records it in the build
+    /// history and kills it if cancelled. `name` names it in messages.
+    async fn run_build(
+        &self,
+        mut cmd: tokio::process::Command,
+        name: &str,
+        release: bool,
+        build_logs: Arc<RwLock<LogBuffer>>,
+        control: &BuildControl,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<()> {
+        // Register for cancellation before the build is visible as in progress
+        let notified = control.cancel.notified();
+        let cancelled = async move {
+            tokio::select! {
+                _ = notified => {}
+                _ = cancelled(cancel) => {}
+            }
+        };
+        *control.in_progress.write().await = true;
+        let started_at = Utc::now();
+        let start = std::time::Instant::now();
+        let counts = Arc::new(DiagnosticCounts::default());
+        let generation = build_logs.read().await.generation();
+
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                *control.in_progress.write().await = false;
+                build_logs.write().await.record_end(generation, "build failed to spawn".to_string());
+                return Err(e).with_context(|| format!("Failed to spawn {}", name));
+            }
+        };
+
+        let mut readers = Vec::new();
 
         // Capture stdout
         if let Some(stdout) = child.stdout.take() {
+            let name = name.to_string();
             let logs = build_logs.clone();
-            tokio::spawn(async move {
-                let reader = BufReader::new(stdout);
-                let mut lines = reader.lines();
-                while let Ok(Some(line)) = lines.next_line().await {
-                    println!("[build] {}", line);
-                    logs.write().await.append(line);
+            let echo = self.echo_output;
+            let max_line_bytes = self.max_line_bytes;
+            let counts = counts.clone();
+            let compiled = control.compiled.clone();
+            readers.push(tokio::spawn(async move {
+                let mut lines = LineReader::new(stdout, max_line_bytes);
+                loop {
+                    let line = match lines.next_line().await {
+                        Ok(Some(line)) => line,
+                        Ok(None) => break,
+                        Err(e) => {
+                            warn!("Stopped capturing {} stdout: {}", name, e);
+                            let line = capture_stopped(&format!("{} stdout", name), &e);
+                            logs.write().await.append_with(line, LineMeta::captured(LogStream::Stdout));
+                            break;
+                        }
+                    };
+                    if echo {
+                        println!("[build] {}", line);
+                    }
+                    if compiling_crate(&line) {
+                        compiled.send_modify(|n| *n += 1);
+                    }
+                    counts.count(&line);
+                    logs.write().await.append_with(line, LineMeta::captured(LogStream::Stdout));
                 }
-            });
+            }));
         }
 
         // Capture stderr (cargo outputs to stderr by default)
         if let Some(stderr) = child.stderr.take() {
+            let name = name.to_string();
             let logs = build_logs.clone();
-            tokio::spawn(async move {
-                let reader = BufReader::new(stderr);
-                let mut lines = reader.lines();
-                while let Ok(Some(line)) = lines.next_line().await {
-                    eprintln!("[build] {}", line);
-                    logs.write().await.append(line);
+            let echo = self.echo_output;
+            let max_line_bytes = self.max_line_bytes;
+            let counts = counts.clone();
+            let compiled = control.compiled.clone();
+            readers.push(tokio::spawn(async move {
+                let mut lines = LineReader::new(stderr, max_line_bytes);
+                loop {
+                    let line = match lines.next_line().await {
+                        Ok(Some(line)) => line,
+                        Ok(None) => break,
+                        Err(e) => {
+                            warn!("Stopped capturing {} stderr: {}", name, e);
+                            let line = capture_stopped(&format!("{} stderr", name), &e);
+                            logs.write().await.append_with(line, LineMeta::captured(LogStream::Stderr));
+                            break;
+                        }
+                    };
+                    if echo {
+                        eprintln!("[build] {}", line);
+                    }
+                    if compiling_crate(&line) {
+                        compiled.send_modify(|n| *n += 1);
+                    }
+                    counts.count(&line);
+                    logs.write().await.append_with(line, LineMeta::captured(LogStream::Stderr));
                 }
-            });
+            }));
         }
 
-        let status = child.wait().await.context("Failed to wait for cargo build")?;
+        let status = tokio::select! {
+            status = child.wait() => status,
+            _ = cancelled => {
+                warn!("Build cancelled, killing {}", name);
+                #[cfg(unix)]
+                if let Some(pid) = child.id() {
+                    use nix::sys::signal::{killpg, Signal};
+                    use nix::unistd::Pid;
+                    let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGKILL);
+                }
+                let _ = child.kill().await;
+                *control.in_progress.write().await = false;
+                build_logs.write().await.record_end(generation, "build cancelled".to_string());
+                control.record(counts.record(release, started_at, start, BuildOutcome::Cancelled)).await;
+                anyhow::bail!("Build cancelled");
+            }
+        };
+        *control.in_progress.write().await = false;
+
+        // Make sure all output is in the build log before reporting the result
+        for reader in readers {
+            let _ = reader.await;
+        }
+
+        let succeeded = status.as_ref().is_ok_and(|s| s.success());
+        let outcome = if succeeded { BuildOutcome::Succeeded } else { BuildOutcome::Failed };
+        build_logs.write().await.record_end(generation, format!("build {}", outcome.as_str()));
+        control.record(counts.record(release, started_at, start, outcome)).await;
 
+        let status = status.with_context(|| format!("Failed to wait for {}", name))?;
         if !status.success() {
             error!("Build failed with status: {}", status);
             anyhow::bail!("Build failed");
         }
 
-        info!("Build completed successfully");
+        Ok(())
+    }
 
-        // Find the binary name from Cargo.toml
-        let binary_path = self.find_rust_binary(release)?;
-        Ok(binary_path)
+    /// Runs `cargo clean` in `project_dir`, with its output going to a new
+    /// build log instance. Waits for any build of the process to finish
+    /// first. Returns cargo's summary of what it removed.
+    pub async fn clean_rust(
+        &self,
+        project_dir: &Path,
+        direnv: &Direnv,
+        build_logs: Arc<RwLock<LogBuffer>>,
+        control: &BuildControl,
+    ) -> Result<String> {
+        let _guard = control.lock.lock().await;
+
+        info!("Cleaning Rust project in {}", project_dir.display());
+        build_logs.write().await.new_instance();
+
+        let mut cmd = direnv.command("cargo").await?;
+        cmd.arg("clean").current_dir(project_dir).kill_on_drop(true);
+        let output = cmd.output().await.context("Failed to run cargo clean")?;
+
+        // cargo clean prints little, and only once it is done
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let mut summary = String::new();
+        let mut logs = build_logs.write().await;
+        let generation = logs.generation();
+        let outcome = if output.status.success() { "succeeded" } else { "failed" };
+        logs.record_end(generation, format!("cargo clean {}", outcome));
+        let lines = stdout
+            .lines()
+            .map(|line| (line, LogStream::Stdout))
+            .chain(stderr.lines().map(|line| (line, LogStream::Stderr)));
+        for (line, stream) in lines {
+            if self.echo_output {
+                eprintln!("[build] {}", line);
+            }
+            logs.append_with(line.to_string(), LineMeta::captured(stream));
+            if !line.trim().is_empty() {
+                summary = line.trim().to_string();
+            }
+        }
+
+        if !output.status.success() {
+            error!("cargo clean failed with status: {}", output.status);
+            anyhow::bail!("cargo clean failed: {}", summary);
+        }
+        Ok(summary)
     }
 
-    fn find_rust_binary(&self, release: bool) -> Result<PathBuf> {
+    fn find_rust_binary(project_dir: &Path, target_dir: &Path, target: Option<&str>, release: bool) -> Result<PathBuf> {
         // Read Cargo.toml to find the package name
-        let cargo_toml_path = self.project_dir.join("Cargo.toml");
+        let cargo_toml_path = project_dir.join("Cargo.toml");
         let content = std::fs::read_to_string(&cargo_toml_path)
             .context("Failed to read Cargo.toml")?;
 
@@ -111,12 +643,12 @@ impl Builder {
             .context("Failed to find package name in Cargo.toml")?;
 
         // Convert package name to binary name (replace hyphens with underscores is not needed for the binary file itself)
-        let target_dir = if release { "release" } else { "debug" };
-        let binary_path = self
-            .project_dir
-            .join("target")
-            .join(target_dir)
-            .join(package_name);
+        let profile_dir = if release { "release" } else { "debug" };
+        let mut binary_path = target_dir.to_path_buf();
+        if let Some(target) = target {
+            binary_path.push(target);
+        }
+        let binary_path = binary_path.join(profile_dir).join(package_name);
 
         if !binary_path.exists() {
             anyhow::bail!("Binary not found at: {}", binary_path.display());
@@ -125,3 +657,338 @@ impl Builder {
         Ok(binary_path)
     }
 }
+
+/// Warnings and errors seen in a build's output.
+#[derive(Default)]
+struct DiagnosticCounts {
+    warnings: AtomicUsize,
+    errors: AtomicUsize,
+    /// Sum of cargo's per-crate "generated N warnings" summaries.
+    summarized_warnings: AtomicUsize,
+    saw_summary: AtomicBool,
+}
+
+impl DiagnosticCounts {
+    fn count(&self, line: &str) {
+        if let Some(n) = warning_summary(line) {
+            self.summarized_warnings.fetch_add(n, Ordering::Relaxed);
+            self.saw_summary.store(true, Ordering::Relaxed);
+            return;
+        }
+        match classify_line(line) {
+            Some(Diagnostic::Warning) => self.warnings.fetch_add(1, Ordering::Relaxed),
+            Some(Diagnostic::Error) => self.errors.fetch_add(1, Ordering::Relaxed),
+            None => 0,
+        };
+    }
+
+    /// Cargo's own count where it printed one, since it is deduplicated;
+    /// otherwise the number of warnings seen.
+    fn warnings(&self) -> usize {
+        if self.saw_summary.load(Ordering::Relaxed) {
+            self.summarized_warnings.load(Ordering::Relaxed)
+        } else {
+            self.warnings.load(Ordering::Relaxed)
+        }
+    }
+
+    fn record(
+        &self,
+        release: bool,
+        started_at: DateTime<Utc>,
+        start: std::time::Instant,
+        outcome: BuildOutcome,
+    ) -> BuildRecord {
+        BuildRecord {
+            release,
+            started_at,
+            finished_at: Utc::now(),
+            duration: start.elapsed(),
+            outcome,
+            warnings: self.warnings(),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Diagnostic {
+    Warning,
+    Error,
+}
+
+/// Classifies a line of cargo output as the start of a warning or error,
+/// ignoring color codes and cargo's summary lines.
+fn classify_line(line: &str) -> Option<Diagnostic> {
+    let line = strip_ansi(line);
+    if line.starts_with("warning") {
+        // "warning: `crate` (bin "x") generated 3 warnings"
+        if line.contains(" generated ") && line.contains(" warning") {
+            return None;
+        }
+        if line.starts_with("warning:") || line.starts_with("warning[") {
+            return Some(Diagnostic::Warning);
+        }
+    } else if line.starts_with("error") {
+        if line.starts_with("error: could not compile") || line.starts_with("error: aborting due to") {
+            return None;
+        }
+        if line.starts_with("error:") || line.starts_with("error[") {
+            return Some(Diagnostic::Error);
+        }
+    }
+    None
+}
+
+/// The count from a summary line like "warning: `app` (bin "app") generated
+/// 12 warnings (run `cargo fix` to apply 3 suggestions)".
+fn warning_summary(line: &str) -> Option<usize> {
+    let line = strip_ansi(line);
+    let rest = line.strip_prefix("warning: ")?;
+    let (_, count) = rest.split_once(" generated ")?;
+    let (n, unit) = count.split_once(' ')?;
+    if !unit.starts_with("warning") {
+        return None;
+    }
+    n.parse().ok()
+}
+
+/// Whether a line of cargo output is one like "   Compiling serde v1.0.200".
+fn compiling_crate(line: &str) -> bool {
+    strip_ansi(line).trim_start().starts_with("Compiling ")
+}
+
+/// Cuts the warning blocks out of a build log: each line starting a warning,
+/// up to and including the blank line that ends it. Summary lines are left out.
+pub fn warning_blocks<S: AsRef<str>>(lines: impl IntoIterator<Item = S>) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut in_block = false;
+    for line in lines {
+        let line = line.as_ref();
+        if warning_summary(line).is_some() {
+            in_block = false;
+            continue;
+        }
+        match classify_line(line) {
+            Some(Diagnostic::Warning) => in_block = true,
+            Some(Diagnostic::Error) => in_block = false,
+            None => {}
+        }
+        if in_block {
+            blocks.push(line.to_string());
+            if strip_ansi(line).trim().is_empty() {
+                in_block = false;
+            }
+        }
+    }
+    blocks
+}
+
+/// Removes ANSI escape sequences (CSI sequences such as colors).
+fn strip_ansi(line: &str) -> std::borrow::Cow<'_, str> {
+    if !line.contains('\x1b') {
+        return std::borrow::Cow::Borrowed(line);
+    }
+
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip "[", parameters and the final byte (a letter)
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    std::borrow::Cow::Owned(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_line() {
+        assert_eq!(classify_line("warning: unused variable: `x`"), Some(Diagnostic::Warning));
+        assert_eq!(classify_line("error[E0308]: mismatched types"), Some(Diagnostic::Error));
+        assert_eq!(classify_line("error: expected one of `;`"), Some(Diagnostic::Error));
+        assert_eq!(
+            classify_line("\x1b[0m\x1b[1m\x1b[33mwarning\x1b[0m\x1b[0m\x1b[1m: unused import\x1b[0m"),
+            Some(Diagnostic::Warning)
+        );
+        assert_eq!(
+            classify_line("\x1b[1m\x1b[38;5;9merror[E0425]\x1b[0m: cannot find value"),
+            Some(Diagnostic::Error)
+        );
+
+        // Summaries and other output
+        assert_eq!(classify_line("warning: `app` (bin \"app\") generated 3 warnings"), None);
+        assert_eq!(classify_line("error: could not compile `app` (bin \"app\") due to 2 previous errors"), None);
+        assert_eq!(classify_line("error: aborting due to 2 previous errors"), None);
+        assert_eq!(classify_line("   Compiling app v0.1.0"), None);
+        assert_eq!(classify_line("  = note: `#[warn(unused)]` on by default"), None);
+    }
+
+    #[test]
+    fn test_warning_summaries_are_summed() {
+        let counts = DiagnosticCounts::default();
+        for line in [
+            "warning: unused variable: `x`",
+            "warning: unused variable: `x`",
+            "warning: `core` (lib) generated 1 warning",
+            "\x1b[1m\x1b[33mwarning\x1b[0m: `app` (bin \"app\") generated 12 warnings (run `cargo fix` to apply 3 suggestions)",
+        ] {
+            counts.count(line);
+        }
+        assert_eq!(counts.warnings(), 13);
+
+        // Without summaries, fall back to counting the warnings themselves
+        let counts = DiagnosticCounts::default();
+        counts.count("warning: unused import");
+        counts.count("warning: unused import");
+        assert_eq!(counts.warnings(), 2);
+    }
+
+    #[test]
+    fn test_dir_size() {
+        let dir = std::env::temp_dir().join(format!("bpm-test-dir-size-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("debug/deps")).unwrap();
+        std::fs::write(dir.join("debug/app"), vec![0u8; 1000]).unwrap();
+        std::fs::write(dir.join("debug/deps/libdep.rlib"), vec![0u8; 500]).unwrap();
+        assert_eq!(dir_size(&dir), 1500);
+        assert_eq!(dir_size(&dir.join("missing")), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(format_bytes(512), "512B");
+        assert_eq!(format_bytes(1536), "1.5KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0GiB");
+    }
+
+    #[test]
+    fn test_warning_blocks() {
+        let log: Vec<String> = [
+            "   Compiling app v0.1.0",
+            "warning: unused variable: `x`",
+            " --> src/main.rs:2:9",
+            "  |",
+            "",
+            "error[E0308]: mismatched types",
+            " --> src/main.rs:3:9",
+            "",
+            "warning: unused import",
+            " --> src/lib.rs:1:5",
+            "warning: `app` (bin \"app\") generated 2 warnings",
+            "    Finished `dev` profile",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+        assert_eq!(
+            warning_blocks(&log),
+            vec![
+                "warning: unused variable: `x`",
+                " --> src/main.rs:2:9",
+                "  |",
+                "",
+                "warning: unused import",
+                " --> src/lib.rs:1:5",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_target_dir() {
+        let dir = std::env::temp_dir().join(format!("bpm-test-target-dir-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join(".cargo")).unwrap();
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(dir.join(".cargo/config.toml"), "[build]\ntarget-dir = \"out\"\n").unwrap();
+
+        let config = Config::parse("mcp_port = 3001\n[process.app]\ntype = \"rust\"\n").unwrap();
+        let builder = Builder { echo_output: false, ..Builder::new(&config) };
+        let direnv = Direnv::new(dir.clone(), crate::config::DirenvStrategy::Off);
+        let spec = BuildSpec { project_dir: &dir, target: None, release: false };
+        let control = BuildControl::new();
+        let binary = builder
+            .build_rust(spec, &direnv, Arc::new(RwLock::new(LogBuffer::new())), &control, None)
+            .await
+            .unwrap();
+        assert_eq!(binary, dir.canonicalize().unwrap().join("out/debug/app"));
+        assert!(binary.exists());
+        assert_eq!(*control.watch_compiled().borrow(), 1);
+        let info = builder.build_info(&dir, Some(&binary)).unwrap();
+        assert!(info.is_current(Some(&binary)).await);
+        assert!(info.binary_hash.is_some());
+
+        // Moving the target dir is picked up without restarting
+        std::fs::write(dir.join(".cargo/config.toml"), "[build]\ntarget-dir = \"elsewhere\"\n").unwrap();
+        let target_dir = builder.target_dir(&dir, &direnv).await.unwrap();
+        assert_eq!(target_dir, dir.canonicalize().unwrap().join("elsewhere"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_build_command() {
+        let config = Config::parse("mcp_port = 3001\n[process.app]\ntype = \"rust\"\n").unwrap();
+        let builder = Builder { echo_output: false, ..Builder::new(&config) };
+        let direnv = Direnv::new(PathBuf::from("."), crate::config::DirenvStrategy::Off);
+        let logs = Arc::new(RwLock::new(LogBuffer::new()));
+        let control = BuildControl::new();
+
+        let command = ["sh", "-c", "echo built"].map(String::from);
+        builder
+            .build_command(&command, Path::new("."), &direnv, logs.clone(), &control, None)
+            .await
+            .unwrap();
+        let lines = logs.read().await.search(None, None, None, None, None);
+        assert_eq!(lines, ["built"]);
+
+        let command = ["sh", "-c", "echo broken >&2; exit 2"].map(String::from);
+        let err = builder
+            .build_command(&command, Path::new("."), &direnv, logs.clone(), &control, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Build failed");
+        assert_eq!(logs.read().await.search(None, None, None, None, None), ["broken"]);
+
+        let outcomes: Vec<_> = control.history.read().await.iter().map(|r| r.outcome).collect();
+        assert_eq!(outcomes, [BuildOutcome::Succeeded, BuildOutcome::Failed]);
+    }
+
+    #[tokio::test]
+    async fn test_history_summary() {
+        let control = BuildControl::new();
+        assert_eq!(control.summary(10).await, None);
+
+        for secs in [4, 2, 9] {
+            control
+                .record(BuildRecord {
+                    release: false,
+                    started_at: Utc::now(),
+                    finished_at: Utc::now(),
+                    duration: Duration::from_secs(secs),
+                    outcome: BuildOutcome::Succeeded,
+                    warnings: 0,
+                    errors: 0,
+                })
+                .await;
+        }
+
+        assert_eq!(control.summary(10).await.unwrap(), "avg 5.0s, max 9.0s over last 3");
+        assert_eq!(control.summary(2).await.unwrap(), "avg 5.5s, max 9.0s over last 2");
+    }
+}
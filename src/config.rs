@@ -1,23 +1,174 @@
+use crate::crash_handler::RunMode;
+use crate::mode::MAX_DEV_HOURS;
 use anyhow::{Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use ignore::gitignore::GitignoreBuilder;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub mcp_port: u16,
+    /// Extra attempts to bind `mcp_port` while another program holds it,
+    /// one second apart.
+    #[serde(default)]
+    pub mcp_port_bind_retries: u32,
+    /// Try the following `mcp_port_fallback_range` ports when `mcp_port`
+    /// stays taken, instead of failing.
+    #[serde(default)]
+    pub mcp_port_fallback: bool,
+    #[serde(default = "default_mcp_port_fallback_range")]
+    pub mcp_port_fallback_range: u16,
     #[serde(default = "default_dev_timeout_hours")]
     pub dev_timeout_hours: u64,
+    /// Mode the manager starts in. Release suits starting at boot; dev
+    /// gives fast builds right away when relaunching during development.
+    #[serde(default = "default_initial_mode")]
+    pub initial_mode: RunMode,
+    /// Switch to release mode after `dev_timeout_hours` without tool calls.
+    /// When off, the mode only changes on request.
+    #[serde(default = "default_auto_release")]
+    pub auto_release: bool,
+    /// Switch between dev and release mode at all. When off, processes are
+    /// always built in `initial_mode` and restarted after crashes with the
+    /// release backoff.
+    #[serde(default = "default_mode_system")]
+    pub mode_system: bool,
     #[serde(default = "default_dev_crash_wait_seconds")]
     pub dev_crash_wait_seconds: u64,
     #[serde(default = "default_release_crash_backoff_initial_seconds")]
     pub release_crash_backoff_initial_seconds: u64,
     #[serde(default = "default_release_crash_backoff_max_seconds")]
     pub release_crash_backoff_max_seconds: u64,
+    /// A process without a port or ready_pattern that exits this soon after
+    /// starting fails the start, instead of starting and then crashing. 0
+    /// turns the check off.
+    #[serde(default = "default_early_exit_window_seconds")]
+    pub early_exit_window_seconds: u64,
+    /// Hold off the automatic switch to release mode while a process is
+    /// crashed or has crashed recently.
+    #[serde(default = "default_release_switch_requires_healthy")]
+    pub release_switch_requires_healthy: bool,
+    /// Maximum number of builds run concurrently during initial startup.
+    #[serde(default = "default_max_concurrent_builds")]
+    pub max_concurrent_builds: usize,
+    /// Maximum number of processes started at once during initial startup.
+    #[serde(default = "default_startup_concurrency")]
+    pub startup_concurrency: usize,
+    /// Maximum number of processes rebuilt and restarted at once by bulk
+    /// restarts (all, by tag, cascades) and the switch to release mode.
+    #[serde(default = "default_max_concurrent_restarts")]
+    pub max_concurrent_restarts: usize,
+    /// Number of events (starts, crashes, hooks) kept per process, and
+    /// separately the number of state changes.
+    #[serde(default = "default_max_events")]
+    pub max_events: usize,
+    /// Echo child and build output to the manager's stdout/stderr. Disable when
+    /// running under a service manager that already journals the children.
+    #[serde(default = "default_echo_child_output")]
+    pub echo_child_output: bool,
+    /// How every process's output is echoed, overriding their own
+    /// `console_output`. Unset, each process decides.
+    #[serde(default)]
+    pub console_output: Option<ConsoleOutput>,
+    /// Lines the search tools return when a query has no pattern, head or
+    /// tail and doesn't set `no_limit`.
+    #[serde(default = "default_search_tail")]
+    pub default_search_tail: usize,
+    /// Hard cap on the lines any search returns, even with `no_limit`.
+    #[serde(default = "default_max_search_result_lines")]
+    pub max_search_result_lines: usize,
+    /// How long a search may spend matching its pattern before returning
+    /// what it found so far.
+    #[serde(default = "default_search_timeout_ms")]
+    pub search_timeout_ms: u64,
+    /// Longest process or build output line kept; the rest of a longer line
+    /// is dropped, with a marker saying how much.
+    #[serde(default = "default_max_log_line_bytes")]
+    pub max_log_line_bytes: usize,
+    /// Memory all process and build logs together may take. Over it, the
+    /// oldest instances and lines are dropped from every log in proportion
+    /// to its size. Unbounded if unset.
+    #[serde(default)]
+    pub max_total_log_mb: Option<u64>,
+    /// Pass `--locked` to cargo builds, so they fail instead of updating
+    /// `Cargo.lock`.
+    #[serde(default)]
+    pub build_locked: bool,
+    /// Pass `--offline` to cargo builds, so they never hit the network.
+    #[serde(default)]
+    pub build_offline: bool,
+    /// How often to measure the size of each Rust process's `target/`
+    /// directory, shown in get_status. Not measured if unset.
+    #[serde(default)]
+    pub target_size_check_minutes: Option<u64>,
+    /// How to apply the project's `.envrc`, if it has one.
+    #[serde(default)]
+    pub direnv: DirenvStrategy,
+    #[serde(default)]
+    pub mcp: McpConfig,
+    /// Exports the manager's tracing spans over OTLP, from `[telemetry]`.
+    /// Nothing is exported without it.
+    #[serde(default)]
+    pub telemetry: Option<TelemetryConfig>,
     #[serde(default)]
     pub process: HashMap<String, ProcessConfig>,
 }
 
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct McpConfig {
+    #[serde(default)]
+    pub limits: LimitsConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TelemetryConfig {
+    /// OTLP/HTTP traces endpoint.
+    #[serde(default = "default_telemetry_endpoint")]
+    pub endpoint: String,
+    /// Reported as the `service.name` resource attribute.
+    #[serde(default = "default_telemetry_service_name")]
+    pub service_name: String,
+}
+
+/// Limits on MCP tool calls, from `[mcp.limits]`. Besides the fields below,
+/// any `<tool> = N` entry limits that tool to N calls per minute; `search`
+/// covers all search tools and 0 means unlimited.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LimitsConfig {
+    #[serde(default = "default_limits_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_max_concurrent_calls")]
+    pub max_concurrent_calls: usize,
+    #[serde(flatten)]
+    pub per_minute: HashMap<String, u32>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_limits_enabled(),
+            max_concurrent_calls: default_max_concurrent_calls(),
+            per_minute: HashMap::new(),
+        }
+    }
+}
+
+impl LimitsConfig {
+    /// Per-minute limits with the defaults filled in for tools that weren't
+    /// configured.
+    pub fn effective_per_minute(&self) -> HashMap<String, u32> {
+        let mut limits: HashMap<String, u32> = [("restart", 20), ("build", 20), ("search", 300)]
+            .into_iter()
+            .map(|(tool, limit)| (tool.to_string(), limit))
+            .collect();
+        limits.extend(self.per_minute.clone());
+        limits.retain(|_, limit| *limit > 0);
+        limits
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ProcessConfig {
     #[serde(rename = "type")]
@@ -26,6 +177,322 @@ pub struct ProcessConfig {
     pub args: Vec<String>,
     #[serde(default)]
     pub command: Vec<String>,
+    /// Command run before starting or restarting an NPM process, e.g.
+    /// `["npm", "run", "build"]`. Its output goes to the build log.
+    #[serde(default)]
+    pub build_command: Vec<String>,
+    /// Directory the process is built and run in, if not the project
+    /// directory. Relative paths are resolved against the project directory.
+    #[serde(default)]
+    pub project_dir: Option<PathBuf>,
+    /// Prefix for lines echoed to the manager's stdout/stderr. Defaults to `[name]`.
+    #[serde(default)]
+    pub log_prefix: Option<String>,
+    /// Color used for the echoed prefix. Without one, the prefix gets a
+    /// color picked from the name when stdout is a terminal.
+    #[serde(default)]
+    pub log_color: Option<LogColor>,
+    /// How the process's output is echoed to the manager's stdout/stderr.
+    /// The log buffer gets all of it either way.
+    #[serde(default)]
+    pub console_output: ConsoleOutput,
+    /// Processes this one depends on.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Labels for grouping processes, e.g. "backend". The restart and
+    /// get_status tools accept a tag to act on all processes that have it.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Default for the restart tool's `restart_dependents` argument.
+    #[serde(default)]
+    pub restart_dependents: bool,
+    /// User to run the process as (name or uid). Unix only.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Group to run the process as (name or gid). Defaults to the user's
+    /// primary group when `user` is set. Unix only.
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Scheduling priority, from -20 (highest) to 19 (lowest). Unix only.
+    #[serde(default)]
+    pub nice: Option<i32>,
+    /// I/O scheduling class. Linux only.
+    #[serde(default)]
+    pub ionice_class: Option<IoniceClass>,
+    /// Priority within the I/O scheduling class, from 0 (highest) to 7.
+    #[serde(default)]
+    pub ionice_level: Option<u8>,
+    /// Port the process listens on. If set, the process only counts as
+    /// running once the port accepts connections on localhost.
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Regex matched against each line of output after spawning. If set, the
+    /// process only counts as running once a line matches.
+    #[serde(default)]
+    pub ready_pattern: Option<String>,
+    /// Regex for output lines counted as errors in the log rate statistics.
+    #[serde(default)]
+    pub error_pattern: Option<String>,
+    /// Error lines per minute above which an error_rate_exceeded event is
+    /// recorded. Requires `error_pattern`.
+    #[serde(default)]
+    pub max_error_rate_per_min: Option<u64>,
+    /// Open files above which an open_files_exceeded event is recorded, to
+    /// catch a descriptor leak before the process hits its ulimit. Linux only.
+    #[serde(default)]
+    pub max_open_files_warn: Option<u64>,
+    /// Exit codes a crash is restarted after; any other exit code stops the
+    /// process for good, also when it exits within the early exit window.
+    /// Empty restarts after any exit code.
+    #[serde(default)]
+    pub restart_exit_codes: Vec<i32>,
+    /// Exit codes that stop the process for good instead of restarting it.
+    #[serde(default)]
+    pub no_restart_exit_codes: Vec<i32>,
+    /// How long to wait for `port` and `ready_pattern` after spawning.
+    #[serde(default = "default_ready_timeout_seconds")]
+    pub ready_timeout_seconds: u64,
+    /// Signal sent to the process when the mode switches, e.g. "SIGHUP". Unix only.
+    #[serde(default)]
+    pub notify_on_mode_change: Option<String>,
+    /// Target triple to cross-compile Rust processes for, passed to cargo as
+    /// `--target`.
+    #[serde(default)]
+    pub target: Option<String>,
+    /// `RUST_BACKTRACE` for Rust processes, unless the manager's environment
+    /// or `.envrc` already sets it: "0", "1" or "full".
+    #[serde(default = "default_rust_backtrace")]
+    pub rust_backtrace: String,
+    /// Always build Rust processes with `--release`, even in dev mode.
+    #[serde(default)]
+    pub always_release: bool,
+    /// Always build Rust processes in the dev profile, even in release mode.
+    #[serde(default)]
+    pub always_dev: bool,
+    /// Modes the process runs in. A process that only runs in one of them
+    /// is stopped on the switch to the other and started again on the switch
+    /// back. Which profile it is built in is up to `always_release` and
+    /// `always_dev`.
+    #[serde(default = "default_profiles")]
+    pub profiles: Vec<RunMode>,
+    /// Tools checked before starting, e.g. "ffmpeg" or ">=node 20".
+    #[serde(default)]
+    pub required_tools: Vec<String>,
+    /// The command forks a daemon and exits. The daemon's PID is read from
+    /// `pid_file` and supervised instead of the spawned command. Unix only.
+    #[serde(default)]
+    pub daemonizes: bool,
+    /// Where a daemonizing process writes its PID, relative to the process's
+    /// directory.
+    #[serde(default)]
+    pub pid_file: Option<PathBuf>,
+    /// How long to wait for `pid_file` to appear after spawning.
+    #[serde(default = "default_pid_file_timeout_seconds")]
+    pub pid_file_timeout_seconds: u64,
+    /// Run before the process is stopped on purpose.
+    #[serde(default)]
+    pub pre_stop: Option<HookConfig>,
+    /// Run once the process has started and is ready.
+    #[serde(default)]
+    pub post_start: Option<HookConfig>,
+    /// Run after the process crashed, before it is restarted.
+    #[serde(default)]
+    pub post_crash: Option<HookConfig>,
+    /// Which files are the process's sources, for telling whether the
+    /// running build is out of date.
+    #[serde(default)]
+    pub watch: WatchConfig,
+    /// Timestamps output lines with the time printed in them rather than
+    /// when they were captured, for services that buffer their output.
+    #[serde(default)]
+    pub log_timestamp: Option<LogTimestampConfig>,
+}
+
+/// A command run at a point in a process's lifecycle, in the process's
+/// directory and environment. Its output goes to the process's log.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HookConfig {
+    pub command: Vec<String>,
+    #[serde(default = "default_hook_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// Abort the stop if the hook fails. Only for `pre_stop`; other hooks
+    /// failing is recorded but changes nothing.
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// Where a line's own timestamp is and how to read it. Lines it can't be
+/// read from keep the time they were captured.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LogTimestampConfig {
+    /// Regex finding the timestamp: its group named `ts` if it has one,
+    /// else the whole match.
+    pub pattern: String,
+    /// chrono format of the timestamp, e.g. "%Y-%m-%d %H:%M:%S%.3f"; one
+    /// without an offset is taken as UTC. Defaults to RFC 3339.
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+/// Narrows down the process's sources. The target directory, `node_modules/`
+/// and `.git/` at the process's root, and whatever `.gitignore` ignores are
+/// never sources.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct WatchConfig {
+    /// Files and directories to consider, relative to the process's
+    /// directory. Defaults to all of it.
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+    /// Gitignore-style patterns for files to leave out, e.g. "*.md".
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+impl ProcessConfig {
+    /// Why the process isn't restarted after exiting with `exit_code`, if
+    /// `restart_exit_codes` or `no_restart_exit_codes` rule it out. Deaths
+    /// by a signal always are restarted.
+    pub fn restart_refusal(&self, exit_code: Option<i32>) -> Option<String> {
+        let code = exit_code?;
+        if !self.restart_exit_codes.is_empty() && !self.restart_exit_codes.contains(&code) {
+            Some(format!("exit code {} is not in restart_exit_codes", code))
+        } else if self.no_restart_exit_codes.contains(&code) {
+            Some(format!("exit code {} is in no_restart_exit_codes", code))
+        } else {
+            None
+        }
+    }
+
+    /// Whether to build with `--release` in `mode`. The mode decides, unless
+    /// `always_release` or `always_dev` pins the profile.
+    pub fn release_build(&self, mode: RunMode) -> bool {
+        if self.always_release {
+            true
+        } else if self.always_dev {
+            false
+        } else {
+            mode == RunMode::Release
+        }
+    }
+
+    /// Whether the process runs in `mode` at all.
+    pub fn runs_in(&self, mode: RunMode) -> bool {
+        self.profiles.contains(&mode)
+    }
+
+    /// Whether the process is built before it starts: Rust processes always
+    /// are, NPM processes if they have a `build_command`.
+    pub fn has_build(&self) -> bool {
+        self.process_type == ProcessType::Rust || !self.build_command.is_empty()
+    }
+
+    /// Whether the build profile is pinned, so a mode switch doesn't change it.
+    pub fn profile_pinned(&self) -> bool {
+        self.always_release || self.always_dev
+    }
+
+    /// The directory the process is built and run in.
+    pub fn root(&self, project_dir: &Path) -> PathBuf {
+        match self.project_dir {
+            Some(ref dir) => project_dir.join(dir),
+            None => project_dir.to_path_buf(),
+        }
+    }
+
+    /// The configured hooks with their names, for validation.
+    fn hooks(&self) -> [(&'static str, Option<&HookConfig>); 3] {
+        [
+            ("pre_stop", self.pre_stop.as_ref()),
+            ("post_start", self.post_start.as_ref()),
+            ("post_crash", self.post_crash.as_ref()),
+        ]
+    }
+
+    /// Returns the prefix used when echoing this process's output, with ANSI
+    /// coloring applied if configured.
+    /// `tty` is whether stdout is a terminal, where a prefix without a
+    /// `log_color` is colored by name.
+    pub fn console_prefix(&self, name: &str, tty: bool) -> String {
+        let prefix = self
+            .log_prefix
+            .clone()
+            .unwrap_or_else(|| format!("[{}]", name));
+        match self.log_color.or_else(|| tty.then(|| LogColor::for_name(name))) {
+            Some(color) => format!("\x1b[{}m{}\x1b[0m", color.ansi_code(), prefix),
+            None => prefix,
+        }
+    }
+
+    /// The I/O priority to set with ioprio_set, if configured.
+    pub fn ioprio(&self) -> Option<i32> {
+        let class = self.ionice_class?;
+        // Same default level as ionice(1)
+        let level = self.ionice_level.unwrap_or(4) as i32;
+        Some((class as i32) << 13 | level)
+    }
+}
+
+/// I/O scheduling classes, with their ioprio class numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoniceClass {
+    Realtime = 1,
+    BestEffort = 2,
+    Idle = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogColor {
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
+/// How a process's output is echoed to the manager's stdout/stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsoleOutput {
+    /// Each line behind the process's prefix.
+    #[default]
+    Prefixed,
+    /// Lines as the process wrote them.
+    Raw,
+    /// Not echoed.
+    Off,
+}
+
+impl LogColor {
+    /// A color for `name` that stays the same from run to run, for telling
+    /// processes apart. White is left for explicit configuration.
+    pub fn for_name(name: &str) -> Self {
+        const COLORS: [LogColor; 6] = [
+            LogColor::Red,
+            LogColor::Green,
+            LogColor::Yellow,
+            LogColor::Blue,
+            LogColor::Magenta,
+            LogColor::Cyan,
+        ];
+        let sum = name.bytes().fold(0usize, |sum, b| sum.wrapping_mul(31).wrapping_add(b as usize));
+        COLORS[sum % COLORS.len()]
+    }
+
+    fn ansi_code(&self) -> u8 {
+        match self {
+            LogColor::Red => 31,
+            LogColor::Green => 32,
+            LogColor::Yellow => 33,
+            LogColor::Blue => 34,
+            LogColor::Magenta => 35,
+            LogColor::Cyan => 36,
+            LogColor::White => 37,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
@@ -35,10 +502,56 @@ pub enum ProcessType {
     Npm,
 }
 
+impl ProcessType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProcessType::Rust => "rust",
+            ProcessType::Npm => "npm",
+        }
+    }
+}
+
+/// How builds and processes pick up the environment from `.envrc`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DirenvStrategy {
+    /// Wrap every command in `direnv exec`.
+    #[default]
+    Exec,
+    /// Run `direnv export json` once, cached until `.envrc` changes, and spawn
+    /// commands directly with that environment.
+    Export,
+    /// Ignore `.envrc`.
+    Off,
+}
+
+impl DirenvStrategy {
+    /// As spelled in the config.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DirenvStrategy::Exec => "exec",
+            DirenvStrategy::Export => "export",
+            DirenvStrategy::Off => "off",
+        }
+    }
+}
+
 fn default_dev_timeout_hours() -> u64 {
     3
 }
 
+fn default_initial_mode() -> RunMode {
+    RunMode::Release
+}
+
+fn default_auto_release() -> bool {
+    true
+}
+
+fn default_mode_system() -> bool {
+    true
+}
+
 fn default_dev_crash_wait_seconds() -> u64 {
     120
 }
@@ -51,35 +564,1246 @@ fn default_release_crash_backoff_max_seconds() -> u64 {
     300
 }
 
+fn default_early_exit_window_seconds() -> u64 {
+    3
+}
+
+fn default_release_switch_requires_healthy() -> bool {
+    true
+}
+
+fn default_max_concurrent_builds() -> usize {
+    2
+}
+
+fn default_startup_concurrency() -> usize {
+    4
+}
+
+fn default_max_concurrent_restarts() -> usize {
+    2
+}
+
+fn default_max_events() -> usize {
+    100
+}
+
+fn default_search_tail() -> usize {
+    200
+}
+
+fn default_max_search_result_lines() -> usize {
+    2000
+}
+
+fn default_search_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_mcp_port_fallback_range() -> u16 {
+    10
+}
+
+fn default_max_log_line_bytes() -> usize {
+    16384
+}
+
+fn default_limits_enabled() -> bool {
+    true
+}
+
+fn default_max_concurrent_calls() -> usize {
+    16
+}
+
+fn default_telemetry_endpoint() -> String {
+    "http://localhost:4318/v1/traces".to_string()
+}
+
+fn default_telemetry_service_name() -> String {
+    "background-process-manager".to_string()
+}
+
+fn default_ready_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_pid_file_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_hook_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_profiles() -> Vec<RunMode> {
+    vec![RunMode::Dev, RunMode::Release]
+}
+
+fn default_rust_backtrace() -> String {
+    "1".to_string()
+}
+
+fn default_echo_child_output() -> bool {
+    true
+}
+
+/// Config filenames, checked in order. The `.toml` variant exists because
+/// editors tend to handle extensionless files poorly.
+pub const CONFIG_FILENAMES: &[&str] = &[".mcp-run", ".mcp-run.toml"];
+
+const EXAMPLE_CONFIG: &str = r#"mcp_port = 3001
+
+[process.main]
+type = "rust"
+args = []
+
+# [process.frontend]
+# type = "npm"
+# command = ["npm", "run", "dev"]"#;
+
 impl Config {
     pub fn load(project_dir: &Path) -> Result<Self> {
-        let config_path = project_dir.join(".mcp-run");
+        let config_path = Self::find(project_dir).with_context(|| {
+            format!(
+                "No config file found; expected {} (or {}). A minimal config looks like:\n\n{}\n",
+                project_dir.join(CONFIG_FILENAMES[0]).display(),
+                CONFIG_FILENAMES[1],
+                EXAMPLE_CONFIG
+            )
+        })?;
         let content = std::fs::read_to_string(&config_path)
             .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
 
-        let config: Config = toml::from_str(&content)
-            .with_context(|| format!("Failed to parse config file: {}", config_path.display()))?;
+        Self::parse_in(&content, Some(project_dir))
+            .with_context(|| format!("Invalid config file: {}", config_path.display()))
+    }
+
+    /// Returns the first existing config file in `project_dir`.
+    pub fn find(project_dir: &Path) -> Option<PathBuf> {
+        CONFIG_FILENAMES
+            .iter()
+            .map(|name| project_dir.join(name))
+            .find(|path| path.is_file())
+    }
+
+    /// Parses and validates config text, without a project directory to
+    /// check the processes' directories against.
+    #[cfg(test)]
+    pub fn parse(content: &str) -> Result<Self> {
+        Self::parse_in(content, None)
+    }
+
+    /// Parses and validates config text, reporting every problem found rather
+    /// than stopping at the first one. The processes' directories are checked
+    /// if the project directory is known.
+    fn parse_in(content: &str, project_dir: Option<&Path>) -> Result<Self> {
+        // Syntax errors come with line/column information from toml.
+        let table: toml::Table = toml::from_str(content)?;
+
+        // Check process types up front, so an unknown type doesn't hide the
+        // remaining problems behind a single deserialization error.
+        let mut errors = Vec::new();
+        let processes = table.get("process").and_then(|p| p.as_table());
+        let mut mistyped = Vec::new();
+        for (name, proc_table) in processes.into_iter().flatten() {
+            match proc_table.get("type") {
+                None => errors.push(format!("Process '{}' is missing 'type'", name)),
+                Some(toml::Value::String(t)) if t == "rust" || t == "npm" => continue,
+                Some(other) => errors.push(format!(
+                    "Process '{}' has unknown type {} (expected \"rust\" or \"npm\")",
+                    name, other
+                )),
+            }
+            mistyped.push(name);
+        }
+
+        // Deserializing stops at the first problem, so on one, the processes
+        // are deserialized one at a time and the rest of the config without
+        // them. What did deserialize is still validated.
+        let (config, unparsed) = match toml::from_str::<Config>(content) {
+            Ok(config) => (Some(config), mistyped),
+            Err(e) => {
+                let mut parsed = HashMap::new();
+                for (name, proc_table) in processes.into_iter().flatten() {
+                    if mistyped.contains(&name) {
+                        continue;
+                    }
+                    match proc_table.clone().try_into::<ProcessConfig>() {
+                        Ok(proc_config) => {
+                            parsed.insert(name.clone(), proc_config);
+                        }
+                        Err(e) => {
+                            errors.push(format!("Process '{}': {}", name, e.message()));
+                            mistyped.push(name);
+                        }
+                    }
+                }
+                let mut rest = table.clone();
+                rest.remove("process");
+                let config = match toml::Value::Table(rest).try_into::<Config>() {
+                    Ok(config) => Some(Config { process: parsed, ..config }),
+                    Err(e) => {
+                        errors.push(e.message().to_string());
+                        None
+                    }
+                };
+                if errors.is_empty() {
+                    errors.push(e.to_string());
+                }
+                (config, mistyped)
+            }
+        };
+
+        if let Some(ref config) = config {
+            errors.extend(config.validate(&unparsed));
+            if let Some(project_dir) = project_dir {
+                errors.extend(config.missing_project_dirs(project_dir));
+            }
+        }
+        match config {
+            Some(config) if errors.is_empty() => Ok(config),
+            _ => anyhow::bail!(format_errors(&errors)),
+        }
+    }
+
+    /// Processes whose `project_dir` doesn't exist under `project_dir`.
+    fn missing_project_dirs(&self, project_dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = self.process.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let root = self.process[name].root(project_dir);
+                (!root.is_dir()).then(|| format!("Process '{}' has project_dir {} which does not exist", name, root.display()))
+            })
+            .collect()
+    }
+
+    /// How process `name`'s output is echoed: not at all without
+    /// `echo_child_output`, else as the global `console_output` or, without
+    /// one, the process's own says.
+    pub fn console_output_for(&self, name: &str) -> ConsoleOutput {
+        if !self.echo_child_output {
+            return ConsoleOutput::Off;
+        }
+        self.console_output
+            .or_else(|| self.process.get(name).map(|p| p.console_output))
+            .unwrap_or_default()
+    }
+
+    /// Returns all semantic problems with this configuration. The `unparsed`
+    /// processes are defined but couldn't be read, so they are missing from
+    /// it; depending on them isn't a problem.
+    fn validate(&self, unparsed: &[&String]) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.mcp_port == 0 {
+            errors.push("mcp_port must be non-zero".to_string());
+        }
+
+        if self.mcp_port_fallback {
+            if self.mcp_port_fallback_range == 0 {
+                errors.push("mcp_port_fallback_range must be at least 1".to_string());
+            } else if self.mcp_port.checked_add(self.mcp_port_fallback_range).is_none() {
+                errors.push(format!(
+                    "mcp_port_fallback_range ({}) goes past port 65535 from mcp_port {}",
+                    self.mcp_port_fallback_range, self.mcp_port
+                ));
+            }
+        }
+
+        if self.dev_timeout_hours == 0 {
+            errors.push("dev_timeout_hours must be at least 1".to_string());
+        } else if self.dev_timeout_hours > MAX_DEV_HOURS {
+            errors.push(format!("dev_timeout_hours must be at most {}", MAX_DEV_HOURS));
+        }
+
+        if let Some(ref telemetry) = self.telemetry {
+            if !telemetry.endpoint.starts_with("http://") && !telemetry.endpoint.starts_with("https://") {
+                errors.push(format!(
+                    "telemetry.endpoint must be an http:// or https:// URL, got '{}'",
+                    telemetry.endpoint
+                ));
+            }
+        }
+
+        if self.release_crash_backoff_initial_seconds > self.release_crash_backoff_max_seconds {
+            errors.push(format!(
+                "release_crash_backoff_initial_seconds ({}) is greater than release_crash_backoff_max_seconds ({})",
+                self.release_crash_backoff_initial_seconds, self.release_crash_backoff_max_seconds
+            ));
+        }
+
+        if self.max_concurrent_builds == 0 {
+            errors.push("max_concurrent_builds must be at least 1".to_string());
+        }
+
+        if self.startup_concurrency == 0 {
+            errors.push("startup_concurrency must be at least 1".to_string());
+        }
+
+        if self.max_concurrent_restarts == 0 {
+            errors.push("max_concurrent_restarts must be at least 1".to_string());
+        }
+
+        if self.max_events == 0 {
+            errors.push("max_events must be at least 1".to_string());
+        }
+
+        if self.default_search_tail == 0 {
+            errors.push("default_search_tail must be at least 1".to_string());
+        }
+
+        if self.default_search_tail > self.max_search_result_lines {
+            errors.push(format!(
+                "default_search_tail ({}) is greater than max_search_result_lines ({})",
+                self.default_search_tail, self.max_search_result_lines
+            ));
+        }
+
+        if self.search_timeout_ms == 0 {
+            errors.push("search_timeout_ms must be at least 1".to_string());
+        }
+
+        if self.max_log_line_bytes == 0 {
+            errors.push("max_log_line_bytes must be at least 1".to_string());
+        }
+
+        if self.max_total_log_mb == Some(0) {
+            errors.push("max_total_log_mb must be at least 1".to_string());
+        }
+
+        if self.target_size_check_minutes == Some(0) {
+            errors.push("target_size_check_minutes must be at least 1".to_string());
+        }
+
+        if self.mcp.limits.max_concurrent_calls == 0 {
+            errors.push("mcp.limits.max_concurrent_calls must be at least 1".to_string());
+        }
 
         // Validate that we have at least one process
-        if config.process.is_empty() {
-            anyhow::bail!("No processes defined in configuration");
+        if self.process.is_empty() && unparsed.is_empty() {
+            errors.push("No processes defined in configuration".to_string());
         }
 
-        // Validate process configurations
-        for (name, proc_config) in &config.process {
+        // Validate process configurations, in a stable order
+        let mut names: Vec<_> = self.process.keys().collect();
+        names.sort();
+        let mut ports: HashMap<u16, &str> = HashMap::new();
+        for name in names {
+            let proc_config = &self.process[name];
             match proc_config.process_type {
                 ProcessType::Rust => {
-                    // For Rust, args are optional
+                    // For Rust, args are optional; cargo does the building
+                    if !proc_config.build_command.is_empty() {
+                        errors.push(format!(
+                            "Process '{}' is type 'rust' but has a build_command; only npm processes take one",
+                            name
+                        ));
+                    }
                 }
                 ProcessType::Npm => {
                     // For NPM, command is required
                     if proc_config.command.is_empty() {
-                        anyhow::bail!("Process '{}' is type 'npm' but has no command specified", name);
+                        errors.push(format!(
+                            "Process '{}' is type 'npm' but has no command specified",
+                            name
+                        ));
                     }
                 }
             }
+
+            if proc_config.port == Some(0) {
+                errors.push(format!("Process '{}' has port 0, must be non-zero", name));
+            }
+            if let Some(port) = proc_config.port.filter(|&port| port != 0) {
+                if port == self.mcp_port {
+                    errors.push(format!("Process '{}' has port {}, which is the mcp_port", name, port));
+                } else if let Some(other) = ports.insert(port, name) {
+                    errors.push(format!(
+                        "Process '{}' has port {}, which is already used by process '{}'",
+                        name, port, other
+                    ));
+                }
+            }
+
+            if let Some(ref pattern) = proc_config.ready_pattern {
+                if let Err(e) = regex::Regex::new(pattern) {
+                    errors.push(format!("Process '{}' has invalid ready_pattern: {}", name, e));
+                }
+            }
+
+            if let Some(ref pattern) = proc_config.error_pattern {
+                if let Err(e) = regex::Regex::new(pattern) {
+                    errors.push(format!("Process '{}' has invalid error_pattern: {}", name, e));
+                }
+            }
+
+            if let Some(ref timestamp) = proc_config.log_timestamp {
+                if let Err(e) = regex::Regex::new(&timestamp.pattern) {
+                    errors.push(format!("Process '{}' has invalid log_timestamp.pattern: {}", name, e));
+                }
+                if let Some(ref format) = timestamp.format {
+                    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                        errors.push(format!("Process '{}' has invalid log_timestamp.format: {}", name, format));
+                    }
+                }
+            }
+
+            match proc_config.max_error_rate_per_min {
+                Some(0) => errors.push(format!("Process '{}' has max_error_rate_per_min 0; it must be at least 1", name)),
+                Some(_) if proc_config.error_pattern.is_none() => errors.push(format!(
+                    "Process '{}' sets max_error_rate_per_min without error_pattern",
+                    name
+                )),
+                _ => {}
+            }
+
+            if proc_config.max_open_files_warn == Some(0) {
+                errors.push(format!("Process '{}' has max_open_files_warn 0; it must be at least 1", name));
+            }
+
+            if !proc_config.restart_exit_codes.is_empty() && !proc_config.no_restart_exit_codes.is_empty() {
+                errors.push(format!(
+                    "Process '{}' sets both restart_exit_codes and no_restart_exit_codes; use one",
+                    name
+                ));
+            }
+
+            if let Some(nice) = proc_config.nice {
+                if !(-20..=19).contains(&nice) {
+                    errors.push(format!(
+                        "Process '{}' has nice {}, must be between -20 and 19",
+                        name, nice
+                    ));
+                }
+            }
+
+            for spec in &proc_config.required_tools {
+                if let Err(e) = crate::preflight::Requirement::parse(spec) {
+                    errors.push(format!("Process '{}' has invalid required_tools entry: {}", name, e));
+                }
+            }
+
+            if let Some(ref signal) = proc_config.notify_on_mode_change {
+                #[cfg(unix)]
+                if signal.parse::<nix::sys::signal::Signal>().is_err() {
+                    errors.push(format!(
+                        "Process '{}' has unknown notify_on_mode_change signal '{}'",
+                        name, signal
+                    ));
+                }
+                #[cfg(not(unix))]
+                errors.push(format!(
+                    "Process '{}' sets notify_on_mode_change ({}), which is only supported on unix",
+                    name, signal
+                ));
+            }
+
+            if !matches!(proc_config.rust_backtrace.as_str(), "0" | "1" | "full") {
+                errors.push(format!(
+                    "Process '{}' has rust_backtrace '{}', must be \"0\", \"1\" or \"full\"",
+                    name, proc_config.rust_backtrace
+                ));
+            }
+
+            for pattern in &proc_config.watch.ignore {
+                if let Err(e) = GitignoreBuilder::new("").add_line(None, pattern) {
+                    errors.push(format!("Process '{}' has an invalid watch.ignore pattern '{}': {}", name, pattern, e));
+                }
+            }
+
+            if proc_config.always_release && proc_config.always_dev {
+                errors.push(format!("Process '{}' sets both always_release and always_dev", name));
+            }
+
+            if proc_config.profiles.is_empty() {
+                errors.push(format!("Process '{}' has no profiles; it has to run in dev, release or both", name));
+            } else if proc_config.always_release && !proc_config.runs_in(RunMode::Release) {
+                errors.push(format!("Process '{}' sets always_release but only runs in dev mode", name));
+            } else if proc_config.always_dev && !proc_config.runs_in(RunMode::Dev) {
+                errors.push(format!("Process '{}' sets always_dev but only runs in release mode", name));
+            }
+
+            match (proc_config.daemonizes, &proc_config.pid_file) {
+                (true, None) => errors.push(format!(
+                    "Process '{}' daemonizes but has no pid_file",
+                    name
+                )),
+                (false, Some(_)) => errors.push(format!(
+                    "Process '{}' sets pid_file without daemonizes",
+                    name
+                )),
+                _ => {}
+            }
+            #[cfg(not(unix))]
+            if proc_config.daemonizes {
+                errors.push(format!(
+                    "Process '{}' daemonizes, which is only supported on unix",
+                    name
+                ));
+            }
+
+            match (proc_config.ionice_class, proc_config.ionice_level) {
+                (None, Some(_)) => errors.push(format!(
+                    "Process '{}' sets ionice_level without ionice_class",
+                    name
+                )),
+                (Some(IoniceClass::Idle), Some(_)) => errors.push(format!(
+                    "Process '{}' sets ionice_level, which the idle class doesn't use",
+                    name
+                )),
+                (_, Some(level)) if level > 7 => errors.push(format!(
+                    "Process '{}' has ionice_level {}, must be between 0 and 7",
+                    name, level
+                )),
+                _ => {}
+            }
+
+            for tag in &proc_config.tags {
+                if tag.is_empty() || tag.contains(char::is_whitespace) {
+                    errors.push(format!("Process '{}' has invalid tag '{}'; tags can't be empty or contain spaces", name, tag));
+                }
+            }
+
+            for (hook_name, hook) in proc_config.hooks() {
+                let Some(hook) = hook else {
+                    continue;
+                };
+                if hook.command.is_empty() {
+                    errors.push(format!("Process '{}' has a {} hook with no command", name, hook_name));
+                }
+                if hook.timeout_seconds == 0 {
+                    errors.push(format!(
+                        "Process '{}' has a {} hook with timeout_seconds 0; it must be at least 1",
+                        name, hook_name
+                    ));
+                }
+                if hook.required && hook_name != "pre_stop" {
+                    errors.push(format!(
+                        "Process '{}' sets required on its {} hook; only pre_stop hooks can be required",
+                        name, hook_name
+                    ));
+                }
+            }
+
+            for dependency in &proc_config.depends_on {
+                if dependency == name {
+                    errors.push(format!("Process '{}' depends on itself", name));
+                } else if !self.process.contains_key(dependency) && !unparsed.contains(&dependency) {
+                    errors.push(format!(
+                        "Process '{}' depends on unknown process '{}'",
+                        name, dependency
+                    ));
+                }
+            }
+        }
+
+        if let Some(cycle) = self.find_dependency_cycle() {
+            errors.push(format!("Dependency cycle: {}", cycle.join(" -> ")));
+        }
+
+        errors
+    }
+
+    /// Returns a dependency cycle (excluding self-dependencies), if any.
+    fn find_dependency_cycle(&self) -> Option<Vec<String>> {
+        fn visit<'a>(
+            config: &'a Config,
+            name: &'a str,
+            path: &mut Vec<&'a str>,
+            done: &mut HashSet<&'a str>,
+        ) -> Option<Vec<String>> {
+            if let Some(pos) = path.iter().position(|n| *n == name) {
+                let mut cycle: Vec<String> = path[pos..].iter().map(|n| n.to_string()).collect();
+                cycle.push(name.to_string());
+                return Some(cycle);
+            }
+            if !done.insert(name) {
+                return None;
+            }
+
+            path.push(name);
+            let mut dependencies: Vec<_> = config.process[name]
+                .depends_on
+                .iter()
+                .filter(|d| *d != name && config.process.contains_key(*d))
+                .collect();
+            dependencies.sort();
+            for dependency in dependencies {
+                if let Some(cycle) = visit(config, dependency, path, done) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            None
         }
 
-        Ok(config)
+        let mut names: Vec<_> = self.process.keys().collect();
+        names.sort();
+        let mut done = HashSet::new();
+        names
+            .into_iter()
+            .find_map(|name| visit(self, name, &mut Vec::new(), &mut done))
+    }
+
+    /// Returns the transitive dependents of `name`, ordered so that every
+    /// process comes after the processes it depends on.
+    pub fn dependents_of(&self, name: &str) -> Vec<String> {
+        // Collect everything that transitively depends on `name`
+        let mut dependents = HashSet::new();
+        let mut queue = vec![name];
+        while let Some(current) = queue.pop() {
+            for (other, other_config) in &self.process {
+                if other_config.depends_on.iter().any(|d| d == current)
+                    && other != name
+                    && dependents.insert(other.as_str())
+                {
+                    queue.push(other);
+                }
+            }
+        }
+
+        self.dependency_order(dependents.into_iter().collect())
+    }
+
+    /// Returns the processes tagged `tag`, ordered so that every process
+    /// comes after the processes it depends on.
+    pub fn tagged(&self, tag: &str) -> Vec<String> {
+        let tagged = self
+            .process
+            .iter()
+            .filter(|(_, config)| config.tags.iter().any(|t| t == tag))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        self.dependency_order(tagged)
+    }
+
+    /// Returns every process, ordered so that every process comes after the
+    /// processes it depends on.
+    pub fn in_dependency_order(&self) -> Vec<String> {
+        self.dependency_order(self.process.keys().map(String::as_str).collect())
+    }
+
+    /// Orders `names` topologically, breaking ties by name. Dependencies
+    /// outside `names` are ignored.
+    fn dependency_order(&self, mut remaining: Vec<&str>) -> Vec<String> {
+        let mut ordered = Vec::new();
+        remaining.sort();
+        while !remaining.is_empty() {
+            let ready = remaining
+                .iter()
+                .position(|candidate| {
+                    self.process[*candidate]
+                        .depends_on
+                        .iter()
+                        .all(|d| !remaining.contains(&d.as_str()))
+                })
+                // Cycles are rejected at load time, but don't loop forever
+                .unwrap_or(0);
+            ordered.push(remaining.remove(ready).to_string());
+        }
+        ordered
+    }
+}
+
+fn format_errors(errors: &[String]) -> String {
+    let mut message = format!("{} configuration error(s):", errors.len());
+    for error in errors {
+        message.push_str("\n  - ");
+        message.push_str(error);
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_minimal() {
+        let config = Config::parse(EXAMPLE_CONFIG).unwrap();
+        assert_eq!(config.mcp_port, 3001);
+        assert_eq!(config.process["main"].process_type, ProcessType::Rust);
+        assert_eq!(config.initial_mode, RunMode::Release);
+
+        let config = Config::parse(&format!("initial_mode = \"dev\"\n{}", EXAMPLE_CONFIG)).unwrap();
+        assert_eq!(config.initial_mode, RunMode::Dev);
+        assert!(Config::parse(&format!("initial_mode = \"debug\"\n{}", EXAMPLE_CONFIG)).is_err());
+    }
+
+    #[test]
+    fn test_collects_all_errors() {
+        let err = Config::parse(
+            r#"
+            mcp_port = 3001
+            release_crash_backoff_initial_seconds = 10
+            release_crash_backoff_max_seconds = 5
+
+            [process.web]
+            type = "npm"
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.starts_with("2 configuration error(s)"), "{}", err);
+        assert!(err.contains("release_crash_backoff_initial_seconds"));
+        assert!(err.contains("Process 'web' is type 'npm'"));
+
+        // Processes that can't be read don't hide the other problems
+        let err = Config::parse(
+            r#"
+            mcp_port = 3001
+            release_crash_backoff_initial_seconds = 10
+            release_crash_backoff_max_seconds = 5
+
+            [process.a]
+            type = "python"
+
+            [process.b]
+            type = "rust"
+            port = "eighty"
+
+            [process.c]
+            type = "rust"
+            depends_on = ["a", "b"]
+            rust_backtrace = "yes"
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.starts_with("4 configuration error(s)"), "{}", err);
+        assert!(err.contains("Process 'a' has unknown type \"python\""), "{}", err);
+        assert!(err.contains("Process 'b': invalid type: string \"eighty\""), "{}", err);
+        assert!(err.contains("release_crash_backoff_initial_seconds"), "{}", err);
+        assert!(err.contains("Process 'c' has rust_backtrace 'yes'"), "{}", err);
+    }
+
+    #[test]
+    fn test_unknown_process_types() {
+        let err = Config::parse(
+            r#"
+            mcp_port = 3001
+
+            [process.a]
+            type = "python"
+
+            [process.b]
+            args = []
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.contains("Process 'a' has unknown type \"python\""), "{}", err);
+        assert!(err.contains("Process 'b' is missing 'type'"), "{}", err);
+    }
+
+    #[test]
+    fn test_console_prefix() {
+        let mut config = Config::parse(EXAMPLE_CONFIG).unwrap().process["main"].clone();
+        assert_eq!(config.console_prefix("main", false), "[main]");
+
+        // On a terminal the name picks the color, the same one every time
+        let color = LogColor::for_name("main");
+        assert_eq!(color, LogColor::for_name("main"));
+        assert_eq!(
+            config.console_prefix("main", true),
+            format!("\x1b[{}m[main]\x1b[0m", color.ansi_code())
+        );
+
+        config.log_prefix = Some("api |".to_string());
+        assert_eq!(config.console_prefix("main", false), "api |");
+
+        config.log_color = Some(LogColor::Cyan);
+        assert_eq!(config.console_prefix("main", false), "\x1b[36mapi |\x1b[0m");
+        assert_eq!(config.console_prefix("main", true), "\x1b[36mapi |\x1b[0m");
+    }
+
+    #[test]
+    fn test_console_output() {
+        let mut config = Config::parse(
+            "mcp_port = 3001\n[process.main]\ntype = \"rust\"\n\
+             [process.web]\ntype = \"npm\"\ncommand = [\"npm\", \"start\"]\nconsole_output = \"raw\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.console_output_for("main"), ConsoleOutput::Prefixed);
+        assert_eq!(config.console_output_for("web"), ConsoleOutput::Raw);
+
+        config.console_output = Some(ConsoleOutput::Off);
+        assert_eq!(config.console_output_for("web"), ConsoleOutput::Off);
+
+        config.console_output = Some(ConsoleOutput::Prefixed);
+        config.echo_child_output = false;
+        assert_eq!(config.console_output_for("main"), ConsoleOutput::Off);
+    }
+
+    #[test]
+    fn test_dependency_validation() {
+        let err = Config::parse(
+            r#"
+            mcp_port = 3001
+
+            [process.a]
+            type = "rust"
+            depends_on = ["b", "missing"]
+
+            [process.b]
+            type = "rust"
+            depends_on = ["a"]
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.contains("Process 'a' depends on unknown process 'missing'"), "{}", err);
+        assert!(err.contains("Dependency cycle: a -> b -> a"), "{}", err);
+    }
+
+    #[test]
+    fn test_priority_validation() {
+        let config = Config::parse(
+            r#"
+            mcp_port = 3001
+
+            [process.indexer]
+            type = "rust"
+            nice = 10
+            ionice_class = "best-effort"
+            ionice_level = 7
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.process["indexer"].ioprio(), Some(2 << 13 | 7));
+
+        let err = Config::parse(
+            r#"
+            mcp_port = 3001
+
+            [process.a]
+            type = "rust"
+            nice = 20
+            ionice_level = 3
+
+            [process.b]
+            type = "rust"
+            ionice_class = "idle"
+            ionice_level = 0
+
+            [process.c]
+            type = "rust"
+            ionice_class = "realtime"
+            ionice_level = 8
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.starts_with("4 configuration error(s)"), "{}", err);
+        assert!(err.contains("Process 'a' has nice 20"), "{}", err);
+        assert!(err.contains("Process 'a' sets ionice_level without ionice_class"), "{}", err);
+        assert!(err.contains("Process 'b' sets ionice_level, which the idle class"), "{}", err);
+        assert!(err.contains("Process 'c' has ionice_level 8"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_notify_on_mode_change_validation() {
+        let err = Config::parse(
+            r#"
+            mcp_port = 3001
+
+            [process.a]
+            type = "rust"
+            notify_on_mode_change = "SIGHUP"
+
+            [process.b]
+            type = "rust"
+            notify_on_mode_change = "HUP"
+            required_tools = ["ffmpeg", ">=node 20", ">=node"]
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.starts_with("2 configuration error(s)"), "{}", err);
+        assert!(err.contains("Process 'b' has unknown notify_on_mode_change signal 'HUP'"), "{}", err);
+        assert!(err.contains("Process 'b' has invalid required_tools entry: '>=node' is missing a version"), "{}", err);
+    }
+
+    #[test]
+    fn test_error_rate_validation() {
+        let err = Config::parse(
+            r#"
+            mcp_port = 3001
+
+            [process.a]
+            type = "rust"
+            error_pattern = "ERROR|panicked"
+            max_error_rate_per_min = 10
+
+            [process.b]
+            type = "rust"
+            error_pattern = "(unclosed"
+
+            [process.c]
+            type = "rust"
+            max_error_rate_per_min = 10
+            max_open_files_warn = 0
+            restart_exit_codes = [75]
+            no_restart_exit_codes = [1]
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.starts_with("4 configuration error(s)"), "{}", err);
+        assert!(err.contains("Process 'c' sets both restart_exit_codes and no_restart_exit_codes"), "{}", err);
+        assert!(err.contains("Process 'c' has max_open_files_warn 0"), "{}", err);
+        assert!(err.contains("Process 'b' has invalid error_pattern"), "{}", err);
+        assert!(err.contains("Process 'c' sets max_error_rate_per_min without error_pattern"), "{}", err);
+    }
+
+    #[test]
+    fn test_log_timestamp_validation() {
+        let config = Config::parse(
+            r#"
+            mcp_port = 3001
+
+            [process.a]
+            type = "rust"
+            log_timestamp = { pattern = '^\[(?P<ts>[^\]]+)\]', format = "%Y-%m-%d %H:%M:%S%.3f" }
+            "#,
+        )
+        .unwrap();
+        let timestamp = config.process["a"].log_timestamp.as_ref().unwrap();
+        assert_eq!(timestamp.format.as_deref(), Some("%Y-%m-%d %H:%M:%S%.3f"));
+
+        let err = Config::parse(
+            r#"
+            mcp_port = 3001
+
+            [process.a]
+            type = "rust"
+            log_timestamp = { pattern = "(unclosed" }
+
+            [process.b]
+            type = "rust"
+            log_timestamp = { pattern = "^\\S+", format = "%Y-%Q" }
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.starts_with("2 configuration error(s)"), "{}", err);
+        assert!(err.contains("Process 'a' has invalid log_timestamp.pattern"), "{}", err);
+        assert!(err.contains("Process 'b' has invalid log_timestamp.format: %Y-%Q"), "{}", err);
+    }
+
+    #[test]
+    fn test_hook_validation() {
+        let err = Config::parse(
+            r#"
+            mcp_port = 3001
+
+            [process.a]
+            type = "rust"
+            pre_stop = { command = ["./scripts/drain.sh"], required = true }
+            post_start = { command = ["./scripts/warm-cache.sh"], timeout_seconds = 60 }
+
+            [process.b]
+            type = "rust"
+            post_crash = { command = [], timeout_seconds = 0 }
+
+            [process.c]
+            type = "rust"
+            post_start = { command = ["true"], required = true }
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.starts_with("3 configuration error(s)"), "{}", err);
+        assert!(err.contains("Process 'b' has a post_crash hook with no command"), "{}", err);
+        assert!(err.contains("Process 'b' has a post_crash hook with timeout_seconds 0"), "{}", err);
+        assert!(err.contains("Process 'c' sets required on its post_start hook"), "{}", err);
+    }
+
+    #[test]
+    fn test_dependents_order() {
+        let config = Config::parse(
+            r#"
+            mcp_port = 3001
+
+            [process.api]
+            type = "rust"
+
+            [process.worker]
+            type = "rust"
+            depends_on = ["api", "cache"]
+
+            [process.cache]
+            type = "rust"
+            depends_on = ["api"]
+
+            [process.frontend]
+            type = "npm"
+            command = ["npm", "run", "dev"]
+            depends_on = ["worker"]
+
+            [process.unrelated]
+            type = "rust"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.dependents_of("api"), vec!["cache", "worker", "frontend"]);
+        assert_eq!(config.dependents_of("worker"), vec!["frontend"]);
+        assert!(config.dependents_of("unrelated").is_empty());
+    }
+
+    #[test]
+    fn test_tags() {
+        let config = Config::parse(
+            r#"
+            mcp_port = 3001
+
+            [process.worker]
+            type = "rust"
+            depends_on = ["api"]
+            tags = ["backend"]
+
+            [process.api]
+            type = "rust"
+            tags = ["backend", "public"]
+
+            [process.frontend]
+            type = "npm"
+            command = ["npm", "run", "dev"]
+            tags = ["public"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.tagged("backend"), vec!["api", "worker"]);
+        assert_eq!(config.tagged("public"), vec!["api", "frontend"]);
+        assert!(config.tagged("infra").is_empty());
+        assert_eq!(config.in_dependency_order(), vec!["api", "frontend", "worker"]);
+
+        let err = Config::parse("mcp_port = 3001\n[process.a]\ntype = \"rust\"\ntags = [\"back end\"]\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Process 'a' has invalid tag 'back end'"), "{}", err);
+    }
+
+    #[test]
+    fn test_limits() {
+        let config = Config::parse(
+            r#"
+            mcp_port = 3001
+
+            [mcp.limits]
+            max_concurrent_calls = 4
+            restart = 4
+            search = 0
+
+            [process.main]
+            type = "rust"
+            "#,
+        )
+        .unwrap();
+
+        let limits = config.mcp.limits.effective_per_minute();
+        assert_eq!(config.mcp.limits.max_concurrent_calls, 4);
+        assert_eq!(limits.get("restart"), Some(&4));
+        assert_eq!(limits.get("build"), Some(&20));
+        assert_eq!(limits.get("search"), None);
+    }
+
+    #[test]
+    fn test_mcp_port_fallback_range() {
+        let config = Config::parse("mcp_port = 65530\nmcp_port_fallback = true\nmcp_port_fallback_range = 5\n[process.main]\ntype = \"rust\"\n").unwrap();
+        assert_eq!(config.mcp_port_bind_retries, 0);
+
+        let err = Config::parse("mcp_port = 65530\nmcp_port_fallback = true\n[process.main]\ntype = \"rust\"\n").unwrap_err().to_string();
+        assert!(err.contains("mcp_port_fallback_range (10) goes past port 65535"), "{}", err);
+
+        // Without fallback the range is unused
+        Config::parse("mcp_port = 65530\nmcp_port_fallback_range = 0\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let err = Config::parse("mcp_port = 3001\nmcp_port_fallback = true\nmcp_port_fallback_range = 0\n[process.main]\ntype = \"rust\"\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("mcp_port_fallback_range must be at least 1"), "{}", err);
+    }
+
+    #[test]
+    fn test_telemetry() {
+        let config = Config::parse(EXAMPLE_CONFIG).unwrap();
+        assert!(config.telemetry.is_none());
+
+        let config = Config::parse(&format!("{}\n[telemetry]\n", EXAMPLE_CONFIG)).unwrap();
+        let telemetry = config.telemetry.unwrap();
+        assert_eq!(telemetry.endpoint, "http://localhost:4318/v1/traces");
+        assert_eq!(telemetry.service_name, "background-process-manager");
+
+        let err = Config::parse(&format!("{}\n[telemetry]\nendpoint = \"localhost:4318\"\n", EXAMPLE_CONFIG))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("telemetry.endpoint must be an http:// or https:// URL"), "{}", err);
+    }
+
+    #[test]
+    fn test_duplicate_ports() {
+        let errors = Config::parse(
+            r#"
+            mcp_port = 3001
+
+            [process.api]
+            type = "rust"
+            port = 8080
+
+            [process.web]
+            type = "rust"
+            port = 8080
+
+            [process.admin]
+            type = "rust"
+            port = 3001
+
+            [process.worker]
+            type = "rust"
+            port = 8081
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(errors.contains("Process 'web' has port 8080, which is already used by process 'api'"), "{}", errors);
+        assert!(errors.contains("Process 'admin' has port 3001, which is the mcp_port"), "{}", errors);
+        assert!(!errors.contains("worker"), "{}", errors);
+    }
+
+    #[test]
+    fn test_rust_backtrace() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        assert_eq!(config.process["main"].rust_backtrace, "1");
+
+        let err = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\nrust_backtrace = \"yes\"\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Process 'main' has rust_backtrace 'yes'"), "{}", err);
+    }
+
+    #[test]
+    fn test_pinned_profile() {
+        let config = Config::parse(
+            "mcp_port = 3001\n[process.heavy]\ntype = \"rust\"\nalways_release = true\n\
+             [process.tiny]\ntype = \"rust\"\nalways_dev = true\n[process.main]\ntype = \"rust\"\n",
+        )
+        .unwrap();
+        for mode in [RunMode::Dev, RunMode::Release] {
+            assert!(config.process["heavy"].release_build(mode));
+            assert!(!config.process["tiny"].release_build(mode));
+        }
+        assert!(!config.process["main"].release_build(RunMode::Dev));
+        assert!(config.process["main"].release_build(RunMode::Release));
+        assert!(config.process["heavy"].profile_pinned());
+        assert!(!config.process["main"].profile_pinned());
+
+        let err = Config::parse(
+            "mcp_port = 3001\n[process.main]\ntype = \"rust\"\nalways_release = true\nalways_dev = true\n",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("Process 'main' sets both always_release and always_dev"), "{}", err);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let config = Config::parse(
+            "mcp_port = 3001\n[process.mock]\ntype = \"rust\"\nprofiles = [\"dev\"]\n\
+             [process.proxy]\ntype = \"rust\"\nprofiles = [\"release\"]\n[process.main]\ntype = \"rust\"\n",
+        )
+        .unwrap();
+        let (mock, proxy, main) = (&config.process["mock"], &config.process["proxy"], &config.process["main"]);
+        assert!(mock.runs_in(RunMode::Dev) && !mock.runs_in(RunMode::Release));
+        assert!(!proxy.runs_in(RunMode::Dev) && proxy.runs_in(RunMode::Release));
+        assert!(main.runs_in(RunMode::Dev) && main.runs_in(RunMode::Release));
+        // Where a process runs doesn't pin how it is built
+        assert!(!mock.profile_pinned() && !proxy.profile_pinned());
+        assert!(proxy.release_build(RunMode::Release));
+
+        let err = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\nprofiles = []\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Process 'main' has no profiles"), "{}", err);
+        let err = Config::parse(
+            "mcp_port = 3001\n[process.main]\ntype = \"rust\"\nprofiles = [\"dev\"]\nalways_release = true\n",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("Process 'main' sets always_release but only runs in dev mode"), "{}", err);
+    }
+
+    #[test]
+    fn test_build_command() {
+        let config = Config::parse(
+            "mcp_port = 3001\n[process.web]\ntype = \"npm\"\ncommand = [\"npm\", \"start\"]\n\
+             build_command = [\"npm\", \"run\", \"build\"]\n\
+             [process.api]\ntype = \"npm\"\ncommand = [\"node\", \"api.js\"]\n[process.main]\ntype = \"rust\"\n",
+        )
+        .unwrap();
+        assert!(config.process["web"].has_build());
+        assert!(!config.process["api"].has_build());
+        assert!(config.process["main"].has_build());
+
+        let err = Config::parse(
+            "mcp_port = 3001\n[process.main]\ntype = \"rust\"\nbuild_command = [\"make\"]\n",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("Process 'main' is type 'rust' but has a build_command"), "{}", err);
+    }
+
+    #[test]
+    fn test_syntax_error_has_location() {
+        let err = Config::parse("mcp_port = \n").unwrap_err().to_string();
+        assert!(err.contains("line 1"), "{}", err);
+    }
+
+    #[test]
+    fn test_process_project_dirs() {
+        let dir = std::env::temp_dir().join(format!("bpm-test-project-dirs-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("api")).unwrap();
+        std::fs::write(
+            dir.join(".mcp-run"),
+            "mcp_port = 3001\n[process.api]\ntype = \"rust\"\nproject_dir = \"api\"\n[process.main]\ntype = \"rust\"\n",
+        )
+        .unwrap();
+
+        let config = Config::load(&dir).unwrap();
+        assert_eq!(config.process["api"].root(&dir), dir.join("api"));
+        assert_eq!(config.process["main"].root(&dir), dir);
+
+        // Absolute paths are used as they are
+        let mut absolute = config.process["main"].clone();
+        absolute.project_dir = Some(PathBuf::from("/srv/frontend"));
+        assert_eq!(absolute.root(&dir), PathBuf::from("/srv/frontend"));
+
+        std::fs::write(
+            dir.join(".mcp-run"),
+            "mcp_port = 3001\nmax_concurrent_builds = 0\n\
+             [process.web]\ntype = \"npm\"\ncommand = [\"npm\"]\nproject_dir = \"web\"\n",
+        )
+        .unwrap();
+        let err = format!("{:#}", Config::load(&dir).unwrap_err());
+        assert!(err.contains("Process 'web' has project_dir"), "{}", err);
+        assert!(err.contains("which does not exist"), "{}", err);
+        // Along with the other problems
+        assert!(err.contains("2 configuration error(s)"), "{}", err);
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
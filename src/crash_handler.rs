@@ -1,17 +1,35 @@
-use tokio::time::{sleep, Duration};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tokio::time::Duration;
 use tracing::info;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum RunMode {
     Dev,
     Release,
 }
 
+impl RunMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunMode::Dev => "dev",
+            RunMode::Release => "release",
+        }
+    }
+}
+
 pub struct CrashHandler {
     dev_crash_wait_seconds: u64,
     release_crash_backoff_initial_seconds: u64,
     release_crash_backoff_max_seconds: u64,
+    /// Back off the same way in every mode, skipping the dev mode wait.
+    uniform_backoff: bool,
     crash_count: usize,
+    /// Manual restarts, never reset.
+    restart_count: usize,
+    /// When the pending automatic restart is due.
+    restart_at: Option<Instant>,
 }
 
 impl CrashHandler {
@@ -24,22 +42,45 @@ impl CrashHandler {
             dev_crash_wait_seconds,
             release_crash_backoff_initial_seconds,
             release_crash_backoff_max_seconds,
+            uniform_backoff: false,
             crash_count: 0,
+            restart_count: 0,
+            restart_at: None,
         }
     }
 
+    /// Uses the release backoff whatever the mode, for when the mode
+    /// system is off.
+    pub fn with_uniform_backoff(mut self, uniform_backoff: bool) -> Self {
+        self.uniform_backoff = uniform_backoff;
+        self
+    }
+
     pub fn reset_crash_count(&mut self) {
         self.crash_count = 0;
+        self.restart_at = None;
     }
 
     pub fn get_crash_count(&self) -> usize {
         self.crash_count
     }
 
-    pub async fn wait_before_restart(&mut self, mode: RunMode) {
+    pub fn record_manual_restart(&mut self) {
+        self.restart_count += 1;
+    }
+
+    pub fn get_restart_count(&self) -> usize {
+        self.restart_count
+    }
+
+    /// Counts a crash and returns how long to wait before restarting. The
+    /// caller sleeps without holding the handler, so status can show the
+    /// remaining backoff meanwhile.
+    pub fn schedule_restart(&mut self, mode: RunMode) -> Duration {
         self.crash_count += 1;
 
         let delay_seconds = match mode {
+            _ if self.uniform_backoff => self.calculate_backoff(),
             RunMode::Dev => {
                 // In dev mode, wait for the configured initial wait time on first crash,
                 // then use backoff for subsequent crashes
@@ -60,7 +101,16 @@ impl CrashHandler {
             delay_seconds, self.crash_count, mode
         );
 
-        sleep(Duration::from_secs(delay_seconds)).await;
+        let delay = Duration::from_secs(delay_seconds);
+        self.restart_at = Some(Instant::now() + delay);
+        delay
+    }
+
+    /// Time left before the pending automatic restart, while backing off.
+    pub fn backoff_remaining(&self) -> Option<Duration> {
+        self.restart_at
+            .and_then(|at| at.checked_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
     }
 
     fn calculate_backoff(&self) -> u64 {
@@ -113,4 +163,41 @@ mod tests {
         handler.reset_crash_count();
         assert_eq!(handler.get_crash_count(), 0);
     }
+
+    #[test]
+    fn test_restart_count_is_separate() {
+        let mut handler = CrashHandler::new(120, 1, 300);
+
+        handler.crash_count = 3;
+        handler.record_manual_restart();
+        handler.record_manual_restart();
+        handler.reset_crash_count();
+
+        assert_eq!(handler.get_crash_count(), 0);
+        assert_eq!(handler.get_restart_count(), 2);
+    }
+
+    #[test]
+    fn test_uniform_backoff() {
+        let mut handler = CrashHandler::new(120, 1, 300).with_uniform_backoff(true);
+
+        // No dev mode wait, even in dev mode
+        assert_eq!(handler.schedule_restart(RunMode::Dev), Duration::from_secs(1));
+        assert_eq!(handler.schedule_restart(RunMode::Dev), Duration::from_secs(1));
+        assert_eq!(handler.schedule_restart(RunMode::Release), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_backoff_remaining() {
+        let mut handler = CrashHandler::new(120, 1, 300);
+        assert_eq!(handler.backoff_remaining(), None);
+
+        assert_eq!(handler.schedule_restart(RunMode::Dev), Duration::from_secs(120));
+        let remaining = handler.backoff_remaining().unwrap();
+        assert!(remaining > Duration::from_secs(119) && remaining <= Duration::from_secs(120));
+
+        // A manual restart cancels the pending one
+        handler.reset_crash_count();
+        assert_eq!(handler.backoff_remaining(), None);
+    }
 }
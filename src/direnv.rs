@@ -0,0 +1,226 @@
+use crate::config::{Config, DirenvStrategy};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::info;
+
+/// Environment changes from `direnv export json`: a value of `None` means the
+/// variable is unset.
+type EnvDiff = HashMap<String, Option<String>>;
+
+/// Builds commands that run inside the project's direnv environment, shared
+/// by builds and process spawns.
+pub struct Direnv {
+    project_dir: PathBuf,
+    strategy: DirenvStrategy,
+    program: PathBuf,
+    /// Exported environment and the `.envrc` mtime it was exported at.
+    cache: Mutex<Option<(SystemTime, EnvDiff)>>,
+}
+
+impl Direnv {
+    pub fn new(project_dir: PathBuf, strategy: DirenvStrategy) -> Self {
+        Self {
+            project_dir,
+            strategy,
+            program: PathBuf::from("direnv"),
+            cache: Mutex::new(None),
+        }
+    }
+
+    fn envrc(&self) -> PathBuf {
+        self.project_dir.join(".envrc")
+    }
+
+    /// Whether commands get the `.envrc` environment: there is one, and the
+    /// strategy isn't `off`.
+    pub fn is_active(&self) -> bool {
+        self.strategy != DirenvStrategy::Off && self.envrc().exists()
+    }
+
+    /// For get_status, e.g. "active (exec)" or "inactive (no .envrc)".
+    pub fn description(&self) -> String {
+        if self.strategy == DirenvStrategy::Off {
+            "inactive (direnv = \"off\")".to_string()
+        } else if !self.envrc().exists() {
+            "inactive (no .envrc)".to_string()
+        } else {
+            format!("active ({})", self.strategy.as_str())
+        }
+    }
+
+    /// For get_process.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "active": self.is_active(),
+            "strategy": self.strategy.as_str(),
+            "envrc": self.envrc(),
+        })
+    }
+
+    /// Returns a command running `program` with the project's environment
+    /// applied according to the configured strategy. Arguments can be added
+    /// to the result as usual.
+    pub async fn command(&self, program: impl AsRef<OsStr>) -> Result<Command> {
+        if !self.envrc().exists() {
+            return Ok(Command::new(program));
+        }
+
+        match self.strategy {
+            DirenvStrategy::Exec => {
+                let mut cmd = Command::new(&self.program);
+                cmd.arg("exec").arg(&self.project_dir).arg(program);
+                Ok(cmd)
+            }
+            DirenvStrategy::Export => {
+                let env = self.exported_env().await?;
+                let mut cmd = Command::new(program);
+                for (key, value) in env {
+                    match value {
+                        Some(value) => cmd.env(key, value),
+                        None => cmd.env_remove(key),
+                    };
+                }
+                Ok(cmd)
+            }
+            DirenvStrategy::Off => Ok(Command::new(program)),
+        }
+    }
+
+    /// Runs `direnv export json`, reusing the previous result until `.envrc`
+    /// is modified.
+    async fn exported_env(&self) -> Result<EnvDiff> {
+        let envrc = self.envrc();
+        let mtime = tokio::fs::metadata(&envrc)
+            .await
+            .and_then(|m| m.modified())
+            .with_context(|| format!("Failed to stat {}", envrc.display()))?;
+
+        let mut cache = self.cache.lock().await;
+        if let Some((cached_mtime, ref env)) = *cache {
+            if cached_mtime == mtime {
+                return Ok(env.clone());
+            }
+        }
+
+        info!("Exporting direnv environment for {}", self.project_dir.display());
+        let env = export(&self.program, &self.project_dir).await?;
+        *cache = Some((mtime, env.clone()));
+        Ok(env)
+    }
+}
+
+/// A `Direnv` for each process, by name, for the directory it runs in.
+/// Processes sharing a directory share an instance, and so its cached export.
+pub fn for_processes(config: &Config, project_dir: &Path) -> HashMap<String, Arc<Direnv>> {
+    let mut by_root: HashMap<PathBuf, Arc<Direnv>> = HashMap::new();
+    config
+        .process
+        .iter()
+        .map(|(name, proc_config)| {
+            let root = proc_config.root(project_dir);
+            let direnv = by_root
+                .entry(root.clone())
+                .or_insert_with(|| Arc::new(Direnv::new(root, config.direnv)))
+                .clone();
+            (name.clone(), direnv)
+        })
+        .collect()
+}
+
+async fn export(program: &Path, project_dir: &Path) -> Result<EnvDiff> {
+    let output = Command::new(program)
+        .arg("export")
+        .arg("json")
+        .current_dir(project_dir)
+        .output()
+        .await
+        .context("Failed to run direnv export")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "direnv export failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    // direnv prints nothing when there is nothing to change
+    if output.stdout.iter().all(u8::is_ascii_whitespace) {
+        return Ok(EnvDiff::new());
+    }
+    serde_json::from_slice(&output.stdout).context("Failed to parse direnv export output")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::Duration;
+
+    /// Stand-in for direnv that sources `.envrc` and exports BPM_MARKER,
+    /// counting its invocations in `.calls`.
+    const FAKE_DIRENV: &str = r#"#!/bin/sh
+echo call >> .calls
+. ./.envrc
+printf '{"BPM_MARKER":"%s","BPM_UNSET":null}' "$BPM_MARKER"
+"#;
+
+    async fn marker(direnv: &Direnv) -> String {
+        let mut cmd = direnv.command("sh").await.unwrap();
+        cmd.arg("-c").arg("echo \"$BPM_MARKER ${BPM_UNSET:-unset}\"");
+        cmd.current_dir(&direnv.project_dir);
+        let output = cmd.output().await.unwrap();
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    fn calls(dir: &Path) -> usize {
+        std::fs::read_to_string(dir.join(".calls")).unwrap().lines().count()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_export_is_cached_until_envrc_changes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("bpm-test-direnv-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fake = dir.join("fake-direnv");
+        std::fs::write(&fake, FAKE_DIRENV).unwrap();
+        std::fs::set_permissions(&fake, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(dir.join(".envrc"), "BPM_MARKER=first\n").unwrap();
+
+        let mut direnv = Direnv::new(dir.clone(), DirenvStrategy::Export);
+        direnv.program = fake;
+
+        assert_eq!(direnv.description(), "active (export)");
+        assert_eq!(marker(&direnv).await, "first unset");
+        assert_eq!(marker(&direnv).await, "first unset");
+        assert_eq!(calls(&dir), 1);
+
+        // Editing .envrc invalidates the cache. Set the mtime explicitly, since
+        // the edit may land within the filesystem's timestamp granularity.
+        std::fs::write(dir.join(".envrc"), "BPM_MARKER=second\n").unwrap();
+        File::options()
+            .write(true)
+            .open(dir.join(".envrc"))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+
+        assert_eq!(marker(&direnv).await, "second unset");
+        assert_eq!(calls(&dir), 2);
+
+        // Without .envrc, commands run as-is
+        std::fs::remove_file(dir.join(".envrc")).unwrap();
+        assert_eq!(marker(&direnv).await, "unset");
+        assert!(!direnv.is_active());
+        assert_eq!(direnv.description(), "inactive (no .envrc)");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
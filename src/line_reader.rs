@@ -0,0 +1,120 @@
+use std::io;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// Reads lines like `AsyncBufReadExt::lines`, but keeps at most `max_bytes`
+/// of each, so a process printing a huge line without a newline can't make
+/// the reader buffer all of it. The rest of a long line is skipped and
+/// replaced with a marker saying how much was cut. Invalid UTF-8 is replaced
+/// with U+FFFD rather than ending the input, so binary or latin-1 output
+/// doesn't stop capture.
+pub struct LineReader<R> {
+    reader: BufReader<R>,
+    max_bytes: usize,
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> LineReader<R> {
+    pub fn new(reader: R, max_bytes: usize) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            max_bytes,
+            buf: Vec::new(),
+        }
+    }
+
+    /// The next line without its line ending, or `None` at end of input.
+    pub async fn next_line(&mut self) -> io::Result<Option<String>> {
+        self.buf.clear();
+        let mut truncated = 0;
+        let mut read_any = false;
+
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                if !read_any {
+                    return Ok(None);
+                }
+                break;
+            }
+            read_any = true;
+
+            let newline = available.iter().position(|&b| b == b'\n');
+            let chunk = &available[..newline.unwrap_or(available.len())];
+            let keep = chunk.len().min(self.max_bytes - self.buf.len().min(self.max_bytes));
+            self.buf.extend_from_slice(&chunk[..keep]);
+            truncated += chunk.len() - keep;
+
+            let consumed = newline.map_or(available.len(), |i| i + 1);
+            self.reader.consume(consumed);
+            if newline.is_some() {
+                break;
+            }
+        }
+
+        if truncated == 0 && self.buf.last() == Some(&b'\r') {
+            self.buf.pop();
+        }
+        if truncated > 0 {
+            // Don't leave half a character where the line was cut
+            if let Err(e) = std::str::from_utf8(&self.buf) {
+                if e.error_len().is_none() {
+                    truncated += self.buf.len() - e.valid_up_to();
+                    self.buf.truncate(e.valid_up_to());
+                }
+            }
+        }
+
+        let line = String::from_utf8_lossy(&self.buf);
+        if truncated > 0 {
+            return Ok(Some(format!("{}…[truncated {} bytes]", line, truncated)));
+        }
+        Ok(Some(line.into_owned()))
+    }
+}
+
+/// Log line recording that reading `stream` failed, so the log shows that
+/// capture stopped rather than the process going quiet.
+pub fn capture_stopped(stream: &str, error: &io::Error) -> String {
+    format!("[manager] Stopped capturing {} after a read error: {}", stream, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read_all(input: &[u8], max_bytes: usize) -> Vec<String> {
+        let mut reader = LineReader::new(input, max_bytes);
+        let mut lines = Vec::new();
+        while let Some(line) = reader.next_line().await.unwrap() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn test_lines() {
+        assert_eq!(read_all(b"one\ntwo\r\n\nlast", 16).await, ["one", "two", "", "last"]);
+        assert!(read_all(b"", 16).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_long_lines_truncated() {
+        let mut input = vec![b'x'; 100_000];
+        input.extend_from_slice(b"\nshort\n");
+        assert_eq!(
+            read_all(&input, 10).await,
+            ["xxxxxxxxxx…[truncated 99990 bytes]", "short"]
+        );
+
+        // Cut before a multi-byte character rather than through it
+        assert_eq!(read_all("abcdé\n".as_bytes(), 5).await, ["abcd…[truncated 2 bytes]"]);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_utf8() {
+        assert_eq!(
+            read_all(b"caf\xe9 latin-1\n\xff\xfe\x00binary\nstill here\n", 64).await,
+            ["caf\u{FFFD} latin-1", "\u{FFFD}\u{FFFD}\u{0}binary", "still here"]
+        );
+    }
+}
@@ -1,26 +1,225 @@
-use regex::Regex;
-use std::collections::VecDeque;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use regex::{Regex, RegexBuilder};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{mpsc, watch, RwLock};
 
 const MAX_LOG_INSTANCES: usize = 10;
 const MAX_LINES_PER_INSTANCE: usize = 10000;
+const REGEX_CACHE_SIZE: usize = 32;
+/// Limits on compiled search patterns, so a huge pattern is rejected rather
+/// than taking memory and time to compile and run.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+const REGEX_DFA_SIZE_LIMIT: usize = 2 << 20;
+/// Lines matched between checks of a search's deadline.
+const DEADLINE_CHECK_INTERVAL: usize = 256;
+const APPENDER_CHANNEL_SIZE: usize = 4096;
+const APPENDER_BATCH_SIZE: usize = 256;
+/// Memory a kept line takes besides its text: the `Arc` and its counts, its
+/// sequence number and its metadata.
+const LINE_OVERHEAD: usize = std::mem::size_of::<Arc<str>>()
+    + 2 * std::mem::size_of::<usize>()
+    + std::mem::size_of::<u64>()
+    + std::mem::size_of::<LineMeta>();
 
+fn line_bytes(line: &str) -> usize {
+    line.len() + LINE_OVERHEAD
+}
+
+/// Which of a process's outputs a line came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        }
+    }
+}
+
+/// When a line was captured, and which output it came from if known.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineMeta {
+    pub timestamp: DateTime<Utc>,
+    pub stream: Option<LogStream>,
+}
+
+impl LineMeta {
+    /// A line captured from `stream` just now.
+    pub fn captured(stream: LogStream) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            stream: Some(stream),
+        }
+    }
+
+    /// Like `captured`, but timestamped with the time `parser` reads from
+    /// `line`, if it can.
+    pub fn read(line: &str, stream: LogStream, parser: Option<&TimestampParser>) -> Self {
+        Self {
+            timestamp: parser.and_then(|parser| parser.parse(line)).unwrap_or_else(Utc::now),
+            stream: Some(stream),
+        }
+    }
+}
+
+/// Reads the timestamps a process prints in its own lines, as its
+/// `log_timestamp` says.
+#[derive(Debug)]
+pub struct TimestampParser {
+    pattern: Regex,
+    format: Option<String>,
+}
+
+impl TimestampParser {
+    /// Finds timestamps with `pattern`, its group named `ts` if it has one,
+    /// and reads them with the chrono `format`, or as RFC 3339 without one.
+    pub fn new(pattern: &str, format: Option<String>) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pattern: Regex::new(pattern)?,
+            format,
+        })
+    }
+
+    /// The timestamp in `line`, if the pattern finds one that parses.
+    pub fn parse(&self, line: &str) -> Option<DateTime<Utc>> {
+        let captures = self.pattern.captures(line)?;
+        let text = captures.name("ts").or_else(|| captures.get(0))?.as_str();
+        let parsed = match self.format {
+            Some(ref format) => DateTime::parse_from_str(text, format)
+                .ok()
+                .or_else(|| Some(NaiveDateTime::parse_from_str(text, format).ok()?.and_utc().fixed_offset())),
+            None => DateTime::parse_from_rfc3339(text).ok(),
+        };
+        parsed.map(|timestamp| timestamp.with_timezone(&Utc))
+    }
+}
+
+/// The kept lines of one run. Lines are shared, so copies of an instance,
+/// e.g. to search it without holding the buffer's lock, don't copy their
+/// text.
 #[derive(Debug, Clone)]
 pub struct LogInstance {
-    pub lines: VecDeque<String>,
+    pub lines: VecDeque<Arc<str>>,
+    /// Sequence number of each line in `lines`, increasing.
+    pub seqs: VecDeque<u64>,
+    /// Capture time and stream of each line in `lines`.
+    pub metas: VecDeque<LineMeta>,
+    /// Lines ever appended, including those dropped from the front since.
+    pub total: u64,
+    /// Memory taken by `lines`, roughly.
+    pub bytes: usize,
+    /// The run this instance logged, counting from 1; 0 for instances not
+    /// started by `new_instance`.
+    pub generation: u64,
+    pub started_at: Option<DateTime<Utc>>,
+    /// How the run ended, e.g. "exited with code 1", once it has.
+    pub ended: Option<String>,
 }
 
 impl LogInstance {
     pub fn new() -> Self {
         Self {
             lines: VecDeque::with_capacity(MAX_LINES_PER_INSTANCE),
+            seqs: VecDeque::with_capacity(MAX_LINES_PER_INSTANCE),
+            metas: VecDeque::with_capacity(MAX_LINES_PER_INSTANCE),
+            total: 0,
+            bytes: 0,
+            generation: 0,
+            started_at: None,
+            ended: None,
         }
     }
 
-    pub fn append(&mut self, line: String) {
+    /// An instance of unnumbered lines without metadata, e.g. picked out of
+    /// another one.
+    pub fn from_lines(lines: VecDeque<String>) -> Self {
+        let lines: VecDeque<Arc<str>> = lines.into_iter().map(Arc::from).collect();
+        Self {
+            total: lines.len() as u64,
+            bytes: lines.iter().map(|line| line_bytes(line)).sum(),
+            lines,
+            seqs: VecDeque::new(),
+            metas: VecDeque::new(),
+            generation: 0,
+            started_at: None,
+            ended: None,
+        }
+    }
+
+    pub fn append(&mut self, line: String, seq: u64, meta: LineMeta) {
         if self.lines.len() >= MAX_LINES_PER_INSTANCE {
-            self.lines.pop_front();
+            self.pop_front();
+        }
+        self.bytes += line_bytes(&line);
+        self.lines.push_back(line.into());
+        self.seqs.push_back(seq);
+        self.metas.push_back(meta);
+        self.total += 1;
+    }
+
+    /// Drops the oldest line, returning the memory it took.
+    fn pop_front(&mut self) -> usize {
+        self.seqs.pop_front();
+        self.metas.pop_front();
+        let freed = self.lines.pop_front().map_or(0, |line| line_bytes(&line));
+        self.bytes -= freed;
+        freed
+    }
+
+    /// A copy with only the lines whose sequence number is above `seq`.
+    pub fn after_seq(&self, seq: u64) -> LogInstance {
+        let start = self.seqs.partition_point(|&s| s <= seq);
+        let lines: VecDeque<Arc<str>> = self.lines.range(start..).cloned().collect();
+        LogInstance {
+            bytes: lines.iter().map(|line| line_bytes(line)).sum(),
+            lines,
+            seqs: self.seqs.range(start..).copied().collect(),
+            metas: self.metas.range(start..).copied().collect(),
+            total: self.total,
+            generation: self.generation,
+            started_at: self.started_at,
+            ended: self.ended.clone(),
         }
-        self.lines.push_back(line);
+    }
+
+    /// A copy with only the lines timestamped from `since` up to `until`,
+    /// either of which may be open. Lines without metadata are left out.
+    pub fn between(&self, since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> LogInstance {
+        let kept: Vec<usize> = (0..self.metas.len())
+            .filter(|&i| {
+                let timestamp = self.metas[i].timestamp;
+                since.is_none_or(|since| timestamp >= since) && until.is_none_or(|until| timestamp <= until)
+            })
+            .collect();
+        let lines: VecDeque<Arc<str>> = kept.iter().map(|&i| self.lines[i].clone()).collect();
+        LogInstance {
+            bytes: lines.iter().map(|line| line_bytes(line)).sum(),
+            lines,
+            seqs: kept.iter().filter_map(|&i| self.seqs.get(i).copied()).collect(),
+            metas: kept.iter().map(|&i| self.metas[i]).collect(),
+            total: self.total,
+            generation: self.generation,
+            started_at: self.started_at,
+            ended: self.ended.clone(),
+        }
+    }
+
+    /// Sequence number of the last line, if it has any.
+    pub fn last_seq(&self) -> Option<u64> {
+        self.seqs.back().copied()
+    }
+
+    /// Lines dropped from the front, to stay within MAX_LINES_PER_INSTANCE or
+    /// the memory budget.
+    pub fn dropped(&self) -> u64 {
+        self.total - self.lines.len() as u64
     }
 
     pub fn search(
@@ -30,79 +229,335 @@ impl LogInstance {
         head: Option<usize>,
         tail: Option<usize>,
     ) -> Vec<String> {
-        let mut result = Vec::new();
+        self.search_within(pattern, context_lines, head, tail, None).0
+    }
+
+    /// Like `search`, but stops matching `pattern` once `deadline` passes and
+    /// returns what matched so far, with the number of lines scanned.
+    pub fn search_within(
+        &self,
+        pattern: Option<&str>,
+        context_lines: Option<usize>,
+        head: Option<usize>,
+        tail: Option<usize>,
+        deadline: Option<Instant>,
+    ) -> (Vec<String>, Option<usize>) {
+        let (selected, timed_out) = match self.select_within(pattern.as_slice(), context_lines, head, tail, deadline) {
+            Ok(found) => found,
+            Err(message) => return (vec![message], None),
+        };
+
+        let mut result: Vec<String> = selected
+            .into_iter()
+            .map(|line| match pattern {
+                Some(_) => {
+                    let marker = if line.matched { " * " } else { "   " };
+                    format!("{}{}", marker, line.text)
+                }
+                None => line.text,
+            })
+            .collect();
+
+        if result.is_empty() {
+            let placeholder = if pattern.is_some() { "No matches found" } else { "(empty)" };
+            result.push(placeholder.to_string());
+        }
+
+        (result, timed_out)
+    }
+
+    /// The lines `search_within` returns, unformatted, for lines matching
+    /// any of `patterns`. Without patterns every line counts as matched. An
+    /// invalid pattern gives the message to show instead.
+    pub fn select_within(
+        &self,
+        patterns: &[&str],
+        context_lines: Option<usize>,
+        head: Option<usize>,
+        tail: Option<usize>,
+        deadline: Option<Instant>,
+    ) -> Result<(Vec<SearchLine>, Option<usize>), String> {
         let context = context_lines.unwrap_or(0);
+        let mut timed_out = None;
+        // Which patterns each matching line matched
+        let mut hits: HashMap<usize, Vec<usize>> = HashMap::new();
 
-        if let Some(pattern) = pattern {
+        // Select (line index, is match) pairs first, and only copy the lines
+        // that survive head/tail limiting
+        let selected: Vec<(usize, bool)> = if !patterns.is_empty() {
             // Regex search with context
-            let re = match Regex::new(pattern) {
-                Ok(re) => re,
-                Err(_) => return vec![format!("Invalid regex pattern: {}", pattern)],
-            };
+            let res = patterns
+                .iter()
+                .map(|pattern| cached_regex(pattern).map_err(|_| format!("Invalid regex pattern: {}", pattern)))
+                .collect::<Result<Vec<_>, _>>()?;
 
-            let lines: Vec<_> = self.lines.iter().collect();
-            let mut matched_indices = Vec::new();
-
-            // Find all matching lines
-            for (i, line) in lines.iter().enumerate() {
-                if re.is_match(line) {
+            // Find all matching lines, or those before the deadline
+            let mut matched_indices: Vec<usize> = Vec::new();
+            for (i, line) in self.lines.iter().enumerate() {
+                if i % DEADLINE_CHECK_INTERVAL == 0 && deadline.is_some_and(|d| Instant::now() >= d) {
+                    timed_out = Some(i);
+                    break;
+                }
+                let matched: Vec<usize> = (0..res.len()).filter(|&k| res[k].is_match(line)).collect();
+                if !matched.is_empty() {
                     matched_indices.push(i);
+                    hits.insert(i, matched);
                 }
             }
 
-            if matched_indices.is_empty() {
-                return vec!["No matches found".to_string()];
-            }
-
-            // Expand to include context
-            let mut included = vec![false; lines.len()];
+            // Expand to include context, merging overlapping ranges
+            let mut selected: Vec<(usize, bool)> = Vec::new();
             for &idx in &matched_indices {
                 let start = idx.saturating_sub(context);
-                let end = (idx + context + 1).min(lines.len());
-                for item in included.iter_mut().take(end).skip(start) {
-                    *item = true;
-                }
-            }
-
-            // Collect lines with context
-            for (i, line) in lines.iter().enumerate() {
-                if included[i] {
-                    let marker = if matched_indices.contains(&i) { " * " } else { "   " };
-                    result.push(format!("{}{}", marker, line));
+                let end = (idx + context + 1).min(self.lines.len());
+                let from = match selected.last() {
+                    Some(&(last, _)) if last >= start => {
+                        // Overlaps the previous range, which is contiguous up to `last`
+                        if idx <= last {
+                            let pos = selected.len() - 1 - (last - idx);
+                            selected[pos].1 = true;
+                        }
+                        last + 1
+                    }
+                    _ => start,
+                };
+                for i in from..end {
+                    selected.push((i, i == idx));
                 }
             }
+            selected
         } else {
             // No pattern, just return all lines
-            for line in &self.lines {
-                result.push(line.clone());
-            }
-        }
+            (0..self.lines.len()).map(|i| (i, true)).collect()
+        };
 
         // Apply head/tail limiting
-        if let Some(n) = head {
-            result.truncate(n);
+        let limited = if let Some(n) = head {
+            &selected[..n.min(selected.len())]
         } else if let Some(n) = tail {
-            let start = result.len().saturating_sub(n);
-            result = result[start..].to_vec();
+            &selected[selected.len().saturating_sub(n)..]
+        } else {
+            &selected[..]
+        };
+
+        let lines = limited
+            .iter()
+            .map(|&(i, matched)| SearchLine {
+                text: self.lines[i].to_string(),
+                seq: self.seqs.get(i).copied(),
+                meta: self.metas.get(i).copied(),
+                matched,
+                patterns: hits.remove(&i).unwrap_or_default(),
+            })
+            .collect();
+        Ok((lines, timed_out))
+    }
+
+    /// The distinct values of the named group `capture` in lines matching
+    /// `pattern`, with how many lines had each, most frequent first. Stops
+    /// once `deadline` passes, returning the number of lines scanned.
+    pub fn count_captures_within(
+        &self,
+        pattern: &str,
+        capture: &str,
+        deadline: Option<Instant>,
+    ) -> Result<(CaptureCounts, Option<usize>), String> {
+        let re = cached_regex(pattern).map_err(|_| format!("Invalid regex pattern: {}", pattern))?;
+        if !re.capture_names().any(|name| name == Some(capture)) {
+            return Err(format!("Pattern has no capture group named '{}'", capture));
         }
 
-        if result.is_empty() {
-            result.push("(empty)".to_string());
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let mut timed_out = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            if i % DEADLINE_CHECK_INTERVAL == 0 && deadline.is_some_and(|d| Instant::now() >= d) {
+                timed_out = Some(i);
+                break;
+            }
+            if let Some(value) = re.captures(line).and_then(|caps| caps.name(capture)) {
+                *counts.entry(value.as_str()).or_default() += 1;
+            }
         }
 
-        result
+        let mut counts: Vec<(String, usize)> = counts
+            .into_iter()
+            .map(|(value, count)| (value.to_string(), count))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok((counts, timed_out))
+    }
+}
+
+/// Distinct captured values with the number of lines each came from.
+pub type CaptureCounts = Vec<(String, usize)>;
+
+/// A line returned by a search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchLine {
+    pub text: String,
+    /// The line's sequence number, if it came from a process's log.
+    pub seq: Option<u64>,
+    /// When and from where the line was captured, unless it was picked out
+    /// of another instance.
+    pub meta: Option<LineMeta>,
+    /// Whether the line matched the pattern, rather than being context.
+    pub matched: bool,
+    /// Indices of the patterns the line matched; empty for context lines and
+    /// searches without a pattern.
+    pub patterns: Vec<usize>,
+}
+
+/// Feeds lines into a `LogBuffer` from a single task, so producers don't
+/// contend for the write lock on every line.
+#[derive(Clone)]
+pub struct LogAppender {
+    sender: mpsc::Sender<(u64, String, LineMeta)>,
+}
+
+impl LogAppender {
+    /// Spawns the appender task for `logs`. Must be called within a tokio runtime.
+    pub fn spawn(logs: Arc<RwLock<LogBuffer>>) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<(u64, String, LineMeta)>(APPENDER_CHANNEL_SIZE);
+
+        tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(APPENDER_BATCH_SIZE);
+            while let Some(first) = receiver.recv().await {
+                batch.push(first);
+                while batch.len() < APPENDER_BATCH_SIZE {
+                    match receiver.try_recv() {
+                        Ok(line) => batch.push(line),
+                        Err(_) => break,
+                    }
+                }
+
+                let mut logs = logs.write().await;
+                for (generation, line, meta) in batch.drain(..) {
+                    logs.append_to_generation(generation, line, meta);
+                }
+            }
+        });
+
+        Self { sender }
+    }
+
+    /// Queues a line for the instance of the given generation. `meta` is
+    /// taken when the line is read rather than when it reaches the buffer.
+    pub async fn append(&self, generation: u64, line: String, meta: LineMeta) {
+        // Only fails if the appender task is gone, in which case there's
+        // nowhere to put the line anyway
+        let _ = self.sender.send((generation, line, meta)).await;
+    }
+}
+
+/// Shrinks `buffers` until together they take at most `budget` bytes, each
+/// in proportion to the memory it takes. Returns the memory freed.
+pub async fn enforce_budget(buffers: &[Arc<RwLock<LogBuffer>>], budget: usize) -> usize {
+    let mut used = Vec::with_capacity(buffers.len());
+    for buffer in buffers {
+        used.push(buffer.read().await.memory_bytes());
+    }
+    let total: usize = used.iter().sum();
+    if total <= budget {
+        return 0;
     }
+
+    let mut freed = 0;
+    for (buffer, used) in buffers.iter().zip(used) {
+        let share = (used as u128 * budget as u128 / total as u128) as usize;
+        freed += buffer.write().await.shrink_to(share);
+    }
+    freed
+}
+
+/// Returns a compiled regex for `pattern`, reusing recently compiled ones so
+/// clients polling with the same pattern don't recompile it every time.
+fn cached_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    static CACHE: Mutex<VecDeque<(String, Regex)>> = Mutex::new(VecDeque::new());
+
+    let mut cache = CACHE.lock().unwrap();
+    if let Some(pos) = cache.iter().position(|(p, _)| p == pattern) {
+        // Move to the back to mark it most recently used
+        let entry = cache.remove(pos).unwrap();
+        let re = entry.1.clone();
+        cache.push_back(entry);
+        return Ok(re);
+    }
+
+    let re = RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_DFA_SIZE_LIMIT)
+        .build()?;
+    if cache.len() >= REGEX_CACHE_SIZE {
+        cache.pop_front();
+    }
+    cache.push_back((pattern.to_string(), re.clone()));
+    Ok(re)
+}
+
+/// Lines appended after a sequence number, from `LogBuffer::lines_after`.
+#[derive(Debug)]
+pub struct NewLines {
+    pub lines: Vec<String>,
+    /// Sequence number up to which lines were returned or missed, to pass
+    /// to the next call.
+    pub latest_seq: u64,
+    /// Lines up to `latest_seq` that were no longer kept.
+    pub missed: u64,
 }
 
 #[derive(Debug)]
 pub struct LogBuffer {
     instances: VecDeque<LogInstance>,
+    /// Incremented by every `new_instance`, so lines can be attributed to the
+    /// instance that was current when they were produced.
+    generation: u64,
+    /// Sequence number of the last line appended to any instance. Lines
+    /// are numbered from 1 in the order they arrive.
+    seq: u64,
+    /// Follows `seq`, so waiters can tell when there are new lines.
+    appended: watch::Sender<u64>,
 }
 
 impl LogBuffer {
     pub fn new() -> Self {
         Self {
             instances: VecDeque::with_capacity(MAX_LOG_INSTANCES),
+            generation: 0,
+            seq: 0,
+            appended: watch::channel(0).0,
+        }
+    }
+
+    /// Changes whenever a line is appended, to its sequence number.
+    pub fn watch_appended(&self) -> watch::Receiver<u64> {
+        self.appended.subscribe()
+    }
+
+    /// Sequence number of the last line appended, 0 before the first.
+    pub fn latest_seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Up to `max` lines appended after sequence number `seq`, to any kept
+    /// instance, in the order they arrived. That includes lines arriving
+    /// late for an instance before the current one.
+    pub fn lines_after(&self, seq: u64, max: usize) -> NewLines {
+        let mut numbered: Vec<(u64, &Arc<str>)> = Vec::new();
+        for instance in &self.instances {
+            let start = instance.seqs.partition_point(|&s| s <= seq);
+            numbered.extend(instance.seqs.range(start..).copied().zip(instance.lines.range(start..)));
+        }
+        numbered.sort_unstable_by_key(|&(seq, _)| seq);
+        let latest_seq = if numbered.len() > max {
+            numbered.truncate(max);
+            numbered.last().map_or(seq, |&(seq, _)| seq)
+        } else {
+            self.seq.max(seq)
+        };
+        NewLines {
+            missed: (latest_seq - seq) - numbered.len() as u64,
+            lines: numbered.into_iter().map(|(_, line)| line.to_string()).collect(),
+            latest_seq,
         }
     }
 
@@ -110,18 +565,108 @@ impl LogBuffer {
         if self.instances.len() >= MAX_LOG_INSTANCES {
             self.instances.pop_front();
         }
-        self.instances.push_back(LogInstance::new());
+        self.generation += 1;
+        self.instances.push_back(LogInstance {
+            generation: self.generation,
+            started_at: Some(Utc::now()),
+            ..LogInstance::new()
+        });
+    }
+
+    /// Records how the run of the given generation ended, unless that is
+    /// already known: the first cause, e.g. a stop, wins over the exit it
+    /// leads to.
+    pub fn record_end(&mut self, generation: u64, how: String) {
+        if let Some(instance) = self.instances.iter_mut().find(|instance| instance.generation == generation) {
+            instance.ended.get_or_insert(how);
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Appends a line to the instance of the given generation, which may be
+    /// older than the current one. Lines for evicted instances are dropped.
+    pub fn append_to_generation(&mut self, generation: u64, line: String, meta: LineMeta) {
+        let back = self.generation.saturating_sub(generation) as usize;
+        if back < self.instances.len() {
+            let pos = self.instances.len() - 1 - back;
+            self.seq += 1;
+            self.instances[pos].append(line, self.seq, meta);
+            self.appended.send_replace(self.seq);
+        }
+    }
+
+    /// The negative index of the instance of the given generation, or an
+    /// error if it hasn't started or has been evicted.
+    pub fn generation_index(&self, generation: u64) -> Result<i32, String> {
+        let oldest = self.generation + 1 - self.instances.len() as u64;
+        if generation > self.generation || self.instances.is_empty() {
+            Err(format!("Generation {} has not started (current: {})", generation, self.generation))
+        } else if generation < oldest {
+            Err(format!("Generation {} is no longer kept (oldest kept: {})", generation, oldest))
+        } else {
+            Ok(-((self.generation - generation) as i32) - 1)
+        }
     }
 
+    /// Appends a line of unknown stream to the current instance, captured
+    /// now.
     pub fn append(&mut self, line: String) {
+        self.append_with(
+            line,
+            LineMeta {
+                timestamp: Utc::now(),
+                stream: None,
+            },
+        );
+    }
+
+    pub fn append_with(&mut self, line: String, meta: LineMeta) {
         if self.instances.is_empty() {
             self.new_instance();
         }
         if let Some(current) = self.instances.back_mut() {
-            current.append(line);
+            self.seq += 1;
+            current.append(line, self.seq, meta);
+            self.appended.send_replace(self.seq);
         }
     }
 
+    pub fn instance_count(&self) -> usize {
+        self.instances.len()
+    }
+
+    /// Memory taken by the kept lines, roughly.
+    pub fn memory_bytes(&self) -> usize {
+        self.instances.iter().map(|instance| instance.bytes).sum()
+    }
+
+    /// Frees memory until the kept lines take at most `bytes`, oldest
+    /// first: whole instances while that doesn't free more than needed, then
+    /// lines from the front of the oldest instance left. The current instance
+    /// is never dropped. Returns the memory freed.
+    pub fn shrink_to(&mut self, bytes: usize) -> usize {
+        let mut used = self.memory_bytes();
+        let before = used;
+        while used > bytes {
+            let older = self.instances.len() > 1;
+            let Some(oldest) = self.instances.front_mut() else {
+                break;
+            };
+            if older && (used - oldest.bytes >= bytes || oldest.lines.is_empty()) {
+                used -= oldest.bytes;
+                self.instances.pop_front();
+            } else if oldest.lines.is_empty() {
+                break;
+            } else {
+                used -= oldest.pop_front();
+            }
+        }
+        before - used
+    }
+
     pub fn get_instance(&self, index: Option<i32>) -> Option<&LogInstance> {
         let idx = index.unwrap_or(-1);
         if self.instances.is_empty() {
@@ -142,6 +687,33 @@ impl LogBuffer {
         }
     }
 
+    /// One line saying which instance `index` is: its position, whether it
+    /// is the current one, when it started and how it ended.
+    pub fn instance_summary(&self, index: Option<i32>) -> Option<String> {
+        let instance = self.get_instance(index)?;
+        let len = self.instances.len() as i32;
+        let idx = index.unwrap_or(-1);
+        let negative = if idx < 0 { idx } else { idx - len };
+        let current = negative == -1;
+        let mut summary = format!(
+            "Log instance {} (generation {}, {})",
+            negative,
+            instance.generation,
+            if current { "the current instance" } else { "not the current instance" }
+        );
+        if let Some(started_at) = instance.started_at {
+            summary.push_str(&format!(": started {}", started_at.format("%Y-%m-%d %H:%M:%S UTC")));
+        } else {
+            summary.push_str(": start not recorded");
+        }
+        match &instance.ended {
+            Some(how) => summary.push_str(&format!(", {}", how)),
+            None if current => summary.push_str(", still running"),
+            None => summary.push_str(", end not recorded"),
+        }
+        Some(summary)
+    }
+
     pub fn search(
         &self,
         index: Option<i32>,
@@ -152,19 +724,34 @@ impl LogBuffer {
     ) -> Vec<String> {
         match self.get_instance(index) {
             Some(instance) => instance.search(pattern, context_lines, head, tail),
-            None => vec![format!(
-                "Log instance {} not found (have {} instances)",
-                index.unwrap_or(-1),
-                self.instances.len()
-            )],
+            None => vec![self.not_found(index)],
         }
     }
+
+    /// A copy of an instance sharing its lines, to search without holding
+    /// the lock on the buffer, or the message `search` returns if there is no
+    /// such instance.
+    pub fn snapshot(&self, index: Option<i32>) -> Result<LogInstance, String> {
+        self.get_instance(index).cloned().ok_or_else(|| self.not_found(index))
+    }
+
+    fn not_found(&self, index: Option<i32>) -> String {
+        format!(
+            "Log instance {} not found (have {} instances)",
+            index.unwrap_or(-1),
+            self.instances.len()
+        )
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn texts(instance: &LogInstance) -> Vec<&str> {
+        instance.lines.iter().map(|line| &**line).collect()
+    }
+
     #[test]
     fn test_log_buffer_indexing() {
         let mut buffer = LogBuffer::new();
@@ -182,16 +769,383 @@ mod tests {
         buffer.append("third-1".to_string());
 
         // Test negative indexing (Python-style)
-        assert_eq!(buffer.get_instance(Some(-1)).unwrap().lines[0], "third-1");
-        assert_eq!(buffer.get_instance(Some(-2)).unwrap().lines[0], "second-1");
-        assert_eq!(buffer.get_instance(Some(-3)).unwrap().lines[0], "first-1");
+        assert_eq!(&*buffer.get_instance(Some(-1)).unwrap().lines[0], "third-1");
+        assert_eq!(&*buffer.get_instance(Some(-2)).unwrap().lines[0], "second-1");
+        assert_eq!(&*buffer.get_instance(Some(-3)).unwrap().lines[0], "first-1");
 
         // Test positive indexing
-        assert_eq!(buffer.get_instance(Some(0)).unwrap().lines[0], "first-1");
-        assert_eq!(buffer.get_instance(Some(1)).unwrap().lines[0], "second-1");
-        assert_eq!(buffer.get_instance(Some(2)).unwrap().lines[0], "third-1");
+        assert_eq!(&*buffer.get_instance(Some(0)).unwrap().lines[0], "first-1");
+        assert_eq!(&*buffer.get_instance(Some(1)).unwrap().lines[0], "second-1");
+        assert_eq!(&*buffer.get_instance(Some(2)).unwrap().lines[0], "third-1");
 
         // Test default (should be -1, most recent)
-        assert_eq!(buffer.get_instance(None).unwrap().lines[0], "third-1");
+        assert_eq!(&*buffer.get_instance(None).unwrap().lines[0], "third-1");
+    }
+
+    #[test]
+    fn test_lines_after() {
+        let mut buffer = LogBuffer::new();
+        assert_eq!(buffer.latest_seq(), 0);
+        assert!(buffer.lines_after(0, 10).lines.is_empty());
+
+        buffer.new_instance();
+        buffer.append("a".to_string());
+        buffer.append("b".to_string());
+        let middle = buffer.latest_seq();
+        buffer.append("c".to_string());
+        buffer.new_instance();
+        buffer.append("d".to_string());
+        buffer.append_to_generation(1, "late".to_string(), LineMeta::captured(LogStream::Stdout));
+
+        // Across the restart, including the late line, up to the end
+        let new = buffer.lines_after(middle, 10);
+        assert_eq!(new.lines, ["c", "d", "late"]);
+        assert_eq!(new.latest_seq, 5);
+        assert_eq!(new.missed, 0);
+        assert_eq!(buffer.lines_after(0, 10).lines, ["a", "b", "c", "d", "late"]);
+
+        // Limited, continuing where the last call stopped
+        let first = buffer.lines_after(0, 3);
+        assert_eq!(first.lines, ["a", "b", "c"]);
+        assert_eq!(first.latest_seq, 3);
+        assert_eq!(buffer.lines_after(first.latest_seq, 3).lines, ["d", "late"]);
+
+        // Lines dropped from the front are counted, not returned
+        for i in 0..MAX_LINES_PER_INSTANCE {
+            buffer.append(i.to_string());
+        }
+        let new = buffer.lines_after(3, 2);
+        assert_eq!(new.lines, ["late", "0"]);
+        assert_eq!(new.latest_seq, 6);
+        assert_eq!(new.missed, 1);
+    }
+
+    #[test]
+    fn test_seqs() {
+        let mut buffer = LogBuffer::new();
+        let appended = buffer.watch_appended();
+        buffer.new_instance();
+        buffer.append("a".to_string());
+        buffer.append("b".to_string());
+        buffer.new_instance();
+        buffer.append("c".to_string());
+        // A late line for the previous instance is numbered in arrival order
+        let late = LineMeta::captured(LogStream::Stderr);
+        buffer.append_to_generation(1, "late".to_string(), late);
+        assert_eq!(*appended.borrow(), 4);
+
+        let previous = buffer.get_instance(Some(-2)).unwrap();
+        assert_eq!(previous.seqs, [1, 2, 4]);
+        let after = previous.after_seq(1);
+        assert_eq!(texts(&after), ["b", "late"]);
+        assert_eq!(after.last_seq(), Some(4));
+        assert!(previous.after_seq(4).lines.is_empty());
+
+        let (lines, _) = after.select_within(&[], None, None, None, None).unwrap();
+        assert_eq!(lines.iter().map(|line| line.seq).collect::<Vec<_>>(), [Some(2), Some(4)]);
+
+        // Each line keeps when and from where it was captured
+        assert_eq!(lines[0].meta.unwrap().stream, None);
+        assert!(lines[0].meta.unwrap().timestamp <= late.timestamp);
+        assert_eq!(lines[1].meta, Some(late));
+    }
+
+    #[test]
+    fn test_generation_index() {
+        let mut buffer = LogBuffer::new();
+        assert!(buffer.generation_index(1).is_err());
+
+        for g in 1..=MAX_LOG_INSTANCES as u64 + 2 {
+            buffer.new_instance();
+            buffer.append(format!("gen-{}", g));
+        }
+
+        let index = buffer.generation_index(5).unwrap();
+        assert_eq!(&*buffer.get_instance(Some(index)).unwrap().lines[0], "gen-5");
+        assert_eq!(buffer.generation_index(12), Ok(-1));
+        assert!(buffer.generation_index(13).unwrap_err().contains("not started"));
+        assert!(buffer.generation_index(2).unwrap_err().contains("oldest kept: 3"));
+    }
+
+    #[test]
+    fn test_snapshot_shares_lines() {
+        let mut buffer = LogBuffer::new();
+        buffer.new_instance();
+        buffer.append("kept".to_string());
+
+        let snapshot = buffer.snapshot(None).unwrap();
+        let instance = buffer.get_instance(None).unwrap();
+        assert!(Arc::ptr_eq(&snapshot.lines[0], &instance.lines[0]));
+        assert!(buffer.snapshot(Some(1)).unwrap_err().contains("not found"));
+    }
+
+    #[test]
+    fn test_instance_summary() {
+        let mut buffer = LogBuffer::new();
+        assert_eq!(buffer.instance_summary(None), None);
+
+        for line in ["first", "second"] {
+            buffer.new_instance();
+            buffer.append(line.to_string());
+        }
+        buffer.record_end(1, "stopped".to_string());
+        buffer.record_end(1, "exited with code 143".to_string());
+
+        let earlier = buffer.instance_summary(Some(0)).unwrap();
+        assert!(earlier.starts_with("Log instance -2 (generation 1, not the current instance): started "), "{}", earlier);
+        assert!(earlier.ends_with(" UTC, stopped"), "{}", earlier);
+        let current = buffer.instance_summary(None).unwrap();
+        assert!(current.starts_with("Log instance -1 (generation 2, the current instance)"), "{}", current);
+        assert!(current.ends_with(", still running"), "{}", current);
+        assert_eq!(buffer.instance_summary(Some(-3)), None);
+    }
+
+    #[tokio::test]
+    async fn test_memory_budget() {
+        let line = "x".repeat(100 - LINE_OVERHEAD);
+        let big = Arc::new(RwLock::new(LogBuffer::new()));
+        let small = Arc::new(RwLock::new(LogBuffer::new()));
+        for _ in 0..3 {
+            big.write().await.new_instance();
+            for _ in 0..100 {
+                big.write().await.append(line.clone());
+            }
+        }
+        for _ in 0..100 {
+            small.write().await.append(line.clone());
+        }
+        assert_eq!(big.read().await.memory_bytes(), 30000);
+        assert_eq!(small.read().await.memory_bytes(), 10000);
+
+        let buffers = [big.clone(), small.clone()];
+        assert_eq!(enforce_budget(&buffers, 40000).await, 0);
+
+        // Each keeps its share of the budget, the oldest instances going first
+        assert_eq!(enforce_budget(&buffers, 20000).await, 20000);
+        let big = big.read().await;
+        assert_eq!(big.memory_bytes(), 15000);
+        assert_eq!(big.instance_count(), 2);
+        assert_eq!(big.get_instance(Some(0)).unwrap().lines.len(), 50);
+        assert_eq!(big.get_instance(Some(-1)).unwrap().lines.len(), 100);
+        assert_eq!(big.generation_index(1).unwrap_err(), "Generation 1 is no longer kept (oldest kept: 2)");
+
+        let small = small.read().await;
+        assert_eq!(small.memory_bytes(), 5000);
+        let instance = small.get_instance(None).unwrap();
+        assert_eq!((instance.lines.len(), instance.total), (50, 100));
+        assert_eq!(instance.seqs.front(), Some(&51));
+    }
+
+    fn instance(lines: &[&str]) -> LogInstance {
+        let mut instance = LogInstance::new();
+        for (seq, line) in lines.iter().enumerate() {
+            instance.append(line.to_string(), seq as u64 + 1, LineMeta::captured(LogStream::Stdout));
+        }
+        instance
+    }
+
+    #[test]
+    fn test_timestamp_parser() {
+        let at = |text: &str| DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc);
+        let parser = |pattern: &str, format: Option<&str>| TimestampParser::new(pattern, format.map(str::to_string)).unwrap();
+
+        // RFC 3339 by default, from the whole match
+        let rfc3339 = parser(r"^\S+", None);
+        assert_eq!(rfc3339.parse("2024-05-01T12:00:00+02:00 INFO up"), Some(at("2024-05-01T10:00:00Z")));
+        assert_eq!(rfc3339.parse("INFO up"), None);
+
+        // A format without an offset is UTC; the ts group picks it out
+        let custom = parser(r"^\[(?P<ts>[^\]]+)\]", Some("%Y-%m-%d %H:%M:%S%.3f"));
+        assert_eq!(custom.parse("[2024-05-01 12:00:00.250] up"), Some(at("2024-05-01T12:00:00.250Z")));
+        assert_eq!(custom.parse("[yesterday] up"), None);
+
+        // Lines it can't read keep their capture time
+        let before = Utc::now();
+        let meta = LineMeta::read("[yesterday] up", LogStream::Stderr, Some(&custom));
+        assert!(meta.timestamp >= before);
+        assert_eq!(meta.stream, Some(LogStream::Stderr));
+        let meta = LineMeta::read("[2024-05-01 12:00:00.250] up", LogStream::Stdout, Some(&custom));
+        assert_eq!(meta.timestamp, at("2024-05-01T12:00:00.250Z"));
+    }
+
+    #[test]
+    fn test_between() {
+        let at = |second: u32| {
+            let text = format!("2024-05-01T12:00:{:02}Z", second);
+            DateTime::parse_from_rfc3339(&text).unwrap().with_timezone(&Utc)
+        };
+        let mut instance = LogInstance::new();
+        // Out of order, as a buffering service's stamped lines may arrive
+        for (seq, second) in [(1, 10), (2, 30), (3, 20), (4, 40)] {
+            let meta = LineMeta { timestamp: at(second), stream: Some(LogStream::Stdout) };
+            instance.append(format!("at {}", second), seq, meta);
+        }
+
+        let window = instance.between(Some(at(15)), Some(at(30)));
+        assert_eq!(texts(&window), ["at 30", "at 20"]);
+        assert_eq!(window.seqs, [2, 3]);
+        assert_eq!(texts(&instance.between(None, Some(at(10)))), ["at 10"]);
+        assert_eq!(texts(&instance.between(Some(at(40)), None)), ["at 40"]);
+        assert!(LogInstance::from_lines(["x".to_string()].into()).between(None, None).lines.is_empty());
+    }
+
+    #[test]
+    fn test_search_context_and_markers() {
+        let instance = instance(&["a", "err 1", "b", "err 2", "c", "d", "e", "err 3"]);
+
+        // Overlapping context ranges are merged, with matches marked
+        assert_eq!(
+            instance.search(Some("err"), Some(1), None, None),
+            vec!["   a", " * err 1", "   b", " * err 2", "   c", "   e", " * err 3"]
+        );
+
+        assert_eq!(instance.search(Some("nope"), None, None, None), vec!["No matches found"]);
+        assert_eq!(
+            instance.search(Some("("), None, None, None),
+            vec!["Invalid regex pattern: ("]
+        );
+    }
+
+    #[test]
+    fn test_select_multiple_patterns() {
+        let instance = instance(&["a", "ERROR req=7", "b", "req=7 done", "c", "ERROR req=9"]);
+
+        let (lines, _) = instance.select_within(&["ERROR", "req=7"], None, None, None, None).unwrap();
+        let found: Vec<(&str, Vec<usize>)> = lines.iter().map(|l| (l.text.as_str(), l.patterns.clone())).collect();
+        assert_eq!(
+            found,
+            vec![("ERROR req=7", vec![0, 1]), ("req=7 done", vec![1]), ("ERROR req=9", vec![0])]
+        );
+
+        // Context lines match no pattern
+        let (lines, _) = instance.select_within(&["done"], Some(1), None, None, None).unwrap();
+        let found: Vec<(&str, bool, usize)> = lines.iter().map(|l| (l.text.as_str(), l.matched, l.patterns.len())).collect();
+        assert_eq!(found, vec![("b", false, 0), ("req=7 done", true, 1), ("c", false, 0)]);
+
+        assert_eq!(
+            instance.select_within(&["ERROR", "("], None, None, None, None).unwrap_err(),
+            "Invalid regex pattern: ("
+        );
+    }
+
+    #[test]
+    fn test_count_captures() {
+        let instance = instance(&["GET /a 200", "GET /b 404", "POST /a 200", "starting", "GET /c 200"]);
+
+        let (counts, timed_out) = instance.count_captures_within(r"(?P<status>\d{3})$", "status", None).unwrap();
+        assert_eq!(counts, vec![("200".to_string(), 3), ("404".to_string(), 1)]);
+        assert_eq!(timed_out, None);
+
+        // Ties are in value order
+        let (counts, _) = instance.count_captures_within(r"(?P<method>GET|POST) /(?P<path>\w)", "path", None).unwrap();
+        assert_eq!(
+            counts,
+            vec![("a".to_string(), 2), ("b".to_string(), 1), ("c".to_string(), 1)]
+        );
+
+        assert_eq!(
+            instance.count_captures_within(r"(\d+)", "status", None).unwrap_err(),
+            "Pattern has no capture group named 'status'"
+        );
+        assert!(instance.count_captures_within("(", "x", None).is_err());
+    }
+
+    #[test]
+    fn test_search_deadline() {
+        let lines: Vec<String> = (0..1000).map(|i| format!("line {}", i)).collect();
+        let instance = instance(&lines.iter().map(String::as_str).collect::<Vec<_>>());
+
+        let (results, timed_out) = instance.search_within(Some("line 9"), None, None, None, None);
+        assert_eq!((results.len(), timed_out), (111, None));
+
+        // Out of time before the first line
+        let (results, timed_out) = instance.search_within(Some("line 9"), None, None, None, Some(Instant::now()));
+        assert_eq!((results, timed_out), (vec!["No matches found".to_string()], Some(0)));
+
+        // Huge patterns are rejected instead of compiled
+        assert_eq!(
+            instance.search(Some("\\w{1000}{1000}"), None, None, None),
+            vec!["Invalid regex pattern: \\w{1000}{1000}"]
+        );
+    }
+
+    #[test]
+    fn test_search_head_tail() {
+        let instance = instance(&["1", "2", "3", "4", "5"]);
+
+        assert_eq!(instance.search(None, None, Some(2), None), vec!["1", "2"]);
+        assert_eq!(instance.search(None, None, None, Some(2)), vec!["4", "5"]);
+        // Head takes precedence over tail
+        assert_eq!(instance.search(None, None, Some(1), Some(2)), vec!["1"]);
+        // Limits larger than the result are fine
+        assert_eq!(instance.search(None, None, None, Some(10)).len(), 5);
+        assert_eq!(instance.search(None, None, Some(0), None), vec!["(empty)"]);
+
+        // Limiting applies after pattern matching and context expansion
+        assert_eq!(
+            instance.search(Some("[24]"), Some(1), None, Some(3)),
+            vec!["   3", " * 4", "   5"]
+        );
+        assert_eq!(
+            instance.search(Some("[24]"), Some(1), Some(2), None),
+            vec!["   1", " * 2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_appender_stress() {
+        const GENERATIONS: usize = MAX_LOG_INSTANCES;
+        const LINES: usize = MAX_LINES_PER_INSTANCE;
+
+        let logs = Arc::new(RwLock::new(LogBuffer::new()));
+        let appender = LogAppender::spawn(logs.clone());
+
+        // Start a new instance while lines for the previous ones are still
+        // in flight, like a restart while the old process is still writing
+        let mut producers = Vec::new();
+        for g in 0..GENERATIONS {
+            let generation = {
+                let mut logs = logs.write().await;
+                logs.new_instance();
+                logs.generation()
+            };
+            let appender = appender.clone();
+            producers.push(tokio::spawn(async move {
+                for i in 0..LINES {
+                    appender.append(generation, format!("{}-{}", g, i), LineMeta::captured(LogStream::Stdout)).await;
+                }
+            }));
+        }
+        for producer in producers {
+            producer.await.unwrap();
+        }
+
+        // Wait for the appender to drain the channel
+        let total = || async {
+            let logs = logs.read().await;
+            (0..GENERATIONS as i32)
+                .map(|i| logs.get_instance(Some(i)).unwrap().lines.len())
+                .sum::<usize>()
+        };
+        for _ in 0..1000 {
+            if total().await == GENERATIONS * LINES {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let logs = logs.read().await;
+        for g in 0..GENERATIONS {
+            let instance = logs.get_instance(Some(g as i32)).unwrap();
+            assert_eq!(instance.lines.len(), LINES, "instance {}", g);
+            for (i, line) in instance.lines.iter().enumerate() {
+                assert_eq!(**line, format!("{}-{}", g, i));
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_instance() {
+        assert_eq!(LogInstance::new().search(None, None, None, None), vec!["(empty)"]);
     }
 }
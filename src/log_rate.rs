@@ -0,0 +1,161 @@
+use regex::Regex;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+/// Length of one slot of the rolling window.
+const SLOT: Duration = Duration::from_secs(10);
+/// Completed slots making up the last minute.
+const SLOTS: usize = 6;
+
+/// Per-minute counts over the last full minute.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rates {
+    pub lines: u64,
+    pub stderr: u64,
+    pub errors: u64,
+}
+
+/// Rolling per-minute counts of a process's output lines, stderr lines and
+/// `error_pattern` matches. Counting a line is an atomic increment; a timer
+/// task moves the counts into the window every 10 seconds.
+pub struct LogRates {
+    error_pattern: Option<Regex>,
+    /// Error lines per minute above which an alert is raised.
+    max_errors: Option<u64>,
+    lines: AtomicU64,
+    stderr: AtomicU64,
+    errors: AtomicU64,
+    window: Mutex<VecDeque<Rates>>,
+    /// Set while the error rate is above `max_errors`, so each excursion
+    /// alerts once.
+    alerting: AtomicBool,
+}
+
+impl LogRates {
+    /// Creates the counters and spawns the task rotating them, which ends
+    /// once they are dropped. Must be called within a tokio runtime.
+    pub fn spawn(error_pattern: Option<Regex>, max_errors: Option<u64>) -> Arc<Self> {
+        let rates = Arc::new(Self::new(error_pattern, max_errors));
+        let weak: Weak<Self> = Arc::downgrade(&rates);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SLOT);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match weak.upgrade() {
+                    Some(rates) => rates.rotate(),
+                    None => break,
+                }
+            }
+        });
+        rates
+    }
+
+    fn new(error_pattern: Option<Regex>, max_errors: Option<u64>) -> Self {
+        Self {
+            error_pattern,
+            max_errors,
+            lines: AtomicU64::new(0),
+            stderr: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            window: Mutex::new(VecDeque::with_capacity(SLOTS)),
+            alerting: AtomicBool::new(false),
+        }
+    }
+
+    /// Counts a line of output. Returns the error rate if this line took it
+    /// above `max_errors`.
+    pub fn record(&self, line: &str, stderr: bool) -> Option<u64> {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+        if stderr {
+            self.stderr.fetch_add(1, Ordering::Relaxed);
+        }
+        if !self.error_pattern.as_ref().is_some_and(|re| re.is_match(line)) {
+            return None;
+        }
+        self.errors.fetch_add(1, Ordering::Relaxed);
+
+        // Errors in the last minute, including the slot still being counted
+        let max = self.max_errors?;
+        let errors = self.rates().errors + self.errors.load(Ordering::Relaxed);
+        (errors > max && !self.alerting.swap(true, Ordering::Relaxed)).then_some(errors)
+    }
+
+    /// Counts over the last full minute, or as much of it as has passed.
+    pub fn rates(&self) -> Rates {
+        let window = self.window.lock().unwrap();
+        window.iter().fold(Rates::default(), |sum, slot| Rates {
+            lines: sum.lines + slot.lines,
+            stderr: sum.stderr + slot.stderr,
+            errors: sum.errors + slot.errors,
+        })
+    }
+
+    /// Closes the current slot. Once the error rate is back within the
+    /// limit, the next excursion alerts again.
+    fn rotate(&self) {
+        let slot = Rates {
+            lines: self.lines.swap(0, Ordering::Relaxed),
+            stderr: self.stderr.swap(0, Ordering::Relaxed),
+            errors: self.errors.swap(0, Ordering::Relaxed),
+        };
+        {
+            let mut window = self.window.lock().unwrap();
+            if window.len() == SLOTS {
+                window.pop_front();
+            }
+            window.push_back(slot);
+        }
+        if self.max_errors.is_some_and(|max| self.rates().errors <= max) {
+            self.alerting.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rolling_window() {
+        let rates = LogRates::new(Some(Regex::new("ERROR").unwrap()), None);
+        for _ in 0..3 {
+            rates.record("ok", false);
+            rates.record("ERROR: failed", true);
+            rates.rotate();
+        }
+        assert_eq!(rates.rates(), Rates { lines: 6, stderr: 3, errors: 3 });
+
+        // Slots older than a minute fall out
+        for _ in 0..SLOTS - 1 {
+            rates.rotate();
+        }
+        assert_eq!(rates.rates(), Rates { lines: 2, stderr: 1, errors: 1 });
+        rates.rotate();
+        assert_eq!(rates.rates(), Rates::default());
+    }
+
+    #[test]
+    fn test_alerts_once_per_excursion() {
+        let rates = LogRates::new(Some(Regex::new("ERROR").unwrap()), Some(2));
+        assert_eq!(rates.record("ERROR", false), None);
+        assert_eq!(rates.record("ERROR", false), None);
+        assert_eq!(rates.record("fine", false), None);
+        assert_eq!(rates.record("ERROR", false), Some(3));
+        assert_eq!(rates.record("ERROR", false), None);
+
+        // Still above the limit after rotating, so no new alert
+        rates.rotate();
+        assert_eq!(rates.record("ERROR", false), None);
+
+        // Back within the limit once the first slot is gone, leaving one error
+        for _ in 0..SLOTS {
+            rates.rotate();
+        }
+        assert_eq!(rates.rates().errors, 1);
+        assert_eq!(rates.record("ERROR", false), None);
+        assert_eq!(rates.record("ERROR", false), Some(3));
+    }
+}
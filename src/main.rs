@@ -1,40 +1,75 @@
+mod build_info;
 mod builder;
 mod config;
 mod crash_handler;
+mod direnv;
+mod line_reader;
 mod log_buffer;
+mod log_rate;
+mod manager_log;
 mod mcp_server;
 mod mode;
+mod preflight;
 mod process;
+mod rate_limit;
+mod restart_queue;
+mod scaffold;
+mod status_summary;
+mod telemetry;
+mod watch;
 
-use anyhow::Result;
-use builder::Builder;
+use anyhow::{Context, Result};
+use builder::{format_bytes, Builder, TargetSize};
 use config::{Config, ProcessType};
 use crash_handler::{CrashHandler, RunMode};
-use mcp_server::{AppState, start_server};
-use mode::ModeManager;
-use process::ProcessManager;
+use manager_log::ManagerLog;
+use mcp_server::{bind_listener, start_server, AppState};
+use mode::{ModeChangeReason, ModeManager};
+use preflight::{CheckOutcome, Preflight};
+use process::{ProcessManager, ProcessState};
+use restart_queue::RestartQueue;
 use std::collections::HashMap;
 use std::env;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Instant;
+use tokio::sync::{mpsc, RwLock, Semaphore};
 use tokio::time::{interval, Duration};
-use tracing::{error, info};
+use tracing::{debug, error, field, info, info_span, warn, Instrument};
+use tracing_subscriber::prelude::*;
+
+/// A crash this recent holds off the switch to release mode.
+const RELEASE_SWITCH_CRASH_WINDOW_MINUTES: i64 = 10;
+
+/// How often the logs are checked against `max_total_log_mb`.
+const LOG_BUDGET_CHECK_SECONDS: u64 = 5;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
+    // Initialize tracing, keeping a copy of the output searchable over MCP
+    let (manager_log, manager_log_layer) = ManagerLog::new();
+    let (telemetry_layer, telemetry_handle) = telemetry::reloadable();
+    tracing_subscriber::registry()
+        .with(telemetry_layer)
+        .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
         )
+        .with(tracing_subscriber::fmt::layer())
+        .with(manager_log_layer)
         .init();
 
     // Parse CLI arguments
     let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("init") => return run_init(&args[0], &args[2..]),
+        Some("validate") => return run_validate(&args[0], &args[2..]).await,
+        _ => {}
+    }
     if args.len() != 2 {
         eprintln!("Usage: {} <project-directory>", args[0]);
+        eprintln!("       {} init [--force] [--dry-run] [project-directory]", args[0]);
+        eprintln!("       {} validate [project-directory]", args[0]);
         std::process::exit(1);
     }
 
@@ -44,15 +79,58 @@ async fn main() -> Result<()> {
         std::process::exit(1);
     }
 
+    run(project_dir, manager_log, &telemetry_handle).await
+}
+
+/// Runs the manager for `project_dir` until it is interrupted: claims the MCP
+/// port, then starts the processes and serves MCP while they run.
+async fn run(
+    project_dir: PathBuf,
+    manager_log: Arc<ManagerLog>,
+    telemetry_handle: &telemetry::TelemetryHandle,
+) -> Result<()> {
     info!("Starting background-process-manager for {}", project_dir.display());
+    let started_at = chrono::Utc::now();
 
     // Load configuration
     let config = Config::load(&project_dir)?;
     info!("Loaded configuration: {} processes", config.process.len());
 
+    // Flushes the spans still batched when `run` returns, on errors too
+    let _telemetry = match config.telemetry {
+        Some(ref telemetry) => {
+            let guard = telemetry::enable(telemetry_handle, telemetry)?;
+            info!("Exporting traces to {}", telemetry.endpoint);
+            Some(guard)
+        }
+        None => None,
+    };
+
+    // Claim the MCP port before starting anything, so that if another
+    // instance holds it we fail without leaving children behind
+    let listener = bind_listener(&config).await?;
+    let listen_port = listener.local_addr()?.port();
+    info!("Using port {} for the MCP server", listen_port);
+
     // Initialize shared state
-    let builder = Arc::new(Builder::new(project_dir.clone()));
-    let mode_manager = Arc::new(ModeManager::new(config.dev_timeout_hours));
+    let direnvs = direnv::for_processes(&config, &project_dir);
+
+    // Check for missing tools up front, rather than letting spawns fail cryptically
+    let preflight = Arc::new(Preflight::new(direnvs.clone()));
+    for check in preflight.run(&config).await {
+        match check.outcome {
+            CheckOutcome::Passed(_) => {}
+            CheckOutcome::Warning(_) => warn!(process = %check.process, "Preflight {}", check.description()),
+            CheckOutcome::Failed(_) => error!(process = %check.process, "Preflight {}", check.description()),
+        }
+    }
+    let builder = Arc::new(Builder::new(&config));
+    let (mode_change_sender, mut mode_changes) = mpsc::unbounded_channel();
+    let mode_manager = Arc::new(
+        ModeManager::with_events(config.dev_timeout_hours, config.initial_mode, mode_change_sender)
+            .with_auto_release(config.auto_release)
+            .with_mode_system(config.mode_system),
+    );
     let processes: Arc<RwLock<HashMap<String, Arc<ProcessManager>>>> =
         Arc::new(RwLock::new(HashMap::new()));
     let crash_handlers: Arc<RwLock<HashMap<String, CrashHandler>>> =
@@ -67,167 +145,711 @@ async fn main() -> Result<()> {
             let pm = Arc::new(ProcessManager::new(
                 name.clone(),
                 proc_config.clone(),
-                project_dir.clone(),
-            ));
+                proc_config.root(&project_dir),
+                direnvs[name].clone(),
+                config.echo_child_output,
+                config.max_events,
+                config.max_log_line_bytes,
+            )
+            .with_early_exit_window(Duration::from_secs(config.early_exit_window_seconds))
+            .with_console_output(config.console_output_for(name)));
             procs.insert(name.clone(), pm);
 
             let handler = CrashHandler::new(
                 config.dev_crash_wait_seconds,
                 config.release_crash_backoff_initial_seconds,
                 config.release_crash_backoff_max_seconds,
-            );
+            )
+            .with_uniform_backoff(!config.mode_system);
             handlers.insert(name.clone(), handler);
         }
     }
 
-    // Start all processes
+    // Bulk restarts over MCP and the switch to release mode share one bound
+    let restart_queue = Arc::new(RestartQueue::new(config.max_concurrent_restarts));
+
+    // Serve MCP while the processes start, so their progress can be followed
+    // and /readyz tells when they are done
+    let app_state = AppState::new(
+        config.clone(),
+        processes.clone(),
+        builder.clone(),
+        mode_manager.clone(),
+        crash_handlers.clone(),
+        manager_log,
+        preflight,
+    )
+    .with_listen_port(listen_port)
+    .with_manager(project_dir.clone(), started_at)
+    .with_restart_queue(restart_queue.clone())
+    .starting();
+    let server = tokio::spawn(start_server(app_state.clone(), listener));
+
+    // Start processes concurrently, bounded by startup_concurrency and
+    // max_concurrent_builds. Each one waits for its dependencies to finish
+    // starting first; config validation rules out cycles.
     info!("Starting all processes...");
-    let procs = processes.read().await;
-    for (name, process) in procs.iter() {
-        let mode = mode_manager.get_mode().await;
-        let release = matches!(mode, RunMode::Release);
-
-        match process.config.process_type {
-            ProcessType::Rust => {
-                info!("Building and starting Rust process: {}", name);
-                match builder.build_rust(release, process.build_logs.clone()).await {
-                    Ok(binary_path) => {
-                        if let Err(e) = process.spawn_process(binary_path).await {
-                            error!("Failed to start process {}: {}", name, e);
+    let build_permits = Arc::new(Semaphore::new(config.max_concurrent_builds));
+    let startup_permits = Arc::new(Semaphore::new(config.startup_concurrency));
+    // Per process: None while starting, then whether it started
+    let started: HashMap<_, _> = config
+        .process
+        .keys()
+        .map(|name| (name.clone(), tokio::sync::watch::channel(None::<bool>)))
+        .collect();
+    let startups: Vec<_> = processes
+        .read()
+        .await
+        .iter()
+        .map(|(name, process)| {
+            let name = name.clone();
+            let process = process.clone();
+            let builder = builder.clone();
+            let mode_manager = mode_manager.clone();
+            let build_permits = build_permits.clone();
+            let startup_permits = startup_permits.clone();
+            let done = started[&name].0.clone();
+            let dependencies: Vec<_> = process
+                .config
+                .depends_on
+                .iter()
+                .map(|dependency| (dependency.clone(), started[dependency].1.clone()))
+                .collect();
+            async move {
+                let result = async {
+                    for (dependency, mut dependency_started) in dependencies {
+                        let ok = dependency_started
+                            .wait_for(Option::is_some)
+                            .await
+                            .map(|started| *started == Some(true))
+                            .unwrap_or(false);
+                        if !ok {
+                            anyhow::bail!("Dependency '{}' failed to start", dependency);
                         }
                     }
-                    Err(e) => {
-                        error!("Failed to build process {}: {}", name, e);
-                    }
+                    let _permit = startup_permits.acquire().await?;
+                    start_process(&process, &builder, &mode_manager, &build_permits).await
                 }
-            }
-            ProcessType::Npm => {
-                info!("Starting NPM process: {}", name);
-                if let Err(e) = process.spawn_npm_process().await {
-                    error!("Failed to start process {}: {}", name, e);
+                .await;
+                done.send_replace(Some(result.is_ok()));
+                if let Err(ref e) = result {
+                    process.report_start_failed().await;
+                    error!("Failed to start process {}: {:#}", name, e);
+                    *process.startup_error.write().await = Some(format!("{:#}", e));
                 }
+                (name, result)
             }
-        }
+        })
+        .collect();
+    let results = futures::future::join_all(startups).await;
+    app_state.mark_ready();
+
+    let failed: Vec<_> = results
+        .iter()
+        .filter(|(_, result)| result.is_err())
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if failed.is_empty() {
+        info!("Startup complete: all {} processes started", results.len());
+    } else {
+        error!(
+            "Startup complete: {} of {} processes started; failed: {}",
+            results.len() - failed.len(),
+            results.len(),
+            failed.join(", ")
+        );
     }
-    drop(procs);
 
     // Spawn crash monitors for each process
     for (name, _) in config.process.iter() {
-        let name = name.clone();
-        let processes = processes.clone();
-        let builder = builder.clone();
-        let mode_manager = mode_manager.clone();
-        let crash_handlers = crash_handlers.clone();
+        tokio::spawn(monitor_crashes(
+            name.clone(),
+            processes.clone(),
+            builder.clone(),
+            mode_manager.clone(),
+            crash_handlers.clone(),
+        ));
+    }
+
+    // Log mode changes and notify processes that asked for it. Processes
+    // that only run in one mode follow it: those that don't run in the new
+    // mode are stopped, and those that didn't run in the old one are started,
+    // through the restart queue.
+    let processes_clone = processes.clone();
+    let builder_clone = builder.clone();
+    let restart_queue_clone = restart_queue.clone();
+    tokio::spawn(async move {
+        while let Some(change) = mode_changes.recv().await {
+            info!(
+                from = ?change.from,
+                to = ?change.to,
+                reason = change.reason.as_str(),
+                "Mode changed"
+            );
+            let procs: Vec<_> = processes_clone.read().await.values().cloned().collect();
+            for process in &procs {
+                process.notify_mode_change().await;
+            }
+            for process in procs {
+                if !process.config.runs_in(change.to) {
+                    info!("Stopping {}, which doesn't run in {} mode", process.name, change.to.as_str());
+                    if let Err(e) = process.request_stop().await {
+                        error!("Failed to stop process {}: {}", process.name, e);
+                    }
+                } else if !process.config.runs_in(change.from) {
+                    let builder = builder_clone.clone();
+                    let restart_queue = restart_queue_clone.clone();
+                    let mode = change.to;
+                    tokio::spawn(async move {
+                        let _permit = restart_queue.acquire(&process.name).await;
+                        // A manual restart holding the lock starts it itself
+                        let Ok(_guard) = process.restart_lock.try_lock() else {
+                            return;
+                        };
+                        if matches!(process.state().await, ProcessState::Stopped | ProcessState::NotStarted) {
+                            info!("Starting {} again in {} mode", process.name, mode.as_str());
+                            let _ = restart_crashed(&process, &builder, process.config.release_build(mode)).await;
+                        }
+                    });
+                }
+            }
+        }
+    });
 
+    // Spawn mode checker, unless the mode never changes
+    if config.mode_system {
+        let mode_manager_clone = mode_manager.clone();
+        let processes_clone = processes.clone();
+        let builder_clone = builder.clone();
+        let restart_queue = restart_queue.clone();
+        let release_switch_requires_healthy = config.release_switch_requires_healthy;
         tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(60)); // Check every minute
             loop {
-                let process = {
-                    let procs = processes.read().await;
-                    procs.get(&name).cloned()
-                };
-
-                if let Some(process) = process {
-                    // Wait for process to exit
-                    process.wait_for_exit().await;
-
-                    // Check if this is a manual restart - if so, skip the automatic restart logic
-                    if process.is_manual_restart_in_progress().await {
-                        info!("Process {} stopped for manual restart, skipping automatic restart", name);
-                        continue;
-                    }
+                interval.tick().await;
 
-                    // Get crash handler and wait before restart
-                    let mode = mode_manager.get_mode().await;
-                    {
-                        let mut handlers = crash_handlers.write().await;
-                        if let Some(handler) = handlers.get_mut(&name) {
-                            handler.wait_before_restart(mode).await;
+                if !mode_manager_clone.should_switch_to_release().await {
+                    mode_manager_clone.set_release_deferral(None).await;
+                    continue;
+                }
+
+                if release_switch_requires_healthy {
+                    if let Some(reason) = release_switch_blocker(&processes_clone).await {
+                        if mode_manager_clone.release_deferral().await.as_ref() != Some(&reason) {
+                            info!("Deferring switch to release mode: {}", reason);
                         }
+                        mode_manager_clone.set_release_deferral(Some(reason)).await;
+                        continue;
                     }
+                }
 
-                    // Rebuild and restart
-                    info!("Restarting process: {}", name);
-                    let release = matches!(mode, RunMode::Release);
-
-                    match process.config.process_type {
-                        ProcessType::Rust => {
-                            match builder.build_rust(release, process.build_logs.clone()).await {
-                                Ok(binary_path) => {
-                                    if let Err(e) = process.spawn_process(binary_path).await {
-                                        error!("Failed to restart process {}: {}", name, e);
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("Failed to build process {}: {}", name, e);
-                                }
-                            }
-                        }
-                        ProcessType::Npm => {
-                            if let Err(e) = process.spawn_npm_process().await {
-                                error!("Failed to restart process {}: {}", name, e);
-                            }
+                info!("Switching to release mode");
+                let span = info_span!("mode_switch", from = "dev", to = "release", reason = "timeout");
+                async {
+                    mode_manager_clone.switch_to_release(ModeChangeReason::Timeout).await;
+
+                    // Rebuild and restart all processes in release mode, apart
+                    // from those whose build profile doesn't follow the mode,
+                    // as many at once as the restart queue allows. Processes
+                    // that only run in one of the modes are stopped or started
+                    // by the mode change itself.
+                    let procs: Vec<_> = processes_clone.read().await.values().cloned().collect();
+                    let mut rebuilds = Vec::new();
+                    for process in procs {
+                        let runs_in_both = process.config.runs_in(RunMode::Dev) && process.config.runs_in(RunMode::Release);
+                        if runs_in_both
+                            && process.config.process_type == ProcessType::Rust
+                            && !process.config.profile_pinned()
+                        {
+                            let builder = &builder_clone;
+                            let restart_queue = &restart_queue;
+                            let mode_manager = &mode_manager_clone;
+                            rebuilds.push(async move {
+                                let _permit = restart_queue.acquire(&process.name).await;
+                                // So that a manual restart can't start an
+                                // instance that the release one then replaces
+                                let _guard = process.restart_lock.lock().await;
+                                let span = info_span!(
+                                    "restart",
+                                    process = %process.name,
+                                    trigger = "mode_switch",
+                                    outcome = field::Empty,
+                                    duration_ms = field::Empty,
+                                );
+                                let started = Instant::now();
+                                let result =
+                                    restart_in_release(&process, builder, mode_manager).instrument(span.clone()).await;
+                                telemetry::record_outcome(&span, started, &result);
+                            });
                         }
                     }
-                } else {
-                    break;
+                    futures::future::join_all(rebuilds).await;
                 }
+                .instrument(span)
+                .await;
             }
         });
     }
 
-    // Spawn mode checker
-    let mode_manager_clone = mode_manager.clone();
-    let processes_clone = processes.clone();
-    let builder_clone = builder.clone();
-    tokio::spawn(async move {
-        let mut interval = interval(Duration::from_secs(60)); // Check every minute
-        loop {
-            interval.tick().await;
+    // Keep the logs within their memory budget
+    if let Some(mb) = config.max_total_log_mb {
+        let processes_clone = processes.clone();
+        let budget = mb as usize * 1024 * 1024;
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(LOG_BUDGET_CHECK_SECONDS));
+            loop {
+                interval.tick().await;
 
-            if mode_manager_clone.should_switch_to_release().await {
-                info!("Switching to release mode");
-                mode_manager_clone.switch_to_release().await;
-
-                // Rebuild and restart all processes in release mode
-                let procs = processes_clone.read().await;
-                for (name, process) in procs.iter() {
-                    if process.config.process_type == ProcessType::Rust {
-                        info!("Rebuilding {} in release mode", name);
-
-                        // Stop process
-                        if let Err(e) = process.stop().await {
-                            error!("Failed to stop process {}: {}", name, e);
-                            continue;
-                        }
+                let buffers: Vec<_> = processes_clone
+                    .read()
+                    .await
+                    .values()
+                    .flat_map(|p| [p.logs.clone(), p.build_logs.clone()])
+                    .collect();
+                let freed = log_buffer::enforce_budget(&buffers, budget).await;
+                if freed > 0 {
+                    debug!("Logs over max_total_log_mb; dropped {} of the oldest lines", format_bytes(freed as u64));
+                }
+            }
+        });
+    }
+
+    // Measure target directories, once per directory since processes can
+    // share one
+    if let Some(minutes) = config.target_size_check_minutes {
+        let processes_clone = processes.clone();
+        let builder_clone = builder.clone();
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(minutes * 60));
+            loop {
+                interval.tick().await;
 
-                        // Build in release mode
-                        match builder_clone.build_rust(true, process.build_logs.clone()).await {
-                            Ok(binary_path) => {
-                                if let Err(e) = process.spawn_process(binary_path).await {
-                                    error!("Failed to start process {} in release mode: {}", name, e);
-                                }
-                            }
-                            Err(e) => {
-                                error!("Failed to build process {} in release mode: {}", name, e);
-                            }
+                let procs: Vec<_> = processes_clone
+                    .read()
+                    .await
+                    .values()
+                    .filter(|p| p.config.process_type == ProcessType::Rust)
+                    .cloned()
+                    .collect();
+                let mut sizes: HashMap<PathBuf, TargetSize> = HashMap::new();
+                for process in procs {
+                    let dir = process.target_dir(&builder_clone).await;
+                    let size = match sizes.get(&dir) {
+                        Some(size) => *size,
+                        None => {
+                            let size = TargetSize::measure(dir.clone()).await;
+                            sizes.insert(dir, size);
+                            size
                         }
-                    }
+                    };
+                    *process.target_size.write().await = Some(size);
+                }
+            }
+        });
+    }
+
+    tokio::select! {
+        result = server => result??,
+        _ = tokio::signal::ctrl_c() => {
+            // Stop processes gracefully rather than killing them on drop, and
+            // keep the crash monitors from restarting them
+            info!("Shutting down");
+            let procs: Vec<_> = processes.read().await.values().cloned().collect();
+            for (process, result) in procs.iter().zip(futures::future::join_all(procs.iter().map(|p| p.retire())).await) {
+                if let Err(e) = result {
+                    error!("Failed to stop process {}: {}", process.name, e);
                 }
             }
         }
-    });
+    }
 
-    // Start MCP server
-    let app_state = AppState::new(
-        config.clone(),
-        processes.clone(),
-        builder.clone(),
-        mode_manager.clone(),
-        crash_handlers.clone(),
-    );
+    Ok(())
+}
+
+/// Restarts process `name` whenever it crashes or fails to start, after
+/// the delay its crash handler asks for. Ends once the process is retired or
+/// removed.
+async fn monitor_crashes(
+    name: String,
+    processes: Arc<RwLock<HashMap<String, Arc<ProcessManager>>>>,
+    builder: Arc<Builder>,
+    mode_manager: Arc<ModeManager>,
+    crash_handlers: Arc<RwLock<HashMap<String, CrashHandler>>>,
+) {
+    loop {
+        let process = {
+            let procs = processes.read().await;
+            procs.get(&name).cloned()
+        };
+
+        if let Some(process) = process {
+            // Wait for process to crash; stops on purpose are waited out
+            process.wait_for_exit().await;
+            if process.is_retired() {
+                break;
+            }
 
-    info!("Starting MCP HTTP server on port {}", config.mcp_port);
-    start_server(app_state, config.mcp_port).await?;
+            // Wait before restarting, without holding the handlers
+            // so status stays available meanwhile
+            let mode = mode_manager.get_mode().await;
+            let delay = crash_handlers
+                .write()
+                .await
+                .get_mut(&name)
+                .map(|handler| handler.schedule_restart(mode));
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+            }
+
+            // It may have been removed, or a manual restart may have
+            // brought it back meanwhile
+            if process.is_retired() {
+                break;
+            }
+            if !process.state().await.needs_restart() {
+                info!("Process {} was restarted while waiting, skipping automatic restart", name);
+                continue;
+            }
+
+            // Rebuild and restart
+            info!("Restarting process: {}", name);
+            let release = process.config.release_build(mode);
+            let span = info_span!(
+                "restart",
+                process = %name,
+                trigger = "crash",
+                outcome = field::Empty,
+                duration_ms = field::Empty,
+            );
+            let started = Instant::now();
+            let result = restart_crashed(&process, &builder, release).instrument(span.clone()).await;
+            telemetry::record_outcome(&span, started, &result);
+        } else {
+            break;
+        }
+    }
+}
+
+/// Rebuilds a process that crashed or failed to start, if it is a Rust
+/// process or has a `build_command`, and starts it again.
+async fn restart_crashed(process: &ProcessManager, builder: &Builder, release: bool) -> Result<()> {
+    if process.config.process_type == ProcessType::Npm {
+        if process.config.has_build() {
+            process.report_building().await;
+            if let Err(e) = process.build_npm(builder, None).await {
+                error!("Failed to build process {}: {}", process.name, e);
+                process.record_build_failure(&e).await;
+                return Err(e);
+            }
+        }
+        return process
+            .spawn_npm_process()
+            .await
+            .inspect_err(|e| error!("Failed to restart process {}: {}", process.name, e));
+    }
+
+    process.report_building().await;
+    let binary_path = match process.build(builder, release, None).await {
+        Ok(binary_path) => binary_path,
+        Err(e) => {
+            error!("Failed to build process {}: {}", process.name, e);
+            process.record_build_failure(&e).await;
+            return Err(e);
+        }
+    };
+    process
+        .spawn_process(binary_path)
+        .await
+        .inspect_err(|e| error!("Failed to restart process {}: {}", process.name, e))
+}
+
+/// Stops a Rust process, rebuilds it in release mode and starts it again.
+/// The caller holds the process's restart lock. If a manual restart, of this
+/// process or another, switched back to dev in the meantime, the process is
+/// left as it is, or started in dev mode once it has been stopped.
+async fn restart_in_release(process: &ProcessManager, builder: &Builder, mode_manager: &ModeManager) -> Result<()> {
+    if mode_manager.get_mode().await != RunMode::Release {
+        info!("Not rebuilding {} in release mode, which was left meanwhile", process.name);
+        return Ok(());
+    }
+    info!("Rebuilding {} in release mode", process.name);
+
+    if let Err(e) = process.request_stop().await {
+        error!("Failed to stop process {}: {}", process.name, e);
+        return Err(e);
+    }
+    process.report_building().await;
+
+    let binary_path = match process.build(builder, true, None).await {
+        Ok(binary_path) => binary_path,
+        Err(e) => {
+            error!("Failed to build process {} in release mode: {}", process.name, e);
+            process.record_build_failure(&e).await;
+            return Err(e);
+        }
+    };
+    if mode_manager.get_mode().await != RunMode::Release {
+        info!("Release mode was left while rebuilding {}; starting it in dev mode", process.name);
+        return restart_crashed(process, builder, process.config.release_build(RunMode::Dev)).await;
+    }
+    process
+        .spawn_process(binary_path)
+        .await
+        .inspect_err(|e| error!("Failed to start process {} in release mode: {}", process.name, e))
+}
+
+/// Why the switch to release mode should wait, if any process is crashed or
+/// has crashed recently.
+async fn release_switch_blocker(processes: &RwLock<HashMap<String, Arc<ProcessManager>>>) -> Option<String> {
+    let procs = processes.read().await;
+    let mut names: Vec<_> = procs.keys().collect();
+    names.sort();
+
+    let window = chrono::Duration::minutes(RELEASE_SWITCH_CRASH_WINDOW_MINUTES);
+    let mut problems = Vec::new();
+    for name in names {
+        if let Some(problem) = procs[name].health_problem(window).await {
+            problems.push(format!("{} {}", name, problem));
+        }
+    }
+
+    if problems.is_empty() {
+        None
+    } else {
+        Some(problems.join(", "))
+    }
+}
+
+/// Builds (for Rust, or NPM with a `build_command`) and spawns a process at
+/// boot. Builds wait for a permit so that only a bounded number run at once.
+async fn start_process(
+    process: &ProcessManager,
+    builder: &Builder,
+    mode_manager: &ModeManager,
+    build_permits: &Semaphore,
+) -> Result<()> {
+    let mode = mode_manager.get_mode().await;
+    if !process.config.runs_in(mode) {
+        info!("Not starting {}, which doesn't run in {} mode", process.name, mode.as_str());
+        return Ok(());
+    }
+    let release = process.config.release_build(mode);
+
+    match process.config.process_type {
+        ProcessType::Rust => {
+            info!("Building and starting Rust process: {}", process.name);
+            process.report_building().await;
+            let binary_path = {
+                let _permit = build_permits.acquire().await?;
+                process.build(builder, release, None).await.context("Build failed")?
+            };
+            process.spawn_process(binary_path).await
+        }
+        ProcessType::Npm => {
+            info!("Starting NPM process: {}", process.name);
+            if process.config.has_build() {
+                process.report_building().await;
+                let _permit = build_permits.acquire().await?;
+                process.build_npm(builder, None).await.context("Build failed")?;
+            }
+            process.spawn_npm_process().await
+        }
+    }
+}
+
+/// Checks the project's config and the tools its processes need, without
+/// starting anything. Exits non-zero if anything is wrong.
+async fn run_validate(program: &str, args: &[String]) -> Result<()> {
+    let project_dir = match args {
+        [] => PathBuf::from("."),
+        [dir] if !dir.starts_with("--") => PathBuf::from(dir),
+        _ => {
+            eprintln!("Usage: {} validate [project-directory]", program);
+            std::process::exit(1);
+        }
+    };
+
+    let config = match Config::load(&project_dir) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("Configuration OK: {} process(es)", config.process.len());
 
+    let results = Preflight::new(direnv::for_processes(&config, &project_dir)).run(&config).await;
+    print!("{}", preflight::report(&results));
+
+    if results.iter().any(|r| matches!(r.outcome, CheckOutcome::Failed(_))) {
+        std::process::exit(1);
+    }
     Ok(())
 }
+
+/// Writes a proposed `.mcp-run` for the project, based on what `scaffold` detects.
+fn run_init(program: &str, args: &[String]) -> Result<()> {
+    let mut force = false;
+    let mut dry_run = false;
+    let mut project_dir = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--force" => force = true,
+            "--dry-run" => dry_run = true,
+            _ if arg.starts_with("--") || project_dir.is_some() => {
+                eprintln!("Usage: {} init [--force] [--dry-run] [project-directory]", program);
+                std::process::exit(1);
+            }
+            _ => project_dir = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let project_dir = project_dir.unwrap_or_else(|| PathBuf::from("."));
+    if !project_dir.is_dir() {
+        anyhow::bail!("Project directory does not exist: {}", project_dir.display());
+    }
+
+    let detection = scaffold::detect(&project_dir)?;
+    let content = scaffold::render(&detection);
+
+    if dry_run {
+        print!("{}", content);
+        return Ok(());
+    }
+
+    // With --force, overwrite whichever config file is already in use
+    let config_path = match Config::find(&project_dir) {
+        Some(existing) if !force => {
+            anyhow::bail!(
+                "{} already exists; pass --force to overwrite",
+                existing.display()
+            );
+        }
+        Some(existing) => existing,
+        None => project_dir.join(config::CONFIG_FILENAMES[0]),
+    };
+    std::fs::write(&config_path, content)
+        .with_context(|| format!("Failed to write {}", config_path.display()))?;
+    println!(
+        "Wrote {} with {} process(es)",
+        config_path.display(),
+        detection.processes.len()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DirenvStrategy;
+    use crate::direnv::Direnv;
+
+    #[tokio::test]
+    async fn test_monitor_crashes() {
+        // Logs a line per start, crashing after the first
+        let marker = env::temp_dir().join(format!("bpm-test-monitor-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let config = Config::parse(&format!(
+            "mcp_port = 3001\ndev_crash_wait_seconds = 0\n[process.main]\ntype = \"npm\"\n\
+             command = [\"sh\", \"-c\", \"echo up; if [ -e {0} ]; then sleep 60; fi; touch {0}; exit 3\"]\n",
+            marker.display()
+        ))
+        .unwrap();
+        let process = Arc::new(ProcessManager::new(
+            "main".to_string(),
+            config.process["main"].clone(),
+            PathBuf::from("."),
+            Arc::new(Direnv::new(PathBuf::from("."), DirenvStrategy::Off)),
+            false,
+            config.max_events,
+            config.max_log_line_bytes,
+        ));
+        let processes = Arc::new(RwLock::new(HashMap::from([("main".to_string(), process.clone())])));
+        let crash_handlers = Arc::new(RwLock::new(HashMap::from([("main".to_string(), CrashHandler::new(0, 0, 0))])));
+        // As in run(), the monitor only starts once startup has, so it never
+        // sees the process NotStarted and races the first spawn
+        let _ = process.spawn_npm_process().await;
+        let monitor = tokio::spawn(monitor_crashes(
+            "main".to_string(),
+            processes,
+            Arc::new(Builder::new(&config)),
+            Arc::new(ModeManager::new(3, RunMode::Dev)),
+            crash_handlers,
+        ));
+
+        // The crash is restarted, and the second instance stays up
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !(process.logs.read().await.instance_count() == 2 && process.state().await == ProcessState::Running) {
+            assert!(Instant::now() < deadline, "not restarted: {}", process.state().await.as_str());
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(!monitor.is_finished());
+
+        // Retiring it ends the monitor without another restart
+        process.retire().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(2), monitor).await.unwrap().unwrap();
+        assert_eq!(process.state().await, ProcessState::Stopped);
+        assert_eq!(process.logs.read().await.instance_count(), 2);
+        assert_eq!(Arc::strong_count(&process), 1);
+        assert_eq!(process.wait_for_exit().await, None);
+
+        std::fs::remove_file(&marker).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restart_in_release_after_leaving_release() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\nargs = [\"60\"]\n").unwrap();
+        let process = ProcessManager::new(
+            "main".to_string(),
+            config.process["main"].clone(),
+            PathBuf::from("."),
+            Arc::new(Direnv::new(PathBuf::from("."), DirenvStrategy::Off)),
+            false,
+            config.max_events,
+            config.max_log_line_bytes,
+        );
+        process.spawn_process(PathBuf::from("sleep")).await.unwrap();
+
+        // A manual restart switched back to dev while the rebuild waited for
+        // its turn
+        let mode_manager = ModeManager::new(3, RunMode::Dev);
+        restart_in_release(&process, &Builder::new(&config), &mode_manager).await.unwrap();
+        assert_eq!(process.state().await, ProcessState::Running);
+        assert_eq!(process.logs.read().await.instance_count(), 1);
+
+        process.retire().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_port_in_use_spawns_nothing() {
+        // Hold the port, as another manager instance would
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let project_dir = env::temp_dir().join(format!("bpm-test-port-in-use-{}", std::process::id()));
+        std::fs::create_dir_all(&project_dir).unwrap();
+        std::fs::write(
+            project_dir.join(config::CONFIG_FILENAMES[0]),
+            format!(
+                "mcp_port = {}\ndirenv = \"off\"\n[process.main]\ntype = \"npm\"\n\
+                 command = [\"sh\", \"-c\", \"touch spawned; sleep 60\"]\n\
+                 build_command = [\"touch\", \"built\"]\n",
+                port
+            ),
+        )
+        .unwrap();
+
+        let (_, telemetry_handle) = telemetry::reloadable();
+        let run = run(project_dir.clone(), ManagerLog::new().0, &telemetry_handle);
+        let err = tokio::time::timeout(Duration::from_secs(10), run).await.unwrap().unwrap_err().to_string();
+        assert!(err.contains(&format!("Port {} is already in use", port)), "{}", err);
+
+        // Neither built nor spawned, even a while later
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!project_dir.join("built").exists());
+        assert!(!project_dir.join("spawned").exists());
+
+        std::fs::remove_dir_all(&project_dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use crate::log_buffer::LogBuffer;
+use chrono::Utc;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, RwLock};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Lines that can be queued before the layer starts dropping them.
+const CHANNEL_CAPACITY: usize = 4096;
+
+/// The manager's own tracing output, kept in memory so it can be searched
+/// over MCP.
+pub struct ManagerLog {
+    pub logs: Arc<RwLock<LogBuffer>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl ManagerLog {
+    /// Creates the log and the layer feeding it. Must be called from within a
+    /// tokio runtime, since it spawns the task that drains the channel.
+    pub fn new() -> (Arc<Self>, ManagerLogLayer) {
+        let (sender, mut receiver) = mpsc::channel::<String>(CHANNEL_CAPACITY);
+        let logs = Arc::new(RwLock::new(LogBuffer::new()));
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        let buffer = logs.clone();
+        tokio::spawn(async move {
+            while let Some(line) = receiver.recv().await {
+                buffer.write().await.append(line);
+            }
+        });
+
+        let layer = ManagerLogLayer {
+            sender,
+            dropped: dropped.clone(),
+        };
+        (Arc::new(Self { logs, dropped }), layer)
+    }
+
+    /// Number of lines dropped because the channel was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Tracing layer that forwards formatted events to the `ManagerLog`. Never
+/// blocks: if the channel is full, the line is dropped and counted.
+pub struct ManagerLogLayer {
+    sender: mpsc::Sender<String>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<S: Subscriber> Layer<S> for ManagerLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = LineVisitor::default();
+        event.record(&mut visitor);
+
+        let line = format!(
+            "{} {:>5} {}: {}{}",
+            Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+            metadata.level(),
+            metadata.target(),
+            visitor.message,
+            visitor.fields
+        );
+
+        if self.sender.try_send(line).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Default)]
+struct LineVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for LineVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    #[tokio::test]
+    async fn test_layer_captures_events() {
+        let (manager_log, layer) = ManagerLog::new();
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(process = "main", "Spawning process");
+        });
+
+        // Let the drain task catch up
+        for _ in 0..100 {
+            if manager_log.logs.read().await.get_instance(None).is_some() {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+
+        let logs = manager_log.logs.read().await;
+        let line = &logs.get_instance(None).unwrap().lines[0];
+        assert!(line.contains(" INFO "), "{}", line);
+        assert!(line.ends_with("Spawning process process=main"), "{}", line);
+        assert_eq!(manager_log.dropped(), 0);
+    }
+}
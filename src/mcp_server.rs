@@ -1,29 +1,427 @@
 use anyhow::{Context, Result};
 use axum::{
     extract::{Json, State},
-    http::{header, Method},
+    http::{header, Method, StatusCode},
     response::{
         sse::{Event, KeepAlive, Sse},
         IntoResponse, Response,
     },
-    routing::post,
+    routing::{get, post},
     Router,
 };
-use futures::stream::{self, Stream};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::convert::Infallible;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, RwLock, Semaphore};
+use tokio_util::sync::CancellationToken;
 use tower_http::cors::{Any, CorsLayer};
-use tracing::{info, warn, trace};
+use tracing::{field, info, info_span, trace, warn, Instrument};
 
-use crate::builder::Builder;
+use crate::builder::{format_bytes, warning_blocks, BuildOutcome, Builder};
+use crate::log_buffer::{LogBuffer, LogInstance, LogStream, SearchLine};
 use crate::config::{Config, ProcessType};
 use crate::crash_handler::{CrashHandler, RunMode};
-use crate::mode::ModeManager;
-use crate::process::ProcessManager;
+use crate::manager_log::ManagerLog;
+use crate::mode::{ModeChangeReason, ModeManager, MAX_DEV_HOURS};
+use crate::preflight::{self, CheckOutcome, CheckResult, Preflight};
+use crate::process::{run_id, ProcessEvent, ProcessManager, ProcessState, ResourceSample, CRASH_TAIL_LINES};
+use crate::rate_limit::RateLimiter;
+use crate::restart_queue::RestartQueue;
+use crate::status_summary::{self, ProcessSummary};
+use crate::telemetry;
+
+/// MCP protocol versions this server can speak, newest first. The first one is
+/// offered to clients requesting a version we don't know.
+const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Server-initiated messages kept for SSE clients that fall behind.
+const NOTIFICATION_BUFFER: usize = 64;
+
+/// Most values a `completion/complete` result may hold, per the spec.
+const MAX_COMPLETION_VALUES: usize = 100;
+
+/// Picks the protocol version to answer an initialize request with: the
+/// client's, if we support it, otherwise our newest.
+fn negotiate_protocol_version(params: Option<&Value>) -> &'static str {
+    let requested = params.and_then(|p| p["protocolVersion"].as_str());
+    SUPPORTED_PROTOCOL_VERSIONS
+        .iter()
+        .find(|v| Some(**v) == requested)
+        .unwrap_or(&SUPPORTED_PROTOCOL_VERSIONS[0])
+}
+
+/// MCP behavior hints for a tool, so clients can decide what needs
+/// confirmation. Everything here only touches the managed project.
+fn tool_annotations(tool: &str) -> Value {
+    match tool {
+        // Stop running processes or builds
+        "restart" | "stop" | "cancel" | "clean_target" => json!({
+            "readOnlyHint": false,
+            "destructiveHint": true,
+            "idempotentHint": false,
+            "openWorldHint": false,
+        }),
+        // Change state, but nothing is lost by calling them
+        "build" | "keepalive" | "recheck_tools" | "set_mode_config" => json!({
+            "readOnlyHint": false,
+            "destructiveHint": false,
+            "idempotentHint": true,
+            "openWorldHint": false,
+        }),
+        _ => json!({
+            "readOnlyHint": true,
+            "openWorldHint": false,
+        }),
+    }
+}
+
+/// The `prompts/list` result.
+fn prompt_list() -> Value {
+    let process_argument = json!([{
+        "name": "process",
+        "description": "Name of the process",
+        "required": true
+    }]);
+    json!({
+        "prompts": [
+            {
+                "name": "diagnose_crash",
+                "description": "Diagnose the most recent crash of a process, given its crash event and the log of the instance that crashed",
+                "arguments": process_argument
+            },
+            {
+                "name": "summarize_build_failure",
+                "description": "Summarize why the latest build of a Rust process failed, given the tail of its build log",
+                "arguments": process_argument
+            },
+            {
+                "name": "health_overview",
+                "description": "Review the health of all processes, given the compact status"
+            }
+        ]
+    })
+}
+
+/// The log resources as a URI template, for `resources/templates/list` and
+/// completing its variables.
+const LOG_URI_TEMPLATE: &str = "log://{process}/{index}";
+
+/// Splits a `log://<process>/<index>` resource URI.
+fn parse_log_uri(uri: &str) -> Option<(&str, i32)> {
+    let (process, index) = uri.strip_prefix("log://")?.rsplit_once('/')?;
+    Some((process, index.parse().ok()?))
+}
+
+/// Log lines as a fenced block for embedding in a prompt.
+fn fenced(lines: &[String]) -> String {
+    if lines.is_empty() {
+        return "(empty)".to_string();
+    }
+    format!("```\n{}\n```", lines.join("\n"))
+}
+
+/// How the search tools return their results: `output` is "text" (lines
+/// joined, matches marked with " * ") or "json".
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SearchOutput {
+    Text,
+    Json,
+}
+
+impl SearchOutput {
+    fn from_args(args: &Value) -> Result<Self> {
+        match args["output"].as_str() {
+            None | Some("text") => Ok(SearchOutput::Text),
+            Some("json") => Ok(SearchOutput::Json),
+            Some(other) => anyhow::bail!("Invalid output '{}': must be \"text\" or \"json\"", other),
+        }
+    }
+}
+
+/// The result of a search, with what the tools say about it.
+struct SearchResults {
+    lines: Vec<SearchLine>,
+    /// Number of patterns searched for.
+    patterns: usize,
+    /// Set instead of lines when the search couldn't run.
+    error: Option<String>,
+    /// Shown before the results, e.g. which instance was searched.
+    header: Option<String>,
+    /// Shown after the results, e.g. that they were cut off.
+    notes: Vec<String>,
+    /// Sequence number to pass as `since_seq` to continue after these
+    /// results, for process logs.
+    latest_seq: Option<u64>,
+}
+
+impl SearchResults {
+    fn error(message: String) -> Self {
+        Self {
+            lines: Vec::new(),
+            patterns: 0,
+            error: Some(message),
+            header: None,
+            notes: Vec::new(),
+            latest_seq: None,
+        }
+    }
+
+    fn render(&self, output: SearchOutput) -> String {
+        match output {
+            SearchOutput::Text => self.text(),
+            SearchOutput::Json => self.json().to_string(),
+        }
+    }
+
+    fn text(&self) -> String {
+        let body = if let Some(ref error) = self.error {
+            error.clone()
+        } else if self.lines.is_empty() {
+            if self.patterns > 0 { "No matches found" } else { "(empty)" }.to_string()
+        } else if self.patterns > 0 {
+            let lines: Vec<String> = self
+                .lines
+                .iter()
+                .map(|line| format!("{}{}", self.marker(line), line.text))
+                .collect();
+            lines.join("\n")
+        } else {
+            let lines: Vec<&str> = self.lines.iter().map(|line| line.text.as_str()).collect();
+            lines.join("\n")
+        };
+
+        let mut text = match self.header {
+            Some(ref header) => format!("[{}]\n{}", header, body),
+            None => body,
+        };
+        for note in &self.notes {
+            text.push_str(&format!("\n[{}]", note));
+        }
+        text
+    }
+
+    /// " * " for a match, or with several patterns which ones matched, e.g.
+    /// "*1,3 "; "   " for context.
+    fn marker(&self, line: &SearchLine) -> String {
+        if !line.matched {
+            return "   ".to_string();
+        }
+        if self.patterns == 1 {
+            return " * ".to_string();
+        }
+        let matched: Vec<String> = line.patterns.iter().map(|k| (k + 1).to_string()).collect();
+        format!("*{} ", matched.join(","))
+    }
+
+    /// `{"lines": [{line, matched, stream, timestamp}], ...}`, plus header,
+    /// notes, error and latest_seq when set. timestamp is when the line was
+    /// captured; it and stream are null where they aren't known, and stderr
+    /// lines lose their "[stderr] " prefix. With several patterns, lines also
+    /// have the indices of the `patterns` they matched, and lines of process
+    /// logs have their `seq`.
+    fn json(&self) -> Value {
+        let lines: Vec<Value> = self
+            .lines
+            .iter()
+            .map(|line| {
+                let stream = line.meta.and_then(|meta| meta.stream);
+                let text = match stream {
+                    Some(LogStream::Stderr) => line.text.strip_prefix("[stderr] ").unwrap_or(&line.text),
+                    _ => &line.text,
+                };
+                let mut entry = json!({
+                    "line": text,
+                    "matched": line.matched,
+                    "stream": stream.map(LogStream::as_str),
+                    "timestamp": line.meta.map(|meta| meta.timestamp.to_rfc3339())
+                });
+                if self.patterns > 1 {
+                    entry["patterns"] = json!(line.patterns);
+                }
+                if let Some(seq) = line.seq {
+                    entry["seq"] = json!(seq);
+                }
+                entry
+            })
+            .collect();
+
+        let mut result = json!({ "lines": lines });
+        if let Some(ref header) = self.header {
+            result["header"] = json!(header);
+        }
+        if !self.notes.is_empty() {
+            result["notes"] = json!(self.notes);
+        }
+        if let Some(ref error) = self.error {
+            result["error"] = json!(error);
+        }
+        if let Some(seq) = self.latest_seq {
+            result["latest_seq"] = json!(seq);
+        }
+        result
+    }
+}
+
+/// Distinct values of a named capture group, from search_logs with `capture`.
+struct CaptureResults {
+    capture: String,
+    /// Values with the number of lines they were captured from, most
+    /// frequent first.
+    values: Vec<(String, usize)>,
+    error: Option<String>,
+    notes: Vec<String>,
+}
+
+impl CaptureResults {
+    fn error(capture: &str, message: String) -> Self {
+        Self {
+            capture: capture.to_string(),
+            values: Vec::new(),
+            error: Some(message),
+            notes: Vec::new(),
+        }
+    }
+
+    /// One "count value" line per value as text, or
+    /// `{"capture", "values": [{value, count}], ...}` as JSON.
+    fn render(&self, output: SearchOutput) -> String {
+        match output {
+            SearchOutput::Text => {
+                let mut text = if let Some(ref error) = self.error {
+                    error.clone()
+                } else if self.values.is_empty() {
+                    "No matches found".to_string()
+                } else {
+                    let lines: Vec<String> = self
+                        .values
+                        .iter()
+                        .map(|(value, count)| format!("{:>6} {}", count, value))
+                        .collect();
+                    lines.join("\n")
+                };
+                for note in &self.notes {
+                    text.push_str(&format!("\n[{}]", note));
+                }
+                text
+            }
+            SearchOutput::Json => {
+                let values: Vec<Value> = self
+                    .values
+                    .iter()
+                    .map(|(value, count)| json!({ "value": value, "count": count }))
+                    .collect();
+                let mut result = json!({ "capture": self.capture, "values": values });
+                if !self.notes.is_empty() {
+                    result["notes"] = json!(self.notes);
+                }
+                if let Some(ref error) = self.error {
+                    result["error"] = json!(error);
+                }
+                result.to_string()
+            }
+        }
+    }
+}
+
+/// A whole number of hours in `args[key]`, up to MAX_DEV_HOURS, or None if
+/// it isn't given.
+fn whole_hours(args: &Value, key: &str) -> Result<Option<u64>> {
+    let value = &args[key];
+    if value.is_null() {
+        return Ok(None);
+    }
+    match value.as_u64() {
+        Some(hours) if hours <= MAX_DEV_HOURS => Ok(Some(hours)),
+        Some(_) => anyhow::bail!("'{}' must be at most {} hours", key, MAX_DEV_HOURS),
+        None => anyhow::bail!("'{}' must be a whole, non-negative number of hours", key),
+    }
+}
+
+/// The summary of a process log instance to head its search results with,
+/// unless it is the current one: an agent looking at an earlier run has to
+/// know it is.
+fn earlier_instance_summary(logs: &LogBuffer, index: Option<i32>) -> Option<String> {
+    let idx = index.unwrap_or(-1);
+    if idx == -1 || idx == logs.instance_count() as i32 - 1 {
+        None
+    } else {
+        logs.instance_summary(index)
+    }
+}
+
+/// The patterns for search_logs: `pattern`, or the list in `patterns`, whose
+/// matches are combined.
+fn search_patterns(args: &Value) -> Result<Vec<&str>> {
+    match (args["pattern"].as_str(), &args["patterns"]) {
+        (Some(_), patterns) if !patterns.is_null() => anyhow::bail!("Pass either 'pattern' or 'patterns', not both"),
+        (Some(pattern), _) => Ok(vec![pattern]),
+        (None, Value::Null) => Ok(Vec::new()),
+        (None, Value::Array(patterns)) => patterns
+            .iter()
+            .map(|p| p.as_str().context("'patterns' must be a list of strings"))
+            .collect(),
+        (None, _) => anyhow::bail!("'patterns' must be a list of strings"),
+    }
+}
+
+/// The RFC 3339 time passed as `name`, if any.
+fn time_arg(args: &Value, name: &str) -> Result<Option<DateTime<Utc>>> {
+    match args[name] {
+        Value::Null => Ok(None),
+        Value::String(ref time) => DateTime::parse_from_rfc3339(time)
+            .map(|time| Some(time.with_timezone(&Utc)))
+            .with_context(|| format!("'{}' must be an RFC 3339 time, e.g. 2024-05-01T12:00:00Z", name)),
+        _ => anyhow::bail!("'{}' must be an RFC 3339 time, e.g. 2024-05-01T12:00:00Z", name),
+    }
+}
+
+/// What `handle_request` returns for a notification. Never sent; see
+/// `handle_post`.
+fn notification_ack() -> JsonRpcResponse {
+    JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        id: Value::Null,
+        result: None,
+        error: None,
+    }
+}
+
+/// Default number of lines `search_logs` returns with `crash_context`.
+const CRASH_CONTEXT_LINES: usize = 50;
+
+/// How long status tools wait for one process's locks before reporting its
+/// status as unavailable, so one stuck process doesn't hide the others.
+const STATUS_LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Tools that change processes or the mode, refused while the processes are
+/// first built and started, since they would race the startup.
+const TOOLS_AFTER_STARTUP: &[&str] = &["restart", "stop", "build", "clean_target", "keepalive", "set_mode_config"];
+
+/// Tools that don't take a `max_concurrent_calls` slot: `cancel`, so it can
+/// still stop the builds holding them all, and cheap status reads.
+const TOOLS_WITHOUT_PERMIT: &[&str] = &[
+    "cancel",
+    "get_status",
+    "get_mode",
+    "list_processes",
+    "get_process",
+    "get_manager_info",
+    "get_server_info",
+];
+
+/// How long `wait_logs` waits for new lines by default, and at most. Each
+/// waiting call holds one of the `max_concurrent_calls` slots.
+const DEFAULT_WAIT_LOGS_SECONDS: u64 = 30;
+const MAX_WAIT_LOGS_SECONDS: u64 = 120;
+
+/// Wait between attempts to bind a taken MCP port.
+const MCP_PORT_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
 
 #[derive(Debug, Deserialize)]
 struct JsonRpcRequest {
@@ -46,49 +444,173 @@ struct JsonRpcResponse {
 struct JsonRpcError {
     code: i32,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
 }
 
 #[derive(Clone)]
 pub struct AppState {
+    config: Arc<Config>,
     processes: Arc<RwLock<HashMap<String, Arc<ProcessManager>>>>,
     builder: Arc<Builder>,
     mode_manager: Arc<ModeManager>,
     crash_handlers: Arc<RwLock<HashMap<String, CrashHandler>>>,
+    manager_log: Arc<ManagerLog>,
+    preflight: Arc<Preflight>,
+    /// Per-tool rate limits and a cap on concurrent tool calls; `None` when
+    /// limits are disabled.
+    rate_limiter: Option<Arc<RateLimiter>>,
+    call_permits: Option<Arc<Semaphore>>,
+    /// Tool calls in progress, by JSON-RPC id, so `notifications/cancelled`
+    /// can stop them.
+    in_flight: Arc<Mutex<HashMap<String, CancellationToken>>>,
+    /// The port actually listened on, which differs from `mcp_port` after a
+    /// fallback.
+    listen_port: u16,
+    /// The directory the manager was started for, reported by get_manager_info.
+    project_dir: Option<PathBuf>,
+    /// When the manager process started.
+    started_at: DateTime<Utc>,
+    /// Server-initiated messages, streamed to clients listening on GET /mcp.
+    notifications: broadcast::Sender<Value>,
+    /// Cleared while the processes are first built and started; GET /readyz
+    /// reports 503 until then.
+    ready: Arc<AtomicBool>,
+    /// Bounds bulk restarts; shared with the switch to release mode.
+    restart_queue: Arc<RestartQueue>,
+}
+
+/// Removes a tool call from `in_flight` when it finishes, however it finishes.
+struct InFlightCall<'a> {
+    in_flight: &'a Mutex<HashMap<String, CancellationToken>>,
+    key: String,
+}
+
+impl Drop for InFlightCall<'_> {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Publishes `notifications/progress` for a tool call whose client passed a
+/// progressToken in `_meta`. There are no sessions, so they go to every
+/// client listening on GET /mcp; each picks out its own by token.
+struct Progress {
+    token: Value,
+    notifications: broadcast::Sender<Value>,
+    step: AtomicU64,
+}
+
+impl Progress {
+    fn report(&self, message: String) {
+        let progress = self.step.fetch_add(1, Ordering::Relaxed) + 1;
+        trace!("Progress {}: {}", self.token, message);
+        // Nobody listening is fine; the tool result is still returned
+        let _ = self.notifications.send(json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/progress",
+            "params": {
+                "progressToken": self.token,
+                "progress": progress,
+                "message": message,
+            }
+        }));
+    }
 }
 
 impl AppState {
     pub fn new(
-        _config: Config,
+        config: Config,
         processes: Arc<RwLock<HashMap<String, Arc<ProcessManager>>>>,
         builder: Arc<Builder>,
         mode_manager: Arc<ModeManager>,
         crash_handlers: Arc<RwLock<HashMap<String, CrashHandler>>>,
+        manager_log: Arc<ManagerLog>,
+        preflight: Arc<Preflight>,
     ) -> Self {
+        let limits = &config.mcp.limits;
+        let (rate_limiter, call_permits) = if limits.enabled {
+            (
+                Some(Arc::new(RateLimiter::new(limits.effective_per_minute()))),
+                Some(Arc::new(Semaphore::new(limits.max_concurrent_calls))),
+            )
+        } else {
+            (None, None)
+        };
+
         Self {
             processes,
             builder,
             mode_manager,
             crash_handlers,
+            manager_log,
+            preflight,
+            rate_limiter,
+            call_permits,
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+            listen_port: config.mcp_port,
+            project_dir: None,
+            started_at: Utc::now(),
+            notifications: broadcast::channel(NOTIFICATION_BUFFER).0,
+            ready: Arc::new(AtomicBool::new(true)),
+            restart_queue: Arc::new(RestartQueue::new(config.max_concurrent_restarts)),
+            config: Arc::new(config),
         }
     }
 
+    /// Marks the processes as still being started, until `mark_ready`.
+    pub fn starting(self) -> Self {
+        self.ready.store(false, Ordering::SeqCst);
+        self
+    }
+
+    /// Records that the initial builds and spawns are done, whether or not
+    /// they all succeeded.
+    pub fn mark_ready(&self) {
+        self.ready.store(true, Ordering::SeqCst);
+    }
+
+    /// Records the port the server was bound to by `bind_listener`.
+    pub fn with_listen_port(mut self, port: u16) -> Self {
+        self.listen_port = port;
+        self
+    }
+
+    /// Shares the queue bounding bulk restarts with the mode switcher.
+    pub fn with_restart_queue(mut self, restart_queue: Arc<RestartQueue>) -> Self {
+        self.restart_queue = restart_queue;
+        self
+    }
+
+    /// Records the project directory and the time the manager started, for
+    /// get_manager_info.
+    pub fn with_manager(mut self, project_dir: PathBuf, started_at: DateTime<Utc>) -> Self {
+        self.project_dir = Some(project_dir);
+        self.started_at = started_at;
+        self
+    }
+
     async fn handle_request(&self, request: JsonRpcRequest) -> JsonRpcResponse {
         let id = request.id.clone().unwrap_or(Value::Null);
 
         match request.method.as_str() {
             "initialize" => {
-                info!("Received initialize request");
+                let protocol_version = negotiate_protocol_version(request.params.as_ref());
+                info!("Received initialize request, using protocol version {}", protocol_version);
                 JsonRpcResponse {
                     jsonrpc: "2.0".to_string(),
                     id,
                     result: Some(json!({
-                        "protocolVersion": "2024-11-05",
+                        "protocolVersion": protocol_version,
                         "capabilities": {
-                            "tools": {}
+                            "tools": {},
+                            "resources": {},
+                            "prompts": {},
+                            "completions": {}
                         },
                         "serverInfo": {
                             "name": "background-process-manager",
-                            "version": "0.1.0"
+                            "version": env!("CARGO_PKG_VERSION")
                         }
                     })),
                     error: None,
@@ -103,6 +625,39 @@ impl AppState {
                 self.mode_manager.record_tool_call().await;
                 self.handle_tool_call(id, request.params).await
             }
+            "resources/list" => {
+                trace!("Received resources/list request");
+                self.handle_list_resources(id).await
+            }
+            "resources/templates/list" => {
+                trace!("Received resources/templates/list request");
+                self.handle_list_resource_templates(id)
+            }
+            "resources/read" => {
+                trace!("Received resources/read request");
+                self.handle_read_resource(id, request.params).await
+            }
+            "notifications/cancelled" => {
+                trace!("Received notifications/cancelled");
+                self.handle_cancelled(request.params.as_ref());
+                notification_ack()
+            }
+            method if method.starts_with("notifications/") => {
+                trace!("Ignoring notification {}", method);
+                notification_ack()
+            }
+            "prompts/list" => {
+                trace!("Received prompts/list request");
+                self.handle_list_prompts(id)
+            }
+            "prompts/get" => {
+                trace!("Received prompts/get request");
+                self.handle_get_prompt(id, request.params).await
+            }
+            "completion/complete" => {
+                trace!("Received completion/complete request");
+                self.handle_complete(id, request.params).await
+            }
             _ => {
                 warn!("Unknown method: {}", request.method);
                 JsonRpcResponse {
@@ -112,372 +667,3869 @@ impl AppState {
                     error: Some(JsonRpcError {
                         code: -32601,
                         message: format!("Method not found: {}", request.method),
+                        data: None,
                     }),
                 }
             }
         }
     }
 
-    async fn handle_list_tools(&self, id: Value) -> JsonRpcResponse {
-        let tools = json!({
-            "tools": [
-                {
-                    "name": "search_logs",
-                    "description": "Search process logs with optional regex pattern, context lines, and head/tail limiting. Execution order: pattern matching → context expansion → head/tail limiting",
-                    "inputSchema": {
-                        "type": "object",
-                        "properties": {
-                            "process": {
-                                "type": "string",
-                                "description": "Process name"
-                            },
-                            "pattern": {
-                                "type": "string",
-                                "description": "Optional regex pattern (Rust regex syntax, case-sensitive). Examples: 'ERROR|WARN' (OR), 'started.*server' (wildcards), '\\\\d{3}' (digits). Matched lines prefixed with ' * ', context lines with '   '"
-                            },
-                            "context_lines": {
-                                "type": "number",
-                                "description": "Number of lines to show before and after each match. Only applies when pattern is provided"
-                            },
-                            "head": {
-                                "type": "number",
-                                "description": "Return only first N lines (applied after pattern/context). Mutually exclusive with tail"
-                            },
-                            "tail": {
-                                "type": "number",
-                                "description": "Return only last N lines (applied after pattern/context). Takes precedence over head if both specified"
-                            },
-                            "index": {
-                                "type": "number",
-                                "description": "Log instance index. Negative = recent (-1 most recent, -2 second-to-last), positive = absolute (0 first, 1 second). Default: -1"
-                            }
-                        },
-                        "required": ["process"]
-                    }
-                },
-                {
-                    "name": "search_build_log",
-                    "description": "Search build logs with optional regex pattern, context lines, and head/tail limiting. Execution order: pattern matching → context expansion → head/tail limiting",
-                    "inputSchema": {
-                        "type": "object",
-                        "properties": {
-                            "process": {
-                                "type": "string",
-                                "description": "Process name"
-                            },
-                            "pattern": {
-                                "type": "string",
-                                "description": "Optional regex pattern (Rust regex syntax, case-sensitive). Examples: 'ERROR|WARN' (OR), 'started.*server' (wildcards), '\\\\d{3}' (digits). Matched lines prefixed with ' * ', context lines with '   '"
-                            },
-                            "context_lines": {
-                                "type": "number",
-                                "description": "Number of lines to show before and after each match. Only applies when pattern is provided"
-                            },
-                            "head": {
-                                "type": "number",
-                                "description": "Return only first N lines (applied after pattern/context). Mutually exclusive with tail"
-                            },
-                            "tail": {
-                                "type": "number",
-                                "description": "Return only last N lines (applied after pattern/context). Takes precedence over head if both specified"
-                            },
-                            "index": {
-                                "type": "number",
-                                "description": "Log instance index. Negative = recent (-1 most recent, -2 second-to-last), positive = absolute (0 first, 1 second). Default: -1"
-                            }
-                        },
-                        "required": ["process"]
-                    }
-                },
-                {
-                    "name": "restart",
-                    "description": "Restart a process (builds first for Rust projects, then restarts). Switches back to dev mode.",
-                    "inputSchema": {
-                        "type": "object",
-                        "properties": {
-                            "process": {
-                                "type": "string",
-                                "description": "Process name"
-                            }
-                        },
-                        "required": ["process"]
-                    }
-                },
-                {
-                    "name": "get_status",
-                    "description": "Get status of all processes including mode, uptime, state, and recent events",
-                    "inputSchema": {
-                        "type": "object",
-                        "properties": {}
-                    }
-                }
-            ]
-        });
+    /// Cancels the tool call named by `requestId`. Calls that already
+    /// finished, or never existed, are ignored.
+    fn handle_cancelled(&self, params: Option<&Value>) {
+        let Some(request_id) = params.map(|p| &p["requestId"]).filter(|id| !id.is_null()) else {
+            return;
+        };
+        let in_flight = self.in_flight.lock().unwrap();
+        match in_flight.get(&request_id.to_string()) {
+            Some(token) => {
+                info!(
+                    "Cancelling tool call {}: {}",
+                    request_id,
+                    params.and_then(|p| p["reason"].as_str()).unwrap_or("no reason given")
+                );
+                token.cancel();
+            }
+            None => trace!("Cancellation for {}, which isn't in progress", request_id),
+        }
+    }
+
+    /// Lists every log instance of every process as a `log://<process>/<index>`
+    /// resource, with absolute indices as in search_logs.
+    async fn handle_list_resources(&self, id: Value) -> JsonRpcResponse {
+        let processes = self.processes.read().await;
+        let mut names: Vec<_> = processes.keys().collect();
+        names.sort();
+
+        let mut resources = Vec::new();
+        for name in names {
+            let logs = processes[name].logs.read().await;
+            let count = logs.instance_count();
+            for index in 0..count {
+                let description = if index + 1 == count {
+                    format!("Output of the current instance of {}", name)
+                } else {
+                    format!("Output of an earlier instance of {}", name)
+                };
+                resources.push(json!({
+                    "uri": format!("log://{}/{}", name, index),
+                    "name": format!("{} log {}", name, index),
+                    "description": description,
+                    "mimeType": "text/plain"
+                }));
+            }
+        }
 
         JsonRpcResponse {
             jsonrpc: "2.0".to_string(),
             id,
-            result: Some(tools),
+            result: Some(json!({ "resources": resources })),
             error: None,
         }
     }
 
-    async fn handle_tool_call(&self, id: Value, params: Option<Value>) -> JsonRpcResponse {
-        let params = match params {
-            Some(p) => p,
-            None => {
-                return JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id,
-                    result: None,
-                    error: Some(JsonRpcError {
-                        code: -32602,
-                        message: "Missing params".to_string(),
-                    }),
-                }
-            }
-        };
+    /// Lists the log resources as one URI template, whose variables can be
+    /// completed with `completion/complete`.
+    fn handle_list_resource_templates(&self, id: Value) -> JsonRpcResponse {
+        JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(json!({
+                "resourceTemplates": [
+                    {
+                        "uriTemplate": LOG_URI_TEMPLATE,
+                        "name": "Process log",
+                        "description": "Output of one instance of a process; negative indices count back from the current one",
+                        "mimeType": "text/plain"
+                    }
+                ]
+            })),
+            error: None,
+        }
+    }
 
-        let tool_name = params["name"].as_str().unwrap_or("");
-        let arguments = &params["arguments"];
+    /// Reads a `log://<process>/<index>` resource. Negative indices count back
+    /// from the current instance, as in search_logs.
+    async fn handle_read_resource(&self, id: Value, params: Option<Value>) -> JsonRpcResponse {
+        let uri = params
+            .as_ref()
+            .and_then(|p| p["uri"].as_str())
+            .unwrap_or_default()
+            .to_string();
 
-        let result = match tool_name {
-            "search_logs" => self.tool_search_logs(arguments).await,
-            "search_build_log" => self.tool_search_build_log(arguments).await,
-            "restart" => self.tool_restart(arguments).await,
-            "get_status" => self.tool_get_status().await,
-            _ => Err(anyhow::anyhow!("Unknown tool: {}", tool_name)),
+        let text = match parse_log_uri(&uri) {
+            Some((process, index)) => match self.processes.read().await.get(process) {
+                Some(process) => {
+                    let logs = process.logs.read().await;
+                    logs.get_instance(Some(index))
+                        .map(|instance| instance.lines.iter().cloned().collect::<Vec<_>>().join("\n"))
+                }
+                None => None,
+            },
+            None => None,
         };
 
-        match result {
-            Ok(content) => JsonRpcResponse {
+        match text {
+            Some(text) => JsonRpcResponse {
                 jsonrpc: "2.0".to_string(),
                 id,
                 result: Some(json!({
-                    "content": [
+                    "contents": [
                         {
-                            "type": "text",
-                            "text": content
+                            "uri": uri,
+                            "mimeType": "text/plain",
+                            "text": text
                         }
                     ]
                 })),
                 error: None,
             },
+            None => JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: None,
+                error: Some(JsonRpcError {
+                    code: -32002,
+                    message: format!("Resource not found: {}", uri),
+                    data: Some(json!({ "uri": uri })),
+                }),
+            },
+        }
+    }
+
+    /// Lists the built-in prompts: canned debugging requests with the relevant
+    /// logs and events already embedded.
+    fn handle_list_prompts(&self, id: Value) -> JsonRpcResponse {
+        JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(prompt_list()),
+            error: None,
+        }
+    }
+
+    /// Completes a `process` or `tag` argument of a prompt (`ref/prompt`) or
+    /// a tool (`ref/tool`) with the names starting with the partial value,
+    /// and the variables of the log resource template (`ref/resource`).
+    /// Other arguments have nothing to complete. The spec only defines prompt
+    /// and resource references; `ref/tool` is our own extension.
+    async fn handle_complete(&self, id: Value, params: Option<Value>) -> JsonRpcResponse {
+        let params = params.unwrap_or(Value::Null);
+        let context = &params["context"]["arguments"];
+        match self.completions(&params["ref"], &params["argument"], context).await {
+            Ok(mut values) => {
+                let total = values.len();
+                values.truncate(MAX_COMPLETION_VALUES);
+                JsonRpcResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id,
+                    result: Some(json!({
+                        "completion": {
+                            "values": values,
+                            "total": total,
+                            "hasMore": total > MAX_COMPLETION_VALUES
+                        }
+                    })),
+                    error: None,
+                }
+            }
             Err(e) => JsonRpcResponse {
                 jsonrpc: "2.0".to_string(),
                 id,
                 result: None,
                 error: Some(JsonRpcError {
-                    code: -32603,
-                    message: format!("Tool execution error: {}", e),
+                    code: -32602,
+                    message: e.to_string(),
+                    data: None,
+                }),
+            },
+        }
+    }
+
+    async fn completions(&self, reference: &Value, argument: &Value, context: &Value) -> Result<Vec<String>> {
+        let argument_name = argument["name"].as_str().context("Missing 'argument.name'")?;
+        let prefix = argument["value"].as_str().unwrap_or_default();
+        let name = || reference["name"].as_str().context("Missing 'ref.name'");
+
+        let (name, known) = match reference["type"].as_str() {
+            Some("ref/resource") => {
+                let uri = reference["uri"].as_str().context("Missing 'ref.uri'")?;
+                if uri != LOG_URI_TEMPLATE {
+                    anyhow::bail!("Unknown resource template '{}'", uri);
+                }
+                (uri, ["process", "index"].contains(&argument_name))
+            }
+            Some("ref/prompt") => {
+                let name = name()?;
+                let prompts = prompt_list();
+                let prompt = prompts["prompts"]
+                    .as_array()
+                    .and_then(|prompts| prompts.iter().find(|p| p["name"] == name))
+                    .with_context(|| format!("Unknown prompt '{}'", name))?;
+                let known = prompt["arguments"]
+                    .as_array()
+                    .is_some_and(|arguments| arguments.iter().any(|a| a["name"] == argument_name));
+                (name, known)
+            }
+            Some("ref/tool") => {
+                let name = name()?;
+                let tools = self.tool_list();
+                let tool = tools["tools"]
+                    .as_array()
+                    .and_then(|tools| tools.iter().find(|t| t["name"] == name))
+                    .with_context(|| format!("Unknown tool '{}'", name))?;
+                (name, tool["inputSchema"]["properties"].get(argument_name).is_some())
+            }
+            other => anyhow::bail!("Unsupported reference type {}", other.map_or("(none)".to_string(), |t| format!("'{}'", t))),
+        };
+        if !known {
+            anyhow::bail!("'{}' has no argument '{}'", name, argument_name);
+        }
+
+        let processes = self.processes.read().await;
+        let mut values: Vec<String> = match argument_name {
+            "process" => processes.keys().cloned().collect(),
+            "tag" => processes.values().flat_map(|p| p.config.tags.iter().cloned()).collect(),
+            // The log instances of the process already filled in
+            "index" => match context["process"].as_str().and_then(|process| processes.get(process)) {
+                Some(process) => (0..process.logs.read().await.instance_count()).map(|i| i.to_string()).collect(),
+                None => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+        values.retain(|value| value.starts_with(prefix));
+        values.sort();
+        values.dedup();
+        Ok(values)
+    }
+
+    async fn handle_get_prompt(&self, id: Value, params: Option<Value>) -> JsonRpcResponse {
+        let params = params.unwrap_or(Value::Null);
+        let name = params["name"].as_str().unwrap_or_default();
+
+        match self.render_prompt(name, &params["arguments"]).await {
+            Ok((description, text)) => JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: Some(json!({
+                    "description": description,
+                    "messages": [
+                        {
+                            "role": "user",
+                            "content": { "type": "text", "text": text }
+                        }
+                    ]
+                })),
+                error: None,
+            },
+            Err(e) => JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: None,
+                error: Some(JsonRpcError {
+                    code: -32602,
+                    message: e.to_string(),
+                    data: None,
                 }),
             },
         }
     }
 
-    async fn tool_search_logs(&self, args: &Value) -> Result<String> {
-        let process_name = args["process"]
-            .as_str()
-            .context("Missing 'process' parameter")?;
+    /// Returns the description and text of prompt `name`.
+    async fn render_prompt(&self, name: &str, args: &Value) -> Result<(String, String)> {
+        let process = match name {
+            "diagnose_crash" | "summarize_build_failure" => {
+                let process_name = args["process"]
+                    .as_str()
+                    .context("Missing 'process' argument")?;
+                let process = self
+                    .processes
+                    .read()
+                    .await
+                    .get(process_name)
+                    .context(format!("Process '{}' not found", process_name))?
+                    .clone();
+                Some(process)
+            }
+            "health_overview" => None,
+            _ => anyhow::bail!("Prompt not found: {}", name),
+        };
+
+        match (name, process) {
+            ("diagnose_crash", Some(process)) => {
+                let crash = process
+                    .events
+                    .read()
+                    .await
+                    .iter()
+                    .rev()
+                    .find(|e| matches!(e, ProcessEvent::Crashed { .. }))
+                    .map(ProcessEvent::description);
+                let mut text = format!(
+                    "The process '{}' crashed. Work out why from the information below, \
+                     and suggest a fix.\n\n",
+                    process.name
+                );
+                let index = match crash {
+                    Some(crash) => {
+                        text.push_str(&format!("Crash event: {}\n\n", crash));
+                        process.crash_instance().await.unwrap_or(-1)
+                    }
+                    None => {
+                        text.push_str("No crash has been recorded since the manager started.\n\n");
+                        -1
+                    }
+                };
+                let lines = process.log_tail(index, CRASH_CONTEXT_LINES).await;
+                text.push_str(&format!(
+                    "Last {} lines of the log instance that crashed (index {}):\n{}",
+                    lines.len(),
+                    index,
+                    fenced(&lines)
+                ));
+                Ok((format!("Diagnose the most recent crash of {}", process.name), text))
+            }
+            ("summarize_build_failure", Some(process)) => {
+                if process.config.process_type != ProcessType::Rust {
+                    anyhow::bail!("Process '{}' is not a Rust process and has no build log", process.name);
+                }
+                let last_build = process
+                    .build_control
+                    .history
+                    .read()
+                    .await
+                    .back()
+                    .map(|build| build.summary());
+                let lines: Vec<String> = {
+                    let logs = process.build_logs.read().await;
+                    match logs.get_instance(None) {
+                        Some(instance) => {
+                            let skip = instance.lines.len().saturating_sub(CRASH_CONTEXT_LINES);
+                            instance.lines.iter().skip(skip).map(|line| line.to_string()).collect()
+                        }
+                        None => Vec::new(),
+                    }
+                };
+                let mut text = format!(
+                    "The build of '{}' failed. Summarize the errors below, most important first, \
+                     and suggest a fix for each.\n\n",
+                    process.name
+                );
+                if let Some(last_build) = last_build {
+                    text.push_str(&format!("Last build: {}\n\n", last_build));
+                }
+                text.push_str(&format!("Last {} lines of the build log:\n{}", lines.len(), fenced(&lines)));
+                Ok((format!("Summarize the latest build failure of {}", process.name), text))
+            }
+            _ => {
+                let text = format!(
+                    "Review the health of the managed processes below. Point out anything \
+                     crashed, crash-looping or stuck restarting, and suggest what to look at first.\n\n{}",
+                    fenced(&self.status_summary(None).await.lines().map(String::from).collect::<Vec<_>>())
+                );
+                Ok(("Review the health of all processes".to_string(), text))
+            }
+        }
+    }
+
+    async fn handle_list_tools(&self, id: Value) -> JsonRpcResponse {
+        JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(self.tool_list()),
+            error: None,
+        }
+    }
+
+    /// The `tools/list` result: every tool with its input schema and
+    /// annotations. Also served as-is at `GET /tools`.
+    fn tool_list(&self) -> Value {
+        let limits = format!(
+            "Without pattern, head or tail, returns the last {} lines unless no_limit is set. Results are capped at {} lines",
+            self.config.default_search_tail, self.config.max_search_result_lines
+        );
+        let no_limit = json!({
+            "type": "boolean",
+            "description": format!(
+                "Don't apply the default tail of {} lines to a query without pattern, head or tail. Results are still capped at {} lines. Default: false",
+                self.config.default_search_tail, self.config.max_search_result_lines
+            )
+        });
+        let output = json!({
+            "type": "string",
+            "enum": ["text", "json"],
+            "description": "\"text\" joins the lines, or \"json\" returns {\"lines\": [{line, matched, stream, timestamp}]} with any header, notes or error alongside. stream is \"stdout\" or \"stderr\" and timestamp is when the line was captured, each null where not known. Default: text"
+        });
+        let mut tools = json!({
+            "tools": [
+                {
+                    "name": "search_logs",
+                    "description": format!("Search process logs with optional regex pattern (or several, with patterns), context lines, and head/tail limiting, or count the distinct values of a named capture group with capture. Execution order: pattern matching → context expansion → head/tail limiting. {}", limits),
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "process": {
+                                "type": "string",
+                                "description": "Process name"
+                            },
+                            "pattern": {
+                                "type": "string",
+                                "description": "Optional regex pattern (Rust regex syntax, case-sensitive). Examples: 'ERROR|WARN' (OR), 'started.*server' (wildcards), '\\\\d{3}' (digits). Matched lines prefixed with ' * ', context lines with '   '"
+                            },
+                            "patterns": {
+                                "type": "array",
+                                "items": { "type": "string" },
+                                "description": "Several regex patterns instead of pattern; lines matching any of them are returned. Matched lines are prefixed with the 1-based numbers of the patterns they matched, e.g. '*1,3 '; in JSON output each line has the 0-based indices in 'patterns'"
+                            },
+                            "capture": {
+                                "type": "string",
+                                "description": "Name of a capture group in the (single) pattern. Instead of lines, returns each distinct captured value with the number of lines it was captured from, most frequent first, e.g. pattern 'status=(?P<code>\\\\d+)' with capture 'code'. Context, head and tail don't apply"
+                            },
+                            "context_lines": {
+                                "type": "number",
+                                "description": "Number of lines to show before and after each match. Only applies when pattern is provided"
+                            },
+                            "head": {
+                                "type": "number",
+                                "description": "Return only first N lines (applied after pattern/context). Mutually exclusive with tail"
+                            },
+                            "tail": {
+                                "type": "number",
+                                "description": "Return only last N lines (applied after pattern/context). Takes precedence over head if both specified"
+                            },
+                            "index": {
+                                "type": "number",
+                                "description": "Log instance index. Negative = recent (-1 most recent, -2 second-to-last), positive = absolute (0 first, 1 second). Default: -1"
+                            },
+                            "generation": {
+                                "type": "number",
+                                "description": "Search the instance of this generation instead of index, as exported to the process in BPM_GENERATION and shown in its started events"
+                            },
+                            "crash_context": {
+                                "type": "boolean",
+                                "description": "Search the instance that most recently crashed instead of index, defaulting to its last 50 lines. The output starts with a header saying which instance is shown. Default: false"
+                            },
+                            "since_seq": {
+                                "type": "number",
+                                "description": "Only search lines with a sequence number above this one, to fetch what was logged since the last call: pass the latest_seq it returned. Without pattern, head or tail, returns the first lines after it rather than the last"
+                            },
+                            "since": {
+                                "type": "string",
+                                "description": "Only search lines timestamped at or after this RFC 3339 time, e.g. '2024-05-01T12:00:00Z'. Lines are timestamped when captured, or with the time they carry if the process sets log_timestamp"
+                            },
+                            "until": {
+                                "type": "string",
+                                "description": "Only search lines timestamped at or before this RFC 3339 time"
+                            },
+                            "no_limit": no_limit,
+                            "output": output
+                        },
+                        "required": ["process"]
+                    }
+                },
+                {
+                    "name": "search_build_log",
+                    "description": format!("Search build logs with optional regex pattern, context lines, and head/tail limiting. Execution order: pattern matching → context expansion → head/tail limiting. {}", limits),
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "process": {
+                                "type": "string",
+                                "description": "Process name"
+                            },
+                            "pattern": {
+                                "type": "string",
+                                "description": "Optional regex pattern (Rust regex syntax, case-sensitive). Examples: 'ERROR|WARN' (OR), 'started.*server' (wildcards), '\\\\d{3}' (digits). Matched lines prefixed with ' * ', context lines with '   '"
+                            },
+                            "context_lines": {
+                                "type": "number",
+                                "description": "Number of lines to show before and after each match. Only applies when pattern is provided"
+                            },
+                            "head": {
+                                "type": "number",
+                                "description": "Return only first N lines (applied after pattern/context). Mutually exclusive with tail"
+                            },
+                            "tail": {
+                                "type": "number",
+                                "description": "Return only last N lines (applied after pattern/context). Takes precedence over head if both specified"
+                            },
+                            "index": {
+                                "type": "number",
+                                "description": "Log instance index. Negative = recent (-1 most recent, -2 second-to-last), positive = absolute (0 first, 1 second). Default: -1"
+                            },
+                            "warnings_only": {
+                                "type": "boolean",
+                                "description": "Only search the compiler warnings, each with the lines that belong to it. Default: false"
+                            },
+                            "no_limit": no_limit,
+                            "output": output
+                        },
+                        "required": ["process"]
+                    }
+                },
+                {
+                    "name": "search_manager_logs",
+                    "description": format!("Search the process manager's own tracing output with optional regex pattern, context lines, and head/tail limiting. Useful when builds hang or the manager misbehaves. Execution order: pattern matching → context expansion → head/tail limiting. {}", limits),
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "pattern": {
+                                "type": "string",
+                                "description": "Optional regex pattern (Rust regex syntax, case-sensitive). Examples: 'ERROR|WARN' (OR), 'started.*server' (wildcards), '\\\\d{3}' (digits). Matched lines prefixed with ' * ', context lines with '   '"
+                            },
+                            "context_lines": {
+                                "type": "number",
+                                "description": "Number of lines to show before and after each match. Only applies when pattern is provided"
+                            },
+                            "head": {
+                                "type": "number",
+                                "description": "Return only first N lines (applied after pattern/context). Mutually exclusive with tail"
+                            },
+                            "tail": {
+                                "type": "number",
+                                "description": "Return only last N lines (applied after pattern/context). Takes precedence over head if both specified"
+                            },
+                            "no_limit": no_limit,
+                            "output": output
+                        }
+                    }
+                },
+                {
+                    "name": "restart",
+                    "description": "Restart a process (builds first for Rust projects, then restarts), all processes with a tag, or all processes. Switches back to dev mode.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "process": {
+                                "type": "string",
+                                "description": "Process name. Required unless tag or all is given"
+                            },
+                            "tag": {
+                                "type": "string",
+                                "description": "Restart all processes with this tag instead, in dependency order. Stopped processes are skipped"
+                            },
+                            "all": {
+                                "type": "boolean",
+                                "description": "Restart every process instead, in dependency order. Stopped processes are skipped"
+                            },
+                            "restart_dependents": {
+                                "type": "boolean",
+                                "description": "Also restart processes that transitively depend on this one (via depends_on), in dependency order. Stopped processes are skipped. Defaults to the process's restart_dependents config"
+                            }
+                        }
+                    }
+                },
+                {
+                    "name": "stop",
+                    "description": "Stop a process, all processes with a tag, or all processes, on purpose: they aren't restarted as crashed. A restart starts a process again. Waits for a restart of the process in progress to finish first",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "process": {
+                                "type": "string",
+                                "description": "Process name. Required unless tag or all is given"
+                            },
+                            "tag": {
+                                "type": "string",
+                                "description": "Stop all processes with this tag instead, dependents first"
+                            },
+                            "all": {
+                                "type": "boolean",
+                                "description": "Stop every process instead, dependents first"
+                            }
+                        }
+                    }
+                },
+                {
+                    "name": "cancel",
+                    "description": "Cancel an in-progress build for a process, and builds of it waiting for that one. Aborts the pending restarts and leaves the old process running.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "process": {
+                                "type": "string",
+                                "description": "Process name"
+                            }
+                        },
+                        "required": ["process"]
+                    }
+                },
+                {
+                    "name": "clean_target",
+                    "description": "Run cargo clean for a Rust process to reclaim the disk space of its target directory. The output goes to the build log. The running process is left alone, but the next restart is a full rebuild",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "process": {
+                                "type": "string",
+                                "description": "Process name"
+                            }
+                        },
+                        "required": ["process"]
+                    }
+                },
+                {
+                    "name": "build",
+                    "description": "Build a Rust process, or run an NPM process's build_command, without restarting it. Returns success/failure and the last 30 lines of build output; the full output is available via search_build_log. Never stops or starts anything",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "process": {
+                                "type": "string",
+                                "description": "Process name"
+                            },
+                            "profile": {
+                                "type": "string",
+                                "enum": ["dev", "release"],
+                                "description": "Build profile. Default: the process's always_release/always_dev, else the current mode"
+                            }
+                        },
+                        "required": ["process"]
+                    }
+                },
+                {
+                    "name": "keepalive",
+                    "description": "Reset the dev mode inactivity timer without doing anything else, optionally holding dev mode for a number of hours regardless of activity. Returns the new time until release mode. Does not switch modes",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "hours": {
+                                "type": "integer",
+                                "description": "Stay in dev mode for at least this many hours from now, even if dev_timeout_hours is shorter; at most 8760"
+                            }
+                        }
+                    }
+                },
+                {
+                    "name": "set_mode_config",
+                    "description": "Change dev_timeout_hours, the inactivity time before switching to release mode, until the manager restarts. Counts from the last tool call, as before. Returns the new mode info as from get_mode",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "dev_timeout_hours": {
+                                "type": "integer",
+                                "description": "Hours without tool calls before switching to release mode; from 1 to 8760"
+                            }
+                        },
+                        "required": ["dev_timeout_hours"]
+                    }
+                },
+                {
+                    "name": "recheck_tools",
+                    "description": "Re-run the preflight checks for the tools each process needs (cargo, node/npm, and required_tools), e.g. after installing a missing one",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {}
+                    }
+                },
+                {
+                    "name": "get_manager_info",
+                    "description": "Get information about the process manager itself as JSON: its version, PID, start time and uptime, project directory, number of processes, the port the MCP server listens on and current rate limiter usage. Also available as get_server_info",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {}
+                    }
+                },
+                {
+                    "name": "get_events",
+                    "description": "Get a process's retained event history (starts, crashes and failed rebuilds) as JSON, newest first, with paging",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "process": {
+                                "type": "string",
+                                "description": "Process name"
+                            },
+                            "limit": {
+                                "type": "number",
+                                "description": "Maximum number of events to return. Default: 20"
+                            },
+                            "offset": {
+                                "type": "number",
+                                "description": "Number of most recent events to skip. Default: 0"
+                            }
+                        },
+                        "required": ["process"]
+                    }
+                },
+                {
+                    "name": "get_crash_report",
+                    "description": "Get the report saved when a process crashed, as JSON: exit code, signal and whether it dumped core, resident memory at the last sample, uptime, version, and the last 200 lines of the instance that crashed. The last 10 crashes are kept",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "process": {
+                                "type": "string",
+                                "description": "Process name"
+                            },
+                            "index": {
+                                "type": "number",
+                                "description": "Which crash. Negative = recent (-1 most recent, -2 the one before), positive = absolute (0 oldest kept). Default: -1"
+                            }
+                        },
+                        "required": ["process"]
+                    }
+                },
+                {
+                    "name": "get_build_history",
+                    "description": "Get a process's recent builds as JSON, newest first: profile, start/end time, duration, outcome, and warning/error counts",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "process": {
+                                "type": "string",
+                                "description": "Process name"
+                            },
+                            "limit": {
+                                "type": "number",
+                                "description": "Maximum number of builds to return. Default: 20"
+                            }
+                        },
+                        "required": ["process"]
+                    }
+                },
+                {
+                    "name": "get_recent_activity",
+                    "description": "Get what the manager has been doing as JSON, newest first: starts, crashes, failed rebuilds and error rate alerts of all processes, finished builds, and mode changes, merged into one feed",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "limit": {
+                                "type": "number",
+                                "description": "Maximum number of entries to return. Default: 50"
+                            }
+                        }
+                    }
+                },
+                {
+                    "name": "get_mode",
+                    "description": "Get the current mode as JSON: {mode, time_until_release_seconds, locked, last_activity, dev_timeout_hours, extended_until}. time_until_release_seconds is exact and null in release mode; locked is true when the mode won't switch automatically; extended_until is set while keepalive with hours holds dev mode",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {}
+                    }
+                },
+                {
+                    "name": "get_status",
+                    "description": "Get status of all processes including mode, uptime, state, and recent events. Use summary for one compact line per process, or process for the full status of just one",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "summary": {
+                                "type": "boolean",
+                                "description": "Return a mode line and one compact line per process, like 'api: running 2h14m | worker: crashed x3, backing off 45s'. Default: false"
+                            },
+                            "process": {
+                                "type": "string",
+                                "description": "Only include this process"
+                            },
+                            "tag": {
+                                "type": "string",
+                                "description": "Only include processes with this tag"
+                            }
+                        }
+                    }
+                },
+                {
+                    "name": "get_process",
+                    "description": "Get everything about one process as JSON in one call: state, uptime_seconds, started_at, crash_count, restart_count, type, last_build, build_in_progress, last_exit (exit code and signal of the last crash), startup_error and recent_events",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "process": {
+                                "type": "string",
+                                "description": "Process name"
+                            },
+                            "events": {
+                                "type": "number",
+                                "description": "Number of recent events to include, newest first. Default: 10"
+                            }
+                        },
+                        "required": ["process"]
+                    }
+                },
+                {
+                    "name": "list_processes",
+                    "description": "List processes as JSON, optionally only those in one state or with one tag, e.g. to find which are crashed. Each entry has name, state, uptime_seconds, crash_count and type; the result also gives the total and the filters applied",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "state": {
+                                "type": "string",
+                                "enum": ["not_started", "building", "starting", "running", "stopping", "stopped", "crashed", "failed"],
+                                "description": "Only list processes in this state"
+                            },
+                            "tag": {
+                                "type": "string",
+                                "description": "Only list processes with this tag"
+                            }
+                        }
+                    }
+                },
+                {
+                    "name": "wait_logs",
+                    "description": "Wait until a process logs new lines, then return them as JSON with the latest_seq to pass to the next call, so a client can follow the log without polling search_logs. Returns timed_out: true and no lines if nothing was logged before the timeout",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": {
+                            "process": {
+                                "type": "string",
+                                "description": "Process name"
+                            },
+                            "since_seq": {
+                                "type": "number",
+                                "description": "Only return lines with a sequence number above this one: pass the latest_seq returned by the previous call, or by search_logs. Default: the end of the log, so only lines logged after the call are returned"
+                            },
+                            "timeout_seconds": {
+                                "type": "number",
+                                "description": "How long to wait for a new line. Default: 30, max: 120"
+                            }
+                        },
+                        "required": ["process"]
+                    }
+                }
+            ]
+        });
+
+        if let Some(list) = tools["tools"].as_array_mut() {
+            for tool in list {
+                let name = tool["name"].as_str().unwrap_or_default().to_string();
+                tool["annotations"] = tool_annotations(&name);
+            }
+        }
+
+        tools
+    }
+
+    async fn handle_tool_call(&self, id: Value, params: Option<Value>) -> JsonRpcResponse {
+        let params = match params {
+            Some(p) => p,
+            None => {
+                return JsonRpcResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id,
+                    result: None,
+                    error: Some(JsonRpcError {
+                        code: -32602,
+                        message: "Missing params".to_string(),
+                        data: None,
+                    }),
+                }
+            }
+        };
+
+        let tool_name = params["name"].as_str().unwrap_or("");
+        let arguments = &params["arguments"];
+
+        if let Some(ref limiter) = self.rate_limiter {
+            if let Err(retry_after) = limiter.check(tool_name) {
+                let seconds = retry_after.as_secs() + 1;
+                warn!("Rate limited call to {}, retry after {}s", tool_name, seconds);
+                return JsonRpcResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id,
+                    result: None,
+                    error: Some(JsonRpcError {
+                        code: -32000,
+                        message: format!("rate limited, retry after {}s", seconds),
+                        data: Some(json!({ "retry_after_seconds": seconds })),
+                    }),
+                };
+            }
+        }
+
+        // Registered until the tool finishes, so the call can be cancelled
+        let cancel = CancellationToken::new();
+        let _in_flight = (!id.is_null()).then(|| {
+            let key = id.to_string();
+            self.in_flight.lock().unwrap().insert(key.clone(), cancel.clone());
+            InFlightCall {
+                in_flight: &self.in_flight,
+                key,
+            }
+        });
+
+        let progress = match params["_meta"]["progressToken"] {
+            Value::Null => None,
+            ref token => Some(Progress {
+                token: token.clone(),
+                notifications: self.notifications.clone(),
+                step: AtomicU64::new(0),
+            }),
+        };
+        let progress = progress.as_ref();
+
+        if !self.ready.load(Ordering::SeqCst) && TOOLS_AFTER_STARTUP.contains(&tool_name) {
+            return JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: None,
+                error: Some(JsonRpcError {
+                    code: -32603,
+                    message: format!(
+                        "Tool execution error: Processes are still being started; call {} once startup is complete",
+                        tool_name
+                    ),
+                    data: None,
+                }),
+            };
+        }
+
+        // Held until the tool finishes. A call still waiting for a slot can
+        // be cancelled too.
+        let _permit = match self.call_permits {
+            Some(ref permits) if !TOOLS_WITHOUT_PERMIT.contains(&tool_name) => tokio::select! {
+                biased;
+                _ = cancel.cancelled() => {
+                    return JsonRpcResponse {
+                        jsonrpc: "2.0".to_string(),
+                        id,
+                        result: None,
+                        error: Some(JsonRpcError {
+                            code: -32603,
+                            message: format!("Tool execution error: {} cancelled while waiting for a free call slot", tool_name),
+                            data: None,
+                        }),
+                    };
+                }
+                permit = permits.acquire() => Some(permit),
+            },
+            _ => None,
+        };
+
+        let result = match tool_name {
+            "search_logs" => self.tool_search_logs(arguments).await,
+            "search_build_log" => self.tool_search_build_log(arguments).await,
+            // Singular form accepted as an alias, since it's an easy slip
+            "search_manager_logs" | "search_manager_log" => self.tool_search_manager_logs(arguments).await,
+            "restart" => self.tool_restart(arguments, &cancel, progress).await,
+            "get_status" => self.tool_get_status(arguments).await,
+            "stop" => self.tool_stop(arguments).await,
+            "cancel" => self.tool_cancel(arguments).await,
+            "get_mode" => self.tool_get_mode().await,
+            "get_events" => self.tool_get_events(arguments).await,
+            "get_crash_report" => self.tool_get_crash_report(arguments).await,
+            "list_processes" => self.tool_list_processes(arguments).await,
+            "get_process" => self.tool_get_process(arguments).await,
+            "wait_logs" => self.tool_wait_logs(arguments, &cancel).await,
+            "get_build_history" => self.tool_get_build_history(arguments).await,
+            "get_recent_activity" => self.tool_get_recent_activity(arguments).await,
+            "build" => self.tool_build(arguments, &cancel, progress).await,
+            "clean_target" => self.tool_clean_target(arguments).await,
+            "keepalive" => self.tool_keepalive(arguments).await,
+            "set_mode_config" => self.tool_set_mode_config(arguments).await,
+            "get_manager_info" | "get_server_info" => self.tool_get_manager_info().await,
+            "recheck_tools" => self.tool_recheck_tools().await,
+            _ => Err(anyhow::anyhow!("Unknown tool: {}", tool_name)),
+        };
+
+        match result {
+            Ok(content) => JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: Some(json!({
+                    "content": [
+                        {
+                            "type": "text",
+                            "text": content
+                        }
+                    ]
+                })),
+                error: None,
+            },
+            Err(e) => JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: None,
+                error: Some(JsonRpcError {
+                    code: -32603,
+                    message: format!("Tool execution error: {}", e),
+                    data: None,
+                }),
+            },
+        }
+    }
+
+    async fn tool_search_logs(&self, args: &Value) -> Result<String> {
+        let process_name = args["process"]
+            .as_str()
+            .context("Missing 'process' parameter")?;
+
+        let patterns = search_patterns(args)?;
+        let context_lines = args["context_lines"].as_u64().map(|n| n as usize);
+        let head = args["head"].as_u64().map(|n| n as usize);
+        let tail = args["tail"].as_u64().map(|n| n as usize);
+        let index = args["index"].as_i64().map(|n| n as i32);
+        let crash_context = args["crash_context"].as_bool().unwrap_or(false);
+        let output = SearchOutput::from_args(args)?;
+        let generation = args["generation"].as_u64();
+        if generation.is_some() && (index.is_some() || crash_context) {
+            anyhow::bail!("'generation' can't be combined with 'index' or 'crash_context'");
+        }
+        let since_seq = args["since_seq"].as_u64();
+        let (since_time, until_time) = (time_arg(args, "since")?, time_arg(args, "until")?);
+        let narrow = |snapshot: Result<LogInstance, String>| {
+            let snapshot = match since_seq {
+                Some(seq) => snapshot.map(|instance| instance.after_seq(seq)),
+                None => snapshot,
+            };
+            match (since_time, until_time) {
+                (None, None) => snapshot,
+                _ => snapshot.map(|instance| instance.between(since_time, until_time)),
+            }
+        };
+
+        let processes = self.processes.read().await;
+        let process = processes
+            .get(process_name)
+            .context(format!("Process '{}' not found", process_name))?;
+
+        let index = match generation {
+            Some(generation) => match process.logs.read().await.generation_index(generation) {
+                Ok(index) => Some(index),
+                Err(message) => return Ok(SearchResults::error(message).render(output)),
+            },
+            None => index,
+        };
+
+        if let Some(capture) = args["capture"].as_str() {
+            let [pattern] = patterns[..] else {
+                anyhow::bail!("'capture' needs exactly one pattern, with a group named '{}'", capture);
+            };
+            let index = if crash_context { process.crash_instance().await } else { index };
+            let snapshot = narrow(process.logs.read().await.snapshot(index));
+            return Ok(self.capture_snapshot(snapshot, pattern, capture).await.render(output));
+        }
+
+        if crash_context {
+            let tail = if head.is_none() && tail.is_none() && !args["no_limit"].as_bool().unwrap_or(false) {
+                Some(CRASH_CONTEXT_LINES)
+            } else {
+                tail
+            };
+            let (index, header) = match process.crash_instance().await {
+                Some(-1) => (-1, "Showing the crashed instance (index -1); the process has not been respawned yet"),
+                Some(index) => (index, "Showing the instance that crashed (index -2); the current instance is the respawned process"),
+                None => (-1, "No recent crash; showing the current instance (index -1)"),
+            };
+            let (snapshot, summary) = {
+                let logs = process.logs.read().await;
+                (narrow(logs.snapshot(Some(index))), earlier_instance_summary(&logs, Some(index)))
+            };
+            let mut results = self.search_snapshot(snapshot, &patterns, context_lines, head, tail).await;
+            results.header = Some(match summary {
+                Some(summary) => format!("{}. {}", header, summary),
+                None => header.to_string(),
+            });
+            return Ok(results.render(output));
+        }
+
+        let tail = self.effective_tail(args, head, tail);
+        // Following the log, the lines right after since_seq have to come
+        // first, so the next call continues without a gap
+        let (head, tail) = match since_seq {
+            Some(_) if args["tail"].is_null() => (head.or(tail), None),
+            _ => (head, tail),
+        };
+        let (snapshot, summary) = {
+            let logs = process.logs.read().await;
+            (narrow(logs.snapshot(index)), earlier_instance_summary(&logs, index))
+        };
+        let mut results = self.search_snapshot(snapshot, &patterns, context_lines, head, tail).await;
+        if results.error.is_none() {
+            results.header = summary;
+        }
+        results.latest_seq = results.latest_seq.or(since_seq);
+        if let (Some(_), Some(seq)) = (since_seq, results.latest_seq) {
+            results.notes.push(format!("latest_seq: {}", seq));
+        }
+        Ok(results.render(output))
+    }
+
+    async fn tool_search_build_log(&self, args: &Value) -> Result<String> {
+        let process_name = args["process"]
+            .as_str()
+            .context("Missing 'process' parameter")?;
+
+        let pattern = args["pattern"].as_str();
+        let context_lines = args["context_lines"].as_u64().map(|n| n as usize);
+        let head = args["head"].as_u64().map(|n| n as usize);
+        let tail = args["tail"].as_u64().map(|n| n as usize);
+        let index = args["index"].as_i64().map(|n| n as i32);
+        let output = SearchOutput::from_args(args)?;
+
+        let tail = self.effective_tail(args, head, tail);
+
+        let processes = self.processes.read().await;
+        let process = processes
+            .get(process_name)
+            .context(format!("Process '{}' not found", process_name))?;
+
+        if args["warnings_only"].as_bool().unwrap_or(false) {
+            let logs = process.build_logs.read().await;
+            let Some(instance) = logs.get_instance(index) else {
+                let message = format!("Log instance {} not found", index.unwrap_or(-1));
+                return Ok(SearchResults::error(message).render(output));
+            };
+            let lines: VecDeque<String> = warning_blocks(&instance.lines).into();
+            let warnings = LogInstance::from_lines(lines);
+            if warnings.lines.is_empty() && output == SearchOutput::Text {
+                return Ok("No warnings".to_string());
+            }
+            let summary = logs.instance_summary(index);
+            drop(logs);
+            let mut results = self.search_snapshot(Ok(warnings), pattern.as_slice(), context_lines, head, tail).await;
+            if results.error.is_none() {
+                results.header = summary;
+            }
+            return Ok(results.render(output));
+        }
+
+        // A build log is never the live process, so it always says which
+        // build it is
+        let (snapshot, summary) = {
+            let logs = process.build_logs.read().await;
+            (logs.snapshot(index), logs.instance_summary(index))
+        };
+        let mut results = self.search_snapshot(snapshot, pattern.as_slice(), context_lines, head, tail).await;
+        if results.error.is_none() {
+            results.header = summary;
+        }
+        Ok(results.render(output))
+    }
+
+    async fn tool_search_manager_logs(&self, args: &Value) -> Result<String> {
+        let pattern = args["pattern"].as_str();
+        let context_lines = args["context_lines"].as_u64().map(|n| n as usize);
+        let head = args["head"].as_u64().map(|n| n as usize);
+        let tail = args["tail"].as_u64().map(|n| n as usize);
+        let tail = self.effective_tail(args, head, tail);
+        let output = SearchOutput::from_args(args)?;
+
+        let snapshot = self.manager_log.logs.read().await.snapshot(None);
+        let mut results = self.search_snapshot(snapshot, pattern.as_slice(), context_lines, head, tail).await;
+        let dropped = self.manager_log.dropped();
+        if dropped > 0 {
+            results.notes.push(format!("{} lines dropped because the log channel was full", dropped));
+        }
+
+        Ok(results.render(output))
+    }
+
+    /// The tail to search with: the configured default for a query without
+    /// pattern, head or tail, unless `no_limit` is set.
+    fn effective_tail(&self, args: &Value, head: Option<usize>, tail: Option<usize>) -> Option<usize> {
+        let unfiltered = args["pattern"].as_str().is_none() && args["patterns"].is_null() && head.is_none() && tail.is_none();
+        if unfiltered && !args["no_limit"].as_bool().unwrap_or(false) {
+            Some(self.config.default_search_tail)
+        } else {
+            tail
+        }
+    }
+
+    /// Searches a copy of a log instance on a blocking thread, so a slow
+    /// pattern holds up neither the log's writers nor the runtime. Matching
+    /// stops after `search_timeout_ms`, and results are cut off at
+    /// `max_search_result_lines`, each with a note saying so.
+    async fn search_snapshot(
+        &self,
+        snapshot: Result<LogInstance, String>,
+        patterns: &[&str],
+        context_lines: Option<usize>,
+        head: Option<usize>,
+        tail: Option<usize>,
+    ) -> SearchResults {
+        let instance = match snapshot {
+            Ok(instance) => instance,
+            Err(message) => return SearchResults::error(message),
+        };
+        let total = instance.lines.len();
+        let owned_patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(self.config.search_timeout_ms);
+
+        let found = tokio::task::spawn_blocking(move || {
+            let patterns: Vec<&str> = owned_patterns.iter().map(String::as_str).collect();
+            let found = instance.select_within(&patterns, context_lines, head, tail, Some(deadline));
+            (found, instance)
+        })
+        .await;
+        let (lines, timed_out, instance) = match found {
+            Ok((Ok((lines, timed_out)), instance)) => (lines, timed_out, instance),
+            Ok((Err(message), _)) => return SearchResults::error(message),
+            Err(e) => return SearchResults::error(format!("Search failed: {}", e)),
+        };
+
+        let mut results = SearchResults {
+            lines,
+            patterns: patterns.len(),
+            error: None,
+            header: None,
+            notes: Vec::new(),
+            latest_seq: None,
+        };
+        let truncated = self.truncate_results(&mut results, tail);
+        // Up to where the results are complete: the last line returned if
+        // they were cut off, the last line scanned if the search timed out
+        let limited = truncated || head.is_some_and(|n| results.lines.len() >= n);
+        results.latest_seq = if limited {
+            results.lines.last().and_then(|line| line.seq)
+        } else if let Some(scanned) = timed_out {
+            scanned.checked_sub(1).and_then(|i| instance.seqs.get(i).copied())
+        } else {
+            instance.last_seq()
+        };
+        if let Some(scanned) = timed_out {
+            results.notes.push(format!(
+                "Search timed out after scanning {} of {} lines (search_timeout_ms); results are partial. Try a simpler pattern",
+                scanned, total
+            ));
+        }
+        results
+    }
+
+    /// Counts the distinct values of the `capture` group in lines matching
+    /// `pattern`, on a blocking thread and within `search_timeout_ms` like
+    /// `search_snapshot`.
+    async fn capture_snapshot(
+        &self,
+        snapshot: Result<LogInstance, String>,
+        pattern: &str,
+        capture: &str,
+    ) -> CaptureResults {
+        let instance = match snapshot {
+            Ok(instance) => instance,
+            Err(message) => return CaptureResults::error(capture, message),
+        };
+        let total = instance.lines.len();
+        let (owned_pattern, owned_capture) = (pattern.to_string(), capture.to_string());
+        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(self.config.search_timeout_ms);
+
+        let found = tokio::task::spawn_blocking(move || {
+            instance.count_captures_within(&owned_pattern, &owned_capture, Some(deadline))
+        })
+        .await;
+        let (mut values, timed_out) = match found {
+            Ok(Ok(found)) => found,
+            Ok(Err(message)) => return CaptureResults::error(capture, message),
+            Err(e) => return CaptureResults::error(capture, format!("Search failed: {}", e)),
+        };
+
+        let mut notes = Vec::new();
+        let max = self.config.max_search_result_lines;
+        if values.len() > max {
+            notes.push(format!(
+                "Truncated: showing the {} most frequent of {} values (max_search_result_lines)",
+                max,
+                values.len()
+            ));
+            values.truncate(max);
+        }
+        if let Some(scanned) = timed_out {
+            notes.push(format!(
+                "Search timed out after scanning {} of {} lines (search_timeout_ms); counts are partial. Try a simpler pattern",
+                scanned, total
+            ));
+        }
+        CaptureResults {
+            capture: capture.to_string(),
+            values,
+            error: None,
+            notes,
+        }
+    }
+
+    /// Cuts results off at `max_search_result_lines`, with a note saying how
+    /// to narrow the query. Tail queries keep the last lines, others the first.
+    fn truncate_results(&self, results: &mut SearchResults, tail: Option<usize>) -> bool {
+        let max = self.config.max_search_result_lines;
+        let total = results.lines.len();
+        if total <= max {
+            return false;
+        }
+
+        let kept = if tail.is_some() {
+            results.lines.drain(..total - max);
+            "last"
+        } else {
+            results.lines.truncate(max);
+            "first"
+        };
+        results.notes.push(format!(
+            "Truncated: showing the {} {} of {} lines (max_search_result_lines). Narrow the query with pattern, head, tail or index",
+            kept, max, total
+        ));
+        true
+    }
+
+    async fn tool_restart(&self, args: &Value, cancel: &CancellationToken, progress: Option<&Progress>) -> Result<String> {
+        let all = args["all"].as_bool().unwrap_or(false);
+        if [args["process"].is_string(), args["tag"].is_string(), all].iter().filter(|&&given| given).count() > 1 {
+            anyhow::bail!("Pass only one of 'process', 'tag' and 'all'");
+        }
+        if let Some(tag) = args["tag"].as_str() {
+            return self.restart_tagged(tag, cancel, progress).await;
+        }
+        if all {
+            let mode = if self.mode_manager.mode_system() { RunMode::Dev } else { self.mode_manager.get_mode().await };
+            let mut result = format!("Restarting all processes in {} mode:", mode.as_str());
+            self.restart_each(self.config.in_dependency_order(), &mut result, cancel, progress).await;
+            return Ok(result);
+        }
+        let process_name = args["process"]
+            .as_str()
+            .context("Missing 'process', 'tag' or 'all' parameter")?;
+
+        let processes = self.processes.read().await;
+        let process = processes
+            .get(process_name)
+            .context(format!("Process '{}' not found", process_name))?
+            .clone();
+        drop(processes);
+
+        let restart_dependents = args["restart_dependents"]
+            .as_bool()
+            .unwrap_or(process.config.restart_dependents);
+        if let Some(mode) = self.mode_excluding(&process).await {
+            anyhow::bail!("Process '{}' doesn't run in {} mode", process_name, mode.as_str());
+        }
+
+        self.restart_process(&process, cancel, progress).await?;
+
+        let mode = self.mode_manager.get_mode().await;
+        let mut result = format!("Process '{}' restarted successfully in {} mode", process_name, mode.as_str());
+        if process.config.process_type == ProcessType::Rust {
+            let history = process.build_control.history.read().await;
+            if let Some(build) = history.back().filter(|b| b.outcome == BuildOutcome::Succeeded) {
+                if build.warnings > 0 {
+                    result.push_str(&format!("; build produced {} warnings", build.warnings));
+                }
+            }
+        }
+        let last_lines = process.log_tail(-2, CRASH_TAIL_LINES).await;
+        if !last_lines.is_empty() {
+            result.push_str(&format!("\nLast {} lines of the previous instance:", last_lines.len()));
+            for line in &last_lines {
+                result.push_str(&format!("\n  {}", line));
+            }
+        }
+        if !restart_dependents {
+            return Ok(result);
+        }
+
+        let dependents = self.config.dependents_of(process_name);
+        if dependents.is_empty() {
+            result.push_str("\nNo dependents to restart");
+            return Ok(result);
+        }
+
+        result.push_str("\nCascade:");
+        self.restart_each(dependents, &mut result, cancel, progress).await;
+        Ok(result)
+    }
+
+    /// Restarts every process tagged `tag`, in dependency order.
+    async fn restart_tagged(&self, tag: &str, cancel: &CancellationToken, progress: Option<&Progress>) -> Result<String> {
+        let names = self.config.tagged(tag);
+        if names.is_empty() {
+            anyhow::bail!("No process has tag '{}'", tag);
+        }
+
+        let mode = if self.mode_manager.mode_system() { RunMode::Dev } else { self.mode_manager.get_mode().await };
+        let mut result = format!("Restarting processes tagged '{}' in {} mode:", tag, mode.as_str());
+        self.restart_each(names, &mut result, cancel, progress).await;
+        Ok(result)
+    }
+
+    /// Restarts `names` through the restart queue, adding a line per
+    /// process to `result` in the order given. A process waits for those of
+    /// its dependencies in `names` to finish restarting first. Processes that
+    /// were stopped on purpose are left alone, as is everything still waiting
+    /// when the call is cancelled.
+    async fn restart_each(
+        &self,
+        names: Vec<String>,
+        result: &mut String,
+        cancel: &CancellationToken,
+        progress: Option<&Progress>,
+    ) {
+        // Per process: whether it is done, however it went
+        let finished: HashMap<_, _> = names
+            .iter()
+            .map(|name| (name.as_str(), tokio::sync::watch::channel(false)))
+            .collect();
+        let restarts = names.iter().map(|name| {
+            let done = &finished[name.as_str()].0;
+            let dependencies: Vec<_> = self
+                .config
+                .process
+                .get(name)
+                .into_iter()
+                .flat_map(|config| &config.depends_on)
+                .filter_map(|dependency| finished.get(dependency.as_str()).map(|(_, done)| done.clone()))
+                .collect();
+            async move {
+                for mut dependency in dependencies {
+                    let _ = dependency.wait_for(|done| *done).await;
+                }
+                let outcome = self.restart_queued(name, cancel, progress).await;
+                done.send_replace(true);
+                outcome
+            }
+        });
+        let outcomes = futures::future::join_all(restarts).await;
+
+        for (name, outcome) in names.iter().zip(outcomes) {
+            if let Some(outcome) = outcome {
+                result.push_str(&format!("\n  - {}: {}", name, outcome));
+            }
+        }
+    }
+
+    /// Restarts `name` once the restart queue lets it, returning how it went,
+    /// or `None` if there is no such process.
+    async fn restart_queued(&self, name: &str, cancel: &CancellationToken, progress: Option<&Progress>) -> Option<String> {
+        if cancel.is_cancelled() {
+            return Some("skipped (cancelled)".to_string());
+        }
+
+        let process = self.processes.read().await.get(name).cloned()?;
+        if let Some(mode) = self.mode_excluding(&process).await {
+            return Some(format!("skipped (doesn't run in {} mode)", mode.as_str()));
+        }
+        if matches!(process.state().await, ProcessState::Stopped | ProcessState::NotStarted) {
+            return Some("skipped (stopped)".to_string());
+        }
+
+        let _permit = tokio::select! {
+            permit = self.restart_queue.acquire(name) => permit,
+            _ = cancel.cancelled() => return Some("skipped (cancelled)".to_string()),
+        };
+        Some(match self.restart_process(&process, cancel, progress).await {
+            Ok(()) => "restarted".to_string(),
+            Err(e) => format!("failed: {}", e),
+        })
+    }
+
+    /// The mode a restart would run `process` in, if the process doesn't run
+    /// in it. A restart switches to dev mode when the mode system is on.
+    async fn mode_excluding(&self, process: &ProcessManager) -> Option<RunMode> {
+        let mode = if self.mode_manager.mode_system() { RunMode::Dev } else { self.mode_manager.get_mode().await };
+        (!process.config.runs_in(mode)).then_some(mode)
+    }
+
+    /// Builds and swaps in a new instance of `process`, holding its restart
+    /// lock so concurrent restarts of the same process are serialized.
+    /// Cancelling before the swap leaves the old process running.
+    async fn restart_process(
+        &self,
+        process: &ProcessManager,
+        cancel: &CancellationToken,
+        progress: Option<&Progress>,
+    ) -> Result<()> {
+        let span = info_span!(
+            "restart",
+            process = %process.name,
+            trigger = "manual",
+            outcome = field::Empty,
+            duration_ms = field::Empty,
+        );
+        let started = std::time::Instant::now();
+        let result = self.restart_process_inner(process, cancel, progress).instrument(span.clone()).await;
+        telemetry::record_outcome(&span, started, &result);
+        result
+    }
+
+    async fn restart_process_inner(
+        &self,
+        process: &ProcessManager,
+        cancel: &CancellationToken,
+        progress: Option<&Progress>,
+    ) -> Result<()> {
+        let _guard = tokio::select! {
+            guard = process.restart_lock.lock() => guard,
+            _ = cancel.cancelled() => anyhow::bail!("Restart cancelled"),
+        };
+
+        // Shown as "Restarting" in get_status while the swap is in progress
+        process.set_manual_restart_flag().await;
+
+        let result = self.swap_process(process, cancel, progress).await;
+
+        // Clear manual restart flag, whether or not the swap worked. If the
+        // build failed the old process is still running; if the new one
+        // failed to start, the crash monitor takes over from here.
+        process.clear_manual_restart_flag().await;
+        result?;
+
+        // Reset crash handler, and count the restart
+        let mut handlers = self.crash_handlers.write().await;
+        if let Some(handler) = handlers.get_mut(&process.name) {
+            handler.reset_crash_count();
+            handler.record_manual_restart();
+        }
+
+        Ok(())
+    }
+
+    /// Builds the new binary, then stops the old process and starts the new
+    /// one. A failed build leaves the old process untouched.
+    async fn swap_process(
+        &self,
+        process: &ProcessManager,
+        cancel: &CancellationToken,
+        progress: Option<&Progress>,
+    ) -> Result<()> {
+        // Switch back to dev mode on restart, if the mode system is on
+        self.mode_manager.switch_to_dev(ModeChangeReason::Restart).await;
+        let mode = self.mode_manager.get_mode().await;
+
+        // Build FIRST (while old process keeps running)
+        let release = process.config.release_build(mode);
+        let binary_path = self.build_process(process, release, cancel, progress).await?;
+        if cancel.is_cancelled() {
+            anyhow::bail!("Restart cancelled; the old process is still running");
+        }
+
+        // Now stop the old process
+        let report = |step: &str| {
+            if let Some(progress) = progress {
+                progress.report(format!("{}: {}", process.name, step));
+            }
+        };
+        report("stopping");
+        process.request_stop().await?;
+
+        // Start the new process; spawning waits for the readiness checks, or
+        // for it to stay up through the early-exit window
+        report("starting");
+        match process.config.process_type {
+            ProcessType::Rust => {
+                if let Some(binary_path) = binary_path {
+                    process.spawn_process(binary_path).await?;
+                }
+            }
+            ProcessType::Npm => {
+                process.spawn_npm_process().await?;
+            }
+        }
+        match (process.state().await, process.readiness.read().await.as_ref()) {
+            (ProcessState::Running, Some(readiness)) => report(&readiness.description()),
+            (ProcessState::Running, None) => report("ready"),
+            (state, _) => report(&format!("not ready: {}", state.as_str())),
+        }
+
+        Ok(())
+    }
+
+    /// Builds `process`, reporting the build and the number of crates
+    /// compiled so far when the client asked for progress. Returns the
+    /// binary for Rust processes; NPM processes are built only if they have
+    /// a `build_command`.
+    async fn build_process(
+        &self,
+        process: &ProcessManager,
+        release: bool,
+        cancel: &CancellationToken,
+        progress: Option<&Progress>,
+    ) -> Result<Option<PathBuf>> {
+        if !process.config.has_build() {
+            return Ok(None);
+        }
+        let build = async {
+            match process.config.process_type {
+                ProcessType::Rust => process.build(&self.builder, release, Some(cancel)).await.map(Some),
+                ProcessType::Npm => process.build_npm(&self.builder, Some(cancel)).await.map(|()| None),
+            }
+        };
+        let Some(progress) = progress else {
+            return build.await;
+        };
+
+        progress.report(format!("{}: building", process.name));
+        let mut compiled = process.build_control.watch_compiled();
+        tokio::pin!(build);
+        loop {
+            tokio::select! {
+                result = &mut build => return result,
+                Ok(()) = compiled.changed() => {
+                    let crates = *compiled.borrow_and_update();
+                    if crates > 0 {
+                        progress.report(format!("{}: compiled {} crates", process.name, crates));
+                    }
+                }
+            }
+        }
+    }
+
+    async fn tool_stop(&self, args: &Value) -> Result<String> {
+        let all = args["all"].as_bool().unwrap_or(false);
+        if [args["process"].is_string(), args["tag"].is_string(), all].iter().filter(|&&given| given).count() > 1 {
+            anyhow::bail!("Pass only one of 'process', 'tag' and 'all'");
+        }
+        let (names, mut result) = if let Some(tag) = args["tag"].as_str() {
+            let names = self.config.tagged(tag);
+            if names.is_empty() {
+                anyhow::bail!("No process has tag '{}'", tag);
+            }
+            (names, format!("Stopping processes tagged '{}':", tag))
+        } else if all {
+            (self.config.in_dependency_order(), "Stopping all processes:".to_string())
+        } else {
+            let process_name = args["process"]
+                .as_str()
+                .context("Missing 'process', 'tag' or 'all' parameter")?;
+            let process = self
+                .processes
+                .read()
+                .await
+                .get(process_name)
+                .context(format!("Process '{}' not found", process_name))?
+                .clone();
+            self.stop_process(&process).await?;
+            return Ok(format!("Process '{}' stopped", process_name));
+        };
+
+        // Dependents first, so nothing runs without what it depends on
+        for name in names.iter().rev() {
+            let Some(process) = self.processes.read().await.get(name).cloned() else {
+                continue;
+            };
+            let outcome = if matches!(process.state().await, ProcessState::Stopped | ProcessState::NotStarted) {
+                "already stopped".to_string()
+            } else {
+                match self.stop_process(&process).await {
+                    Ok(()) => "stopped".to_string(),
+                    Err(e) => format!("failed: {}", e),
+                }
+            };
+            result.push_str(&format!("\n  - {}: {}", name, outcome));
+        }
+        Ok(result)
+    }
+
+    /// Stops `process` on purpose, once a restart of it in progress is done,
+    /// so that the restart can't start a new instance after it.
+    async fn stop_process(&self, process: &ProcessManager) -> Result<()> {
+        let _guard = process.restart_lock.lock().await;
+        process.request_stop().await
+    }
+
+    async fn tool_cancel(&self, args: &Value) -> Result<String> {
+        let process_name = args["process"]
+            .as_str()
+            .context("Missing 'process' parameter")?;
+
+        let processes = self.processes.read().await;
+        let process = processes
+            .get(process_name)
+            .context(format!("Process '{}' not found", process_name))?
+            .clone();
+        drop(processes);
+
+        if process.build_control.cancel().await {
+            Ok(format!("Cancelled build for '{}'", process_name))
+        } else {
+            Ok(format!("No build in progress for '{}'", process_name))
+        }
+    }
+
+    async fn tool_build(&self, args: &Value, cancel: &CancellationToken, progress: Option<&Progress>) -> Result<String> {
+        let process_name = args["process"]
+            .as_str()
+            .context("Missing 'process' parameter")?;
+
+        let processes = self.processes.read().await;
+        let process = processes
+            .get(process_name)
+            .context(format!("Process '{}' not found", process_name))?
+            .clone();
+        drop(processes);
+
+        let release = match args["profile"].as_str() {
+            Some("dev") => false,
+            Some("release") => true,
+            Some(other) => anyhow::bail!("Unknown profile '{}' (expected 'dev' or 'release')", other),
+            None => process.config.release_build(self.mode_manager.get_mode().await),
+        };
+
+        if !process.config.has_build() {
+            anyhow::bail!(
+                "Process '{}' is not a Rust process and has no build_command; nothing to build",
+                process_name
+            );
+        }
+
+        let result = self.build_process(&process, release, cancel, progress).await;
+
+        let profile = match process.config.process_type {
+            ProcessType::Rust if release => "release",
+            ProcessType::Rust => "dev",
+            ProcessType::Npm => "build_command",
+        };
+        let mut output = match result {
+            Ok(Some(path)) => format!("Build succeeded ({}): {}\n", profile, path.display()),
+            Ok(None) => format!("Build succeeded ({})\n", profile),
+            Err(e) => format!("Build failed ({}): {}\n", profile, e),
+        };
+
+        output.push_str("\nLast 30 lines of build output:\n");
+        let lines = process
+            .build_logs
+            .read()
+            .await
+            .search(None, None, None, None, Some(30));
+        output.push_str(&lines.join("\n"));
+
+        Ok(output)
+    }
+
+    async fn tool_clean_target(&self, args: &Value) -> Result<String> {
+        let process_name = args["process"]
+            .as_str()
+            .context("Missing 'process' parameter")?;
+
+        let processes = self.processes.read().await;
+        let process = processes
+            .get(process_name)
+            .context(format!("Process '{}' not found", process_name))?
+            .clone();
+        drop(processes);
+
+        if process.config.process_type != ProcessType::Rust {
+            anyhow::bail!("Process '{}' is not a Rust process; nothing to clean", process_name);
+        }
+
+        let before = process.target_size.read().await.map(|size| size.bytes);
+        let summary = process.clean(&self.builder).await?;
+        let mut output = format!("Cleaned {}: {}\n", process.target_dir(&self.builder).await.display(), summary);
+        if let Some(after) = *process.target_size.read().await {
+            output.push_str(&format!("Target dir is now {}", format_bytes(after.bytes)));
+            if let Some(before) = before {
+                output.push_str(&format!(" (was {} when last measured)", format_bytes(before)));
+            }
+            output.push('\n');
+        }
+        Ok(output)
+    }
+
+    async fn tool_keepalive(&self, args: &Value) -> Result<String> {
+        // Every tool call counts as activity, but be explicit about it here
+        self.mode_manager.record_tool_call().await;
+        if !self.mode_manager.mode_system() {
+            let mode = self.mode_manager.get_mode().await;
+            return Ok(format!(
+                "The mode system is off (mode_system = false); processes always run in {} mode",
+                mode.as_str()
+            ));
+        }
+        if let Some(hours) = whole_hours(args, "hours")? {
+            if self.mode_manager.get_mode().await == RunMode::Dev {
+                self.mode_manager.extend_dev_mode(hours).await;
+            }
+        }
+
+        match self.mode_manager.get_time_until_release_mode().await {
+            Some(time) => Ok(format!(
+                "Dev mode extended; time until release mode: {} hours {} minutes ({} seconds)",
+                time.num_hours(),
+                time.num_minutes() % 60,
+                time.num_seconds()
+            )),
+            None if self.mode_manager.get_mode().await == RunMode::Dev => {
+                Ok("In dev mode; auto_release is off, so it won't switch to release mode".to_string())
+            }
+            None => Ok("Currently in release mode; call restart to switch to dev mode".to_string()),
+        }
+    }
+
+    async fn tool_get_manager_info(&self) -> Result<String> {
+        let limits = match (&self.rate_limiter, &self.call_permits) {
+            (Some(limiter), Some(permits)) => json!({
+                "enabled": true,
+                "max_concurrent_calls": self.config.mcp.limits.max_concurrent_calls,
+                "available_call_slots": permits.available_permits(),
+                "per_minute": limiter.state(),
+            }),
+            _ => json!({ "enabled": false }),
+        };
+
+        let result = json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "pid": std::process::id(),
+            "started_at": self.started_at.to_rfc3339(),
+            "uptime_seconds": (Utc::now() - self.started_at).num_seconds(),
+            "project_dir": self.project_dir,
+            "process_count": self.processes.read().await.len(),
+            "mcp_port": self.listen_port,
+            "run_id": run_id(),
+            "rate_limits": limits,
+        });
+
+        Ok(serde_json::to_string_pretty(&result)?)
+    }
+
+    async fn tool_get_events(&self, args: &Value) -> Result<String> {
+        let process_name = args["process"]
+            .as_str()
+            .context("Missing 'process' parameter")?;
+        let limit = args["limit"].as_u64().unwrap_or(20) as usize;
+        let offset = args["offset"].as_u64().unwrap_or(0) as usize;
+
+        let processes = self.processes.read().await;
+        let process = processes
+            .get(process_name)
+            .context(format!("Process '{}' not found", process_name))?
+            .clone();
+        drop(processes);
+
+        let events = process.events.read().await;
+        let page: Vec<Value> = events
+            .iter()
+            .rev()
+            .skip(offset)
+            .take(limit)
+            .map(|event| event.to_json())
+            .collect();
+
+        let result = json!({
+            "process": process_name,
+            "total": events.len(),
+            "offset": offset,
+            "events": page,
+        });
+
+        Ok(serde_json::to_string_pretty(&result)?)
+    }
+
+    /// Waits until a process logs lines after `since_seq`, or the timeout
+    /// passes, and returns them as JSON with the sequence number to continue
+    /// from.
+    async fn tool_wait_logs(&self, args: &Value, cancel: &CancellationToken) -> Result<String> {
+        let process_name = args["process"]
+            .as_str()
+            .context("Missing 'process' parameter")?;
+        let timeout = args["timeout_seconds"]
+            .as_u64()
+            .unwrap_or(DEFAULT_WAIT_LOGS_SECONDS)
+            .min(MAX_WAIT_LOGS_SECONDS);
+
+        let processes = self.processes.read().await;
+        let process = processes
+            .get(process_name)
+            .context(format!("Process '{}' not found", process_name))?
+            .clone();
+        drop(processes);
+
+        // Subscribed along with reading the latest seq, so no line slips
+        // between
+        let (mut appended, mut since) = {
+            let logs = process.logs.read().await;
+            let latest = logs.latest_seq();
+            let since = match args["since_seq"].as_u64() {
+                Some(seq) if seq > latest => anyhow::bail!(
+                    "since_seq {} is ahead of the log, which is at {}: was the manager restarted?",
+                    seq,
+                    latest
+                ),
+                Some(seq) => seq,
+                None => latest,
+            };
+            (logs.watch_appended(), since)
+        };
+
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(timeout);
+        let new = loop {
+            let new = process
+                .logs
+                .read()
+                .await
+                .lines_after(since, self.config.max_search_result_lines);
+            if !new.lines.is_empty() {
+                break new;
+            }
+            since = new.latest_seq;
+            tokio::select! {
+                changed = appended.changed() => if changed.is_err() {
+                    break new;
+                },
+                _ = tokio::time::sleep_until(deadline) => break new,
+                _ = cancel.cancelled() => anyhow::bail!("Cancelled while waiting for new lines"),
+            }
+        };
+
+        Ok(serde_json::to_string_pretty(&json!({
+            "process": process_name,
+            "lines": new.lines,
+            "latest_seq": new.latest_seq,
+            "missed": new.missed,
+            "timed_out": new.lines.is_empty(),
+        }))?)
+    }
+
+    /// Runtime and build status of one process together, as JSON.
+    async fn tool_get_process(&self, args: &Value) -> Result<String> {
+        let process_name = args["process"]
+            .as_str()
+            .context("Missing 'process' parameter")?;
+        let limit = args["events"].as_u64().unwrap_or(10) as usize;
+
+        let processes = self.processes.read().await;
+        let process = processes
+            .get(process_name)
+            .context(format!("Process '{}' not found", process_name))?
+            .clone();
+        let only = [process_name.to_string()];
+        let mut result = match self.process_summaries(&processes, Some(&only)).await.first() {
+            Some(summary) => summary.to_json(),
+            None => anyhow::bail!("Process '{}' not found", process_name),
+        };
+        drop(processes);
+
+        let restart_count = self
+            .crash_handlers
+            .read()
+            .await
+            .get(process_name)
+            .map_or(0, |h| h.get_restart_count());
+        let last_build = process.build_control.history.read().await.back().map(|build| build.to_json());
+        let last_exit = process.crash_reports.read().await.back().map(|report| {
+            json!({
+                "timestamp": report.timestamp.to_rfc3339(),
+                "exit_code": report.exit_code,
+                "signal": report.signal,
+            })
+        });
+        let events: Vec<Value> = process
+            .events
+            .read()
+            .await
+            .iter()
+            .rev()
+            .take(limit)
+            .map(|event| event.to_json())
+            .collect();
+
+        result["started_at"] = json!(process.started_at.read().await.map(|t| t.to_rfc3339()));
+        result["restart_count"] = json!(restart_count);
+        result["startup_error"] = json!(*process.startup_error.read().await);
+        result["build_in_progress"] = json!(process.build_control.is_in_progress().await);
+        result["last_build"] = json!(last_build);
+        result["last_exit"] = json!(last_exit);
+        result["direnv"] = process.direnv().to_json();
+        result["resources"] = ResourceSample::to_json(process.last_sample.read().await.as_ref());
+        result["recent_events"] = json!(events);
+
+        Ok(serde_json::to_string_pretty(&result)?)
+    }
+
+    async fn tool_list_processes(&self, args: &Value) -> Result<String> {
+        let state = match args["state"].as_str() {
+            Some(name) => Some(ProcessState::parse(name).with_context(|| {
+                let names: Vec<_> = ProcessState::ALL.iter().map(|s| s.as_str()).collect();
+                format!("Unknown state '{}'; expected one of {}", name, names.join(", "))
+            })?),
+            None => None,
+        };
+        let only = match args["tag"].as_str() {
+            Some(tag) => {
+                let names = self.config.tagged(tag);
+                if names.is_empty() {
+                    anyhow::bail!("No process has tag '{}'", tag);
+                }
+                Some(names)
+            }
+            None => None,
+        };
+
+        let processes = self.processes.read().await;
+        let summaries = self.process_summaries(&processes, only.as_deref()).await;
+        let listed: Vec<Value> = summaries
+            .iter()
+            .filter(|summary| state.is_none_or(|state| summary.state == state.as_str()))
+            .map(ProcessSummary::to_json)
+            .collect();
+
+        let result = json!({
+            "filters": {
+                "state": state.map(|s| s.as_str()),
+                "tag": args["tag"].as_str(),
+            },
+            "total": listed.len(),
+            "processes": listed,
+        });
+
+        Ok(serde_json::to_string_pretty(&result)?)
+    }
+
+    async fn tool_get_crash_report(&self, args: &Value) -> Result<String> {
+        let process_name = args["process"]
+            .as_str()
+            .context("Missing 'process' parameter")?;
+        let index = args["index"].as_i64().unwrap_or(-1);
+
+        let processes = self.processes.read().await;
+        let process = processes
+            .get(process_name)
+            .context(format!("Process '{}' not found", process_name))?
+            .clone();
+        drop(processes);
+
+        let reports = process.crash_reports.read().await;
+        if reports.is_empty() {
+            return Ok(format!("No crashes recorded for '{}'", process_name));
+        }
+        let position = if index < 0 { reports.len() as i64 + index } else { index };
+        let report = usize::try_from(position)
+            .ok()
+            .and_then(|i| reports.get(i))
+            .context(format!("Crash report {} not found; {} kept", index, reports.len()))?;
+
+        let result = json!({
+            "process": process_name,
+            "index": position,
+            "total": reports.len(),
+            "report": report.to_json(),
+        });
+
+        Ok(serde_json::to_string_pretty(&result)?)
+    }
+
+    async fn tool_recheck_tools(&self) -> Result<String> {
+        let results = self.preflight.run(&self.config).await;
+        Ok(preflight::report(&results))
+    }
+
+    async fn tool_get_build_history(&self, args: &Value) -> Result<String> {
+        let process_name = args["process"]
+            .as_str()
+            .context("Missing 'process' parameter")?;
+        let limit = args["limit"].as_u64().unwrap_or(20) as usize;
+
+        let processes = self.processes.read().await;
+        let process = processes
+            .get(process_name)
+            .context(format!("Process '{}' not found", process_name))?
+            .clone();
+        drop(processes);
+
+        let history = process.build_control.history.read().await;
+        let builds: Vec<Value> = history
+            .iter()
+            .rev()
+            .take(limit)
+            .map(|build| build.to_json())
+            .collect();
+
+        let result = json!({
+            "process": process_name,
+            "total": history.len(),
+            "builds": builds,
+        });
+
+        Ok(serde_json::to_string_pretty(&result)?)
+    }
+
+    /// Process events (other than state changes, which the others imply),
+    /// builds and mode changes, merged newest first.
+    async fn tool_get_recent_activity(&self, args: &Value) -> Result<String> {
+        let limit = args["limit"].as_u64().unwrap_or(50) as usize;
+
+        let mut activity: Vec<(DateTime<Utc>, Option<String>, &str, String)> = Vec::new();
+        let processes = self.processes.read().await;
+        for (name, process) in processes.iter() {
+            for event in process.events.read().await.iter() {
+                if !matches!(event, ProcessEvent::StateChanged { .. }) {
+                    activity.push((event.timestamp(), Some(name.clone()), "event", event.summary()));
+                }
+            }
+            for build in process.build_control.history.read().await.iter() {
+                activity.push((build.finished_at, Some(name.clone()), "build", build.summary()));
+            }
+        }
+        drop(processes);
+        for change in self.mode_manager.changes().await {
+            activity.push((change.timestamp, None, "mode_change", change.summary()));
+        }
+
+        activity.sort_by_key(|a| std::cmp::Reverse(a.0));
+        let total = activity.len();
+        let entries: Vec<Value> = activity
+            .into_iter()
+            .take(limit)
+            .map(|(timestamp, process, kind, summary)| {
+                json!({
+                    "timestamp": timestamp.to_rfc3339(),
+                    "process": process,
+                    "kind": kind,
+                    "summary": summary,
+                })
+            })
+            .collect();
+
+        Ok(serde_json::to_string_pretty(&json!({
+            "total": total,
+            "activity": entries,
+        }))?)
+    }
+
+    async fn tool_get_mode(&self) -> Result<String> {
+        let mode = self.mode_manager.get_mode().await;
+        let time_until_release = self.mode_manager.get_time_until_release_mode().await;
+
+        let result = json!({
+            "mode": mode.as_str(),
+            "mode_system": self.mode_manager.mode_system(),
+            "time_until_release_seconds": time_until_release.map(|t| t.num_seconds()),
+            "locked": !self.mode_manager.auto_release(),
+            "last_activity": self.mode_manager.last_tool_call().await.to_rfc3339(),
+            "dev_timeout_hours": self.mode_manager.dev_timeout_hours().await,
+            "extended_until": self.mode_manager.extended_until().await.map(|t| t.to_rfc3339()),
+        });
+
+        Ok(serde_json::to_string_pretty(&result)?)
+    }
+
+    async fn tool_set_mode_config(&self, args: &Value) -> Result<String> {
+        let hours = whole_hours(args, "dev_timeout_hours")?.context("Missing 'dev_timeout_hours' parameter")?;
+        if hours == 0 {
+            anyhow::bail!("dev_timeout_hours must be at least 1");
+        }
+        if !self.mode_manager.mode_system() {
+            anyhow::bail!("The mode system is off (mode_system = false), so there is no dev mode timer");
+        }
+        self.mode_manager.set_dev_timeout_hours(hours).await;
+        info!("dev_timeout_hours set to {}", hours);
+        self.tool_get_mode().await
+    }
+
+    async fn tool_get_status(&self, args: &Value) -> Result<String> {
+        let only = self.status_selection(args).await?;
+        let only = only.as_deref();
+        if args["summary"].as_bool().unwrap_or(false) {
+            return Ok(self.status_summary(only).await);
+        }
+
+        // Without the mode system there is only one mode, not worth a line
+        let mut status = String::new();
+        if self.mode_manager.mode_system() {
+            let mode = self.mode_manager.get_mode().await;
+            let time_until_release = self.mode_manager.get_time_until_release_mode().await;
+
+            status.push_str(&format!("Mode: {:?}\n", mode));
+            if let Some(time) = time_until_release {
+                status.push_str(&format!(
+                    "Time until release mode: {} hours {} minutes\n",
+                    time.num_hours(),
+                    time.num_minutes() % 60
+                ));
+            } else if mode == RunMode::Dev {
+                status.push_str("Automatic switch to release mode is off (auto_release = false)\n");
+            } else {
+                status.push_str("Currently in release mode\n");
+            }
+            if let Some(reason) = self.mode_manager.release_deferral().await {
+                status.push_str(&format!("Release switch deferred: {}\n", reason));
+            }
+            if let Some(change) = self.mode_manager.last_change().await {
+                status.push_str(&format!("Last mode change: {}\n", change.description()));
+            }
+            status.push('\n');
+        }
+
+        let processes = self.processes.read().await;
+        let mut log_bytes = 0;
+        let mut uncounted = Vec::new();
+        for (name, process) in processes.iter() {
+            let bytes = async {
+                process.logs.read().await.memory_bytes() + process.build_logs.read().await.memory_bytes()
+            };
+            match tokio::time::timeout(STATUS_LOCK_TIMEOUT, bytes).await {
+                Ok(bytes) => log_bytes += bytes,
+                Err(_) => uncounted.push(name.as_str()),
+            }
+        }
+        match self.config.max_total_log_mb {
+            Some(mb) => status.push_str(&format!(
+                "Log memory: {} of {} (max_total_log_mb)",
+                format_bytes(log_bytes as u64),
+                format_bytes(mb * 1024 * 1024)
+            )),
+            None => status.push_str(&format!("Log memory: {}", format_bytes(log_bytes as u64))),
+        }
+        if !uncounted.is_empty() {
+            status.push_str(&format!(", not counting {} (internal lock timeout)", uncounted.join(", ")));
+        }
+        status.push_str("\n\n");
+        status.push_str("Processes:\n");
+
+        let preflight_results = self.preflight.results().await;
+        for (name, process) in processes.iter().filter(|(name, _)| only.is_none_or(|only| only.contains(name))) {
+            match tokio::time::timeout(STATUS_LOCK_TIMEOUT, self.process_status(name, process, &preflight_results)).await {
+                Ok(section) => status.push_str(&section),
+                Err(_) => status.push_str(&format!("\n  {}: {}\n", name, status_summary::UNAVAILABLE)),
+            }
+        }
+
+        Ok(status)
+    }
+
+    /// The get_status section of one process.
+    async fn process_status(&self, name: &str, process: &ProcessManager, preflight_results: &[CheckResult]) -> String {
+        let mut status = String::new();
+        let state = process.state().await;
+        status.push_str(&format!("\n  {}: {}\n", name, state.as_str()));
+
+        if !process.config.tags.is_empty() {
+            status.push_str(&format!("    Tags: {}\n", process.config.tags.join(", ")));
+        }
+
+        if process.config.project_dir.is_some() {
+            status.push_str(&format!("    Project dir: {}\n", process.project_dir.display()));
+        }
+
+        let mode = self.mode_manager.get_mode().await;
+        if !process.config.runs_in(mode) {
+            status.push_str(&format!("    Profile: none, doesn't run in {} mode\n", mode.as_str()));
+        } else if process.config.process_type == ProcessType::Rust || process.config.profile_pinned() {
+            let profile = if process.config.release_build(mode) { "release" } else { "dev" };
+            let pinned = if process.config.profile_pinned() { " (pinned)" } else { "" };
+            status.push_str(&format!("    Profile: {}{}\n", profile, pinned));
+        }
+        status.push_str(&format!("    Direnv: {}\n", process.direnv().description()));
+
+        // Covers the manual swap as well as crash-recovery rebuilds, so
+        // clients don't mistake the transient state for crashed or stopped
+        if process.is_manual_restart_in_progress().await
+            || process.build_control.is_in_progress().await
+        {
+            status.push_str("    Restarting: true\n");
+        }
+        if let Some(position) = self.restart_queue.position(name) {
+            status.push_str(&format!("    Queued for restart, position {}\n", position));
+        }
+
+        if let Some(ref startup_error) = *process.startup_error.read().await {
+            status.push_str(&format!("    Startup failed: {}\n", startup_error));
+        }
+
+        for check in preflight_results.iter().filter(|c| c.process == name) {
+            match check.outcome {
+                CheckOutcome::Passed(_) => {}
+                CheckOutcome::Warning(ref message) => status.push_str(&format!(
+                    "    Preflight warning: {}: {}\n",
+                    check.check, message
+                )),
+                CheckOutcome::Failed(ref message) => status.push_str(&format!(
+                    "    Preflight failed: {}: {}\n",
+                    check.check, message
+                )),
+            }
+        }
+
+        if let Some(ref readiness) = *process.readiness.read().await {
+            status.push_str(&format!("    Ready: {}\n", readiness.description()));
+        }
+
+        let rates = process.log_rates.rates();
+        if rates.lines > 0 || process.config.error_pattern.is_some() {
+            let mut line = format!("    Log rate: ~{} lines/min, {} stderr/min", rates.lines, rates.stderr);
+            if process.config.error_pattern.is_some() {
+                line.push_str(&format!(", {} err/min", rates.errors));
+            }
+            status.push_str(&line);
+            status.push('\n');
+        }
+
+        if let Some(size) = *process.target_size.read().await {
+            let age = Utc::now() - size.measured_at;
+            status.push_str(&format!(
+                "    Target dir: {} (measured {} minutes ago)\n",
+                format_bytes(size.bytes),
+                age.num_minutes()
+            ));
+        }
+
+        if process.config.daemonizes {
+            let pid = match *process.daemon_pid.read().await {
+                Some(pid) => format!("PID {}", pid),
+                None => "no PID yet".to_string(),
+            };
+            status.push_str(&format!(
+                "    Daemonized: {} from pid_file; logs only include what the launcher printed\n",
+                pid
+            ));
+        }
+
+        if let Some(ref build_info) = *process.build_info.read().await {
+            let stale = if build_info.is_stale().await {
+                " [stale: newer binary on disk, restart to pick it up]"
+            } else if build_info.binary_mtime.is_some_and(|built| process.sources_changed(&self.builder, built)) {
+                " [stale: sources changed since this build, restart to pick them up]"
+            } else {
+                ""
+            };
+            status.push_str(&format!(
+                "    Version: running build of {}{}\n",
+                build_info.summary(),
+                stale
+            ));
+        }
+
+        if let Some(summary) = process.build_control.summary(10).await {
+            status.push_str(&format!("    Builds: {}\n", summary));
+        }
+
+        if matches!(state, ProcessState::Starting | ProcessState::Running) {
+            if let Some(sample) = *process.last_sample.read().await {
+                let mut parts = Vec::new();
+                if let Some(rss) = sample.rss {
+                    parts.push(format!("{} RSS", format_bytes(rss)));
+                }
+                if let Some(open_files) = sample.open_files {
+                    match process.config.max_open_files_warn {
+                        Some(limit) => parts.push(format!("{} open files (warn above {})", open_files, limit)),
+                        None => parts.push(format!("{} open files", open_files)),
+                    }
+                }
+                if let Some(threads) = sample.threads {
+                    parts.push(format!("{} threads", threads));
+                }
+                if !parts.is_empty() {
+                    status.push_str(&format!("    Resources: {}\n", parts.join(", ")));
+                }
+            }
+        }
+
+        if let Some(uptime) = process.get_uptime().await {
+            status.push_str(&format!(
+                "    Uptime: {} hours {} minutes\n",
+                uptime.num_hours(),
+                uptime.num_minutes() % 60
+            ));
+        }
+
+        // State changes are left to get_events; the state is shown above
+        let events = process.events.read().await;
+        let recent: Vec<_> = events
+            .iter()
+            .rev()
+            .filter(|e| !matches!(e, ProcessEvent::StateChanged { .. }))
+            .take(5)
+            .collect();
+        if !recent.is_empty() {
+            status.push_str("    Recent events:\n");
+            for event in recent {
+                status.push_str(&format!("      - {}\n", event.description()));
+            }
+        }
+
+        let handlers = self.crash_handlers.read().await;
+        if let Some(handler) = handlers.get(name) {
+            let crash_count = handler.get_crash_count();
+            if crash_count > 0 {
+                status.push_str(&format!("    Crash count: {}\n", crash_count));
+            }
+            let restart_count = handler.get_restart_count();
+            if restart_count > 0 {
+                status.push_str(&format!("    Restart count: {}\n", restart_count));
+            }
+        }
+
+        status
+    }
+
+    /// The processes a get_status call is limited to by its `process` or
+    /// `tag` argument, if any.
+    async fn status_selection(&self, args: &Value) -> Result<Option<Vec<String>>> {
+        match (args["process"].as_str(), args["tag"].as_str()) {
+            (Some(_), Some(_)) => anyhow::bail!("Pass either 'process' or 'tag', not both"),
+            (Some(name), None) => {
+                if !self.processes.read().await.contains_key(name) {
+                    anyhow::bail!("Process '{}' not found", name);
+                }
+                Ok(Some(vec![name.to_string()]))
+            }
+            (None, Some(tag)) => {
+                let names = self.config.tagged(tag);
+                if names.is_empty() {
+                    anyhow::bail!("No process has tag '{}'", tag);
+                }
+                Ok(Some(names))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// The compact form of get_status: a mode line, unless the mode system is
+    /// off, then one short line per process, in name order.
+    async fn status_summary(&self, only: Option<&[String]>) -> String {
+        let mode = self.mode_manager.get_mode().await.as_str();
+        let time_until_release = self
+            .mode_manager
+            .get_time_until_release_mode()
+            .await
+            .and_then(|t| t.to_std().ok());
+
+        let processes = self.processes.read().await;
+        let summaries = self.process_summaries(&processes, only).await;
+        if !self.mode_manager.mode_system() {
+            return status_summary::processes_line(&summaries);
+        }
+        status_summary::format(&status_summary::mode_line(mode, time_until_release), &summaries)
+    }
+
+    /// Summaries of the processes in `only`, or of all of them, in name order.
+    async fn process_summaries<'a>(
+        &self,
+        processes: &'a HashMap<String, Arc<ProcessManager>>,
+        only: Option<&[String]>,
+    ) -> Vec<ProcessSummary<'a>> {
+        let handlers = self.crash_handlers.read().await;
+        let mut names: Vec<_> = processes
+            .keys()
+            .filter(|name| only.is_none_or(|only| only.contains(name)))
+            .collect();
+        names.sort();
+
+        let mut summaries = Vec::new();
+        for name in names {
+            let process = &processes[name];
+            let process_type = process.config.process_type.as_str();
+            let summary = async {
+                let state = process.state().await;
+                let uptime = match state {
+                    ProcessState::Running => process.get_uptime().await.and_then(|u| u.to_std().ok()),
+                    _ => None,
+                };
+                let handler = handlers.get(name);
+                ProcessSummary {
+                    name,
+                    state: state.as_str(),
+                    process_type,
+                    uptime,
+                    crashes: handler.map_or(0, |h| h.get_crash_count()),
+                    backoff: handler.and_then(|h| h.backoff_remaining()),
+                    restarting: process.is_manual_restart_in_progress().await
+                        || process.build_control.is_in_progress().await,
+                }
+            };
+            let summary = tokio::time::timeout(STATUS_LOCK_TIMEOUT, summary)
+                .await
+                .unwrap_or_else(|_| ProcessSummary::unavailable(name, process_type));
+            summaries.push(summary);
+        }
+        summaries
+    }
+}
+
+async fn handle_post(
+    State(state): State<AppState>,
+    Json(request): Json<JsonRpcRequest>,
+) -> Response {
+    // Notifications get no response
+    let notification = request.id.is_none() && request.method.starts_with("notifications/");
+    let response = state.handle_request(request).await;
+    if notification {
+        return StatusCode::ACCEPTED.into_response();
+    }
+    Json(response).into_response()
+}
+
+async fn handle_get(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // Server-initiated messages, such as progress notifications, sent from
+    // the time the client connects
+    let notifications = stream::unfold(state.notifications.subscribe(), |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(message) => return Some((Ok(Event::default().data(message.to_string())), receiver)),
+                // A slow client misses some progress, which it can live with
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    let stream = stream::once(async { Ok(Event::default().comment("connected")) }).chain(notifications);
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Liveness for load balancers and uptime checks, without JSON-RPC: the
+/// mode and how many processes are running or down. A process whose state
+/// can't be read within `STATUS_LOCK_TIMEOUT` counts as unknown.
+async fn handle_healthz(State(state): State<AppState>) -> Json<Value> {
+    let mut running = 0;
+    let mut crashed = 0;
+    let mut unknown = 0;
+    let processes = state.processes.read().await;
+    for process in processes.values() {
+        match tokio::time::timeout(STATUS_LOCK_TIMEOUT, process.state()).await {
+            Ok(ProcessState::Running) => running += 1,
+            Ok(ProcessState::Crashed | ProcessState::Failed) => crashed += 1,
+            Ok(_) => {}
+            Err(_) => unknown += 1,
+        }
+    }
+    Json(json!({
+        "status": "ok",
+        "mode": state.mode_manager.get_mode().await.as_str(),
+        "processes": {
+            "running": running,
+            "crashed": crashed,
+            "unknown": unknown,
+            "total": processes.len(),
+        },
+    }))
+}
+
+/// 503 until the processes have first been built and started, then 200.
+async fn handle_readyz(State(state): State<AppState>) -> Response {
+    if state.ready.load(Ordering::SeqCst) {
+        Json(json!({ "status": "ready" })).into_response()
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "status": "starting" }))).into_response()
+    }
+}
+
+/// Tool list for clients that don't speak MCP, without the JSON-RPC
+/// handshake.
+async fn handle_get_tools(State(state): State<AppState>) -> Json<Value> {
+    Json(state.tool_list())
+}
+
+pub async fn create_router(state: AppState) -> Router {
+    let cors = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([header::CONTENT_TYPE]);
+
+    Router::new()
+        .route("/mcp", post(handle_post).get(handle_get))
+        .route("/tools", get(handle_get_tools))
+        .route("/healthz", get(handle_healthz))
+        .route("/readyz", get(handle_readyz))
+        .layer(cors)
+        .with_state(state)
+}
+
+/// Binds the MCP port, retrying while it's taken and then moving on to the
+/// fallback ports if configured. Done before any process starts, so a taken
+/// port fails startup without leaving children behind.
+pub async fn bind_listener(config: &Config) -> Result<tokio::net::TcpListener> {
+    let fallbacks = if config.mcp_port_fallback { config.mcp_port_fallback_range } else { 0 };
+    let last_port = config.mcp_port.saturating_add(fallbacks);
+
+    for port in config.mcp_port..=last_port {
+        let addr = format!("127.0.0.1:{}", port);
+        let mut attempt = 0;
+        loop {
+            match tokio::net::TcpListener::bind(&addr).await {
+                Ok(listener) => {
+                    if port != config.mcp_port {
+                        warn!("Port {} is in use, using fallback port {}", config.mcp_port, port);
+                    }
+                    return Ok(listener);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                    if attempt >= config.mcp_port_bind_retries {
+                        break;
+                    }
+                    attempt += 1;
+                    warn!(
+                        "Port {} is in use, retrying ({}/{})",
+                        port, attempt, config.mcp_port_bind_retries
+                    );
+                    tokio::time::sleep(MCP_PORT_RETRY_DELAY).await;
+                }
+                Err(e) => return Err(e).context(format!("Failed to bind to {}", addr)),
+            }
+        }
+    }
+
+    if last_port == config.mcp_port {
+        anyhow::bail!(
+            "Port {} is already in use; is another manager running for this project? \
+             Set mcp_port_fallback = true to try the following ports",
+            config.mcp_port
+        )
+    }
+    anyhow::bail!("Ports {} through {} are all in use", config.mcp_port, last_port)
+}
+
+pub async fn start_server(state: AppState, listener: tokio::net::TcpListener) -> Result<()> {
+    let app = create_router(state).await;
+
+    let addr = listener.local_addr()?;
+    info!("MCP HTTP server listening on http://{}/mcp (health checks: /healthz, /readyz)", addr);
+
+    axum::serve(listener, app)
+        .await
+        .context("Server error")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DirenvStrategy;
+    use crate::direnv::Direnv;
+    use crate::log_buffer::LineMeta;
+    use std::path::{Path, PathBuf};
+
+    /// Creates an empty project directory containing a Cargo.toml that
+    /// doesn't parse, so any build fails quickly.
+    fn broken_project(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bpm-test-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Cargo.toml"), "this is not toml [").unwrap();
+        dir
+    }
+
+    fn direnv() -> Arc<Direnv> {
+        Arc::new(Direnv::new(PathBuf::from("."), DirenvStrategy::Off))
+    }
+
+    fn process(config: &Config, name: &str) -> Arc<ProcessManager> {
+        process_in(config, name, Path::new("."))
+    }
+
+    fn process_in(config: &Config, name: &str, project_dir: &Path) -> Arc<ProcessManager> {
+        Arc::new(ProcessManager::new(
+            name.to_string(),
+            config.process[name].clone(),
+            project_dir.to_path_buf(),
+            direnv(),
+            false,
+            config.max_events,
+            config.max_log_line_bytes,
+        ))
+    }
+
+    fn app_state(config: Config, process: Arc<ProcessManager>) -> AppState {
+        let mut processes = HashMap::new();
+        processes.insert(process.name.clone(), process);
+        let builder = Arc::new(Builder::new(&config));
+        AppState::new(
+            config,
+            Arc::new(RwLock::new(processes)),
+            builder,
+            Arc::new(ModeManager::new(3, RunMode::Release)),
+            Arc::new(RwLock::new(HashMap::new())),
+            ManagerLog::new().0,
+            Arc::new(Preflight::new(HashMap::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_tool_annotations() {
+        let project_dir = broken_project("annotations");
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process_in(&config, "main", &project_dir);
+        let state = app_state(config, process);
+
+        let response = state.handle_list_tools(json!(1)).await;
+        let tools = response.result.unwrap()["tools"].as_array().unwrap().clone();
+        let annotations = |name: &str| {
+            tools
+                .iter()
+                .find(|t| t["name"] == name)
+                .map(|t| t["annotations"].clone())
+                .unwrap()
+        };
+
+        for name in ["search_logs", "search_build_log", "get_status"] {
+            assert_eq!(annotations(name)["readOnlyHint"], true, "{}", name);
+        }
+        assert_eq!(annotations("restart")["destructiveHint"], true);
+        assert_eq!(annotations("restart")["readOnlyHint"], false);
+
+        // GET /tools serves the same list
+        let Json(listed) = handle_get_tools(State(state.clone())).await;
+        assert_eq!(listed["tools"].as_array().unwrap(), &tools);
+        assert!(tools.iter().all(|t| t["annotations"].is_object()));
+    }
+
+    #[tokio::test]
+    async fn test_health_endpoints() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process(&config, "main");
+        let state = app_state(config, process).starting();
+        let get = |uri: &str| {
+            let router = create_router(state.clone());
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            async move {
+                let response = router.await.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+
+        let (status, health) = get("/healthz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            health,
+            json!({
+                "status": "ok",
+                "mode": "release",
+                "processes": { "running": 0, "crashed": 0, "unknown": 0, "total": 1 }
+            })
+        );
+
+        // A stuck process doesn't hold up the health check
+        {
+            let process = state.processes.read().await["main"].clone();
+            let _locked = process.hold_state_lock().await;
+            let (status, health) = tokio::time::timeout(std::time::Duration::from_secs(5), get("/healthz")).await.unwrap();
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(health["processes"]["unknown"], 1);
+        }
+
+        // Tools that change processes or the mode wait for startup; looking
+        // at them doesn't
+        assert_eq!(get("/readyz").await, (StatusCode::SERVICE_UNAVAILABLE, json!({ "status": "starting" })));
+        let call = |name: &str, arguments: Value| {
+            state.handle_request(JsonRpcRequest {
+                id: Some(json!(1)),
+                method: "tools/call".to_string(),
+                params: Some(json!({ "name": name, "arguments": arguments })),
+            })
+        };
+        for (tool, arguments) in [
+            ("restart", json!({ "process": "main" })),
+            ("stop", json!({ "process": "main" })),
+            ("build", json!({ "process": "main" })),
+            ("clean_target", json!({ "process": "main" })),
+            ("keepalive", json!({ "hours": 1 })),
+            ("set_mode_config", json!({ "dev_timeout_hours": 2 })),
+        ] {
+            let error = call(tool, arguments).await.error.unwrap();
+            assert!(error.message.contains("still being started"), "{}: {}", tool, error.message);
+        }
+        assert!(call("get_status", json!({})).await.error.is_none());
+
+        state.mark_ready();
+        assert_eq!(get("/readyz").await, (StatusCode::OK, json!({ "status": "ready" })));
+    }
+
+    #[tokio::test]
+    async fn test_search_limits() {
+        let project_dir = PathBuf::from(".");
+        let config = Config::parse(
+            "mcp_port = 3001\ndefault_search_tail = 5\nmax_search_result_lines = 10\n[process.main]\ntype = \"rust\"\n",
+        )
+        .unwrap();
+        let process = process_in(&config, "main", &project_dir);
+        {
+            let mut logs = process.logs.write().await;
+            logs.new_instance();
+            for i in 0..30 {
+                logs.append(format!("line {}", i));
+            }
+        }
+        let state = app_state(config, process);
+        let search = |args: Value| {
+            let state = state.clone();
+            async move { state.tool_search_logs(&args).await.unwrap() }
+        };
+
+        let output = search(json!({ "process": "main" })).await;
+        assert_eq!(output.lines().collect::<Vec<_>>(), ["line 25", "line 26", "line 27", "line 28", "line 29"]);
+
+        let output = search(json!({ "process": "main", "no_limit": true })).await;
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[9], "line 9");
+        assert!(lines[10].starts_with("[Truncated: showing the first 10 of 30 lines"), "{}", lines[10]);
+
+        let output = search(json!({ "process": "main", "tail": 20 })).await;
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], "line 20");
+        assert!(lines[10].starts_with("[Truncated: showing the last 10 of 20 lines"), "{}", lines[10]);
+
+        // A pattern alone isn't limited by the default tail
+        let output = search(json!({ "process": "main", "pattern": "line 1" })).await;
+        assert_eq!(output.lines().count(), 11);
+    }
+
+    #[tokio::test]
+    async fn test_search_json_output() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process(&config, "main");
+        let captured = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().with_timezone(&Utc);
+        let meta = |stream| LineMeta { timestamp: captured, stream: Some(stream) };
+        {
+            let mut logs = process.logs.write().await;
+            logs.new_instance();
+            logs.append_with("listening on 8080".to_string(), meta(LogStream::Stdout));
+            logs.append_with("[stderr] error: multi\nline".to_string(), meta(LogStream::Stderr));
+            logs.append_with("[stderr] retrying, says stdout".to_string(), meta(LogStream::Stdout));
+        }
+        let state = app_state(config, process);
+
+        let output = state
+            .tool_search_logs(&json!({ "process": "main", "pattern": "error", "context_lines": 1, "output": "json" }))
+            .await
+            .unwrap();
+        let results: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(
+            results,
+            json!({
+                "lines": [
+                    { "line": "listening on 8080", "matched": false, "stream": "stdout", "timestamp": "2024-05-01T12:00:00+00:00", "seq": 1 },
+                    { "line": "error: multi\nline", "matched": true, "stream": "stderr", "timestamp": "2024-05-01T12:00:00+00:00", "seq": 2 },
+                    { "line": "[stderr] retrying, says stdout", "matched": false, "stream": "stdout", "timestamp": "2024-05-01T12:00:00+00:00", "seq": 3 }
+                ],
+                "latest_seq": 3
+            })
+        );
+
+        let output = state
+            .tool_search_logs(&json!({ "process": "main", "pattern": "(", "output": "json" }))
+            .await
+            .unwrap();
+        let results: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(results["lines"], json!([]));
+        assert_eq!(results["error"], "Invalid regex pattern: (");
+
+        assert!(state.tool_search_logs(&json!({ "process": "main", "output": "xml" })).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_search_time_window() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process(&config, "main");
+        {
+            let mut logs = process.logs.write().await;
+            logs.new_instance();
+            for minute in 0..5 {
+                let timestamp = DateTime::parse_from_rfc3339(&format!("2024-05-01T12:0{}:00Z", minute)).unwrap();
+                let meta = LineMeta { timestamp: timestamp.with_timezone(&Utc), stream: None };
+                logs.append_with(format!("minute {}", minute), meta);
+            }
+        }
+        let state = app_state(config, process);
+
+        let output = state
+            .tool_search_logs(&json!({ "process": "main", "since": "2024-05-01T12:01:00Z", "until": "2024-05-01T14:03:00+02:00" }))
+            .await
+            .unwrap();
+        assert_eq!(output, "minute 1\nminute 2\nminute 3");
+        let output = state
+            .tool_search_logs(&json!({ "process": "main", "pattern": "minute", "since": "2024-05-01T12:04:00Z" }))
+            .await
+            .unwrap();
+        assert_eq!(output, " * minute 4");
+
+        let err = state.tool_search_logs(&json!({ "process": "main", "since": "noon" })).await.unwrap_err();
+        assert!(err.to_string().contains("'since' must be an RFC 3339 time"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_search_by_generation() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process(&config, "main");
+        {
+            let mut logs = process.logs.write().await;
+            for line in ["first", "second", "third"] {
+                logs.new_instance();
+                logs.append(line.to_string());
+            }
+            logs.record_end(2, "exited with code 1".to_string());
+        }
+        let state = app_state(config, process);
+
+        // An earlier instance says which run it was
+        let output = state
+            .tool_search_logs(&json!({ "process": "main", "generation": 2 }))
+            .await
+            .unwrap();
+        let (header, lines) = output.split_once('\n').unwrap();
+        assert!(header.starts_with("[Log instance -2 (generation 2, not the current instance): started "), "{}", header);
+        assert!(header.ends_with(", exited with code 1]"), "{}", header);
+        assert_eq!(lines, "second");
+
+        let output = state
+            .tool_search_logs(&json!({ "process": "main", "generation": 3, "tail": 1 }))
+            .await
+            .unwrap();
+        assert_eq!(output, "third");
+
+        let output = state
+            .tool_search_logs(&json!({ "process": "main", "generation": 4 }))
+            .await
+            .unwrap();
+        assert!(output.contains("Generation 4 has not started"), "{}", output);
+
+        assert!(state
+            .tool_search_logs(&json!({ "process": "main", "generation": 2, "index": -1 }))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_search_since_seq() {
+        let config =
+            Config::parse("mcp_port = 3001\ndefault_search_tail = 2\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process(&config, "main");
+        {
+            let mut logs = process.logs.write().await;
+            logs.new_instance();
+            for i in 1..=5 {
+                logs.append(format!("line {}", i));
+            }
+        }
+        let state = app_state(config, process);
+        let search = |args: Value| {
+            let state = state.clone();
+            async move {
+                let output = state.tool_search_logs(&args).await.unwrap();
+                serde_json::from_str::<Value>(&output).unwrap()
+            }
+        };
+        let texts = |results: &Value| -> Vec<String> {
+            results["lines"].as_array().unwrap().iter().map(|l| l["line"].as_str().unwrap().to_string()).collect()
+        };
+
+        // The default limit takes the first lines after since_seq, and
+        // latest_seq continues from there
+        let results = search(json!({ "process": "main", "since_seq": 0, "output": "json" })).await;
+        assert_eq!(texts(&results), ["line 1", "line 2"]);
+        assert_eq!(results["latest_seq"], 2);
+        let results = search(json!({ "process": "main", "since_seq": 2, "head": 2, "output": "json" })).await;
+        assert_eq!(texts(&results), ["line 3", "line 4"]);
+        assert_eq!(results["latest_seq"], 4);
+
+        // With a pattern, lines that don't match are passed over
+        let results = search(json!({ "process": "main", "since_seq": 2, "pattern": "3", "output": "json" })).await;
+        assert_eq!(texts(&results), ["line 3"]);
+        assert_eq!(results["latest_seq"], 5);
+
+        let results = search(json!({ "process": "main", "since_seq": 5, "output": "json" })).await;
+        assert_eq!(results["lines"], json!([]));
+        assert_eq!(results["latest_seq"], 5);
+        let output = state.tool_search_logs(&json!({ "process": "main", "since_seq": 4 })).await.unwrap();
+        assert_eq!(output, "line 5\n[latest_seq: 5]");
+    }
+
+    #[tokio::test]
+    async fn test_search_patterns_and_captures() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process(&config, "main");
+        {
+            let mut logs = process.logs.write().await;
+            logs.new_instance();
+            for line in ["req=1 status=200", "ERROR req=2 timeout", "req=2 status=500", "req=3 status=200"] {
+                logs.append(line.to_string());
+            }
+        }
+        let state = app_state(config, process);
 
-        let pattern = args["pattern"].as_str();
-        let context_lines = args["context_lines"].as_u64().map(|n| n as usize);
-        let head = args["head"].as_u64().map(|n| n as usize);
-        let tail = args["tail"].as_u64().map(|n| n as usize);
-        let index = args["index"].as_i64().map(|n| n as i32);
+        let output = state
+            .tool_search_logs(&json!({ "process": "main", "patterns": ["ERROR", "req=2"] }))
+            .await
+            .unwrap();
+        assert_eq!(output, "*1,2 ERROR req=2 timeout\n*2 req=2 status=500");
 
-        let processes = self.processes.read().await;
-        let process = processes
-            .get(process_name)
-            .context(format!("Process '{}' not found", process_name))?;
+        let output = state
+            .tool_search_logs(&json!({ "process": "main", "patterns": ["ERROR", "status=500"], "output": "json" }))
+            .await
+            .unwrap();
+        let results: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(results["lines"][1]["patterns"], json!([1]));
 
-        let results = process
-            .logs
-            .read()
+        let output = state
+            .tool_search_logs(&json!({ "process": "main", "pattern": "status=(?P<code>\\d+)", "capture": "code" }))
+            .await
+            .unwrap();
+        assert_eq!(output, "     2 200\n     1 500");
+        let output = state
+            .tool_search_logs(&json!({ "process": "main", "pattern": "status=(?P<code>\\d+)", "capture": "code", "output": "json" }))
             .await
-            .search(index, pattern, context_lines, head, tail);
+            .unwrap();
+        let results: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(results["values"], json!([{ "value": "200", "count": 2 }, { "value": "500", "count": 1 }]));
 
-        Ok(results.join("\n"))
+        assert!(state
+            .tool_search_logs(&json!({ "process": "main", "patterns": ["a", "b"], "capture": "code" }))
+            .await
+            .is_err());
+        assert!(state
+            .tool_search_logs(&json!({ "process": "main", "pattern": "a", "patterns": ["b"] }))
+            .await
+            .is_err());
     }
 
-    async fn tool_search_build_log(&self, args: &Value) -> Result<String> {
-        let process_name = args["process"]
-            .as_str()
-            .context("Missing 'process' parameter")?;
+    #[tokio::test]
+    async fn test_recent_activity() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process(&config, "main");
+        let minutes_ago = |m| Utc::now() - chrono::Duration::minutes(m);
+        {
+            let mut events = process.events.write().await;
+            events.push_back(ProcessEvent::Started {
+                timestamp: minutes_ago(5),
+                version: "abc123".to_string(),
+                generation: 1,
+            });
+            events.push_back(ProcessEvent::StateChanged {
+                timestamp: minutes_ago(5),
+                from: ProcessState::Starting,
+                to: ProcessState::Running,
+            });
+            events.push_back(ProcessEvent::Crashed {
+                timestamp: minutes_ago(2),
+                exit_code: Some(1),
+                last_lines: Vec::new(),
+                repeats: 1,
+                since: minutes_ago(2),
+            });
+        }
+        process.build_control.history.write().await.push_back(crate::builder::BuildRecord {
+            release: false,
+            started_at: minutes_ago(4),
+            finished_at: minutes_ago(3),
+            duration: std::time::Duration::from_secs(60),
+            outcome: BuildOutcome::Succeeded,
+            warnings: 2,
+            errors: 0,
+        });
+        let state = app_state(config, process);
+        state.mode_manager.switch_to_dev(ModeChangeReason::Restart).await;
 
-        let pattern = args["pattern"].as_str();
-        let context_lines = args["context_lines"].as_u64().map(|n| n as usize);
-        let head = args["head"].as_u64().map(|n| n as usize);
-        let tail = args["tail"].as_u64().map(|n| n as usize);
-        let index = args["index"].as_i64().map(|n| n as i32);
+        let result: Value = serde_json::from_str(&state.tool_get_recent_activity(&json!({})).await.unwrap()).unwrap();
+        let summaries: Vec<_> = result["activity"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|a| a["summary"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            summaries,
+            [
+                "mode changed Release -> Dev (restart)",
+                "crashed (exit code: Some(1))",
+                "dev build succeeded after 60.0s (0 errors, 2 warnings)",
+                "started (abc123)",
+            ]
+        );
+        assert_eq!(result["activity"][1]["process"], "main");
+        assert_eq!(result["activity"][0]["process"], Value::Null);
 
-        let processes = self.processes.read().await;
-        let process = processes
-            .get(process_name)
-            .context(format!("Process '{}' not found", process_name))?;
+        let result: Value =
+            serde_json::from_str(&state.tool_get_recent_activity(&json!({"limit": 1})).await.unwrap()).unwrap();
+        assert_eq!(result["total"], 4);
+        assert_eq!(result["activity"].as_array().unwrap().len(), 1);
+    }
 
-        let results = process
-            .build_logs
-            .read()
+    #[tokio::test]
+    async fn test_status_summary_and_scope() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\ntags = [\"backend\"]\n").unwrap();
+        let process = process(&config, "main");
+        let state = app_state(config, process);
+        state.mode_manager.switch_to_dev(ModeChangeReason::Restart).await;
+
+        assert_eq!(
+            state.tool_get_status(&json!({"summary": true})).await.unwrap(),
+            "mode: dev, release in 2h59m\nmain: not_started"
+        );
+
+        let status = state.tool_get_status(&json!({"process": "main"})).await.unwrap();
+        assert!(
+            status.contains("\n  main: not_started\n    Tags: backend\n    Profile: dev\n    Direnv: inactive (direnv = \"off\")\n"),
+            "{}",
+            status
+        );
+        assert!(state.tool_get_status(&json!({"process": "other"})).await.is_err());
+
+        assert_eq!(
+            state.tool_get_status(&json!({"summary": true, "tag": "backend"})).await.unwrap(),
+            "mode: dev, release in 2h59m\nmain: not_started"
+        );
+        assert!(state.tool_get_status(&json!({"tag": "infra"})).await.is_err());
+        assert!(state.tool_get_status(&json!({"tag": "backend", "process": "main"})).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_restart_all() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process(&config, "main");
+        let state = app_state(config, process);
+        let cancel = CancellationToken::new();
+
+        let result = state.tool_restart(&json!({"all": true}), &cancel, None).await.unwrap();
+        assert_eq!(result, "Restarting all processes in dev mode:\n  - main: skipped (stopped)");
+
+        let err = state.tool_restart(&json!({"all": true, "process": "main"}), &cancel, None).await.unwrap_err();
+        assert_eq!(err.to_string(), "Pass only one of 'process', 'tag' and 'all'");
+    }
+
+    #[tokio::test]
+    async fn test_stop() {
+        let config = Config::parse(
+            "mcp_port = 3001\n\
+             [process.db]\ntype = \"npm\"\ncommand = [\"sleep\", \"60\"]\ntags = [\"backend\"]\n\
+             [process.api]\ntype = \"npm\"\ncommand = [\"sleep\", \"60\"]\ntags = [\"backend\"]\ndepends_on = [\"db\"]\n\
+             [process.web]\ntype = \"npm\"\ncommand = [\"sleep\", \"60\"]\n",
+        )
+        .unwrap();
+        let processes: Vec<_> = ["db", "api", "web"].into_iter().map(|name| process(&config, name)).collect();
+        let state = app_state(config, processes[0].clone());
+        for process in &processes {
+            state.processes.write().await.insert(process.name.clone(), process.clone());
+            process.spawn_npm_process().await.unwrap();
+        }
+
+        let err = state.tool_stop(&json!({"all": true, "tag": "backend"})).await.unwrap_err();
+        assert_eq!(err.to_string(), "Pass only one of 'process', 'tag' and 'all'");
+        assert!(state.tool_stop(&json!({"tag": "nothing"})).await.is_err());
+
+        // Dependents go first
+        let result = state.tool_stop(&json!({"tag": "backend"})).await.unwrap();
+        assert_eq!(result, "Stopping processes tagged 'backend':\n  - api: stopped\n  - db: stopped");
+        let result = state.tool_stop(&json!({"all": true})).await.unwrap();
+        assert_eq!(
+            result,
+            "Stopping all processes:\n  - web: stopped\n  - api: already stopped\n  - db: already stopped"
+        );
+        for process in &processes {
+            assert_eq!(process.state().await, ProcessState::Stopped);
+        }
+
+        processes[0].spawn_npm_process().await.unwrap();
+        assert_eq!(state.tool_stop(&json!({"process": "db"})).await.unwrap(), "Process 'db' stopped");
+        assert_eq!(processes[0].state().await, ProcessState::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_restart_queue() {
+        let mut toml = "mcp_port = 3001\nmax_concurrent_restarts = 1\n".to_string();
+        for name in ["a", "b", "c"] {
+            toml.push_str(&format!(
+                "[process.{}]\ntype = \"npm\"\ncommand = [\"sleep\", \"60\"]\nbuild_command = [\"sleep\", \"0.3\"]\n",
+                name
+            ));
+        }
+        let config = Config::parse(&toml).unwrap();
+        let processes: Vec<_> = ["a", "b", "c"]
+            .into_iter()
+            .map(|name| process(&config, name))
+            .collect();
+        let state = app_state(config, processes[0].clone());
+        for process in &processes {
+            state.processes.write().await.insert(process.name.clone(), process.clone());
+            process.spawn_npm_process().await.unwrap();
+        }
+
+        let restart = tokio::spawn({
+            let state = state.clone();
+            async move { state.tool_restart(&json!({"all": true}), &CancellationToken::new(), None).await }
+        });
+
+        // One slow build at a time, with the others waiting their turn
+        let mut seen_queued = false;
+        while !restart.is_finished() {
+            let status = state.tool_get_status(&json!({})).await.unwrap();
+            assert!(status.matches("Restarting: true").count() <= 1, "{}", status);
+            seen_queued |= status.contains("Queued for restart, position 2");
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(seen_queued);
+
+        let result = restart.await.unwrap().unwrap();
+        assert_eq!(
+            result,
+            "Restarting all processes in dev mode:\n  - a: restarted\n  - b: restarted\n  - c: restarted"
+        );
+        for process in &processes {
+            process.request_stop().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_build() {
+        let config = Config::parse(
+            "mcp_port = 3001\n[process.main]\ntype = \"npm\"\ncommand = [\"sleep\", \"60\"]\nbuild_command = [\"sleep\", \"30\"]\n",
+        )
+        .unwrap();
+        let process = process(&config, "main");
+        let state = app_state(config, process.clone());
+        process.spawn_npm_process().await.unwrap();
+
+        let result = state.tool_cancel(&json!({"process": "main"})).await.unwrap();
+        assert_eq!(result, "No build in progress for 'main'");
+
+        // A restart's build, and a build waiting for it to finish
+        let restart = tokio::spawn({
+            let state = state.clone();
+            async move { state.tool_restart(&json!({"process": "main"}), &CancellationToken::new(), None).await }
+        });
+        while !process.build_control.is_in_progress().await {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        let build = tokio::spawn({
+            let state = state.clone();
+            async move { state.tool_build(&json!({"process": "main"}), &CancellationToken::new(), None).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let result = state.tool_cancel(&json!({"process": "main"})).await.unwrap();
+        assert_eq!(result, "Cancelled build for 'main'");
+        let restart_err = tokio::time::timeout(std::time::Duration::from_secs(5), restart).await.unwrap().unwrap();
+        assert!(format!("{:#}", restart_err.unwrap_err()).contains("Build cancelled"));
+        let build = tokio::time::timeout(std::time::Duration::from_secs(5), build).await.unwrap().unwrap();
+        assert!(build.unwrap().contains("Build cancelled"));
+
+        // Only the build that ran is in the history, and the old instance
+        // was left running
+        let outcomes: Vec<_> = process.build_control.history.read().await.iter().map(|r| r.outcome).collect();
+        assert_eq!(outcomes, [BuildOutcome::Cancelled]);
+        assert_eq!(process.state().await, ProcessState::Running);
+        assert_eq!(process.logs.read().await.instance_count(), 1);
+
+        process.request_stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_process() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"npm\"\ncommand = [\"sh\", \"-c\", \"exit 3\"]\n").unwrap();
+        let process = process(&config, "main");
+        let state = app_state(config, process.clone());
+
+        let info: Value = serde_json::from_str(&state.tool_get_process(&json!({"process": "main"})).await.unwrap()).unwrap();
+        assert_eq!(info["state"], "not_started");
+        assert_eq!(info["type"], "npm");
+        assert_eq!(info["last_exit"], Value::Null);
+        assert_eq!(info["last_build"], Value::Null);
+        assert_eq!(info["direnv"]["active"], false);
+        assert_eq!(info["direnv"]["strategy"], "off");
+
+        process.spawn_npm_process().await.unwrap();
+        process.wait_for_exit().await;
+        let info: Value = serde_json::from_str(&state.tool_get_process(&json!({"process": "main", "events": 1})).await.unwrap()).unwrap();
+        assert_eq!(info["state"], "crashed");
+        assert_eq!(info["last_exit"]["exit_code"], 3);
+        assert!(info["started_at"].is_string());
+        assert_eq!(info["recent_events"].as_array().unwrap().len(), 1);
+
+        assert!(state.tool_get_process(&json!({"process": "other"})).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mode_system_disabled() {
+        let config = Config::parse("mcp_port = 3001\nmode_system = false\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process(&config, "main");
+        let mut state = app_state(config, process);
+        state.mode_manager = Arc::new(ModeManager::new(3, RunMode::Dev).with_mode_system(false));
+
+        let status = state.tool_get_status(&json!({})).await.unwrap();
+        assert!(status.starts_with("Log memory: "), "{}", status);
+        assert!(!status.contains("Mode:"), "{}", status);
+        let summary = state.tool_get_status(&json!({ "summary": true })).await.unwrap();
+        assert_eq!(summary, "main: not_started");
+
+        let mode: Value = serde_json::from_str(&state.tool_get_mode().await.unwrap()).unwrap();
+        assert_eq!(mode["mode"], "dev");
+        assert_eq!(mode["mode_system"], false);
+        assert!(state.tool_keepalive(&json!({ "hours": 8 })).await.unwrap().contains("mode_system = false"));
+        assert!(state.tool_set_mode_config(&json!({ "dev_timeout_hours": 8 })).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mode_hours_validation() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process(&config, "main");
+        let mut state = app_state(config, process);
+        state.mode_manager = Arc::new(ModeManager::new(3, RunMode::Dev));
+
+        // Out of range or fractional hours are refused instead of overflowing
+        for args in [json!({ "dev_timeout_hours": 1e15 }), json!({ "dev_timeout_hours": 1.5 }), json!({ "dev_timeout_hours": 0 })] {
+            assert!(state.tool_set_mode_config(&args).await.is_err(), "{}", args);
+        }
+        for args in [json!({ "hours": u64::MAX }), json!({ "hours": 2.5 }), json!({ "hours": -1 })] {
+            assert!(state.tool_keepalive(&args).await.is_err(), "{}", args);
+        }
+        assert_eq!(state.mode_manager.extended_until().await, None);
+
+        state.tool_set_mode_config(&json!({ "dev_timeout_hours": MAX_DEV_HOURS })).await.unwrap();
+        state.tool_keepalive(&json!({ "hours": MAX_DEV_HOURS })).await.unwrap();
+        assert!(state.mode_manager.extended_until().await.is_some());
+        assert!(!state.mode_manager.should_switch_to_release().await);
+    }
+
+    #[tokio::test]
+    async fn test_status_with_stuck_process() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n[process.other]\ntype = \"rust\"\n").unwrap();
+        let (main, other) = (process(&config, "main"), process(&config, "other"));
+        let state = app_state(config.clone(), main.clone());
+        state.processes.write().await.insert("other".to_string(), other);
+
+        // As held by an operation that hangs
+        let _stuck = main.hold_state_lock().await;
+        let _stuck_logs = main.logs.write().await;
+        let status = tokio::time::timeout(std::time::Duration::from_secs(5), state.tool_get_status(&json!({})))
             .await
-            .search(index, pattern, context_lines, head, tail);
+            .expect("get_status waited for the stuck process")
+            .unwrap();
+        assert!(status.contains("  main: status unavailable (internal lock timeout)"), "{}", status);
+        assert!(status.contains(", not counting main (internal lock timeout)\n"), "{}", status);
+        assert!(status.contains("  other: not_started"), "{}", status);
 
-        Ok(results.join("\n"))
+        let summary = state.tool_get_status(&json!({ "summary": true })).await.unwrap();
+        assert!(summary.ends_with("main: status unavailable (internal lock timeout) | other: not_started"), "{}", summary);
+
+        let list: Value = serde_json::from_str(&state.tool_list_processes(&json!({})).await.unwrap()).unwrap();
+        assert_eq!(list["processes"][0]["state"], "unavailable");
+        assert_eq!(list["processes"][1]["state"], "not_started");
     }
 
-    async fn tool_restart(&self, args: &Value) -> Result<String> {
-        let process_name = args["process"]
-            .as_str()
-            .context("Missing 'process' parameter")?;
+    #[tokio::test]
+    async fn test_manager_info() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process(&config, "main");
+        let started_at = Utc::now() - chrono::Duration::minutes(5);
+        let state = app_state(config, process).with_manager(PathBuf::from("/srv/app"), started_at);
 
-        let processes = self.processes.read().await;
-        let process = processes
-            .get(process_name)
-            .context(format!("Process '{}' not found", process_name))?
-            .clone();
-        drop(processes);
+        let info: Value = serde_json::from_str(&state.tool_get_manager_info().await.unwrap()).unwrap();
+        assert_eq!(info["pid"], std::process::id());
+        assert_eq!(info["started_at"], started_at.to_rfc3339());
+        assert!(info["uptime_seconds"].as_i64().unwrap() >= 300);
+        assert_eq!(info["project_dir"], "/srv/app");
+        assert_eq!(info["process_count"], 1);
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    }
 
-        // Set manual restart flag to prevent crash monitor interference
-        process.set_manual_restart_flag().await;
+    #[tokio::test]
+    async fn test_list_processes() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\ntags = [\"backend\"]\n").unwrap();
+        let process = process(&config, "main");
+        let state = app_state(config, process);
 
-        // Switch back to dev mode on restart
-        self.mode_manager.switch_to_dev().await;
-        let mode = self.mode_manager.get_mode().await;
+        let list = |args: Value| {
+            let state = &state;
+            async move { serde_json::from_str::<Value>(&state.tool_list_processes(&args).await.unwrap()).unwrap() }
+        };
+        assert_eq!(
+            list(json!({})).await,
+            json!({
+                "filters": {"state": null, "tag": null},
+                "total": 1,
+                "processes": [{"name": "main", "state": "not_started", "uptime_seconds": null, "crash_count": 0, "type": "rust"}],
+            })
+        );
+        assert_eq!(list(json!({"tag": "backend", "state": "not_started"})).await["total"], 1);
 
-        // Build FIRST (while old process keeps running)
-        let binary_path = match process.config.process_type {
-            ProcessType::Rust => {
-                let release = matches!(mode, RunMode::Release);
-                Some(self
-                    .builder
-                    .build_rust(release, process.build_logs.clone())
-                    .await?)
-            }
-            ProcessType::Npm => None,
+        let crashed = list(json!({"state": "crashed"})).await;
+        assert_eq!(crashed["total"], 0);
+        assert_eq!(crashed["filters"]["state"], "crashed");
+
+        assert!(state.tool_list_processes(&json!({"state": "exploded"})).await.is_err());
+        assert!(state.tool_list_processes(&json!({"tag": "infra"})).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_log_resources() {
+        let project_dir = PathBuf::from(".");
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process_in(&config, "main", &project_dir);
+        {
+            let mut logs = process.logs.write().await;
+            logs.new_instance();
+            logs.append("first run".to_string());
+            logs.new_instance();
+            logs.append("second run".to_string());
+            logs.append("still second".to_string());
+        }
+        let state = app_state(config, process);
+
+        let response = state.handle_list_resources(json!(1)).await;
+        let resources = response.result.unwrap()["resources"].clone();
+        let uris: Vec<_> = resources.as_array().unwrap().iter().map(|r| r["uri"].clone()).collect();
+        assert_eq!(uris, [json!("log://main/0"), json!("log://main/1")]);
+
+        let read = |uri: &str| {
+            let state = state.clone();
+            let params = json!({ "uri": uri });
+            async move { state.handle_read_resource(json!(1), Some(params)).await }
         };
+        let response = read("log://main/0").await;
+        assert_eq!(response.result.unwrap()["contents"][0]["text"], "first run");
+        let response = read("log://main/-1").await;
+        assert_eq!(response.result.unwrap()["contents"][0]["text"], "second run\nstill second");
 
-        // Now stop the old process
-        process.stop().await?;
+        for uri in ["log://main/2", "log://other/0", "log://main", "file:///etc/passwd"] {
+            let response = read(uri).await;
+            assert_eq!(response.error.unwrap().code, -32002, "{}", uri);
+        }
+    }
 
-        // Start the new process
-        match process.config.process_type {
-            ProcessType::Rust => {
-                if let Some(binary_path) = binary_path {
-                    process.spawn_process(binary_path).await?;
-                }
-            }
-            ProcessType::Npm => {
-                process.spawn_npm_process().await?;
+    #[tokio::test]
+    async fn test_completion() {
+        let mut toml = "mcp_port = 3001\n".to_string();
+        for (name, tag) in [("api", "backend"), ("api-gateway", "backend"), ("frontend", "web")] {
+            toml.push_str(&format!("[process.{}]\ntype = \"rust\"\ntags = [\"{}\"]\n", name, tag));
+        }
+        let config = Config::parse(&toml).unwrap();
+        let (api, gateway, frontend) =
+            (process(&config, "api"), process(&config, "api-gateway"), process(&config, "frontend"));
+        let state = app_state(config.clone(), api);
+        state.processes.write().await.insert("api-gateway".to_string(), gateway);
+        state.processes.write().await.insert("frontend".to_string(), frontend);
+
+        let complete = |reference: Value, argument: Value| {
+            let state = state.clone();
+            async move {
+                state
+                    .handle_request(JsonRpcRequest {
+                        id: Some(json!(1)),
+                        method: "completion/complete".to_string(),
+                        params: Some(json!({ "ref": reference, "argument": argument })),
+                    })
+                    .await
             }
+        };
+        let values = |response: JsonRpcResponse| response.result.unwrap()["completion"]["values"].clone();
+
+        let initialize = state
+            .handle_request(JsonRpcRequest { id: Some(json!(0)), method: "initialize".to_string(), params: None })
+            .await;
+        assert!(initialize.result.unwrap()["capabilities"]["completions"].is_object());
+
+        // Process names across tools and prompts, filtered by the prefix
+        let tool = json!({"type": "ref/tool", "name": "restart"});
+        let response = complete(tool.clone(), json!({"name": "process", "value": "api"})).await;
+        assert_eq!(values(response), json!(["api", "api-gateway"]));
+        let response = complete(json!({"type": "ref/prompt", "name": "diagnose_crash"}), json!({"name": "process", "value": "f"})).await;
+        assert_eq!(values(response), json!(["frontend"]));
+        let response = complete(tool.clone(), json!({"name": "process", "value": ""})).await;
+        assert_eq!(response.result.unwrap()["completion"]["total"], 3);
+        let response = complete(tool.clone(), json!({"name": "tag", "value": "b"})).await;
+        assert_eq!(values(response), json!(["backend"]));
+
+        // Arguments without names to complete
+        let response = complete(json!({"type": "ref/tool", "name": "search_logs"}), json!({"name": "pattern", "value": "x"})).await;
+        assert_eq!(values(response), json!([]));
+
+        // The variables of the log resource template, the index given the
+        // process already filled in
+        let templates = state
+            .handle_request(JsonRpcRequest { id: Some(json!(0)), method: "resources/templates/list".to_string(), params: None })
+            .await;
+        assert_eq!(templates.result.unwrap()["resourceTemplates"][0]["uriTemplate"], LOG_URI_TEMPLATE);
+        let resource = json!({"type": "ref/resource", "uri": LOG_URI_TEMPLATE});
+        let response = complete(resource.clone(), json!({"name": "process", "value": "front"})).await;
+        assert_eq!(values(response), json!(["frontend"]));
+        for _ in 0..2 {
+            state.processes.read().await["api"].logs.write().await.new_instance();
         }
+        let response = state
+            .handle_request(JsonRpcRequest {
+                id: Some(json!(1)),
+                method: "completion/complete".to_string(),
+                params: Some(json!({
+                    "ref": resource,
+                    "argument": {"name": "index", "value": ""},
+                    "context": {"arguments": {"process": "api"}}
+                })),
+            })
+            .await;
+        assert_eq!(values(response), json!(["0", "1"]));
+        let response = complete(resource.clone(), json!({"name": "index", "value": "0"})).await;
+        assert_eq!(values(response), json!([]));
 
-        // Clear manual restart flag
-        process.clear_manual_restart_flag().await;
+        // Unknown references and arguments
+        for (reference, argument) in [
+            (json!({"type": "ref/tool", "name": "deploy"}), "process"),
+            (json!({"type": "ref/prompt", "name": "health_overview"}), "process"),
+            (tool.clone(), "color"),
+            (json!({"type": "ref/resource", "uri": "log://api/0"}), "process"),
+            (resource.clone(), "tag"),
+        ] {
+            let response = complete(reference, json!({"name": argument, "value": ""})).await;
+            assert_eq!(response.error.unwrap().code, -32602);
+        }
+    }
 
-        // Reset crash handler
-        let mut handlers = self.crash_handlers.write().await;
-        if let Some(handler) = handlers.get_mut(process_name) {
-            handler.reset_crash_count();
+    #[tokio::test]
+    async fn test_prompts() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process(&config, "main");
+        {
+            let mut logs = process.logs.write().await;
+            logs.new_instance();
+            logs.append("thread 'main' panicked at src/main.rs:3:5".to_string());
+        }
+        process.events.write().await.push_back(ProcessEvent::Crashed {
+            timestamp: Utc::now(),
+            exit_code: Some(101),
+            last_lines: Vec::new(),
+            repeats: 1,
+            since: Utc::now(),
+        });
+        {
+            let mut logs = process.build_logs.write().await;
+            logs.new_instance();
+            logs.append("error[E0425]: cannot find value `x` in this scope".to_string());
         }
+        let state = app_state(config, process);
+
+        let response = state.handle_list_prompts(json!(1));
+        let prompts = response.result.unwrap()["prompts"].clone();
+        let names: Vec<_> = prompts.as_array().unwrap().iter().map(|p| p["name"].clone()).collect();
+        assert_eq!(names, [json!("diagnose_crash"), json!("summarize_build_failure"), json!("health_overview")]);
+
+        let get = |name: &str, arguments: Value| {
+            let state = state.clone();
+            let params = json!({ "name": name, "arguments": arguments });
+            async move { state.handle_get_prompt(json!(1), Some(params)).await }
+        };
+        let text = |response: JsonRpcResponse| {
+            response.result.unwrap()["messages"][0]["content"]["text"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let crash = text(get("diagnose_crash", json!({"process": "main"})).await);
+        assert!(crash.contains("exit code: Some(101)"), "{}", crash);
+        assert!(crash.contains("```\nthread 'main' panicked at src/main.rs:3:5\n```"), "{}", crash);
+
+        let build = text(get("summarize_build_failure", json!({"process": "main"})).await);
+        assert!(build.contains("error[E0425]: cannot find value `x` in this scope"), "{}", build);
+
+        let health = text(get("health_overview", json!({})).await);
+        assert!(health.contains("main: not_started"), "{}", health);
 
-        Ok(format!("Process '{}' restarted successfully in dev mode", process_name))
+        for (name, arguments) in [
+            ("diagnose_crash", json!({})),
+            ("diagnose_crash", json!({"process": "other"})),
+            ("summarize_build_failure", json!({"process": "other"})),
+            ("unknown", json!({})),
+        ] {
+            let response = get(name, arguments).await;
+            assert_eq!(response.error.unwrap().code, -32602, "{}", name);
+        }
     }
 
-    async fn tool_get_status(&self) -> Result<String> {
-        let mode = self.mode_manager.get_mode().await;
-        let time_until_release = self.mode_manager.get_time_until_release_mode().await;
+    #[tokio::test]
+    async fn test_cancel_tool_call() {
+        let config = Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let process = process(&config, "main");
+        let state = app_state(config, process.clone());
+        let request = |id: Option<Value>, method: &str, params: Value| JsonRpcRequest {
+            id,
+            method: method.to_string(),
+            params: Some(params),
+        };
+        let restart = json!({ "name": "restart", "arguments": { "process": "main" } });
 
-        let mut status = format!("Mode: {:?}\n", mode);
-        if let Some(time) = time_until_release {
-            status.push_str(&format!(
-                "Time until release mode: {} hours {} minutes\n",
-                time.num_hours(),
-                time.num_minutes() % 60
-            ));
-        } else {
-            status.push_str("Currently in release mode\n");
+        // Cancelled while waiting for another restart of the same process
+        let other_restart = process.restart_lock.lock().await;
+        let call = tokio::spawn({
+            let state = state.clone();
+            let restart = request(Some(json!(7)), "tools/call", restart);
+            async move { state.handle_request(restart).await }
+        });
+        while !state.in_flight.lock().unwrap().contains_key("7") {
+            tokio::task::yield_now().await;
         }
-        status.push_str("\nProcesses:\n");
+        state
+            .handle_request(request(None, "notifications/cancelled", json!({ "requestId": 7, "reason": "user" })))
+            .await;
+        let response = tokio::time::timeout(std::time::Duration::from_secs(5), call)
+            .await
+            .expect("cancelled call should return immediately")
+            .unwrap();
+        drop(other_restart);
+        let error = response.error.unwrap();
+        assert!(error.message.contains("Restart cancelled"), "{}", error.message);
+        assert!(process.build_control.history.read().await.is_empty());
+        assert!(state.in_flight.lock().unwrap().is_empty());
 
-        let processes = self.processes.read().await;
-        for (name, process) in processes.iter() {
-            let state = process.state.read().await;
-            status.push_str(&format!("\n  {}: {}\n", name, state.as_str()));
+        // Cancelling a call that already finished does nothing
+        let response = state
+            .handle_request(request(Some(json!("done")), "tools/call", json!({ "name": "get_mode", "arguments": {} })))
+            .await;
+        assert!(response.error.is_none());
+        assert!(state.in_flight.lock().unwrap().is_empty());
+        state
+            .handle_request(request(None, "notifications/cancelled", json!({ "requestId": "done" })))
+            .await;
+        assert!(state.in_flight.lock().unwrap().is_empty());
+    }
 
-            if let Some(uptime) = process.get_uptime().await {
-                status.push_str(&format!(
-                    "    Uptime: {} hours {} minutes\n",
-                    uptime.num_hours(),
-                    uptime.num_minutes() % 60
-                ));
-            }
+    #[tokio::test]
+    async fn test_call_slots() {
+        let config = Config::parse(
+            "mcp_port = 3001\n[mcp.limits]\nmax_concurrent_calls = 1\n[process.main]\ntype = \"rust\"\n",
+        )
+        .unwrap();
+        let process = process(&config, "main");
+        let state = app_state(config, process);
+        let request = |id: Option<Value>, method: &str, params: Value| JsonRpcRequest {
+            id,
+            method: method.to_string(),
+            params: Some(params),
+        };
+        let call = |id: u64, name: &str, arguments: Value| {
+            let state = state.clone();
+            let request = request(Some(json!(id)), "tools/call", json!({ "name": name, "arguments": arguments }));
+            tokio::spawn(async move { state.handle_request(request).await })
+        };
 
-            let events = process.events.read().await;
-            if !events.is_empty() {
-                status.push_str("    Recent events:\n");
-                for event in events.iter().rev().take(5) {
-                    status.push_str(&format!("      - {}\n", event.description()));
-                }
-            }
+        // A long-poll takes the only slot
+        let waiting = call(1, "wait_logs", json!({ "process": "main", "timeout_seconds": 60 }));
+        let permits = state.call_permits.clone().unwrap();
+        while permits.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
 
-            let handlers = self.crash_handlers.read().await;
-            if let Some(handler) = handlers.get(name) {
-                let crash_count = handler.get_crash_count();
-                if crash_count > 0 {
-                    status.push_str(&format!("    Crash count: {}\n", crash_count));
-                }
-            }
+        // Cancel and status reads don't need one
+        for (id, name) in [(2, "cancel"), (3, "get_status"), (4, "get_mode")] {
+            let response = tokio::time::timeout(std::time::Duration::from_secs(5), call(id, name, json!({ "process": "main" })))
+                .await
+                .unwrap_or_else(|_| panic!("{} waited for a slot", name))
+                .unwrap();
+            assert!(response.error.is_none(), "{}", name);
         }
 
-        Ok(status)
+        // A call queued for a slot can be cancelled before it gets one
+        let queued = call(5, "search_logs", json!({ "process": "main", "pattern": "x" }));
+        while !state.in_flight.lock().unwrap().contains_key("5") {
+            tokio::task::yield_now().await;
+        }
+        for id in [5, 1] {
+            state
+                .handle_request(request(None, "notifications/cancelled", json!({ "requestId": id })))
+                .await;
+        }
+        for call in [queued, waiting] {
+            let response = tokio::time::timeout(std::time::Duration::from_secs(5), call)
+                .await
+                .expect("cancelled call should return immediately")
+                .unwrap();
+            assert!(response.error.unwrap().message.to_lowercase().contains("cancelled"));
+        }
+        assert_eq!(permits.available_permits(), 1);
     }
-}
 
-async fn handle_post(
-    State(state): State<AppState>,
-    Json(request): Json<JsonRpcRequest>,
-) -> Response {
-    let response = state.handle_request(request).await;
-    Json(response).into_response()
-}
+    #[tokio::test]
+    async fn test_restart_outside_profiles() {
+        let config = Config::parse(
+            "mcp_port = 3001\n[process.proxy]\ntype = \"npm\"\ncommand = [\"sleep\", \"60\"]\nprofiles = [\"release\"]\n",
+        )
+        .unwrap();
+        let process = process(&config, "proxy");
+        let state = app_state(config, process.clone());
+        let cancel = CancellationToken::new();
 
-async fn handle_get(
-    State(_state): State<AppState>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    // For now, we just send keep-alive events
-    // In the future, this could be used for server-initiated messages
-    let stream = stream::iter(vec![
-        Ok(Event::default().comment("connected")),
-    ]);
+        // A restart would switch to dev mode, where the process doesn't run
+        let err = state.tool_restart(&json!({ "process": "proxy" }), &cancel, None).await.unwrap_err();
+        assert!(err.to_string().contains("Process 'proxy' doesn't run in dev mode"), "{}", err);
+        let result = state.tool_restart(&json!({ "all": true }), &cancel, None).await.unwrap();
+        assert!(result.contains("proxy: skipped (doesn't run in dev mode)"), "{}", result);
+        assert_eq!(state.mode_manager.get_mode().await, RunMode::Release);
+        assert_eq!(process.state().await, ProcessState::NotStarted);
+    }
 
-    Sse::new(stream).keep_alive(KeepAlive::default())
-}
+    #[tokio::test]
+    async fn test_restart_progress() {
+        let config =
+            Config::parse("mcp_port = 3001\n[process.main]\ntype = \"npm\"\ncommand = [\"sleep\", \"60\"]\n").unwrap();
+        let process = process(&config, "main");
+        process.spawn_npm_process().await.unwrap();
+        let state = app_state(config, process.clone());
+        let mut notifications = state.notifications.subscribe();
+        let restart = |meta: Value| JsonRpcRequest {
+            id: Some(json!(1)),
+            method: "tools/call".to_string(),
+            params: Some(json!({ "name": "restart", "arguments": { "process": "main" }, "_meta": meta })),
+        };
 
-pub async fn create_router(state: AppState) -> Router {
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST])
-        .allow_headers([header::CONTENT_TYPE]);
+        let response = state.handle_request(restart(json!({ "progressToken": "abc" }))).await;
+        assert!(response.error.is_none());
+        let mut messages = Vec::new();
+        while let Ok(notification) = notifications.try_recv() {
+            assert_eq!(notification["method"], "notifications/progress");
+            assert_eq!(notification["params"]["progressToken"], "abc");
+            assert_eq!(notification["params"]["progress"], messages.len() as u64 + 1);
+            messages.push(notification["params"]["message"].as_str().unwrap().to_string());
+        }
+        assert_eq!(messages, ["main: stopping", "main: starting", "main: ready"]);
 
-    Router::new()
-        .route("/mcp", post(handle_post).get(handle_get))
-        .layer(cors)
-        .with_state(state)
-}
+        // Without a token, nothing is sent
+        let response = state.handle_request(restart(json!({}))).await;
+        assert!(response.error.is_none());
+        assert!(notifications.try_recv().is_err());
 
-pub async fn start_server(state: AppState, port: u16) -> Result<()> {
-    let app = create_router(state).await;
+        process.request_stop().await.unwrap();
+    }
 
-    let addr = format!("127.0.0.1:{}", port);
-    let listener = tokio::net::TcpListener::bind(&addr)
-        .await
-        .context(format!("Failed to bind to {}", addr))?;
+    #[tokio::test]
+    async fn test_restart_progress_with_build() {
+        // A build_command printing cargo's progress lines, and a process that
+        // is ready once it logs a line
+        let config = Config::parse(concat!(
+            "mcp_port = 3001\n[process.main]\ntype = \"npm\"\n",
+            "build_command = [\"sh\", \"-c\", \"echo '   Compiling foo v0.1.0'; sleep 0.3; echo '   Compiling bar v0.2.0'; sleep 0.3\"]\n",
+            "command = [\"sh\", \"-c\", \"sleep 0.5; echo listening; sleep 60\"]\n",
+            "ready_pattern = \"listening\"\n",
+        ))
+        .unwrap();
+        let process = process(&config, "main");
+        let state = app_state(config, process.clone());
+        let mut notifications = state.notifications.subscribe();
+
+        let response = state
+            .handle_request(JsonRpcRequest {
+                id: Some(json!(1)),
+                method: "tools/call".to_string(),
+                params: Some(json!({
+                    "name": "restart",
+                    "arguments": { "process": "main" },
+                    "_meta": { "progressToken": 7 }
+                })),
+            })
+            .await;
+        assert!(response.error.is_none());
+        let mut messages = Vec::new();
+        while let Ok(notification) = notifications.try_recv() {
+            messages.push(notification["params"]["message"].as_str().unwrap().to_string());
+        }
+        assert_eq!(messages[..3], ["main: building", "main: compiled 1 crates", "main: compiled 2 crates"]);
+        assert_eq!(messages[3..5], ["main: stopping", "main: starting"]);
+        // Reported once the ready_pattern matched, not right after spawning
+        assert!(messages[5].starts_with("main: ready after "), "{:?}", messages);
+        assert!(messages[5].contains("'listening'"), "{:?}", messages);
+        assert_eq!(messages.len(), 6);
 
-    info!("MCP HTTP server listening on http://{}/mcp", addr);
+        process.request_stop().await.unwrap();
+    }
 
-    axum::serve(listener, app)
-        .await
-        .context("Server error")?;
+    #[tokio::test]
+    async fn test_wait_logs() {
+        let config = Config::parse(
+            "mcp_port = 3001\n[process.main]\ntype = \"npm\"\ncommand = [\"sh\", \"-c\", \"sleep 0.5; echo one; echo two; sleep 60\"]\n",
+        )
+        .unwrap();
+        let process = process(&config, "main");
+        process.spawn_npm_process().await.unwrap();
+        let state = app_state(config, process.clone());
+        let cancel = CancellationToken::new();
 
-    Ok(())
+        // Blocks until the process logs something
+        let result = state.tool_wait_logs(&json!({ "process": "main", "timeout_seconds": 10 }), &cancel).await.unwrap();
+        let result: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(result["timed_out"], false);
+        let first = result["lines"].as_array().unwrap().clone();
+        assert_eq!(first[0], "one");
+
+        // Picks up after since_seq, and times out once nothing new comes
+        let mut seq = result["latest_seq"].clone();
+        if first.len() == 1 {
+            let result = state
+                .tool_wait_logs(&json!({ "process": "main", "since_seq": seq, "timeout_seconds": 10 }), &cancel)
+                .await
+                .unwrap();
+            let result: Value = serde_json::from_str(&result).unwrap();
+            assert_eq!(result["lines"], json!(["two"]));
+            seq = result["latest_seq"].clone();
+        }
+        assert_eq!(seq, 2);
+        let result = state
+            .tool_wait_logs(&json!({ "process": "main", "since_seq": seq, "timeout_seconds": 1 }), &cancel)
+            .await
+            .unwrap();
+        let result: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(result["timed_out"], true);
+        assert_eq!(result["lines"], json!([]));
+        assert_eq!(result["latest_seq"], seq);
+
+        // The same numbers as search_logs, so either can continue the other
+        let result = state
+            .tool_wait_logs(&json!({ "process": "main", "since_seq": 1, "timeout_seconds": 1 }), &cancel)
+            .await
+            .unwrap();
+        let result: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(result["lines"], json!(["two"]));
+
+        let err = state
+            .tool_wait_logs(&json!({ "process": "main", "since_seq": 100 }), &cancel)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("ahead of the log"), "{}", err);
+
+        process.request_stop().await.unwrap();
+    }
+
+    #[test]
+    fn test_negotiate_protocol_version() {
+        let params = |v: &str| json!({ "protocolVersion": v, "capabilities": {} });
+
+        assert_eq!(negotiate_protocol_version(Some(&params("2024-11-05"))), "2024-11-05");
+        assert_eq!(negotiate_protocol_version(Some(&params("2025-03-26"))), "2025-03-26");
+        assert_eq!(negotiate_protocol_version(Some(&params("2099-01-01"))), SUPPORTED_PROTOCOL_VERSIONS[0]);
+        assert_eq!(negotiate_protocol_version(None), SUPPORTED_PROTOCOL_VERSIONS[0]);
+    }
+
+    #[tokio::test]
+    async fn test_restart_build_failure_keeps_old_process() {
+        let project_dir = broken_project("restart-build-fails");
+        let config = Config::parse(
+            "mcp_port = 3001\n[process.main]\ntype = \"rust\"\nargs = [\"60\"]\n",
+        )
+        .unwrap();
+        let process = process_in(&config, "main", &project_dir);
+
+        // Stand-in for the old process: something that keeps running
+        process.spawn_process(PathBuf::from("sleep")).await.unwrap();
+        let state = app_state(config, process.clone());
+
+        let result = state.tool_restart(&json!({ "process": "main" }), &CancellationToken::new(), None).await;
+        assert!(result.is_err());
+
+        assert!(!process.is_manual_restart_in_progress().await);
+        assert_eq!(process.state().await, ProcessState::Running);
+        assert!(process
+            .build_logs
+            .read()
+            .await
+            .search(None, Some("Cargo.toml"), None, None, None)
+            .iter()
+            .any(|line| line.starts_with(" * ")));
+
+        process.request_stop().await.unwrap();
+        std::fs::remove_dir_all(project_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bind_listener_port_in_use() {
+        // Hold a port, as another manager instance would
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let mut config = Config::parse(&format!("mcp_port = {}\n[process.main]\ntype = \"rust\"\n", port)).unwrap();
+
+        let err = bind_listener(&config).await.unwrap_err().to_string();
+        assert!(err.contains(&format!("Port {} is already in use", port)), "{}", err);
+
+        config.mcp_port_fallback = true;
+        let listener = bind_listener(&config).await.unwrap();
+        let fallback = listener.local_addr().unwrap().port();
+        assert!(fallback > port && fallback <= port + config.mcp_port_fallback_range, "{}", fallback);
+
+        // The state reports the port in use rather than the configured one
+        let project_dir = broken_project("bind");
+        let config = Config::parse(&format!("mcp_port = {}\n[process.main]\ntype = \"rust\"\n", port)).unwrap();
+        let process = process_in(&config, "main", &project_dir);
+        let state = app_state(config, process).with_listen_port(fallback);
+        let info: Value = serde_json::from_str(&state.tool_get_manager_info().await.unwrap()).unwrap();
+        assert_eq!(info["mcp_port"], fallback);
+        std::fs::remove_dir_all(project_dir).unwrap();
+    }
 }
@@ -1,22 +1,126 @@
 use crate::crash_handler::RunMode;
 use chrono::{DateTime, Duration, Utc};
-use tokio::sync::RwLock;
+use std::collections::VecDeque;
+use tokio::sync::{mpsc, RwLock};
+
+/// Number of mode changes kept for get_recent_activity.
+const MAX_MODE_CHANGES: usize = 50;
+
+/// Longest dev timeout or keepalive extension, a year. Anything longer is
+/// refused, rather than overflowing the clock.
+pub const MAX_DEV_HOURS: u64 = 24 * 365;
+
+/// Why the mode changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModeChangeReason {
+    /// No tool calls within the dev timeout.
+    Timeout,
+    /// A restart switched back to dev mode.
+    Restart,
+}
+
+impl ModeChangeReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModeChangeReason::Timeout => "timeout",
+            ModeChangeReason::Restart => "restart",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ModeChange {
+    pub timestamp: DateTime<Utc>,
+    pub from: RunMode,
+    pub to: RunMode,
+    pub reason: ModeChangeReason,
+}
+
+impl ModeChange {
+    /// One-line summary without the timestamp, for the activity feed.
+    pub fn summary(&self) -> String {
+        format!("mode changed {:?} -> {:?} ({})", self.from, self.to, self.reason.as_str())
+    }
+
+    pub fn description(&self) -> String {
+        format!(
+            "{} ({:?} -> {:?}, {})",
+            self.timestamp,
+            self.from,
+            self.to,
+            self.reason.as_str()
+        )
+    }
+}
 
 pub struct ModeManager {
     current_mode: RwLock<RunMode>,
     last_tool_call: RwLock<DateTime<Utc>>,
-    dev_timeout_hours: i64,
+    /// Adjustable at runtime with set_mode_config.
+    dev_timeout_hours: RwLock<i64>,
+    /// Dev mode held until at least this time by keepalive with hours.
+    extended_until: RwLock<Option<DateTime<Utc>>>,
+    /// Whether dev mode times out to release mode at all.
+    auto_release: bool,
+    /// When off, the mode never changes from the initial one.
+    mode_system: bool,
+    /// Recent mode changes, oldest first.
+    changes: RwLock<VecDeque<ModeChange>>,
+    /// Why a due switch to release mode is being held off, if it is.
+    release_deferral: RwLock<Option<String>>,
+    /// Receives every mode change, if set.
+    events: Option<mpsc::UnboundedSender<ModeChange>>,
 }
 
 impl ModeManager {
-    pub fn new(dev_timeout_hours: u64) -> Self {
+    pub fn new(dev_timeout_hours: u64, initial_mode: RunMode) -> Self {
         Self {
-            current_mode: RwLock::new(RunMode::Release),
+            current_mode: RwLock::new(initial_mode),
             last_tool_call: RwLock::new(Utc::now()),
-            dev_timeout_hours: dev_timeout_hours as i64,
+            dev_timeout_hours: RwLock::new(dev_timeout_hours as i64),
+            extended_until: RwLock::new(None),
+            auto_release: true,
+            mode_system: true,
+            changes: RwLock::new(VecDeque::new()),
+            release_deferral: RwLock::new(None),
+            events: None,
+        }
+    }
+
+    /// Like `new`, but sends every mode change to `events`.
+    pub fn with_events(
+        dev_timeout_hours: u64,
+        initial_mode: RunMode,
+        events: mpsc::UnboundedSender<ModeChange>,
+    ) -> Self {
+        Self {
+            events: Some(events),
+            ..Self::new(dev_timeout_hours, initial_mode)
         }
     }
 
+    /// Turns the timeout to release mode on or off.
+    pub fn with_auto_release(mut self, auto_release: bool) -> Self {
+        self.auto_release = auto_release;
+        self
+    }
+
+    pub fn auto_release(&self) -> bool {
+        self.auto_release
+    }
+
+    /// Turns mode changes on or off. Without them, the initial mode is kept
+    /// for good and nothing times out.
+    pub fn with_mode_system(mut self, mode_system: bool) -> Self {
+        self.mode_system = mode_system;
+        self.auto_release &= mode_system;
+        self
+    }
+
+    pub fn mode_system(&self) -> bool {
+        self.mode_system
+    }
+
     pub async fn record_tool_call(&self) {
         *self.last_tool_call.write().await = Utc::now();
     }
@@ -25,36 +129,135 @@ impl ModeManager {
         *self.current_mode.read().await
     }
 
+    pub async fn last_tool_call(&self) -> DateTime<Utc> {
+        *self.last_tool_call.read().await
+    }
+
+    pub async fn dev_timeout_hours(&self) -> u64 {
+        *self.dev_timeout_hours.read().await as u64
+    }
+
+    /// Changes the dev timeout, counting from the last tool call as before.
+    pub async fn set_dev_timeout_hours(&self, hours: u64) {
+        *self.dev_timeout_hours.write().await = hours as i64;
+    }
+
+    pub async fn extended_until(&self) -> Option<DateTime<Utc>> {
+        *self.extended_until.read().await
+    }
+
+    /// Holds dev mode for at least `hours` from now, even without tool calls.
+    /// Never shortens an earlier extension.
+    pub async fn extend_dev_mode(&self, hours: u64) {
+        let Some(until) = i64::try_from(hours)
+            .ok()
+            .and_then(Duration::try_hours)
+            .and_then(|hours| Utc::now().checked_add_signed(hours))
+        else {
+            return;
+        };
+        let mut extended_until = self.extended_until.write().await;
+        if extended_until.is_none_or(|current| current < until) {
+            *extended_until = Some(until);
+        }
+    }
+
+    /// When dev mode times out: the dev timeout after the last tool call, or
+    /// the end of an extension if that is later.
+    async fn release_due_at(&self) -> DateTime<Utc> {
+        let timeout = Duration::try_hours(*self.dev_timeout_hours.read().await);
+        let last_tool_call = *self.last_tool_call.read().await;
+        let due = timeout
+            .and_then(|timeout| last_tool_call.checked_add_signed(timeout))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        match *self.extended_until.read().await {
+            Some(until) if until > due => until,
+            _ => due,
+        }
+    }
+
     pub async fn should_switch_to_release(&self) -> bool {
+        if !self.auto_release {
+            return false;
+        }
         let current_mode = *self.current_mode.read().await;
         if matches!(current_mode, RunMode::Release) {
             return false; // Already in release mode
         }
 
-        let last_call = *self.last_tool_call.read().await;
-        let elapsed = Utc::now() - last_call;
-        elapsed > Duration::hours(self.dev_timeout_hours)
+        Utc::now() > self.release_due_at().await
     }
 
-    pub async fn switch_to_release(&self) {
-        *self.current_mode.write().await = RunMode::Release;
+    pub async fn switch_to_release(&self, reason: ModeChangeReason) {
+        self.switch_to(RunMode::Release, reason).await;
     }
 
-    pub async fn switch_to_dev(&self) {
-        *self.current_mode.write().await = RunMode::Dev;
+    pub async fn switch_to_dev(&self, reason: ModeChangeReason) {
+        self.switch_to(RunMode::Dev, reason).await;
     }
 
+    /// Sets the mode, recording and emitting a change event unless it was
+    /// already set.
+    async fn switch_to(&self, mode: RunMode, reason: ModeChangeReason) {
+        let mut current_mode = self.current_mode.write().await;
+        if *current_mode == mode || !self.mode_system {
+            return;
+        }
+
+        let change = ModeChange {
+            timestamp: Utc::now(),
+            from: *current_mode,
+            to: mode,
+            reason,
+        };
+        *current_mode = mode;
+        {
+            let mut changes = self.changes.write().await;
+            if changes.len() >= MAX_MODE_CHANGES {
+                changes.pop_front();
+            }
+            changes.push_back(change.clone());
+        }
+        *self.release_deferral.write().await = None;
+        if mode == RunMode::Release {
+            *self.extended_until.write().await = None;
+        }
+
+        if let Some(ref events) = self.events {
+            // The receiver going away only means nobody is listening anymore
+            let _ = events.send(change);
+        }
+    }
+
+    /// Records why a due switch to release mode is being held off, or clears
+    /// it with `None`.
+    pub async fn set_release_deferral(&self, reason: Option<String>) {
+        *self.release_deferral.write().await = reason;
+    }
+
+    pub async fn release_deferral(&self) -> Option<String> {
+        self.release_deferral.read().await.clone()
+    }
+
+    /// The most recent mode change, if the mode has changed since startup.
+    pub async fn last_change(&self) -> Option<ModeChange> {
+        self.changes.read().await.back().cloned()
+    }
+
+    /// Recent mode changes, oldest first.
+    pub async fn changes(&self) -> Vec<ModeChange> {
+        self.changes.read().await.iter().cloned().collect()
+    }
+
+    /// Time left before dev mode times out, or `None` in release mode or
+    /// with auto_release off.
     pub async fn get_time_until_release_mode(&self) -> Option<Duration> {
         let current_mode = *self.current_mode.read().await;
-        if matches!(current_mode, RunMode::Release) {
-            return None; // Already in release mode
+        if matches!(current_mode, RunMode::Release) || !self.auto_release {
+            return None; // Already in release mode, or never switching
         }
 
-        let last_call = *self.last_tool_call.read().await;
-        let elapsed = Utc::now() - last_call;
-        let timeout = Duration::hours(self.dev_timeout_hours);
-
-        let remaining = timeout - elapsed;
+        let remaining = self.release_due_at().await - Utc::now();
         if remaining.num_seconds() > 0 {
             Some(remaining)
         } else {
@@ -69,13 +272,13 @@ mod tests {
 
     #[tokio::test]
     async fn test_mode_manager() {
-        let manager = ModeManager::new(1); // 1 hour timeout
+        let manager = ModeManager::new(1, RunMode::Release); // 1 hour timeout
 
         // Should start in release mode (designed for system boot)
         assert!(matches!(manager.get_mode().await, RunMode::Release));
 
         // Switch to dev mode
-        manager.switch_to_dev().await;
+        manager.switch_to_dev(ModeChangeReason::Restart).await;
         assert!(matches!(manager.get_mode().await, RunMode::Dev));
 
         // Initially should not need to switch back to release
@@ -87,16 +290,95 @@ mod tests {
         // Now should switch back to release
         assert!(manager.should_switch_to_release().await);
 
-        manager.switch_to_release().await;
+        manager.switch_to_release(ModeChangeReason::Timeout).await;
         assert!(matches!(manager.get_mode().await, RunMode::Release));
     }
 
+    #[tokio::test]
+    async fn test_adjust_timer() {
+        let manager = ModeManager::new(1, RunMode::Dev);
+        *manager.last_tool_call.write().await = Utc::now() - Duration::hours(2);
+        assert!(manager.should_switch_to_release().await);
+
+        // A longer timeout applies to the existing last tool call
+        manager.set_dev_timeout_hours(3).await;
+        assert!(!manager.should_switch_to_release().await);
+        assert_eq!(manager.get_time_until_release_mode().await.unwrap().num_minutes(), 59);
+
+        // An extension outlasts the timeout, and isn't shortened by a smaller one
+        manager.extend_dev_mode(5).await;
+        manager.extend_dev_mode(2).await;
+        assert!(manager.get_time_until_release_mode().await.unwrap().num_minutes() >= 4 * 60 + 59);
+        manager.set_dev_timeout_hours(1).await;
+        assert!(!manager.should_switch_to_release().await);
+
+        // Switching to release mode ends the extension
+        manager.switch_to_release(ModeChangeReason::Timeout).await;
+        assert!(manager.extended_until().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_initial_dev_mode() {
+        // Counts down from startup, without recording a mode change
+        let manager = ModeManager::new(1, RunMode::Dev);
+        assert!(matches!(manager.get_mode().await, RunMode::Dev));
+        assert!(manager.last_change().await.is_none());
+        assert!(manager.get_time_until_release_mode().await.unwrap().num_minutes() >= 59);
+    }
+
+    #[tokio::test]
+    async fn test_auto_release_disabled() {
+        let manager = ModeManager::new(1, RunMode::Release).with_auto_release(false);
+        manager.switch_to_dev(ModeChangeReason::Restart).await;
+        *manager.last_tool_call.write().await = Utc::now() - Duration::hours(2);
+
+        assert!(!manager.should_switch_to_release().await);
+        assert!(manager.get_time_until_release_mode().await.is_none());
+        assert!(matches!(manager.get_mode().await, RunMode::Dev));
+    }
+
+    #[tokio::test]
+    async fn test_mode_system_disabled() {
+        let manager = ModeManager::new(1, RunMode::Release).with_mode_system(false);
+        manager.switch_to_dev(ModeChangeReason::Restart).await;
+        *manager.last_tool_call.write().await = Utc::now() - Duration::hours(2);
+
+        assert!(matches!(manager.get_mode().await, RunMode::Release));
+        assert!(manager.last_change().await.is_none());
+        assert!(!manager.should_switch_to_release().await);
+        assert!(!manager.auto_release());
+    }
+
+    #[tokio::test]
+    async fn test_mode_change_events() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let manager = ModeManager::with_events(1, RunMode::Release, sender);
+        assert!(manager.last_change().await.is_none());
+
+        manager.switch_to_dev(ModeChangeReason::Restart).await;
+        let change = receiver.try_recv().unwrap();
+        assert_eq!((change.from, change.to), (RunMode::Release, RunMode::Dev));
+        assert_eq!(change.reason, ModeChangeReason::Restart);
+
+        // Switching to the current mode is not a change
+        manager.switch_to_dev(ModeChangeReason::Restart).await;
+        assert!(receiver.try_recv().is_err());
+
+        manager.switch_to_release(ModeChangeReason::Timeout).await;
+        let change = receiver.try_recv().unwrap();
+        assert_eq!((change.from, change.to), (RunMode::Dev, RunMode::Release));
+        assert_eq!(change.reason, ModeChangeReason::Timeout);
+
+        let last = manager.last_change().await.unwrap();
+        assert_eq!(last.timestamp, change.timestamp);
+    }
+
     #[tokio::test]
     async fn test_tool_call_recording() {
-        let manager = ModeManager::new(1);
+        let manager = ModeManager::new(1, RunMode::Release);
 
         // Start in release mode, switch to dev
-        manager.switch_to_dev().await;
+        manager.switch_to_dev(ModeChangeReason::Restart).await;
 
         // Record a tool call
         manager.record_tool_call().await;
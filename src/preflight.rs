@@ -0,0 +1,336 @@
+use crate::config::{Config, ProcessType};
+use crate::direnv::Direnv;
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// How long a `--version` call may take before the tool counts as broken.
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Ge,
+    Gt,
+    Le,
+    Lt,
+    Eq,
+}
+
+impl Comparison {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Comparison::Ge => ">=",
+            Comparison::Gt => ">",
+            Comparison::Le => "<=",
+            Comparison::Lt => "<",
+            Comparison::Eq => "=",
+        }
+    }
+
+    fn holds(&self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Ge => ordering != Ordering::Less,
+            Comparison::Gt => ordering == Ordering::Greater,
+            Comparison::Le => ordering != Ordering::Greater,
+            Comparison::Lt => ordering == Ordering::Less,
+            Comparison::Eq => ordering == Ordering::Equal,
+        }
+    }
+}
+
+/// A tool a process needs, optionally in a version range: "ffmpeg" or
+/// ">=node 20".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
+    tool: String,
+    version: Option<(Comparison, Version)>,
+}
+
+impl Requirement {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        let comparison = [
+            Comparison::Ge,
+            Comparison::Le,
+            Comparison::Gt,
+            Comparison::Lt,
+            Comparison::Eq,
+        ]
+        .into_iter()
+        .find(|c| spec.starts_with(c.as_str()));
+
+        let Some(comparison) = comparison else {
+            if spec.is_empty() || spec.contains(char::is_whitespace) {
+                return Err(format!("'{}' is not a tool name or \">=tool version\"", spec));
+            }
+            return Ok(Self {
+                tool: spec.to_string(),
+                version: None,
+            });
+        };
+
+        let rest = &spec[comparison.as_str().len()..];
+        let (tool, version) = rest
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("'{}' is missing a version", spec))?;
+        let version = Version::parse(version.trim())
+            .ok_or_else(|| format!("'{}' has an invalid version '{}'", spec, version.trim()))?;
+
+        Ok(Self {
+            tool: tool.to_string(),
+            version: Some((comparison, version)),
+        })
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version {
+            Some((comparison, ref version)) => {
+                write!(f, "{} {} {}", self.tool, comparison.as_str(), version)
+            }
+            None => write!(f, "{}", self.tool),
+        }
+    }
+}
+
+/// A major.minor.patch version, with missing parts treated as 0.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Version(u64, u64, u64);
+
+impl Version {
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.strip_prefix('v').unwrap_or(s);
+        let mut parts = s.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().map(str::parse).transpose().ok()?.unwrap_or(0);
+        let patch = parts.next().map(str::parse).transpose().ok()?.unwrap_or(0);
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self(major, minor, patch))
+    }
+
+    /// Finds the first version number in `--version` output.
+    fn find(output: &str) -> Option<Self> {
+        static VERSION: OnceLock<Regex> = OnceLock::new();
+        let re = VERSION.get_or_init(|| Regex::new(r"\d+(\.\d+){0,2}").expect("valid regex"));
+        re.find(output).and_then(|m| Self::parse(m.as_str()))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckOutcome {
+    /// The tool is there, with the version it reported.
+    Passed(String),
+    /// The tool is there but its version couldn't be checked.
+    Warning(String),
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub process: String,
+    pub check: String,
+    pub outcome: CheckOutcome,
+}
+
+impl CheckResult {
+    pub fn description(&self) -> String {
+        match self.outcome {
+            CheckOutcome::Passed(ref version) => format!("ok: {} ({})", self.check, version),
+            CheckOutcome::Warning(ref message) => format!("warning: {}: {}", self.check, message),
+            CheckOutcome::Failed(ref message) => format!("FAILED: {}: {}", self.check, message),
+        }
+    }
+}
+
+/// Checks that the tools each process needs are installed, in the same
+/// environment processes are spawned in. Results are kept until rechecked.
+pub struct Preflight {
+    /// The environment of each process, by name.
+    direnvs: HashMap<String, Arc<Direnv>>,
+    results: RwLock<Vec<CheckResult>>,
+}
+
+impl Preflight {
+    pub fn new(direnvs: HashMap<String, Arc<Direnv>>) -> Self {
+        Self {
+            direnvs,
+            results: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Runs all checks for `config`, replacing the previous results.
+    pub async fn run(&self, config: &Config) -> Vec<CheckResult> {
+        let mut names: Vec<_> = config.process.keys().collect();
+        names.sort();
+
+        let mut checks = Vec::new();
+        for name in names {
+            let proc_config = &config.process[name];
+            let Some(direnv) = self.direnvs.get(name) else {
+                continue;
+            };
+            let base_tools: &[&str] = match proc_config.process_type {
+                ProcessType::Rust => &["cargo"],
+                ProcessType::Npm => &["node", "npm"],
+            };
+            for tool in base_tools {
+                let requirement = Requirement {
+                    tool: tool.to_string(),
+                    version: None,
+                };
+                checks.push((name.clone(), direnv, requirement));
+            }
+            // Validated when the config was loaded
+            for spec in &proc_config.required_tools {
+                if let Ok(requirement) = Requirement::parse(spec) {
+                    checks.push((name.clone(), direnv, requirement));
+                }
+            }
+        }
+
+        let results = futures::future::join_all(checks.into_iter().map(|(process, direnv, requirement)| async move {
+            CheckResult {
+                process,
+                check: requirement.to_string(),
+                outcome: Self::check(direnv, &requirement).await,
+            }
+        }))
+        .await;
+
+        *self.results.write().await = results.clone();
+        results
+    }
+
+    pub async fn results(&self) -> Vec<CheckResult> {
+        self.results.read().await.clone()
+    }
+
+    /// Runs the tool with `--version`. A bare name only has to spawn, since
+    /// not every tool knows `--version`; a version constraint has to find a
+    /// version that meets it in the output.
+    async fn check(direnv: &Direnv, requirement: &Requirement) -> CheckOutcome {
+        let mut cmd = match direnv.command(&requirement.tool).await {
+            Ok(cmd) => cmd,
+            Err(e) => return CheckOutcome::Failed(format!("{:#}", e)),
+        };
+        cmd.arg("--version").kill_on_drop(true);
+
+        let output = match tokio::time::timeout(VERSION_TIMEOUT, cmd.output()).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                return CheckOutcome::Failed("not found".to_string())
+            }
+            Ok(Err(e)) => return CheckOutcome::Failed(e.to_string()),
+            Err(_) if requirement.version.is_none() => return CheckOutcome::Passed("unknown version".to_string()),
+            Err(_) => {
+                return CheckOutcome::Failed(format!(
+                    "--version took longer than {}s",
+                    VERSION_TIMEOUT.as_secs()
+                ))
+            }
+        };
+
+        // Some tools print their version to stderr
+        let text = format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let found = Version::find(&text);
+
+        match (requirement.version.as_ref(), found) {
+            (Some((comparison, wanted)), Some(found)) => {
+                if comparison.holds(found.cmp(wanted)) {
+                    CheckOutcome::Passed(found.to_string())
+                } else {
+                    CheckOutcome::Failed(format!("found {}", found))
+                }
+            }
+            (Some(_), None) => {
+                CheckOutcome::Warning("couldn't find a version in its --version output".to_string())
+            }
+            // Only trusted from a tool that understood the flag
+            (None, Some(found)) if output.status.success() => CheckOutcome::Passed(found.to_string()),
+            (None, _) => CheckOutcome::Passed("unknown version".to_string()),
+        }
+    }
+}
+
+/// Formats results grouped by process, for the validate subcommand and the
+/// recheck_tools tool.
+pub fn report(results: &[CheckResult]) -> String {
+    let mut report = String::new();
+    let mut current = None;
+    for result in results {
+        if current != Some(&result.process) {
+            report.push_str(&format!("{}:\n", result.process));
+            current = Some(&result.process);
+        }
+        report.push_str(&format!("  {}\n", result.description()));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DirenvStrategy;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_requirements() {
+        assert_eq!(Requirement::parse("ffmpeg").unwrap().to_string(), "ffmpeg");
+        assert_eq!(Requirement::parse(">=node 20").unwrap().to_string(), "node >= 20.0.0");
+        assert_eq!(Requirement::parse("<python3 3.12.1").unwrap().to_string(), "python3 < 3.12.1");
+
+        assert!(Requirement::parse(">=node").is_err());
+        assert!(Requirement::parse(">=node twenty").is_err());
+        assert!(Requirement::parse("node 20").is_err());
+        assert!(Requirement::parse("").is_err());
+    }
+
+    #[test]
+    fn test_find_version() {
+        assert_eq!(Version::find("v20.11.1\n"), Some(Version(20, 11, 1)));
+        assert_eq!(Version::find("cargo 1.80.0 (376290515 2024-07-16)"), Some(Version(1, 80, 0)));
+        assert_eq!(Version::find("no digits here"), None);
+        assert!(Version(20, 0, 0) > Version(18, 19, 1));
+    }
+
+    #[tokio::test]
+    async fn test_checks() {
+        let direnv = Direnv::new(PathBuf::from("."), DirenvStrategy::Off);
+        let check = |spec: &str| Requirement::parse(spec).unwrap();
+
+        // cargo is always around when the tests run
+        assert!(matches!(Preflight::check(&direnv, &check("cargo")).await, CheckOutcome::Passed(_)));
+        assert!(matches!(Preflight::check(&direnv, &check(">=cargo 1.0")).await, CheckOutcome::Passed(_)));
+        assert_eq!(
+            Preflight::check(&direnv, &check("bpm-no-such-tool")).await,
+            CheckOutcome::Failed("not found".to_string())
+        );
+        // A tool that doesn't take --version still counts as installed
+        assert_eq!(
+            Preflight::check(&direnv, &check("false")).await,
+            CheckOutcome::Passed("unknown version".to_string())
+        );
+        assert!(matches!(
+            Preflight::check(&direnv, &check(">=cargo 999")).await,
+            CheckOutcome::Failed(ref msg) if msg.starts_with("found ")
+        ));
+    }
+}
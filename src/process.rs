@@ -1,376 +1,3059 @@
-use crate::config::ProcessConfig;
-use crate::log_buffer::LogBuffer;
+use crate::build_info::BuildInfo;
+use crate::builder::{BuildControl, BuildSpec, Builder, TargetSize};
+use crate::config::{ConsoleOutput, HookConfig, ProcessConfig};
+use crate::direnv::Direnv;
+use crate::line_reader::{capture_stopped, LineReader};
+use crate::log_buffer::{LineMeta, LogAppender, LogBuffer, LogStream, TimestampParser};
+use crate::log_rate::LogRates;
+use crate::telemetry;
+use crate::watch::PathFilter;
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
-use std::path::PathBuf;
-use std::process::Stdio;
-use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use regex::Regex;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
 use tokio::process::{Child, Command};
-use tokio::sync::RwLock;
+use tokio::sync::{oneshot, watch, Mutex, RwLock};
 use tokio::time::{sleep, Duration};
-use tracing::{error, info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, field, info, info_span, warn, Instrument};
+
+/// Lines of the dying instance included with crash events and restart output.
+pub const CRASH_TAIL_LINES: usize = 20;
+
+/// A crash this soon after an identical one is collapsed into its event.
+const CRASH_REPEAT_WINDOW: chrono::Duration = chrono::Duration::minutes(1);
+
+/// Lines of the dying instance kept in a crash report.
+pub const CRASH_REPORT_LINES: usize = 200;
+/// Crash reports kept per process.
+const MAX_CRASH_REPORTS: usize = 10;
+/// How often the resources of a running process are sampled.
+const RESOURCE_SAMPLE_INTERVAL: chrono::Duration = chrono::Duration::seconds(5);
+/// Lines of the new instance included when it exits right after starting.
+const EARLY_EXIT_LINES: usize = 30;
+/// How long a walk of the sources answers `sources_changed`, so polling
+/// get_status doesn't walk them on every call.
+const SOURCES_CHECK_TTL: Duration = Duration::from_secs(5);
+
+/// Random id of this manager run, exported to every process as `BPM_RUN_ID`
+/// alongside its `BPM_GENERATION`, so their output can be correlated with
+/// the manager's restarts.
+pub fn run_id() -> &'static str {
+    static RUN_ID: OnceLock<String> = OnceLock::new();
+    RUN_ID.get_or_init(|| {
+        // RandomState is seeded randomly per process, which is enough here
+        let id = RandomState::new().build_hasher().finish();
+        format!("{:016x}", id)
+    })
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProcessState {
-    Idle,
+    NotStarted,
+    /// Being built before it is started; nothing is running meanwhile.
+    Building,
+    /// Spawned, but not ready yet: its pid file or port isn't there yet, or
+    /// it hasn't printed its ready_pattern.
+    Starting,
     Running,
+    /// Asked to exit, so its exit won't count as a crash.
+    Stopping,
+    /// Stopped on purpose, e.g. to restart it or switch modes.
+    Stopped,
+    /// Exited without being asked to.
     Crashed,
+    /// Couldn't be built, spawned, or made ready.
+    Failed,
 }
 
 impl ProcessState {
+    pub const ALL: [ProcessState; 8] = [
+        ProcessState::NotStarted,
+        ProcessState::Building,
+        ProcessState::Starting,
+        ProcessState::Running,
+        ProcessState::Stopping,
+        ProcessState::Stopped,
+        ProcessState::Crashed,
+        ProcessState::Failed,
+    ];
+
+    /// The state named `name`, as written by `as_str`.
+    pub fn parse(name: &str) -> Option<ProcessState> {
+        Self::ALL.into_iter().find(|state| state.as_str() == name)
+    }
+
     pub fn as_str(&self) -> &'static str {
         match self {
-            ProcessState::Idle => "idle",
+            ProcessState::NotStarted => "not_started",
+            ProcessState::Building => "building",
+            ProcessState::Starting => "starting",
             ProcessState::Running => "running",
+            ProcessState::Stopping => "stopping",
+            ProcessState::Stopped => "stopped",
             ProcessState::Crashed => "crashed",
+            ProcessState::Failed => "failed",
+        }
+    }
+
+    /// Whether moving from this state to `to` is a legal transition.
+    pub fn can_transition_to(self, to: ProcessState) -> bool {
+        use ProcessState::*;
+        match (self, to) {
+            _ if self == to => false,
+            // Starting over, from anything that isn't running
+            (NotStarted | Stopped | Crashed | Failed, Building | Starting | Failed) => true,
+            (Building, Starting | Failed) => true,
+            (Starting, Running | Stopping | Crashed | Failed) => true,
+            (Running, Stopping | Crashed) => true,
+            (Stopping, Stopped) => true,
+            // Stopping something that isn't running just marks it stopped
+            (NotStarted | Crashed | Failed, Stopped) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether nothing is running or about to run, so the crash monitor
+    /// should start it.
+    pub fn needs_restart(self) -> bool {
+        matches!(self, ProcessState::NotStarted | ProcessState::Crashed | ProcessState::Failed)
+    }
+}
+
+/// Outcome of waiting for a process to be ready after spawning it: for its
+/// port to accept connections and/or for a line matching its ready_pattern.
+/// `check` says what was waited for.
+#[derive(Debug, Clone)]
+pub enum Readiness {
+    Waiting { check: String },
+    Ready { check: String, after: Duration },
+    TimedOut { check: String, timeout: Duration },
+    Exited { check: String },
+}
+
+impl Readiness {
+    pub fn description(&self) -> String {
+        match self {
+            Readiness::Waiting { check } => format!("waiting for {}", check),
+            Readiness::Ready { check, after } => {
+                format!("ready after {:.1}s ({})", after.as_secs_f64(), check)
+            }
+            Readiness::TimedOut { check, timeout } => {
+                format!("failed, not ready after {}s (waited for {})", timeout.as_secs(), check)
+            }
+            Readiness::Exited { check } => {
+                format!("failed, exited before it was ready (waited for {})", check)
+            }
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum ProcessEvent {
-    Started { timestamp: DateTime<Utc> },
-    Crashed { timestamp: DateTime<Utc>, exit_code: Option<i32> },
+    Started {
+        timestamp: DateTime<Utc>,
+        version: String,
+        /// Log generation of the instance, exported as `BPM_GENERATION`.
+        generation: u64,
+    },
+    /// One or more identical crashes in a row; `timestamp` is the latest.
+    Crashed {
+        timestamp: DateTime<Utc>,
+        exit_code: Option<i32>,
+        /// The last lines the process logged before exiting.
+        last_lines: Vec<String>,
+        repeats: u32,
+        /// When the first of the repeated crashes happened.
+        since: DateTime<Utc>,
+    },
+    /// A rebuild for a restart failed, so the process couldn't be started.
+    BuildFailed { timestamp: DateTime<Utc>, error: String },
+    /// More lines matched `error_pattern` in the last minute than
+    /// `max_error_rate_per_min` allows.
+    ErrorRateExceeded {
+        timestamp: DateTime<Utc>,
+        rate: u64,
+        limit: u64,
+    },
+    /// The process had more open files than `max_open_files_warn`.
+    OpenFilesExceeded {
+        timestamp: DateTime<Utc>,
+        open_files: u64,
+        limit: u64,
+    },
+    /// The process exited with a code `restart_exit_codes` or
+    /// `no_restart_exit_codes` rules out restarting after, so it was
+    /// stopped instead; `reason` says which.
+    RestartSkipped {
+        timestamp: DateTime<Utc>,
+        exit_code: Option<i32>,
+        reason: String,
+    },
+    StateChanged {
+        timestamp: DateTime<Utc>,
+        from: ProcessState,
+        to: ProcessState,
+    },
+    /// A lifecycle hook ran; `error` says why it failed, if it did.
+    HookRan {
+        timestamp: DateTime<Utc>,
+        hook: &'static str,
+        duration: Duration,
+        error: Option<String>,
+    },
 }
 
 impl ProcessEvent {
+    /// Structured form for the get_events tool.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            ProcessEvent::Started {
+                timestamp,
+                version,
+                generation,
+            } => serde_json::json!({
+                "type": "started",
+                "timestamp": timestamp.to_rfc3339(),
+                "exit_code": null,
+                "cause": null,
+                "version": version,
+                "generation": generation,
+                "run_id": run_id(),
+            }),
+            ProcessEvent::Crashed {
+                timestamp,
+                exit_code,
+                last_lines,
+                repeats,
+                since,
+            } => serde_json::json!({
+                "type": "crashed",
+                "timestamp": timestamp.to_rfc3339(),
+                "exit_code": exit_code,
+                "cause": match exit_code {
+                    Some(code) => format!("exited with code {}", code),
+                    None => "killed by signal or lost track of process".to_string(),
+                },
+                "last_lines": last_lines,
+                "repeats": repeats,
+                "since": since.to_rfc3339(),
+            }),
+            ProcessEvent::BuildFailed { timestamp, error } => serde_json::json!({
+                "type": "build_failed",
+                "timestamp": timestamp.to_rfc3339(),
+                "exit_code": null,
+                "cause": error,
+            }),
+            ProcessEvent::ErrorRateExceeded { timestamp, rate, limit } => serde_json::json!({
+                "type": "error_rate_exceeded",
+                "timestamp": timestamp.to_rfc3339(),
+                "exit_code": null,
+                "cause": format!("{} error lines in the last minute (max {})", rate, limit),
+            }),
+            ProcessEvent::OpenFilesExceeded { timestamp, open_files, limit } => serde_json::json!({
+                "type": "open_files_exceeded",
+                "timestamp": timestamp.to_rfc3339(),
+                "exit_code": null,
+                "cause": format!("{} open files (warn above {})", open_files, limit),
+            }),
+            ProcessEvent::RestartSkipped { timestamp, exit_code, reason } => serde_json::json!({
+                "type": "restart_skipped",
+                "timestamp": timestamp.to_rfc3339(),
+                "exit_code": exit_code,
+                "cause": reason,
+            }),
+            ProcessEvent::StateChanged { timestamp, from, to } => serde_json::json!({
+                "type": "state_changed",
+                "timestamp": timestamp.to_rfc3339(),
+                "exit_code": null,
+                "cause": null,
+                "from": from.as_str(),
+                "to": to.as_str(),
+            }),
+            ProcessEvent::HookRan {
+                timestamp,
+                hook,
+                duration,
+                error,
+            } => serde_json::json!({
+                "type": "hook",
+                "timestamp": timestamp.to_rfc3339(),
+                "exit_code": null,
+                "cause": error,
+                "hook": hook,
+                "duration_seconds": duration.as_secs_f64(),
+            }),
+        }
+    }
+
+    /// Whether this is a state change, which `max_events` caps on its own.
+    pub fn is_state_change(&self) -> bool {
+        matches!(self, ProcessEvent::StateChanged { .. })
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        match *self {
+            ProcessEvent::Started { timestamp, .. }
+            | ProcessEvent::Crashed { timestamp, .. }
+            | ProcessEvent::BuildFailed { timestamp, .. }
+            | ProcessEvent::ErrorRateExceeded { timestamp, .. }
+            | ProcessEvent::OpenFilesExceeded { timestamp, .. }
+            | ProcessEvent::RestartSkipped { timestamp, .. }
+            | ProcessEvent::StateChanged { timestamp, .. }
+            | ProcessEvent::HookRan { timestamp, .. } => timestamp,
+        }
+    }
+
+    /// One-line summary without the timestamp, for the activity feed.
+    pub fn summary(&self) -> String {
+        match self {
+            ProcessEvent::Started { version, .. } => format!("started ({})", version),
+            ProcessEvent::Crashed {
+                exit_code, repeats: 1, ..
+            } => format!("crashed (exit code: {:?})", exit_code),
+            ProcessEvent::Crashed { exit_code, repeats, .. } => {
+                format!("crashed ×{} (exit code: {:?})", repeats, exit_code)
+            }
+            ProcessEvent::BuildFailed { error, .. } => format!("build failed ({})", error),
+            ProcessEvent::ErrorRateExceeded { rate, limit, .. } => {
+                format!("error rate exceeded ({}/min, max {})", rate, limit)
+            }
+            ProcessEvent::OpenFilesExceeded { open_files, limit, .. } => {
+                format!("too many open files ({}, warn above {})", open_files, limit)
+            }
+            ProcessEvent::RestartSkipped { reason, .. } => format!("not restarted ({})", reason),
+            ProcessEvent::StateChanged { from, to, .. } => {
+                format!("state changed {} -> {}", from.as_str(), to.as_str())
+            }
+            ProcessEvent::HookRan {
+                hook, duration, error: None, ..
+            } => format!("{} hook ran ({:.1}s)", hook, duration.as_secs_f64()),
+            ProcessEvent::HookRan {
+                hook, error: Some(error), ..
+            } => format!("{} hook failed ({})", hook, error),
+        }
+    }
+
     pub fn description(&self) -> String {
         match self {
-            ProcessEvent::Started { timestamp } => format!("Started at {}", timestamp),
-            ProcessEvent::Crashed { timestamp, exit_code } => {
+            ProcessEvent::Started {
+                timestamp,
+                version,
+                generation,
+            } => {
+                format!("Started at {} ({}, generation {})", timestamp, version, generation)
+            }
+            ProcessEvent::Crashed {
+                timestamp,
+                exit_code,
+                repeats: 1,
+                ..
+            } => {
                 format!("Crashed at {} (exit code: {:?})", timestamp, exit_code)
             }
+            ProcessEvent::Crashed {
+                timestamp,
+                exit_code,
+                repeats,
+                since,
+                ..
+            } => {
+                format!(
+                    "Crashed ×{} from {} to {} (exit code: {:?})",
+                    repeats, since, timestamp, exit_code
+                )
+            }
+            ProcessEvent::BuildFailed { timestamp, error } => {
+                format!("Build failed at {} ({})", timestamp, error)
+            }
+            ProcessEvent::ErrorRateExceeded { timestamp, rate, limit } => {
+                format!("Error rate exceeded at {} ({}/min, max {})", timestamp, rate, limit)
+            }
+            ProcessEvent::OpenFilesExceeded { timestamp, open_files, limit } => {
+                format!("Too many open files at {} ({}, warn above {})", timestamp, open_files, limit)
+            }
+            ProcessEvent::RestartSkipped { timestamp, reason, .. } => {
+                format!("Not restarted at {} ({})", timestamp, reason)
+            }
+            ProcessEvent::StateChanged { timestamp, from, to } => {
+                format!("State changed at {} ({} -> {})", timestamp, from.as_str(), to.as_str())
+            }
+            ProcessEvent::HookRan {
+                timestamp,
+                hook,
+                duration,
+                error: None,
+            } => {
+                format!("Hook {} ran at {} ({:.1}s)", hook, timestamp, duration.as_secs_f64())
+            }
+            ProcessEvent::HookRan {
+                timestamp,
+                hook,
+                error: Some(error),
+                ..
+            } => {
+                format!("Hook {} failed at {} ({})", hook, timestamp, error)
+            }
+        }
+    }
+}
+
+/// The resources a running instance was using at one point, from /proc.
+/// Fields are `None` where there is no /proc, or it couldn't be read.
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceSample {
+    pub at: DateTime<Utc>,
+    /// Resident memory in bytes.
+    pub rss: Option<u64>,
+    pub open_files: Option<u64>,
+    pub threads: Option<u64>,
+}
+
+impl ResourceSample {
+    /// Takes a sample of `pid`.
+    fn read(pid: i32) -> Self {
+        Self {
+            at: Utc::now(),
+            rss: read_proc_status(pid, "VmRSS:").map(|kib| kib * 1024),
+            open_files: read_open_files(pid),
+            threads: read_proc_status(pid, "Threads:"),
         }
     }
+
+    /// Structured form for get_process; all null without a sample, rather
+    /// than zero.
+    pub fn to_json(sample: Option<&Self>) -> serde_json::Value {
+        serde_json::json!({
+            "sampled_at": sample.map(|s| s.at.to_rfc3339()),
+            "rss_bytes": sample.and_then(|s| s.rss),
+            "open_files": sample.and_then(|s| s.open_files),
+            "threads": sample.and_then(|s| s.threads),
+        })
+    }
+}
+
+/// Everything known about a crash when it was detected, for get_crash_report.
+#[derive(Debug, Clone)]
+pub struct CrashReport {
+    pub timestamp: DateTime<Utc>,
+    pub exit_code: Option<i32>,
+    /// Signal that killed the process. Unix only.
+    pub signal: Option<i32>,
+    pub core_dumped: bool,
+    /// Resident memory in bytes at the last sample, and when it was taken.
+    /// Linux only.
+    pub rss: Option<(u64, DateTime<Utc>)>,
+    /// Open files and threads at the last sample. Linux only.
+    pub open_files: Option<u64>,
+    pub threads: Option<u64>,
+    pub uptime: Option<chrono::Duration>,
+    pub version: Option<String>,
+    /// The last CRASH_REPORT_LINES lines of the instance that crashed.
+    pub last_lines: Vec<String>,
+}
+
+impl CrashReport {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "timestamp": self.timestamp.to_rfc3339(),
+            "exit_code": self.exit_code,
+            "signal": self.signal,
+            "core_dumped": self.core_dumped,
+            "rss_bytes": self.rss.map(|(bytes, _)| bytes),
+            "rss_sampled_at": self.rss.map(|(_, at)| at.to_rfc3339()),
+            "open_files": self.open_files,
+            "threads": self.threads,
+            "uptime_seconds": self.uptime.map(|u| u.num_seconds()),
+            "version": self.version,
+            "last_lines": self.last_lines,
+        })
+    }
 }
 
 pub struct ProcessManager {
     pub name: String,
     pub config: ProcessConfig,
     pub project_dir: PathBuf,
-    pub state: RwLock<ProcessState>,
+    /// Only written by `transition`.
+    state: RwLock<ProcessState>,
     pub logs: Arc<RwLock<LogBuffer>>,
+    log_appender: LogAppender,
     pub build_logs: Arc<RwLock<LogBuffer>>,
+    pub build_control: BuildControl,
     pub started_at: RwLock<Option<DateTime<Utc>>>,
-    pub events: RwLock<Vec<ProcessEvent>>,
-    child: RwLock<Option<Child>>,
-    has_direnv: bool,
+    pub build_info: RwLock<Option<BuildInfo>>,
+    /// What the last build produced, for the next spawn of it.
+    built: RwLock<Option<BuildInfo>>,
+    pub events: Arc<RwLock<VecDeque<ProcessEvent>>>,
+    max_events: usize,
+    /// The most recent crashes, oldest first.
+    pub crash_reports: RwLock<VecDeque<CrashReport>>,
+    /// Resources of the running instance at the last sample.
+    pub last_sample: RwLock<Option<ResourceSample>>,
+    /// Set once an instance goes over `max_open_files_warn`, so the event
+    /// is recorded once per excursion rather than on every sample.
+    open_files_warned: AtomicBool,
+    /// Lines, stderr lines and error_pattern matches per minute.
+    pub log_rates: Arc<LogRates>,
+    /// Reads the timestamps output lines carry, per `log_timestamp`.
+    timestamps: Option<Arc<TimestampParser>>,
+    /// Size of `target/`, for Rust processes, if it has been measured.
+    pub target_size: RwLock<Option<TargetSize>>,
+    /// What the last walk of the sources found, for `sources_changed`.
+    sources_check: Arc<std::sync::Mutex<Option<SourcesCheck>>>,
+    /// Set while a walk of the sources runs, so only one runs at a time.
+    sources_walking: Arc<AtomicBool>,
+    /// Why initial startup failed; cleared once the process starts successfully.
+    pub startup_error: RwLock<Option<String>>,
+    /// Port readiness of the current instance, if the process has a port.
+    pub readiness: RwLock<Option<Readiness>>,
+    /// PID read from `pid_file`, for processes that daemonize.
+    pub daemon_pid: RwLock<Option<i32>>,
+    child: RwLock<Option<SpawnedChild>>,
+    /// Log generation of `child`, updated whenever it is replaced, so a waiter
+    /// that polled an earlier child can tell its exit is no longer this
+    /// process's.
+    spawn_generation: AtomicU64,
+    /// Changes on every state transition, for waiting on the state.
+    state_changes: watch::Sender<ProcessState>,
+    direnv: Arc<Direnv>,
+    /// How output is echoed to the manager's stdout/stderr.
+    console_output: ConsoleOutput,
+    /// Longest output line kept in the log.
+    max_line_bytes: usize,
     manual_restart_in_progress: RwLock<bool>,
+    /// Held for the duration of a manual restart.
+    pub restart_lock: Mutex<()>,
+    /// Set once the process is removed for good; ends its crash monitor.
+    retired: watch::Sender<bool>,
+    /// How long a process without readiness checks has to stay up after
+    /// starting for the start to count as successful.
+    early_exit_window: Duration,
+    /// Set while a spawn is in charge of the process; see `StartInProgress`.
+    start_in_progress: AtomicBool,
+    /// Set when the last exit stopped the process for good, because of
+    /// `restart_exit_codes` or `no_restart_exit_codes`, until it is started
+    /// again. A failed start then isn't reported over it.
+    restart_skipped: AtomicBool,
+}
+
+/// Whether the sources changed after a binary was built, as of a walk.
+struct SourcesCheck {
+    binary_mtime: std::time::SystemTime,
+    checked_at: std::time::Instant,
+    changed: bool,
+}
+
+/// Marks a start in progress until dropped. Meanwhile the crash monitor
+/// leaves an exit of the starting instance to the start, which reports it as
+/// a failed start rather than a crash. A start that is abandoned halfway
+/// hands the process back to the monitor.
+struct StartInProgress<'a>(&'a AtomicBool);
+
+impl Drop for StartInProgress<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// The child of one spawn. A task owns it and waits for it, publishing its
+/// exit the moment it happens, so the exit is tied to this spawn rather than
+/// rediscovered by polling whatever child is current. Dropping the handle
+/// kills the child, as replacing it does.
+struct SpawnedChild {
+    generation: u64,
+    pid: Option<u32>,
+    /// The exit status once the child has exited; the inner None if it
+    /// couldn't be waited for.
+    exited: watch::Receiver<Option<Option<ExitStatus>>>,
+    /// Kills the child when sent or dropped.
+    kill: Option<oneshot::Sender<()>>,
+}
+
+impl SpawnedChild {
+    fn new(mut child: Child, generation: u64) -> Self {
+        let pid = child.id();
+        let (exited_sender, exited) = watch::channel(None);
+        let (kill, killed) = oneshot::channel();
+        tokio::spawn(async move {
+            let status = tokio::select! {
+                status = child.wait() => status,
+                _ = killed => {
+                    let _ = child.start_kill();
+                    child.wait().await
+                }
+            };
+            exited_sender.send_replace(Some(status.ok()));
+        });
+        Self {
+            generation,
+            pid,
+            exited,
+            kill: Some(kill),
+        }
+    }
+
+    /// The exit status if the child has exited.
+    fn exit_status(&self) -> Option<Option<ExitStatus>> {
+        *self.exited.borrow()
+    }
+
+    /// Waits until the child has exited.
+    fn wait(&self) -> impl std::future::Future<Output = ()> + 'static {
+        let mut exited = self.exited.clone();
+        async move {
+            let _ = exited.wait_for(Option::is_some).await;
+        }
+    }
+
+    fn start_kill(&mut self) {
+        self.kill.take();
+    }
 }
 
 impl ProcessManager {
-    pub fn new(name: String, config: ProcessConfig, project_dir: PathBuf) -> Self {
-        let has_direnv = project_dir.join(".envrc").exists();
+    pub fn new(
+        name: String,
+        config: ProcessConfig,
+        project_dir: PathBuf,
+        direnv: Arc<Direnv>,
+        echo_output: bool,
+        max_events: usize,
+        max_line_bytes: usize,
+    ) -> Self {
+        let logs = Arc::new(RwLock::new(LogBuffer::new()));
+        let log_appender = LogAppender::spawn(logs.clone());
+        // Validated when the config was loaded
+        let log_rates = LogRates::spawn(
+            config.error_pattern.as_deref().and_then(|p| Regex::new(p).ok()),
+            config.max_error_rate_per_min,
+        );
+        let timestamps = config
+            .log_timestamp
+            .as_ref()
+            .and_then(|timestamp| TimestampParser::new(&timestamp.pattern, timestamp.format.clone()).ok())
+            .map(Arc::new);
+        let console_output = if echo_output { config.console_output } else { ConsoleOutput::Off };
 
         Self {
             name,
             config,
             project_dir,
-            state: RwLock::new(ProcessState::Idle),
-            logs: Arc::new(RwLock::new(LogBuffer::new())),
+            state: RwLock::new(ProcessState::NotStarted),
+            logs,
+            log_appender,
             build_logs: Arc::new(RwLock::new(LogBuffer::new())),
+            build_control: BuildControl::new(),
             started_at: RwLock::new(None),
-            events: RwLock::new(Vec::new()),
+            build_info: RwLock::new(None),
+            built: RwLock::new(None),
+            events: Arc::new(RwLock::new(VecDeque::new())),
+            max_events,
+            crash_reports: RwLock::new(VecDeque::new()),
+            last_sample: RwLock::new(None),
+            open_files_warned: AtomicBool::new(false),
+            log_rates,
+            timestamps,
+            target_size: RwLock::new(None),
+            startup_error: RwLock::new(None),
+            readiness: RwLock::new(None),
+            daemon_pid: RwLock::new(None),
             child: RwLock::new(None),
-            has_direnv,
+            spawn_generation: AtomicU64::new(0),
+            state_changes: watch::Sender::new(ProcessState::NotStarted),
+            direnv,
+            console_output,
+            max_line_bytes,
             manual_restart_in_progress: RwLock::new(false),
+            restart_lock: Mutex::new(()),
+            sources_check: Arc::new(std::sync::Mutex::new(None)),
+            sources_walking: Arc::new(AtomicBool::new(false)),
+            retired: watch::Sender::new(false),
+            early_exit_window: Duration::ZERO,
+            start_in_progress: AtomicBool::new(false),
+            restart_skipped: AtomicBool::new(false),
         }
     }
 
-    pub async fn spawn_process(&self, binary_path: PathBuf) -> Result<()> {
-        info!("Spawning process: {}", self.name);
+    /// Fails starts of processes without a port or ready_pattern if they exit
+    /// within `window`.
+    pub fn with_early_exit_window(mut self, window: Duration) -> Self {
+        self.early_exit_window = window;
+        self
+    }
 
-        // Create new log instance
-        self.logs.write().await.new_instance();
+    /// How commands of this process get the `.envrc` environment.
+    pub fn direnv(&self) -> &Direnv {
+        &self.direnv
+    }
 
-        let mut cmd = if self.has_direnv {
-            let mut c = Command::new("direnv");
-            c.arg("exec").arg(&self.project_dir).arg(&binary_path);
-            c
-        } else {
-            Command::new(&binary_path)
-        };
+    /// Echoes output as `output` says, e.g. the global `console_output`.
+    pub fn with_console_output(mut self, output: ConsoleOutput) -> Self {
+        self.console_output = output;
+        self
+    }
 
-        // Add configured arguments
-        for arg in &self.config.args {
-            cmd.arg(arg);
+    /// The prefix echoed lines get, empty for raw output, or `None` if
+    /// output isn't echoed.
+    fn echo_prefix(&self) -> Option<String> {
+        match self.console_output {
+            ConsoleOutput::Prefixed => Some(self.config.console_prefix(&self.name, std::io::stdout().is_terminal())),
+            ConsoleOutput::Raw => Some(String::new()),
+            ConsoleOutput::Off => None,
         }
+    }
 
-        cmd.current_dir(&self.project_dir)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .kill_on_drop(true);
-
-        let mut child = cmd.spawn().context("Failed to spawn process")?;
+    fn start_in_progress(&self) -> StartInProgress<'_> {
+        self.start_in_progress.store(true, Ordering::SeqCst);
+        StartInProgress(&self.start_in_progress)
+    }
 
-        // Capture stdout
-        if let Some(stdout) = child.stdout.take() {
-            let logs = self.logs.clone();
-            let name = self.name.clone();
-            tokio::spawn(async move {
-                let reader = BufReader::new(stdout);
-                let mut lines = reader.lines();
-                while let Ok(Some(line)) = lines.next_line().await {
-                    println!("[{}] {}", name, line);
-                    logs.write().await.append(line);
-                }
-            });
-        }
+    pub async fn spawn_process(&self, binary_path: PathBuf) -> Result<()> {
+        info!("Spawning process: {}", self.name);
+        let _start = self.start_in_progress();
 
-        // Capture stderr
-        if let Some(stderr) = child.stderr.take() {
-            let logs = self.logs.clone();
-            let name = self.name.clone();
-            tokio::spawn(async move {
-                let reader = BufReader::new(stderr);
-                let mut lines = reader.lines();
-                while let Ok(Some(line)) = lines.next_line().await {
-                    eprintln!("[{}] {}", name, line);
-                    logs.write().await.append(format!("[stderr] {}", line));
-                }
-            });
-        }
+        let result = async {
+            let mut cmd = self.direnv.command(&binary_path).await?;
 
-        *self.child.write().await = Some(child);
-        *self.state.write().await = ProcessState::Running;
-        *self.started_at.write().await = Some(Utc::now());
+            // Add configured arguments
+            for arg in &self.config.args {
+                cmd.arg(arg);
+            }
 
-        self.events.write().await.push(ProcessEvent::Started {
-            timestamp: Utc::now(),
-        });
+            // Backtraces for the crash report, unless the environment asks
+            // for something else. A .envrc applied by direnv exec wins anyway
+            let overridden = std::env::var_os("RUST_BACKTRACE").is_some()
+                || cmd.as_std().get_envs().any(|(key, _)| key == "RUST_BACKTRACE");
+            if !overridden {
+                cmd.env("RUST_BACKTRACE", &self.config.rust_backtrace);
+            }
 
-        info!("Process {} started successfully", self.name);
-        Ok(())
+            self.spawn_instance(cmd, Some(&binary_path)).await
+        }
+        .await;
+        self.finish_start(result).await
     }
 
     pub async fn spawn_npm_process(&self) -> Result<()> {
         info!("Spawning NPM process: {}", self.name);
+        let _start = self.start_in_progress();
 
-        // Create new log instance
-        self.logs.write().await.new_instance();
+        let result = async {
+            let command = &self.config.command;
+            if command.is_empty() {
+                anyhow::bail!("No command specified for NPM process");
+            }
 
-        let command = &self.config.command;
-        if command.is_empty() {
-            anyhow::bail!("No command specified for NPM process");
-        }
+            let mut cmd = self.direnv.command(&command[0]).await?;
+            cmd.args(&command[1..]);
 
-        let mut cmd = if self.has_direnv {
-            let mut c = Command::new("direnv");
-            c.arg("exec").arg(&self.project_dir);
-            c.args(command);
-            c
-        } else {
-            let mut c = Command::new(&command[0]);
-            c.args(&command[1..]);
-            c
-        };
+            self.spawn_instance(cmd, None).await
+        }
+        .await;
+        self.finish_start(result).await
+    }
 
-        cmd.current_dir(&self.project_dir)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .kill_on_drop(true);
+    /// Builds the Rust binary in the process's directory and environment.
+    pub async fn build(&self, builder: &Builder, release: bool, cancel: Option<&CancellationToken>) -> Result<PathBuf> {
+        let span = info_span!(
+            "build",
+            process = %self.name,
+            profile = if release { "release" } else { "dev" },
+            outcome = field::Empty,
+            duration_ms = field::Empty,
+        );
+        let started = std::time::Instant::now();
+        let result = builder
+            .build_rust(
+                BuildSpec {
+                    project_dir: &self.project_dir,
+                    target: self.config.target.as_deref(),
+                    release,
+                },
+                &self.direnv,
+                self.build_logs.clone(),
+                &self.build_control,
+                cancel,
+            )
+            .instrument(span.clone())
+            .await;
+        telemetry::record_outcome(&span, started, &result);
+        if let Ok(ref binary) = result {
+            *self.built.write().await = builder.build_info(&self.project_dir, Some(binary));
+        }
+        result
+    }
 
-        let mut child = cmd.spawn().context("Failed to spawn NPM process")?;
+    /// Runs the `build_command` of an NPM process, with its output going to
+    /// the build log.
+    pub async fn build_npm(&self, builder: &Builder, cancel: Option<&CancellationToken>) -> Result<()> {
+        let span = info_span!(
+            "build",
+            process = %self.name,
+            profile = "npm",
+            outcome = field::Empty,
+            duration_ms = field::Empty,
+        );
+        let started = std::time::Instant::now();
+        let result = builder
+            .build_command(
+                &self.config.build_command,
+                &self.project_dir,
+                &self.direnv,
+                self.build_logs.clone(),
+                &self.build_control,
+                cancel,
+            )
+            .instrument(span.clone())
+            .await;
+        telemetry::record_outcome(&span, started, &result);
+        if result.is_ok() {
+            *self.built.write().await = builder.build_info(&self.project_dir, None);
+        }
+        result
+    }
 
-        // Capture stdout
-        if let Some(stdout) = child.stdout.take() {
-            let logs = self.logs.clone();
-            let name = self.name.clone();
+    /// Whether a source file changed after the binary modified at `built`
+    /// was built, so a restart would pick up edits. Walking the sources skips
+    /// the target directory, `.gitignore`d files and `watch.ignore`, and can
+    /// take a while on a large tree, so it runs in the background: this only
+    /// answers from the last walk, and starts a new one if that is older than
+    /// `SOURCES_CHECK_TTL` or was of another binary. Until the first walk of
+    /// a binary finishes, the answer is false. Once a change was seen, it
+    /// stands for as long as the binary stays the same.
+    pub fn sources_changed(&self, builder: &Arc<Builder>, built: std::time::SystemTime) -> bool {
+        let (changed, fresh) = match *self.sources_check.lock().unwrap() {
+            Some(ref last) if last.binary_mtime == built => {
+                (last.changed, last.changed || last.checked_at.elapsed() < SOURCES_CHECK_TTL)
+            }
+            _ => (false, false),
+        };
+        if !fresh && !self.sources_walking.swap(true, Ordering::SeqCst) {
+            let project_dir = self.project_dir.clone();
+            let watch = self.config.watch.clone();
+            let builder = builder.clone();
+            let direnv = self.direnv.clone();
+            let check = self.sources_check.clone();
+            let walking = self.sources_walking.clone();
             tokio::spawn(async move {
-                let reader = BufReader::new(stdout);
-                let mut lines = reader.lines();
-                while let Ok(Some(line)) = lines.next_line().await {
-                    println!("[{}] {}", name, line);
-                    logs.write().await.append(line);
-                }
+                let target_dir = builder
+                    .target_dir(&project_dir, &direnv)
+                    .await
+                    .unwrap_or_else(|_| project_dir.join("target"));
+                let changed = tokio::task::spawn_blocking(move || {
+                    PathFilter::new(&project_dir, &watch, &target_dir).changed_since(built)
+                })
+                .await
+                .unwrap_or(false);
+                *check.lock().unwrap() = Some(SourcesCheck {
+                    binary_mtime: built,
+                    checked_at: std::time::Instant::now(),
+                    changed,
+                });
+                walking.store(false, Ordering::SeqCst);
             });
         }
+        changed
+    }
 
-        // Capture stderr
-        if let Some(stderr) = child.stderr.take() {
-            let logs = self.logs.clone();
-            let name = self.name.clone();
-            tokio::spawn(async move {
-                let reader = BufReader::new(stderr);
-                let mut lines = reader.lines();
-                while let Ok(Some(line)) = lines.next_line().await {
-                    eprintln!("[{}] {}", name, line);
-                    logs.write().await.append(format!("[stderr] {}", line));
-                }
-            });
+    /// The directory cargo builds the process into, or `target/` if cargo
+    /// can't tell.
+    pub async fn target_dir(&self, builder: &Builder) -> PathBuf {
+        match builder.target_dir(&self.project_dir, &self.direnv).await {
+            Ok(dir) => dir,
+            Err(e) => {
+                warn!("Couldn't find the target directory of {}: {:#}", self.name, e);
+                self.project_dir.join("target")
+            }
         }
+    }
 
-        *self.child.write().await = Some(child);
-        *self.state.write().await = ProcessState::Running;
-        *self.started_at.write().await = Some(Utc::now());
+    /// Runs `cargo clean` for the process, then measures what is left of its
+    /// target directory. Returns cargo's summary.
+    pub async fn clean(&self, builder: &Builder) -> Result<String> {
+        let summary = builder
+            .clean_rust(&self.project_dir, &self.direnv, self.build_logs.clone(), &self.build_control)
+            .await?;
+        *self.target_size.write().await = Some(TargetSize::measure(self.target_dir(builder).await).await);
+        Ok(summary)
+    }
 
-        self.events.write().await.push(ProcessEvent::Started {
-            timestamp: Utc::now(),
-        });
+    /// Spawns `cmd` as a new instance, capturing its output, and waits for it
+    /// to be ready. `binary_path` is the binary for Rust processes.
+    async fn spawn_instance(&self, mut cmd: Command, binary_path: Option<&Path>) -> Result<()> {
+        cmd.current_dir(&self.project_dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        // In its own process group, so stopping it also reaches whatever a
+        // wrapper like direnv or npm started
+        #[cfg(unix)]
+        cmd.process_group(0);
+        self.apply_credentials(&mut cmd)?;
+        self.apply_priority(&mut cmd)?;
 
-        info!("NPM process {} started successfully", self.name);
-        Ok(())
-    }
+        self.check_port_free().await?;
+        self.check_pid_file_free().await?;
+        // Captured by the build, unless this isn't what it built
+        let built = self.built.read().await.clone();
+        let build_info = match built {
+            Some(info) if info.is_current(binary_path).await => info,
+            _ => BuildInfo::capture(&self.project_dir, binary_path).await,
+        };
 
-    pub async fn stop(&self) -> Result<()> {
-        info!("Stopping process: {}", self.name);
+        // Not this start's yet: a process still starting was left by a start
+        // that was abandoned
+        self.claim_crash(true).await;
 
-        // Get PID and send SIGTERM
-        let pid = {
-            info!("Acquiring child lock to get PID for {}", self.name);
-            let mut child = self.child.write().await;
-            info!("Got child lock for {}", self.name);
-            if let Some(ref mut child) = *child {
-                let pid = child.id().map(|id| id as i32);
-                info!("Got PID {:?} for {}", pid, self.name);
-                pid
-            } else {
-                info!("No child process found for {}", self.name);
-                None
+        // Create new log instance; lines still arriving from the previous
+        // process go to the previous instance
+        let generation = {
+            let mut logs = self.logs.write().await;
+            logs.new_instance();
+            logs.generation()
+        };
+
+        cmd.env("BPM_RUN_ID", run_id()).env("BPM_GENERATION", generation.to_string());
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                self.logs.write().await.record_end(generation, "failed to spawn".to_string());
+                return Err(e).context("Failed to spawn process");
             }
         };
 
-        if let Some(pid) = pid {
-            #[cfg(unix)]
-            {
-                use nix::sys::signal::{self, Signal};
-                use nix::unistd::Pid;
+        self.capture_output(&mut child, generation);
+        *self.last_sample.write().await = None;
+        self.open_files_warned.store(false, Ordering::SeqCst);
 
-                info!("Sending SIGTERM to {} (PID {})", self.name, pid);
-                let _ = signal::kill(Pid::from_raw(pid), Signal::SIGTERM);
-                info!("SIGTERM sent to {} (PID {})", self.name, pid);
+        {
+            let mut slot = self.child.write().await;
+            *slot = Some(SpawnedChild::new(child, generation));
+            self.spawn_generation.store(generation, Ordering::SeqCst);
+        }
+        self.restart_skipped.store(false, Ordering::SeqCst);
+        self.transition(|_| Some(ProcessState::Starting)).await;
+        self.wait_for_pid_file().await?;
+        *self.started_at.write().await = Some(Utc::now());
 
-                // Wait up to 5 seconds for graceful shutdown WITHOUT holding lock
-                let timeout = Duration::from_secs(5);
-                let start = std::time::Instant::now();
+        self.push_event(ProcessEvent::Started {
+            timestamp: Utc::now(),
+            version: build_info.summary(),
+            generation,
+        })
+        .await;
+        *self.build_info.write().await = Some(build_info);
+        *self.startup_error.write().await = None;
 
-                let mut terminated = false;
-                let mut check_count = 0;
-                while start.elapsed() < timeout {
-                    check_count += 1;
-                    if check_count % 10 == 0 {
-                        info!("Still waiting for {} to terminate (check {})", self.name, check_count);
-                    }
-                    {
-                        let mut child = self.child.write().await;
-                        if let Some(ref mut child) = *child {
-                            if let Ok(Some(_)) = child.try_wait() {
-                                info!("Process {} terminated gracefully after {} checks", self.name, check_count);
-                                terminated = true;
-                                break;
-                            }
-                        }
-                    } // Lock dropped here
-                    sleep(Duration::from_millis(100)).await;
-                }
+        self.wait_until_ready().await
+    }
 
-                if !terminated {
-                    warn!("Process {} did not terminate gracefully after {} checks, sending SIGKILL", self.name, check_count);
-                    let _ = signal::kill(Pid::from_raw(pid), Signal::SIGKILL);
-                    info!("SIGKILL sent to {} (PID {})", self.name, pid);
-                    sleep(Duration::from_millis(500)).await; // Give it time to die
-                    info!("Finished waiting after SIGKILL for {}", self.name);
+    /// Marks the process running after a successful start, or failed.
+    async fn finish_start(&self, result: Result<()>) -> Result<()> {
+        match result {
+            Ok(()) => {
+                self.transition(|from| (from == ProcessState::Starting).then_some(ProcessState::Running))
+                    .await;
+                info!("Process {} started successfully", self.name);
+                // Failures are recorded, but don't undo the start
+                let _ = self.run_hook("post_start", self.config.post_start.as_ref()).await;
+                Ok(())
+            }
+            Err(e) => {
+                self.report_start_failed().await;
+                if let Some(early_exit) = e.downcast_ref::<EarlyExit>() {
+                    self.skip_restart(early_exit.exit_code).await;
                 }
+                Err(e)
             }
+        }
+    }
 
-            #[cfg(not(unix))]
-            {
-                let mut child = self.child.write().await;
-                if let Some(ref mut child) = *child {
-                    child.kill().await.context("Failed to kill process")?;
-                }
+    /// Sends the configured `notify_on_mode_change` signal, if any, to the
+    /// running process.
+    pub async fn notify_mode_change(&self) {
+        let Some(ref signal) = self.config.notify_on_mode_change else {
+            return;
+        };
+        let Some(pid) = self.pid().await else {
+            return;
+        };
+
+        #[cfg(unix)]
+        {
+            use nix::sys::signal::{self, Signal};
+            use nix::unistd::Pid;
+
+            // Validated when the config was loaded
+            let Ok(signal) = signal.parse::<Signal>() else {
+                return;
+            };
+            info!("Sending {} to {} (PID {}) for mode change", signal, self.name, pid);
+            if let Err(e) = signal::kill(Pid::from_raw(pid), signal) {
+                warn!("Failed to send {} to {}: {}", signal, self.name, e);
             }
         }
+        #[cfg(not(unix))]
+        let _ = (signal, pid);
+    }
 
-        info!("Setting state to Idle for {}", self.name);
-        *self.state.write().await = ProcessState::Idle;
-        info!("Process {} stopped", self.name);
+    /// Fails if something is already listening on the configured port, since
+    /// the new process would then fail to bind it.
+    async fn check_port_free(&self) -> Result<()> {
+        if let Some(port) = self.config.port {
+            if port_is_listening(port).await {
+                anyhow::bail!(
+                    "Port {} is already in use, not starting process {}",
+                    port,
+                    self.name
+                );
+            }
+        }
         Ok(())
     }
 
-    pub async fn wait_for_exit(&self) -> Option<i32> {
-        info!("Starting wait_for_exit for {}", self.name);
+    /// Waits for the configured port to accept connections and for a line
+    /// matching the configured ready_pattern, whichever are set. If the
+    /// process exits first or the timeout passes, it is killed.
+    async fn wait_until_ready(&self) -> Result<()> {
+        let port = self.config.port;
+        let pattern = match self.config.ready_pattern {
+            Some(ref pattern) => Some(Regex::new(pattern).context("Invalid ready_pattern")?),
+            None => None,
+        };
+        let mut checks = Vec::new();
+        if let Some(port) = port {
+            checks.push(format!("port {} to accept connections", port));
+        }
+        if let Some(ref pattern) = self.config.ready_pattern {
+            checks.push(format!("a line matching '{}'", pattern));
+        }
+        if checks.is_empty() {
+            *self.readiness.write().await = None;
+            return self.check_early_exit().await;
+        }
+        let check = checks.join(" and ");
+        let timeout = Duration::from_secs(self.config.ready_timeout_seconds);
+        *self.readiness.write().await = Some(Readiness::Waiting { check: check.clone() });
 
-        // Poll for exit without holding the lock
-        loop {
-            let result = {
-                let mut child = self.child.write().await;
-                if let Some(ref mut child) = *child {
-                    child.try_wait()
-                } else {
-                    info!("No child in wait_for_exit for {}", self.name);
-                    return None;
+        let mut port_ready = port.is_none();
+        let mut pattern_ready = pattern.is_none();
+        // Lines of the new instance already checked against the pattern,
+        // counting those dropped from the front of its buffer since
+        let mut scanned: u64 = 0;
+        let start = std::time::Instant::now();
+        let readiness = loop {
+            if self.has_exited().await {
+                break Readiness::Exited { check };
+            }
+            if let (false, Some(port)) = (port_ready, port) {
+                port_ready = port_is_listening(port).await;
+            }
+            if let (false, Some(ref pattern)) = (pattern_ready, &pattern) {
+                let logs = self.logs.read().await;
+                if let Some(instance) = logs.get_instance(None) {
+                    let skip = scanned.saturating_sub(instance.dropped()) as usize;
+                    pattern_ready = instance.lines.iter().skip(skip).any(|line| pattern.is_match(line));
+                    scanned = instance.total;
                 }
-            };
-
-            match result {
-                Ok(Some(status)) => {
-                    let exit_code = status.code();
-                    info!("Process {} exited with code {:?}", self.name, exit_code);
-
-                    // Check if this is a manual restart
-                    let is_manual_restart = self.is_manual_restart_in_progress().await;
+            }
+            if port_ready && pattern_ready {
+                break Readiness::Ready {
+                    check,
+                    after: start.elapsed(),
+                };
+            }
+            if start.elapsed() >= timeout {
+                break Readiness::TimedOut { check, timeout };
+            }
+            sleep(Duration::from_millis(100)).await;
+        };
+        *self.readiness.write().await = Some(readiness.clone());
 
-                    if is_manual_restart {
-                        info!("Process {} stopped for manual restart, not marking as crashed", self.name);
-                        *self.state.write().await = ProcessState::Idle;
-                    } else {
-                        *self.state.write().await = ProcessState::Crashed;
+        if let Readiness::Ready { .. } = readiness {
+            return Ok(());
+        }
 
-                        self.events.write().await.push(ProcessEvent::Crashed {
-                            timestamp: Utc::now(),
-                            exit_code,
-                        });
+        if let Some(ref mut child) = *self.child.write().await {
+            child.start_kill();
+        }
+        anyhow::bail!("Process {} {}", self.name, readiness.description())
+    }
 
-                        error!(
-                            "Process {} exited with code {:?}",
-                            self.name, exit_code
-                        );
+    /// Waits out the early exit window, failing if the new instance exits
+    /// meanwhile, e.g. right after printing a config error. Processes with
+    /// readiness checks are covered by those instead.
+    async fn check_early_exit(&self) -> Result<()> {
+        let start = std::time::Instant::now();
+        while start.elapsed() < self.early_exit_window {
+            if let Some((_, status)) = self.poll_exit().await {
+                // Give the output readers a moment to pass on the last lines
+                sleep(Duration::from_millis(200)).await;
+                let exit_code = status.and_then(|s| s.code());
+                let mut message = format!(
+                    "Process {} exited {:.1}s after starting (exit code: {:?})",
+                    self.name,
+                    start.elapsed().as_secs_f64(),
+                    exit_code
+                );
+                let last_lines = self.log_tail(-1, EARLY_EXIT_LINES).await;
+                if !last_lines.is_empty() {
+                    message.push_str(&format!("\nLast {} lines of the new instance:", last_lines.len()));
+                    for line in &last_lines {
+                        message.push_str(&format!("\n  {}", line));
                     }
-                    return exit_code;
                 }
-                Ok(None) => {
-                    // Still running, sleep and check again
-                    sleep(Duration::from_millis(100)).await;
-                }
-                Err(e) => {
-                    error!("Error waiting for process {}: {}", self.name, e);
+                return Err(EarlyExit { exit_code, message }.into());
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        Ok(())
+    }
+
+    /// Fails if the configured pid file names a running process, since that's
+    /// most likely an instance started earlier; removes it if it's stale.
+    async fn check_pid_file_free(&self) -> Result<()> {
+        let Some(pid_file) = self.pid_file() else {
+            return Ok(());
+        };
+        if let Some(pid) = read_pid_file(&pid_file).await {
+            if pid_alive(pid) {
+                anyhow::bail!(
+                    "{} names running PID {}, not starting process {}",
+                    pid_file.display(),
+                    pid,
+                    self.name
+                );
+            }
+        }
+        *self.daemon_pid.write().await = None;
+        match tokio::fs::remove_file(&pid_file).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove stale {}", pid_file.display()))
+            }
+            _ => Ok(()),
+        }
+    }
 
-                    let is_manual_restart = self.is_manual_restart_in_progress().await;
+    /// For processes that daemonize, waits for the launcher to write the pid
+    /// file and records the daemon's PID, which is supervised from then on.
+    /// If the launcher fails or the timeout passes, it is killed.
+    async fn wait_for_pid_file(&self) -> Result<()> {
+        let Some(pid_file) = self.pid_file() else {
+            return Ok(());
+        };
+        let timeout = Duration::from_secs(self.config.pid_file_timeout_seconds);
 
-                    if !is_manual_restart {
-                        *self.state.write().await = ProcessState::Crashed;
+        let start = std::time::Instant::now();
+        loop {
+            if let Some(pid) = read_pid_file(&pid_file).await.filter(|pid| pid_alive(*pid)) {
+                info!("Process {} daemonized with PID {}", self.name, pid);
+                *self.daemon_pid.write().await = Some(pid);
+                return Ok(());
+            }
 
-                        self.events.write().await.push(ProcessEvent::Crashed {
-                            timestamp: Utc::now(),
-                            exit_code: None,
-                        });
-                    } else {
-                        *self.state.write().await = ProcessState::Idle;
+            // A launcher that exits successfully may still be about to write
+            // the file; one that fails never will
+            let mut child = self.child.write().await;
+            if let Some(ref mut child) = *child {
+                if let Some(Some(status)) = child.exit_status() {
+                    if !status.success() {
+                        anyhow::bail!(
+                            "Process {} exited with {} before writing {}",
+                            self.name,
+                            status,
+                            pid_file.display()
+                        );
                     }
-                    return None;
                 }
+                if start.elapsed() >= timeout {
+                    child.start_kill();
+                }
+            }
+            if start.elapsed() >= timeout {
+                anyhow::bail!(
+                    "Process {} did not write {} within {}s",
+                    self.name,
+                    pid_file.display(),
+                    timeout.as_secs()
+                );
             }
+            drop(child);
+            sleep(Duration::from_millis(100)).await;
         }
     }
 
-    pub async fn set_manual_restart_flag(&self) {
-        *self.manual_restart_in_progress.write().await = true;
-        info!("Manual restart flag set for {}", self.name);
+    fn pid_file(&self) -> Option<PathBuf> {
+        let pid_file = self.config.pid_file.as_ref().filter(|_| self.config.daemonizes)?;
+        Some(self.project_dir.join(pid_file))
     }
 
-    pub async fn clear_manual_restart_flag(&self) {
-        *self.manual_restart_in_progress.write().await = false;
-        info!("Manual restart flag cleared for {}", self.name);
+    /// The PID being supervised: the daemon's for processes that daemonize,
+    /// otherwise the spawned child's.
+    async fn pid(&self) -> Option<i32> {
+        if self.config.daemonizes {
+            return *self.daemon_pid.read().await;
+        }
+        self.child.read().await.as_ref().and_then(|c| c.pid).map(|id| id as i32)
     }
 
-    pub async fn is_manual_restart_in_progress(&self) -> bool {
-        *self.manual_restart_in_progress.read().await
-    }
+    /// Sends `signal` to the process, if it's still running: to its whole
+    /// process group, or to the daemon named in its pid file. Returns whether
+    /// it was sent.
+    ///
+    /// The child is only signaled while its handle says it's running, with
+    /// the handle locked: until it is reaped, its PID (and so its process
+    /// group ID) can't be reused by an unrelated process.
+    #[cfg(unix)]
+    async fn signal_stop(&self, signal: nix::sys::signal::Signal) -> bool {
+        use nix::sys::signal;
+        use nix::unistd::Pid;
 
-    pub async fn get_uptime(&self) -> Option<chrono::Duration> {
-        let started = *self.started_at.read().await;
-        started.map(|start| Utc::now() - start)
+        if self.config.daemonizes {
+            let Some(pid) = *self.daemon_pid.read().await else {
+                return false;
+            };
+            if self.has_exited().await {
+                return false;
+            }
+            info!("Sending {} to {} (daemon PID {})", signal, self.name, pid);
+            return signal::kill(Pid::from_raw(pid), signal).is_ok();
+        }
+
+        let child = self.child.read().await;
+        let Some(ref child) = *child else {
+            return false;
+        };
+        let (None, Some(pid)) = (child.exit_status(), child.pid) else {
+            return false;
+        };
+        info!("Sending {} to {} (process group {})", signal, self.name, pid);
+        signal::killpg(Pid::from_raw(pid as i32), signal).is_ok()
+    }
+
+    /// Polls until the process and everything left in its process `group`
+    /// have exited, for up to `timeout`.
+    #[cfg(unix)]
+    async fn wait_until_exited(&self, group: Option<i32>, timeout: Duration) -> bool {
+        let start = std::time::Instant::now();
+        loop {
+            if self.has_exited().await && !group.is_some_and(group_alive) {
+                return true;
+            }
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    async fn has_exited(&self) -> bool {
+        if self.config.daemonizes {
+            return !self.daemon_pid.read().await.is_some_and(pid_alive);
+        }
+        match *self.child.read().await {
+            Some(ref child) => child.exit_status().is_some(),
+            None => true,
+        }
+    }
+
+    /// Makes `cmd` run as the configured user/group, if any.
+    #[cfg(unix)]
+    fn apply_credentials(&self, cmd: &mut Command) -> Result<()> {
+        let (uid, gid) = resolve_credentials(self.config.user.as_deref(), self.config.group.as_deref())
+            .with_context(|| format!("Cannot run process {} as the configured user/group", self.name))?;
+
+        // Only root can switch to another user or group; fail here with a
+        // clear message rather than with EPERM from the spawn
+        let euid = nix::unistd::geteuid();
+        let egid = nix::unistd::getegid();
+        if !euid.is_root()
+            && (uid.is_some_and(|u| u != euid.as_raw()) || gid.is_some_and(|g| g != egid.as_raw()))
+        {
+            anyhow::bail!(
+                "Cannot run process {} as the configured user/group: the manager is running as uid {} and needs root to switch",
+                self.name,
+                euid
+            );
+        }
+
+        if let Some(gid) = gid {
+            cmd.gid(gid);
+        }
+        if let Some(uid) = uid {
+            cmd.uid(uid);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply_credentials(&self, _cmd: &mut Command) -> Result<()> {
+        if self.config.user.is_some() || self.config.group.is_some() {
+            anyhow::bail!("user and group are only supported on unix (process {})", self.name);
+        }
+        Ok(())
+    }
+
+    /// Sets the configured CPU and I/O priority in the child before it execs.
+    #[cfg(unix)]
+    fn apply_priority(&self, cmd: &mut Command) -> Result<()> {
+        let nice = self.config.nice;
+        let ioprio = self.config.ioprio();
+        if nice.is_none() && ioprio.is_none() {
+            return Ok(());
+        }
+        #[cfg(not(target_os = "linux"))]
+        if ioprio.is_some() {
+            anyhow::bail!("ionice is only supported on Linux (process {})", self.name);
+        }
+
+        // SAFETY: the closure only makes async-signal-safe syscalls. It runs
+        // after any uid switch, so raising priority needs the target user to
+        // be allowed to.
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(nice) = nice {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                #[cfg(target_os = "linux")]
+                if let Some(ioprio) = ioprio {
+                    // ioprio_set(IOPRIO_WHO_PROCESS, self, ioprio)
+                    if libc::syscall(libc::SYS_ioprio_set, 1, 0, ioprio) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply_priority(&self, _cmd: &mut Command) -> Result<()> {
+        if self.config.nice.is_some() || self.config.ionice_class.is_some() {
+            anyhow::bail!("nice and ionice are only supported on unix (process {})", self.name);
+        }
+        Ok(())
+    }
+
+    /// Spawns reader tasks that append the child's stdout/stderr to the log
+    /// buffer, echoing each line to the manager's own output if enabled.
+    fn capture_output(&self, child: &mut Child, generation: u64) {
+        // Capture stdout
+        if let Some(stdout) = child.stdout.take() {
+            let appender = self.log_appender.clone();
+            let echo = self.echo_prefix();
+            let max_line_bytes = self.max_line_bytes;
+            let rate_alert = self.rate_alert();
+            let timestamps = self.timestamps.clone();
+            let name = self.name.clone();
+            tokio::spawn(async move {
+                let mut lines = LineReader::new(stdout, max_line_bytes);
+                loop {
+                    let line = match lines.next_line().await {
+                        Ok(Some(line)) => line,
+                        Ok(None) => break,
+                        Err(e) => {
+                            warn!("Stopped capturing stdout of {}: {}", name, e);
+                            let line = capture_stopped("stdout", &e);
+                            appender.append(generation, line, LineMeta::captured(LogStream::Stdout)).await;
+                            break;
+                        }
+                    };
+                    if let Some(ref prefix) = echo {
+                        println!("{}", console_line(prefix, &line));
+                    }
+                    rate_alert.record(&line, false).await;
+                    let meta = LineMeta::read(&line, LogStream::Stdout, timestamps.as_deref());
+                    appender.append(generation, line, meta).await;
+                }
+            });
+        }
+
+        // Capture stderr
+        if let Some(stderr) = child.stderr.take() {
+            let appender = self.log_appender.clone();
+            let echo = self.echo_prefix();
+            let max_line_bytes = self.max_line_bytes;
+            let rate_alert = self.rate_alert();
+            let timestamps = self.timestamps.clone();
+            let name = self.name.clone();
+            tokio::spawn(async move {
+                let mut lines = LineReader::new(stderr, max_line_bytes);
+                loop {
+                    let line = match lines.next_line().await {
+                        Ok(Some(line)) => line,
+                        Ok(None) => break,
+                        Err(e) => {
+                            warn!("Stopped capturing stderr of {}: {}", name, e);
+                            let line = capture_stopped("stderr", &e);
+                            appender.append(generation, line, LineMeta::captured(LogStream::Stderr)).await;
+                            break;
+                        }
+                    };
+                    if let Some(ref prefix) = echo {
+                        eprintln!("{}", console_line(prefix, &line));
+                    }
+                    rate_alert.record(&line, true).await;
+                    let meta = LineMeta::read(&line, LogStream::Stderr, timestamps.as_deref());
+                    appender.append(generation, format!("[stderr] {}", line), meta).await;
+                }
+            });
+        }
+    }
+
+    fn rate_alert(&self) -> RateAlert {
+        RateAlert {
+            name: self.name.clone(),
+            rates: self.log_rates.clone(),
+            limit: self.config.max_error_rate_per_min,
+            events: self.events.clone(),
+            max_events: self.max_events,
+        }
+    }
+
+    /// Stops the process for good, e.g. when it is removed from the config
+    /// or the manager shuts down. Its crash monitor returns from
+    /// `wait_for_exit` and ends instead of restarting it.
+    pub async fn retire(&self) -> Result<()> {
+        self.retired.send_replace(true);
+        self.request_stop().await
+    }
+
+    pub fn is_retired(&self) -> bool {
+        *self.retired.borrow()
+    }
+
+    /// Stops the process on purpose and waits for it to exit, so the exit
+    /// isn't taken for a crash. Something that isn't running is just marked
+    /// stopped. Runs the pre_stop hook first, and fails without stopping if
+    /// that is required and fails, unless the process is being retired.
+    pub async fn request_stop(&self) -> Result<()> {
+        info!("Stopping process: {}", self.name);
+
+        // Its crash monitor may not have looked since it exited
+        if self.claim_crash(!self.start_in_progress.load(Ordering::SeqCst)).await {
+            warn!("Process {} had already crashed when asked to stop", self.name);
+        }
+
+        if matches!(self.state().await, ProcessState::Starting | ProcessState::Running) {
+            if let Err(e) = self.run_hook("pre_stop", self.config.pre_stop.as_ref()).await {
+                // Nothing stops a retired process from going away
+                if self.config.pre_stop.as_ref().is_some_and(|h| h.required) && !self.is_retired() {
+                    return Err(e.context(format!("Not stopping {}: its required pre_stop hook failed", self.name)));
+                }
+            }
+        }
+
+        let stopping = self
+            .transition(|from| match from {
+                ProcessState::Starting | ProcessState::Running => Some(ProcessState::Stopping),
+                _ => Some(ProcessState::Stopped),
+            })
+            .await
+            == Some(ProcessState::Stopping);
+
+        if stopping {
+            #[cfg(unix)]
+            {
+                use nix::sys::signal::{killpg, Signal};
+                use nix::unistd::Pid;
+
+                // A wrapper may exit before what it started, so the whole
+                // group has to be gone
+                let group = if self.config.daemonizes { None } else { self.pid().await };
+
+                if self.signal_stop(Signal::SIGTERM).await {
+                    // Wait up to 5 seconds for graceful shutdown
+                    if self.wait_until_exited(group, Duration::from_secs(5)).await {
+                        info!("Process {} terminated gracefully", self.name);
+                    } else {
+                        warn!("Process {} did not terminate gracefully, sending SIGKILL", self.name);
+                        if !self.signal_stop(Signal::SIGKILL).await {
+                            // Only the wrapped processes are left. The group
+                            // ID can't be reused while they are alive.
+                            if let Some(group) = group {
+                                let _ = killpg(Pid::from_raw(group), Signal::SIGKILL);
+                            }
+                        }
+                        if !self.wait_until_exited(group, Duration::from_secs(5)).await {
+                            error!("Process {} still hasn't exited after SIGKILL", self.name);
+                        }
+                    }
+                } else {
+                    info!("No running process found for {}", self.name);
+                }
+            }
+
+            #[cfg(not(unix))]
+            {
+                let exited = self.child.write().await.as_mut().map(|child| {
+                    child.start_kill();
+                    child.wait()
+                });
+                if let Some(exited) = exited {
+                    exited.await;
+                }
+            }
+        }
+
+        if stopping {
+            let generation = self.spawn_generation.load(Ordering::SeqCst);
+            self.logs.write().await.record_end(generation, "stopped".to_string());
+        }
+
+        // The crash monitor may already have seen the exit
+        self.transition(|from| (from == ProcessState::Stopping).then_some(ProcessState::Stopped))
+            .await;
+        info!("Process {} stopped", self.name);
+        Ok(())
+    }
+
+    /// Waits until the process needs restarting: it crashed, or it isn't
+    /// running because it failed to start. Stops on purpose are waited out,
+    /// since whoever stopped the process starts it again. Returns the exit
+    /// code of the crash, if known, or None as soon as the process is
+    /// retired.
+    pub async fn wait_for_exit(&self) -> Option<i32> {
+        info!("Starting wait_for_exit for {}", self.name);
+
+        let mut retired = self.retired.subscribe();
+        tokio::select! {
+            exit_code = self.wait_for_crash() => exit_code,
+            _ = retired.wait_for(|retired| *retired) => {
+                info!("Process {} was retired, not waiting", self.name);
+                None
+            }
+        }
+    }
+
+    async fn wait_for_crash(&self) -> Option<i32> {
+        let mut state_changes = self.state_changes.subscribe();
+        loop {
+            state_changes.borrow_and_update();
+            let state = self.state().await;
+            if state.needs_restart() {
+                info!("Process {} is {}, not waiting", self.name, state.as_str());
+                return None;
+            }
+            let left_to_start = state == ProcessState::Starting && self.start_in_progress.load(Ordering::SeqCst);
+            let mut exited = None;
+            if matches!(
+                state,
+                ProcessState::Starting | ProcessState::Running | ProcessState::Stopping
+            ) && !left_to_start
+            {
+                if let Some((generation, status)) = self.poll_exit().await {
+                    if self.report_exit(generation, status).await {
+                        return status.and_then(|s| s.code());
+                    }
+                } else {
+                    self.sample_resources().await;
+                    exited = self.child.read().await.as_ref().map(SpawnedChild::wait);
+                }
+            }
+
+            // The spawn's exit and state changes wake this up as they
+            // happen. Daemons, which aren't children, are still polled for,
+            // as is a start handing the process back, and a running process
+            // is woken up for resource samples.
+            let poll = if self.config.daemonizes || left_to_start {
+                Duration::from_millis(100)
+            } else {
+                RESOURCE_SAMPLE_INTERVAL.to_std().unwrap_or(Duration::from_secs(5))
+            };
+            let exited = async move {
+                match exited {
+                    Some(exited) => exited.await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = state_changes.changed() => {}
+                _ = exited => {}
+                _ = sleep(poll) => {}
+            }
+        }
+    }
+
+    /// Samples the resources of the running process, at most every
+    /// RESOURCE_SAMPLE_INTERVAL, for get_status and crash reports. Records
+    /// an event when it goes over `max_open_files_warn`.
+    async fn sample_resources(&self) {
+        let due = self
+            .last_sample
+            .read()
+            .await
+            .is_none_or(|sample| Utc::now() - sample.at >= RESOURCE_SAMPLE_INTERVAL);
+        if !due {
+            return;
+        }
+        let Some(pid) = self.pid().await else {
+            return;
+        };
+        let sample = ResourceSample::read(pid);
+        *self.last_sample.write().await = Some(sample);
+
+        let (Some(open_files), Some(limit)) = (sample.open_files, self.config.max_open_files_warn) else {
+            return;
+        };
+        if open_files <= limit {
+            self.open_files_warned.store(false, Ordering::SeqCst);
+        } else if !self.open_files_warned.swap(true, Ordering::SeqCst) {
+            warn!(
+                "Process {} has {} open files (max_open_files_warn is {})",
+                self.name, open_files, limit
+            );
+            let event = ProcessEvent::OpenFilesExceeded {
+                timestamp: Utc::now(),
+                open_files,
+                limit,
+            };
+            push_capped(&self.events, self.max_events, event).await;
+        }
+    }
+
+    /// Reports the exit of a running process whose child has already exited,
+    /// so a crash just before a stop or respawn isn't taken for part of it
+    /// or missed along with the replaced child. With `starting`, an exit
+    /// while starting is claimed too, for callers that know no start is in
+    /// charge of reporting it. Returns whether it crashed.
+    async fn claim_crash(&self, starting: bool) -> bool {
+        match self.state().await {
+            ProcessState::Running => {}
+            ProcessState::Starting if starting => {}
+            _ => return false,
+        }
+        match self.poll_exit().await {
+            Some((generation, status)) => self.report_exit(generation, status).await,
+            None => false,
+        }
+    }
+
+    /// Checks whether the supervised process has exited, with the spawn
+    /// generation of the child that was polled and its exit status if it has
+    /// one.
+    async fn poll_exit(&self) -> Option<(u64, Option<ExitStatus>)> {
+        if self.config.daemonizes {
+            // Until the pid file is read the launcher is in charge, and
+            // failures are reported by the spawn
+            let generation = self.spawn_generation.load(Ordering::SeqCst);
+            self.daemon_pid.read().await.as_ref()?;
+            return self.has_exited().await.then_some((generation, None));
+        }
+
+        let child = self.child.read().await;
+        let Some(ref child) = *child else {
+            return Some((self.spawn_generation.load(Ordering::SeqCst), None));
+        };
+        let status = child.exit_status()?;
+        if status.is_none() {
+            error!("Error waiting for process {}", self.name);
+        }
+        Some((child.generation, status))
+    }
+
+    /// Records that the child of spawn `generation` exited, returning whether
+    /// that was a crash to restart after: an exit while it is being stopped
+    /// isn't, and neither is the exit of a child that a newer spawn has since
+    /// replaced. An exit code the config rules out restarting after stops
+    /// the process instead.
+    async fn report_exit(&self, generation: u64, status: Option<ExitStatus>) -> bool {
+        let exit_code = status.and_then(|s| s.code());
+        // Checked under the state lock, which a spawn only takes after
+        // replacing the child
+        let next = self
+            .transition(|from| {
+                if self.spawn_generation.load(Ordering::SeqCst) != generation {
+                    return None;
+                }
+                match from {
+                    ProcessState::Stopping => Some(ProcessState::Stopped),
+                    ProcessState::Starting | ProcessState::Running => Some(ProcessState::Crashed),
+                    _ => None,
+                }
+            })
+            .await;
+        let how = describe_exit(status);
+        let how = if next == Some(ProcessState::Stopped) { format!("stopped, {}", how) } else { how };
+        self.logs.write().await.record_end(generation, how);
+        if next.is_none() && self.spawn_generation.load(Ordering::SeqCst) != generation {
+            info!("Process {} was respawned since its exit was seen; ignoring that exit", self.name);
+            return false;
+        }
+        if next != Some(ProcessState::Crashed) {
+            info!("Process {} exited with code {:?} after being stopped", self.name, exit_code);
+            return false;
+        }
+
+        self.record_crash_report(status).await;
+        let last_lines = self.log_tail(-1, CRASH_TAIL_LINES).await;
+        match self.record_crash(exit_code, last_lines).await {
+            1 => error!("Process {} exited with code {:?}", self.name, exit_code),
+            // Only every doubling of a crash loop is worth an error
+            repeats if repeats.is_power_of_two() => error!(
+                "Process {} exited with code {:?}, {} times in a row",
+                self.name, exit_code, repeats
+            ),
+            repeats => debug!(
+                "Process {} exited with code {:?}, {} times in a row",
+                self.name, exit_code, repeats
+            ),
+        }
+        let _ = self.run_hook("post_crash", self.config.post_crash.as_ref()).await;
+        !self.skip_restart(exit_code).await
+    }
+
+    /// Stops the process for good after it crashed or failed to start, if it
+    /// exited with a code that `restart_exit_codes` or
+    /// `no_restart_exit_codes` rules out restarting after: some exit codes
+    /// mean the process is done, or that restarting it won't help. Returns
+    /// whether it did.
+    async fn skip_restart(&self, exit_code: Option<i32>) -> bool {
+        let Some(reason) = self.config.restart_refusal(exit_code) else {
+            return false;
+        };
+        warn!("Not restarting {}: {}", self.name, reason);
+        self.restart_skipped.store(true, Ordering::SeqCst);
+        self.transition(|from| {
+            matches!(from, ProcessState::Crashed | ProcessState::Failed).then_some(ProcessState::Stopped)
+        })
+        .await;
+        self.push_event(ProcessEvent::RestartSkipped {
+            timestamp: Utc::now(),
+            exit_code,
+            reason,
+        })
+        .await;
+        true
+    }
+
+    /// Keeps a crash report for the instance that just exited with `status`,
+    /// dropping the oldest beyond MAX_CRASH_REPORTS.
+    async fn record_crash_report(&self, status: Option<ExitStatus>) {
+        #[cfg(unix)]
+        let (signal, core_dumped) = {
+            use std::os::unix::process::ExitStatusExt;
+            (status.and_then(|s| s.signal()), status.is_some_and(|s| s.core_dumped()))
+        };
+        #[cfg(not(unix))]
+        let (signal, core_dumped) = (None, false);
+
+        let sample = *self.last_sample.read().await;
+        let report = CrashReport {
+            timestamp: Utc::now(),
+            exit_code: status.and_then(|s| s.code()),
+            signal,
+            core_dumped,
+            rss: sample.and_then(|s| s.rss.map(|bytes| (bytes, s.at))),
+            open_files: sample.and_then(|s| s.open_files),
+            threads: sample.and_then(|s| s.threads),
+            uptime: self.get_uptime().await,
+            version: self.build_info.read().await.as_ref().map(|info| info.summary()),
+            last_lines: self.log_tail(-1, CRASH_REPORT_LINES).await,
+        };
+
+        let mut reports = self.crash_reports.write().await;
+        if reports.len() >= MAX_CRASH_REPORTS {
+            reports.pop_front();
+        }
+        reports.push_back(report);
+    }
+
+    /// Runs the `name` hook, if configured, and records it as an event. Its
+    /// output goes to the current log instance, prefixed with `[hook]`.
+    async fn run_hook(&self, name: &'static str, hook: Option<&HookConfig>) -> Result<()> {
+        let Some(hook) = hook else {
+            return Ok(());
+        };
+        info!("Running {} hook for {}", name, self.name);
+
+        let start = std::time::Instant::now();
+        let result = self.run_hook_command(hook).await;
+        if let Err(ref e) = result {
+            warn!("The {} hook for {} failed: {:#}", name, self.name, e);
+        }
+        self.push_event(ProcessEvent::HookRan {
+            timestamp: Utc::now(),
+            hook: name,
+            duration: start.elapsed(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+        })
+        .await;
+        result.with_context(|| format!("The {} hook for {} failed", name, self.name))
+    }
+
+    async fn run_hook_command(&self, hook: &HookConfig) -> Result<()> {
+        // Validated when the config was loaded
+        let mut cmd = self.direnv.command(&hook.command[0]).await?;
+        cmd.args(&hook.command[1..])
+            .current_dir(&self.project_dir)
+            .stdin(Stdio::null())
+            .kill_on_drop(true);
+        self.apply_credentials(&mut cmd)?;
+
+        let timeout = Duration::from_secs(hook.timeout_seconds);
+        let output = tokio::time::timeout(timeout, cmd.output())
+            .await
+            .map_err(|_| anyhow::anyhow!("timed out after {}s", hook.timeout_seconds))?
+            .context("Failed to run hook")?;
+
+        let generation = self.logs.read().await.generation();
+        let echo = self.echo_prefix();
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if let Some(ref prefix) = echo {
+                println!("{}", console_line(prefix, &format!("[hook] {}", line)));
+            }
+            self.log_appender
+                .append(generation, format!("[hook] {}", line), LineMeta::captured(LogStream::Stdout))
+                .await;
+        }
+        for line in String::from_utf8_lossy(&output.stderr).lines() {
+            if let Some(ref prefix) = echo {
+                eprintln!("{}", console_line(prefix, &format!("[hook] {}", line)));
+            }
+            self.log_appender
+                .append(generation, format!("[hook] [stderr] {}", line), LineMeta::captured(LogStream::Stderr))
+                .await;
+        }
+
+        if !output.status.success() {
+            anyhow::bail!("exited with {}", output.status);
+        }
+        Ok(())
+    }
+
+    /// Pushes a Crashed event, collapsing it into the previous one if that
+    /// was identical (same exit code and last line of output) and within
+    /// CRASH_REPEAT_WINDOW. The collapsed event moves to the end, so it
+    /// still follows the Started events before it. Returns how many times in
+    /// a row the process has now crashed like this.
+    async fn record_crash(&self, exit_code: Option<i32>, last_lines: Vec<String>) -> u32 {
+        let now = Utc::now();
+        let (repeats, since) = {
+            let mut events = self.events.write().await;
+            let previous = events
+                .iter()
+                .rposition(|e| matches!(e, ProcessEvent::Crashed { .. }))
+                .filter(|&i| match events[i] {
+                    ProcessEvent::Crashed {
+                        timestamp,
+                        exit_code: previous_code,
+                        last_lines: ref previous_lines,
+                        ..
+                    } => {
+                        previous_code == exit_code
+                            && previous_lines.last() == last_lines.last()
+                            && now - timestamp < CRASH_REPEAT_WINDOW
+                    }
+                    _ => false,
+                });
+            match previous.and_then(|i| events.remove(i)) {
+                Some(ProcessEvent::Crashed { repeats, since, .. }) => (repeats + 1, since),
+                _ => (1, now),
+            }
+        };
+
+        self.push_event(ProcessEvent::Crashed {
+            timestamp: now,
+            exit_code,
+            last_lines,
+            repeats,
+            since,
+        })
+        .await;
+        repeats
+    }
+
+    /// Marks the process as being built, before starting it.
+    pub async fn report_building(&self) {
+        self.restart_skipped.store(false, Ordering::SeqCst);
+        self.transition(|_| Some(ProcessState::Building)).await;
+    }
+
+    /// Marks the process failed to start, unless it already crashed while
+    /// starting or its exit stopped it for good.
+    pub async fn report_start_failed(&self) {
+        if self.restart_skipped.load(Ordering::SeqCst) {
+            return;
+        }
+        self.transition(|from| (from != ProcessState::Crashed).then_some(ProcessState::Failed))
+            .await;
+    }
+
+    pub async fn state(&self) -> ProcessState {
+        *self.state.read().await
+    }
+
+    /// Holds the state lock as a stuck operation would, until dropped.
+    #[cfg(test)]
+    pub async fn hold_state_lock(&self) -> tokio::sync::RwLockWriteGuard<'_, ProcessState> {
+        self.state.write().await
+    }
+
+    /// Moves to the state `next` picks for the current one, if any. This is
+    /// the only place the state is written: illegal transitions are refused
+    /// and logged, and every change is recorded as an event. Returns the new
+    /// state if it changed.
+    async fn transition(&self, next: impl FnOnce(ProcessState) -> Option<ProcessState>) -> Option<ProcessState> {
+        let mut state = self.state.write().await;
+        let from = *state;
+        let to = next(from).filter(|to| *to != from)?;
+        if !from.can_transition_to(to) {
+            warn!(
+                "Refusing illegal state transition for {}: {} -> {}",
+                self.name,
+                from.as_str(),
+                to.as_str()
+            );
+            return None;
+        }
+
+        *state = to;
+        self.state_changes.send_replace(to);
+        info!("Process {} is now {} (was {})", self.name, to.as_str(), from.as_str());
+        // Still holding the state lock, so events are recorded in order
+        self.push_event(ProcessEvent::StateChanged {
+            timestamp: Utc::now(),
+            from,
+            to,
+        })
+        .await;
+        Some(to)
+    }
+
+    /// Records a failed rebuild, so status shows why the process isn't back.
+    pub async fn record_build_failure(&self, error: &anyhow::Error) {
+        self.push_event(ProcessEvent::BuildFailed {
+            timestamp: Utc::now(),
+            error: format!("{:#}", error),
+        })
+        .await;
+        self.report_start_failed().await;
+    }
+
+    /// Records an event, evicting the oldest ones beyond `max_events`.
+    async fn push_event(&self, event: ProcessEvent) {
+        push_capped(&self.events, self.max_events, event).await;
+    }
+
+    /// Why the process shouldn't be considered healthy, if it isn't: it is
+    /// crashed or failed, or has crashed within `window`.
+    pub async fn health_problem(&self, window: chrono::Duration) -> Option<&'static str> {
+        match self.state().await {
+            ProcessState::Crashed => return Some("crashed"),
+            ProcessState::Failed => return Some("failed to start"),
+            _ => {}
+        }
+
+        let events = self.events.read().await;
+        let cutoff = Utc::now() - window;
+        events
+            .iter()
+            .rev()
+            .any(|event| matches!(event, ProcessEvent::Crashed { timestamp, .. } if *timestamp > cutoff))
+            .then_some("crash-looping")
+    }
+
+    /// The last `lines` lines of a log instance (same indexing as search_logs),
+    /// or nothing if the instance doesn't exist.
+    pub async fn log_tail(&self, index: i32, lines: usize) -> Vec<String> {
+        let logs = self.logs.read().await;
+        match logs.get_instance(Some(index)) {
+            Some(instance) => {
+                let skip = instance.lines.len().saturating_sub(lines);
+                instance.lines.iter().skip(skip).map(|line| line.to_string()).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// The log instance holding the most recent crash, if the process crashed
+    /// and hasn't been started successfully since: -1 while it's still down,
+    /// -2 once it has been respawned.
+    pub async fn crash_instance(&self) -> Option<i32> {
+        let events = self.events.read().await;
+        // Only starts and crashes change which instance crashed
+        let mut recent = events
+            .iter()
+            .rev()
+            .filter(|e| matches!(e, ProcessEvent::Started { .. } | ProcessEvent::Crashed { .. }));
+        match (recent.next(), recent.next()) {
+            (Some(ProcessEvent::Crashed { .. }), _) => Some(-1),
+            (Some(ProcessEvent::Started { .. }), Some(ProcessEvent::Crashed { .. })) => Some(-2),
+            _ => None,
+        }
+    }
+
+    pub async fn set_manual_restart_flag(&self) {
+        *self.manual_restart_in_progress.write().await = true;
+        info!("Manual restart flag set for {}", self.name);
+    }
+
+    pub async fn clear_manual_restart_flag(&self) {
+        *self.manual_restart_in_progress.write().await = false;
+        info!("Manual restart flag cleared for {}", self.name);
+    }
+
+    pub async fn is_manual_restart_in_progress(&self) -> bool {
+        *self.manual_restart_in_progress.read().await
+    }
+
+    pub async fn get_uptime(&self) -> Option<chrono::Duration> {
+        let started = *self.started_at.read().await;
+        started.map(|start| Utc::now() - start)
+    }
+}
+
+/// A new instance exiting within the early exit window, which fails its
+/// start.
+#[derive(Debug)]
+struct EarlyExit {
+    exit_code: Option<i32>,
+    message: String,
+}
+
+impl std::fmt::Display for EarlyExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for EarlyExit {}
+
+/// An echoed line: behind `prefix`, unless the output is raw.
+fn console_line(prefix: &str, line: &str) -> String {
+    if prefix.is_empty() {
+        line.to_string()
+    } else {
+        format!("{} {}", prefix, line)
+    }
+}
+
+/// How a child exited, for the summary of its log instance.
+fn describe_exit(status: Option<ExitStatus>) -> String {
+    #[cfg(unix)]
+    let signal = {
+        use std::os::unix::process::ExitStatusExt;
+        status.and_then(|s| s.signal())
+    };
+    #[cfg(not(unix))]
+    let signal: Option<i32> = None;
+
+    match (status.and_then(|s| s.code()), signal) {
+        (Some(code), _) => format!("exited with code {}", code),
+        (None, Some(signal)) => format!("killed by signal {}", signal),
+        (None, None) => "exited".to_string(),
+    }
+}
+
+/// True if something accepts TCP connections on the port on localhost.
+async fn port_is_listening(port: u16) -> bool {
+    let connect = tokio::net::TcpStream::connect(("127.0.0.1", port));
+    matches!(tokio::time::timeout(Duration::from_millis(500), connect).await, Ok(Ok(_)))
+}
+
+/// A numeric field of /proc/`pid`/status, e.g. "VmRSS:" in KiB or
+/// "Threads:". Linux only.
+fn read_proc_status(pid: i32, field: &str) -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+        let line = status.lines().find(|line| line.starts_with(field))?;
+        line.split_whitespace().nth(1)?.parse().ok()
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (pid, field);
+        None
+    }
+}
+
+/// Number of file descriptors `pid` has open, from /proc. Linux only.
+fn read_open_files(pid: i32) -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        let entries = std::fs::read_dir(format!("/proc/{}/fd", pid)).ok()?;
+        Some(entries.count() as u64)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = pid;
+        None
+    }
+}
+
+/// Reads a PID from a pid file, if it exists and holds one.
+async fn read_pid_file(path: &Path) -> Option<i32> {
+    let contents = tokio::fs::read_to_string(path).await.ok()?;
+    contents.trim().parse().ok().filter(|pid| *pid > 0)
+}
+
+/// True if a process with the PID exists and hasn't exited. Zombies count as
+/// exited, since a daemon's zombie is for its new parent to reap.
+#[cfg(unix)]
+fn pid_alive(pid: i32) -> bool {
+    use nix::errno::Errno;
+    use nix::sys::signal::kill;
+    use nix::unistd::Pid;
+
+    // EPERM: it exists but belongs to another user
+    if !matches!(kill(Pid::from_raw(pid), None), Ok(()) | Err(Errno::EPERM)) {
+        return false;
+    }
+    #[cfg(target_os = "linux")]
+    if let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        // The state follows the parenthesized command name, which may itself
+        // contain parentheses
+        let state = stat.rsplit_once(')').and_then(|(_, rest)| rest.trim_start().chars().next());
+        return state != Some('Z');
+    }
+    true
+}
+
+/// What an output capture task needs to count its lines and record an event
+/// when the error rate goes over the limit.
+struct RateAlert {
+    name: String,
+    rates: Arc<LogRates>,
+    limit: Option<u64>,
+    events: Arc<RwLock<VecDeque<ProcessEvent>>>,
+    max_events: usize,
+}
+
+impl RateAlert {
+    async fn record(&self, line: &str, stderr: bool) {
+        let (Some(rate), Some(limit)) = (self.rates.record(line, stderr), self.limit) else {
+            return;
+        };
+        warn!(
+            "Process {} logged {} error lines in the last minute (max_error_rate_per_min is {})",
+            self.name, rate, limit
+        );
+        let event = ProcessEvent::ErrorRateExceeded {
+            timestamp: Utc::now(),
+            rate,
+            limit,
+        };
+        push_capped(&self.events, self.max_events, event).await;
+    }
+}
+
+/// Appends to an event list, dropping the oldest beyond `max_events`. State
+/// changes are capped apart from the other events, so a flapping process
+/// can't push its crashes out of the history.
+async fn push_capped(events: &RwLock<VecDeque<ProcessEvent>>, max_events: usize, event: ProcessEvent) {
+    let mut events = events.write().await;
+    let state_change = event.is_state_change();
+    events.push_back(event);
+    let mut kept = events.iter().filter(|e| e.is_state_change() == state_change).count();
+    while kept > max_events {
+        if let Some(oldest) = events.iter().position(|e| e.is_state_change() == state_change) {
+            events.remove(oldest);
+        }
+        kept -= 1;
+    }
+}
+
+#[cfg(not(unix))]
+fn pid_alive(_pid: i32) -> bool {
+    false
+}
+
+/// Whether any process in process group `pgid` is still running. Zombies
+/// don't count, as with `pid_alive`.
+#[cfg(target_os = "linux")]
+fn group_alive(pgid: i32) -> bool {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+            return false;
+        };
+        // After the command name: state, ppid, pgrp
+        let mut fields = stat.rsplit_once(')').map(|(_, rest)| rest.split_whitespace()).into_iter().flatten();
+        let state = fields.next();
+        let pgrp = fields.nth(1).and_then(|pgrp| pgrp.parse::<i32>().ok());
+        pgrp == Some(pgid) && state != Some("Z")
+    })
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn group_alive(pgid: i32) -> bool {
+    use nix::errno::Errno;
+    use nix::sys::signal::killpg;
+    use nix::unistd::Pid;
+
+    matches!(killpg(Pid::from_raw(pgid), None), Ok(()) | Err(Errno::EPERM))
+}
+
+/// Resolves a user and group, given by name or numeric id, to the uid and gid
+/// to spawn with. A user without a group gets the user's primary group.
+#[cfg(unix)]
+fn resolve_credentials(user: Option<&str>, group: Option<&str>) -> Result<(Option<u32>, Option<u32>)> {
+    use nix::unistd::{Gid, Group, Uid, User};
+
+    let user = match user {
+        Some(name) => {
+            let found = match name.parse::<u32>() {
+                Ok(uid) => User::from_uid(Uid::from_raw(uid)),
+                Err(_) => User::from_name(name),
+            };
+            Some(
+                found
+                    .with_context(|| format!("Failed to look up user '{}'", name))?
+                    .with_context(|| format!("User '{}' does not exist", name))?,
+            )
+        }
+        None => None,
+    };
+
+    let gid = match group {
+        Some(name) => {
+            let found = match name.parse::<u32>() {
+                Ok(gid) => Group::from_gid(Gid::from_raw(gid)),
+                Err(_) => Group::from_name(name),
+            };
+            let group = found
+                .with_context(|| format!("Failed to look up group '{}'", name))?
+                .with_context(|| format!("Group '{}' does not exist", name))?;
+            Some(group.gid.as_raw())
+        }
+        None => user.as_ref().map(|u| u.gid.as_raw()),
+    };
+
+    Ok((user.map(|u| u.uid.as_raw()), gid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DirenvStrategy;
+
+    fn no_direnv() -> Arc<Direnv> {
+        Arc::new(Direnv::new(PathBuf::from("."), DirenvStrategy::Off))
+    }
+
+    #[tokio::test]
+    async fn test_events_are_capped() {
+        let config: ProcessConfig = toml::from_str("type = \"rust\"").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 3, 16384);
+
+        for code in 0..5 {
+            process
+                .push_event(ProcessEvent::Crashed {
+                    timestamp: Utc::now(),
+                    exit_code: Some(code),
+                    last_lines: Vec::new(),
+                    repeats: 1,
+                    since: Utc::now(),
+                })
+                .await;
+        }
+
+        let events = process.events.read().await;
+        let codes: Vec<_> = events
+            .iter()
+            .map(|e| match e {
+                ProcessEvent::Crashed { exit_code, .. } => *exit_code,
+                _ => None,
+            })
+            .collect();
+        assert_eq!(codes, vec![Some(2), Some(3), Some(4)]);
+        drop(events);
+
+        // Flapping through states doesn't push the crashes out
+        for _ in 0..5 {
+            process
+                .push_event(ProcessEvent::StateChanged {
+                    timestamp: Utc::now(),
+                    from: ProcessState::Starting,
+                    to: ProcessState::Crashed,
+                })
+                .await;
+        }
+        let events = process.events.read().await;
+        assert_eq!(events.len(), 6);
+        assert_eq!(events.iter().filter(|e| e.is_state_change()).count(), 3);
+        assert!(matches!(events[0], ProcessEvent::Crashed { exit_code: Some(2), .. }));
+    }
+
+    #[tokio::test]
+    async fn test_sources_changed_walks_in_background() {
+        let root = std::env::temp_dir().join(format!("bpm-test-sources-changed-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+        let config: ProcessConfig = toml::from_str("type = \"rust\"").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, root.clone(), no_direnv(), false, 10, 16384);
+        let built = std::time::SystemTime::now() - Duration::from_secs(3600);
+
+        // Nothing is known until the walk it starts has finished
+        let config = crate::config::Config::parse("mcp_port = 3001\n[process.main]\ntype = \"rust\"\n").unwrap();
+        let builder = Arc::new(Builder::new(&config));
+        assert!(!process.sources_changed(&builder, built));
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !process.sources_changed(&builder, built) {
+            assert!(std::time::Instant::now() < deadline, "walk never answered");
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!process.sources_walking.load(Ordering::SeqCst));
+
+        // A newer binary needs a walk of its own
+        assert!(!process.sources_changed(&builder, std::time::SystemTime::now() + Duration::from_secs(3600)));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_repeated_crashes_are_collapsed() {
+        let config: ProcessConfig = toml::from_str("type = \"rust\"").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+        let started = || ProcessEvent::Started {
+            timestamp: Utc::now(),
+            version: "unknown".to_string(),
+            generation: 1,
+        };
+        let lines = |last: &str| vec!["starting".to_string(), last.to_string()];
+
+        for _ in 0..3 {
+            process.push_event(started()).await;
+            process.record_crash(Some(1), lines("panicked")).await;
+        }
+        {
+            let events = process.events.read().await;
+            assert_eq!(events.len(), 4);
+            // The crash comes after the starts it collapsed
+            assert!(matches!(events[3], ProcessEvent::Crashed { repeats: 3, .. }));
+            assert!(events[3].description().starts_with("Crashed ×3 from "));
+        }
+        assert_eq!(process.crash_instance().await, Some(-1));
+
+        // A different exit code or last line starts a new event
+        assert_eq!(process.record_crash(Some(2), lines("panicked")).await, 1);
+        assert_eq!(process.record_crash(Some(2), lines("out of memory")).await, 1);
+        assert_eq!(process.record_crash(Some(2), lines("out of memory")).await, 2);
+
+        // So does one long after the previous
+        process.events.write().await.push_back(ProcessEvent::Crashed {
+            timestamp: Utc::now() - chrono::Duration::minutes(5),
+            exit_code: Some(3),
+            last_lines: Vec::new(),
+            repeats: 1,
+            since: Utc::now() - chrono::Duration::minutes(5),
+        });
+        assert_eq!(process.record_crash(Some(3), Vec::new()).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_crash_instance() {
+        let config: ProcessConfig = toml::from_str("type = \"rust\"").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+        let started = || ProcessEvent::Started {
+            timestamp: Utc::now(),
+            version: "unknown".to_string(),
+            generation: 1,
+        };
+
+        process.push_event(started()).await;
+        assert_eq!(process.crash_instance().await, None);
+
+        process
+            .push_event(ProcessEvent::Crashed {
+                timestamp: Utc::now(),
+                exit_code: Some(1),
+                last_lines: Vec::new(),
+                repeats: 1,
+                since: Utc::now(),
+            })
+            .await;
+        assert_eq!(process.crash_instance().await, Some(-1));
+
+        process
+            .record_build_failure(&anyhow::anyhow!("Binary not found"))
+            .await;
+        assert_eq!(process.crash_instance().await, Some(-1));
+
+        process.push_event(started()).await;
+        assert_eq!(process.crash_instance().await, Some(-2));
+
+        // A manual restart after that isn't a crash anymore
+        process.push_event(started()).await;
+        assert_eq!(process.crash_instance().await, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_credentials() {
+        assert_eq!(resolve_credentials(None, None).unwrap(), (None, None));
+
+        // root exists everywhere, with uid and primary gid 0
+        assert_eq!(resolve_credentials(Some("root"), None).unwrap(), (Some(0), Some(0)));
+        assert_eq!(resolve_credentials(Some("0"), Some("0")).unwrap(), (Some(0), Some(0)));
+        assert_eq!(resolve_credentials(None, Some("0")).unwrap(), (None, Some(0)));
+
+        let err = resolve_credentials(Some("no-such-user-bpm"), None).unwrap_err();
+        assert!(err.to_string().contains("does not exist"), "{}", err);
+        let err = resolve_credentials(None, Some("no-such-group-bpm")).unwrap_err();
+        assert!(err.to_string().contains("does not exist"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_nice_is_applied() {
+        let config: ProcessConfig =
+            toml::from_str("type = \"npm\"\ncommand = [\"sh\", \"-c\", \"nice\"]\nnice = 7").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+
+        process.spawn_npm_process().await.unwrap();
+        process.wait_for_exit().await;
+
+        // Give the appender a moment to catch up with the output
+        let mut output = Vec::new();
+        for _ in 0..100 {
+            output = process.log_tail(-1, 1).await;
+            if !output.is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(output, vec!["7".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_port_readiness() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config: ProcessConfig = toml::from_str(&format!(
+            "type = \"npm\"\ncommand = [\"sleep\", \"60\"]\nport = {}\nready_timeout_seconds = 1",
+            port
+        ))
+        .unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+
+        // Something else holds the port
+        let err = process.spawn_npm_process().await.unwrap_err();
+        assert!(err.to_string().contains("already in use"), "{}", err);
+        assert_eq!(process.state().await, ProcessState::Failed);
+
+        // The port is free, but the process never listens on it
+        drop(listener);
+        let err = process.spawn_npm_process().await.unwrap_err();
+        assert!(err.to_string().contains("not ready after 1s"), "{}", err);
+        assert!(matches!(
+            *process.readiness.read().await,
+            Some(Readiness::TimedOut { .. })
+        ));
+        // It was killed and marked failed, so the crash monitor takes over
+        assert_eq!(process.state().await, ProcessState::Failed);
+        assert_eq!(process.wait_for_exit().await, None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_ready_pattern() {
+        let config: ProcessConfig = toml::from_str(
+            "type = \"npm\"\ncommand = [\"sh\", \"-c\", \"echo booting; sleep 0.3; echo Listening on 1234; sleep 60\"]\nready_pattern = \"^Listening on \\\\d+\"",
+        )
+        .unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+
+        process.spawn_npm_process().await.unwrap();
+        assert_eq!(process.state().await, ProcessState::Running);
+        let readiness = process.readiness.read().await.clone().unwrap();
+        assert!(matches!(readiness, Readiness::Ready { after, .. } if after >= Duration::from_millis(300)));
+        assert!(process.log_tail(-1, 5).await.contains(&"Listening on 1234".to_string()));
+        process.request_stop().await.unwrap();
+
+        // Exiting without printing it fails the start
+        let config: ProcessConfig = toml::from_str(
+            "type = \"npm\"\ncommand = [\"echo\", \"booting\"]\nready_pattern = \"Listening\"",
+        )
+        .unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+        let err = process.spawn_npm_process().await.unwrap_err();
+        assert!(err.to_string().contains("exited before it was ready"), "{}", err);
+        assert_eq!(process.state().await, ProcessState::Failed);
+
+        // Found after the log has started dropping lines from the front
+        let config: ProcessConfig = toml::from_str(
+            "type = \"npm\"\ncommand = [\"sh\", \"-c\", \"sleep 0.3; seq 1 10500; sleep 0.5; echo Listening on 1234; sleep 60\"]\nready_pattern = \"^Listening on\"",
+        )
+        .unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+        process.spawn_npm_process().await.unwrap();
+        assert_eq!(process.state().await, ProcessState::Running);
+        process.request_stop().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_exit_notification() {
+        // The exit wakes the monitor up, well before the next resource sample
+        let config: ProcessConfig = toml::from_str("type = \"npm\"\ncommand = [\"sh\", \"-c\", \"sleep 0.3; exit 4\"]").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+        process.spawn_npm_process().await.unwrap();
+        let exit = tokio::time::timeout(Duration::from_secs(2), process.wait_for_exit()).await;
+        assert_eq!(exit.unwrap(), Some(4));
+
+        // A start abandoned while waiting for readiness leaves the process
+        // starting; its exit is still claimed as a crash
+        let config: ProcessConfig = toml::from_str(
+            "type = \"npm\"\ncommand = [\"sh\", \"-c\", \"sleep 0.3; exit 3\"]\nready_pattern = \"never\"",
+        )
+        .unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+        assert!(tokio::time::timeout(Duration::from_millis(100), process.spawn_npm_process()).await.is_err());
+        assert_eq!(process.state().await, ProcessState::Starting);
+        sleep(Duration::from_millis(500)).await;
+        process.request_stop().await.unwrap();
+        let events = process.events.read().await;
+        assert!(events.iter().any(|e| matches!(e, ProcessEvent::Crashed { exit_code: Some(3), .. })));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_early_exit() {
+        let config: ProcessConfig =
+            toml::from_str("type = \"npm\"\ncommand = [\"sh\", \"-c\", \"echo bad config; exit 1\"]").unwrap();
+        let process = Arc::new(
+            ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384)
+                .with_early_exit_window(Duration::from_secs(3)),
+        );
+        let start = tokio::spawn({
+            let process = process.clone();
+            async move { process.spawn_npm_process().await }
+        });
+        while process.state().await == ProcessState::NotStarted {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        // The crash monitor leaves the exit to the start: a failed start, not
+        // a start and a crash
+        assert_eq!(process.wait_for_exit().await, None);
+        let err = start.await.unwrap().unwrap_err().to_string();
+        assert!(err.contains("exit code: Some(1)"), "{}", err);
+        assert!(err.contains("\n  bad config"), "{}", err);
+        assert_eq!(process.state().await, ProcessState::Failed);
+        assert!(!process.events.read().await.iter().any(|e| matches!(e, ProcessEvent::Crashed { .. })));
+
+        // Staying up for the window is a successful start
+        let config: ProcessConfig = toml::from_str("type = \"npm\"\ncommand = [\"sleep\", \"60\"]").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384)
+            .with_early_exit_window(Duration::from_millis(300));
+        process.spawn_npm_process().await.unwrap();
+        assert_eq!(process.state().await, ProcessState::Running);
+        process.request_stop().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_daemonized_process() {
+        let dir = std::env::temp_dir().join(format!("bpm-test-daemon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config: ProcessConfig = toml::from_str(
+            "type = \"npm\"\ncommand = [\"sh\", \"-c\", \"sleep 60 & echo $! > daemon.pid\"]\ndaemonizes = true\npid_file = \"daemon.pid\"",
+        )
+        .unwrap();
+        let process = ProcessManager::new("main".to_string(), config, dir.clone(), no_direnv(), false, 10, 16384);
+
+        // The launcher exiting right away is fine; the daemon is supervised
+        process.spawn_npm_process().await.unwrap();
+        let pid = process.daemon_pid.read().await.unwrap();
+        assert!(pid_alive(pid));
+        sleep(Duration::from_millis(200)).await;
+        assert!(!process.has_exited().await);
+        assert_eq!(process.state().await, ProcessState::Running);
+
+        // A live PID in the pid file blocks a second instance
+        let err = process.spawn_npm_process().await.unwrap_err();
+        assert!(err.to_string().contains("names running PID"), "{}", err);
+
+        nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), nix::sys::signal::Signal::SIGKILL).unwrap();
+        process.wait_for_exit().await;
+        assert_eq!(process.state().await, ProcessState::Crashed);
+        assert_eq!(process.crash_instance().await, Some(-1));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_capture_survives_invalid_utf8() {
+        let config: ProcessConfig = toml::from_str(
+            "type = \"npm\"\ncommand = [\"sh\", \"-c\", \"printf 'bin\\\\377ary\\\\n'; echo after; sleep 60\"]",
+        )
+        .unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+
+        process.spawn_npm_process().await.unwrap();
+        for _ in 0..50 {
+            if process.log_tail(-1, 5).await.len() == 2 {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(process.log_tail(-1, 5).await, ["bin\u{FFFD}ary", "after"]);
+        process.request_stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_generation_exported() {
+        let config: ProcessConfig = toml::from_str(
+            "type = \"npm\"\ncommand = [\"sh\", \"-c\", \"echo $BPM_RUN_ID $BPM_GENERATION; sleep 60\"]",
+        )
+        .unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+
+        for generation in 1..=2 {
+            process.spawn_npm_process().await.unwrap();
+            let expected = format!("{} {}", run_id(), generation);
+            for _ in 0..50 {
+                if !process.log_tail(-1, 1).await.is_empty() {
+                    break;
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+            assert_eq!(process.log_tail(-1, 1).await, [expected]);
+            process.request_stop().await.unwrap();
+        }
+        let events = process.events.read().await;
+        let started = events.iter().rev().find(|e| matches!(e, ProcessEvent::Started { .. }));
+        assert!(matches!(started, Some(ProcessEvent::Started { generation: 2, .. })));
+    }
+
+    #[tokio::test]
+    async fn test_crash_before_stop_reported() {
+        let config: ProcessConfig =
+            toml::from_str("type = \"npm\"\ncommand = [\"sh\", \"-c\", \"sleep 0.3; exit 3\"]").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+
+        // No crash monitor polls it, as when one is between polls
+        process.spawn_npm_process().await.unwrap();
+        sleep(Duration::from_millis(800)).await;
+        process.request_stop().await.unwrap();
+
+        assert_eq!(process.state().await, ProcessState::Stopped);
+        let reports = process.crash_reports.read().await;
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].exit_code, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_stale_exit_ignored() {
+        let config: ProcessConfig = toml::from_str("type = \"npm\"\ncommand = [\"sleep\", \"60\"]").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+
+        process.spawn_npm_process().await.unwrap();
+        let first = process.spawn_generation.load(Ordering::SeqCst);
+        process.request_stop().await.unwrap();
+        process.spawn_npm_process().await.unwrap();
+
+        // A waiter that saw the first child exit reports it only now
+        assert!(!process.report_exit(first, None).await);
+        assert_eq!(process.state().await, ProcessState::Running);
+        assert!(process.crash_reports.read().await.is_empty());
+
+        process.request_stop().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_crash_report() {
+        let config: ProcessConfig = toml::from_str(
+            "type = \"npm\"\ncommand = [\"sh\", \"-c\", \"echo about to crash; sleep 0.5; kill -KILL $$\"]",
+        )
+        .unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+
+        process.spawn_npm_process().await.unwrap();
+        process.wait_for_exit().await;
+
+        let reports = process.crash_reports.read().await;
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(report.exit_code, None);
+        assert_eq!(report.signal, Some(9));
+        assert_eq!(report.last_lines, ["about to crash"]);
+        #[cfg(target_os = "linux")]
+        assert!(report.rss.is_some_and(|(bytes, _)| bytes > 0));
+        #[cfg(target_os = "linux")]
+        assert_eq!(report.threads, Some(1));
+        assert_eq!(report.to_json()["signal"], 9);
+    }
+
+    #[tokio::test]
+    async fn test_restart_exit_codes() {
+        let command = |code: i32| format!("type = \"npm\"\ncommand = [\"sh\", \"-c\", \"sleep 0.3; exit {}\"]\n", code);
+
+        // A code that is allowed crashes as usual
+        let config: ProcessConfig = toml::from_str(&format!("{}restart_exit_codes = [75]", command(75))).unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+        process.spawn_npm_process().await.unwrap();
+        assert_eq!(process.wait_for_exit().await, Some(75));
+        assert_eq!(process.state().await, ProcessState::Crashed);
+
+        // An excluded one stops the process for good, so there is no crash
+        // to wait for
+        let config: ProcessConfig = toml::from_str(&format!("{}no_restart_exit_codes = [1]", command(1))).unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+        process.spawn_npm_process().await.unwrap();
+        assert!(tokio::time::timeout(Duration::from_secs(1), process.wait_for_exit()).await.is_err());
+        assert_eq!(process.state().await, ProcessState::Stopped);
+        let events = process.events.read().await;
+        let skipped = events.iter().find(|e| matches!(e, ProcessEvent::RestartSkipped { .. })).unwrap();
+        assert_eq!(skipped.to_json()["exit_code"], 1);
+        assert_eq!(skipped.summary(), "not restarted (exit code 1 is in no_restart_exit_codes)");
+
+        drop(events);
+
+        // So does one right after starting, e.g. on a config error, which
+        // would otherwise be a failed start to retry
+        let config: ProcessConfig =
+            toml::from_str("type = \"npm\"\ncommand = [\"sh\", \"-c\", \"exit 1\"]\nno_restart_exit_codes = [1]").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384)
+            .with_early_exit_window(Duration::from_secs(2));
+        let err = process.spawn_npm_process().await.unwrap_err().to_string();
+        assert!(err.contains("exit code: Some(1)"), "{}", err);
+        process.report_start_failed().await;
+        assert_eq!(process.state().await, ProcessState::Stopped);
+        assert!(!process.state().await.needs_restart());
+        assert!(process.events.read().await.iter().any(|e| matches!(e, ProcessEvent::RestartSkipped { exit_code: Some(1), .. })));
+
+        // Deaths by a signal aren't ruled out
+        let config: ProcessConfig = toml::from_str("type = \"npm\"\nrestart_exit_codes = [75]").unwrap();
+        assert_eq!(config.restart_refusal(None), None);
+        assert_eq!(
+            config.restart_refusal(Some(0)).as_deref(),
+            Some("exit code 0 is not in restart_exit_codes")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resource_sample() {
+        let config: ProcessConfig =
+            toml::from_str("type = \"npm\"\ncommand = [\"sleep\", \"60\"]\nmax_open_files_warn = 1").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+        process.spawn_npm_process().await.unwrap();
+
+        process.sample_resources().await;
+        let sample = process.last_sample.read().await.unwrap();
+        let json = ResourceSample::to_json(Some(&sample));
+        #[cfg(target_os = "linux")]
+        {
+            assert!(sample.rss.is_some_and(|bytes| bytes > 0));
+            assert!(sample.open_files.is_some_and(|n| n >= 3));
+            assert_eq!(sample.threads, Some(1));
+            assert_eq!(json["threads"], 1);
+
+            // Over max_open_files_warn: one event, however often it is sampled
+            *process.last_sample.write().await = None;
+            process.sample_resources().await;
+            let exceeded: Vec<_> = process
+                .events
+                .read()
+                .await
+                .iter()
+                .filter(|e| matches!(e, ProcessEvent::OpenFilesExceeded { limit: 1, .. }))
+                .cloned()
+                .collect();
+            assert_eq!(exceeded.len(), 1);
+            assert_eq!(exceeded[0].to_json()["type"], "open_files_exceeded");
+        }
+        #[cfg(not(target_os = "linux"))]
+        assert_eq!(json["open_files"], serde_json::Value::Null);
+
+        // Without a sample, the fields are null rather than zero
+        assert_eq!(ResourceSample::to_json(None)["open_files"], serde_json::Value::Null);
+
+        process.request_stop().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_missing_pid_file() {
+        let config: ProcessConfig = toml::from_str(
+            "type = \"npm\"\ncommand = [\"true\"]\ndaemonizes = true\npid_file = \"bpm-no-such.pid\"\npid_file_timeout_seconds = 1",
+        )
+        .unwrap();
+        let process = ProcessManager::new("main".to_string(), config, std::env::temp_dir(), no_direnv(), false, 10, 16384);
+
+        let err = process.spawn_npm_process().await.unwrap_err();
+        assert!(err.to_string().contains("did not write"), "{}", err);
+        assert_eq!(process.state().await, ProcessState::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_health_problem() {
+        let config: ProcessConfig = toml::from_str("type = \"rust\"").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+        let window = chrono::Duration::minutes(10);
+        assert_eq!(process.health_problem(window).await, None);
+
+        process
+            .push_event(ProcessEvent::Crashed {
+                timestamp: Utc::now() - chrono::Duration::minutes(30),
+                exit_code: Some(1),
+                last_lines: Vec::new(),
+                repeats: 1,
+                since: Utc::now() - chrono::Duration::minutes(30),
+            })
+            .await;
+        assert_eq!(process.health_problem(window).await, None);
+
+        process
+            .push_event(ProcessEvent::Crashed {
+                timestamp: Utc::now() - chrono::Duration::minutes(2),
+                exit_code: Some(1),
+                last_lines: Vec::new(),
+                repeats: 1,
+                since: Utc::now() - chrono::Duration::minutes(2),
+            })
+            .await;
+        assert_eq!(process.health_problem(window).await, Some("crash-looping"));
+
+        process.transition(|_| Some(ProcessState::Starting)).await;
+        process.transition(|_| Some(ProcessState::Crashed)).await;
+        assert_eq!(process.health_problem(window).await, Some("crashed"));
+
+        process.report_start_failed().await;
+        assert_eq!(process.health_problem(window).await, Some("crashed"));
+        process.report_building().await;
+        process.report_start_failed().await;
+        assert_eq!(process.health_problem(window).await, Some("failed to start"));
+    }
+
+    #[test]
+    fn test_state_transitions() {
+        use ProcessState::*;
+        let all = [NotStarted, Building, Starting, Running, Stopping, Stopped, Crashed, Failed];
+        let legal = [
+            (NotStarted, Building),
+            (NotStarted, Starting),
+            (NotStarted, Stopped),
+            (NotStarted, Failed),
+            (Building, Starting),
+            (Building, Failed),
+            (Starting, Running),
+            (Starting, Stopping),
+            (Starting, Crashed),
+            (Starting, Failed),
+            (Running, Stopping),
+            (Running, Crashed),
+            (Stopping, Stopped),
+            (Stopped, Building),
+            (Stopped, Starting),
+            (Stopped, Failed),
+            (Crashed, Building),
+            (Crashed, Starting),
+            (Crashed, Stopped),
+            (Crashed, Failed),
+            (Failed, Building),
+            (Failed, Starting),
+            (Failed, Stopped),
+        ];
+
+        for from in all {
+            for to in all {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    legal.contains(&(from, to)),
+                    "{} -> {}",
+                    from.as_str(),
+                    to.as_str()
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transitions_are_recorded() {
+        let config: ProcessConfig = toml::from_str("type = \"rust\"").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+
+        process.report_building().await;
+        // Illegal: nothing was spawned
+        assert_eq!(process.transition(|_| Some(ProcessState::Running)).await, None);
+        assert_eq!(process.state().await, ProcessState::Building);
+        // Same state: not a change
+        process.report_building().await;
+
+        let changes: Vec<_> = process
+            .events
+            .read()
+            .await
+            .iter()
+            .map(|e| match e {
+                ProcessEvent::StateChanged { from, to, .. } => (*from, *to),
+                _ => panic!("unexpected event {:?}", e),
+            })
+            .collect();
+        assert_eq!(changes, [(ProcessState::NotStarted, ProcessState::Building)]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stop_is_not_a_crash() {
+        let config: ProcessConfig = toml::from_str("type = \"npm\"\ncommand = [\"sleep\", \"60\"]").unwrap();
+        let process = Arc::new(ProcessManager::new(
+            "main".to_string(),
+            config,
+            PathBuf::from("."),
+            no_direnv(),
+            false,
+            10,
+            16384,
+        ));
+
+        process.spawn_npm_process().await.unwrap();
+        assert_eq!(process.state().await, ProcessState::Running);
+
+        // The crash monitor watches while the process is stopped and started
+        // again, and only returns for the crash afterwards
+        let monitor = tokio::spawn({
+            let process = process.clone();
+            async move { process.wait_for_exit().await }
+        });
+        process.request_stop().await.unwrap();
+        assert_eq!(process.state().await, ProcessState::Stopped);
+        sleep(Duration::from_millis(300)).await;
+        assert!(!monitor.is_finished());
+
+        process.spawn_npm_process().await.unwrap();
+        process.request_stop().await.unwrap();
+        process.spawn_npm_process().await.unwrap();
+        let pid = process.pid().await.unwrap();
+        nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), nix::sys::signal::Signal::SIGKILL).unwrap();
+        assert_eq!(monitor.await.unwrap(), None);
+        assert_eq!(process.state().await, ProcessState::Crashed);
+
+        let crashes = process
+            .events
+            .read()
+            .await
+            .iter()
+            .filter(|e| matches!(e, ProcessEvent::Crashed { .. }))
+            .count();
+        assert_eq!(crashes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_hooks() {
+        let config: ProcessConfig = toml::from_str(
+            r#"
+            type = "npm"
+            command = ["sleep", "60"]
+            post_start = { command = ["echo", "warm"] }
+            pre_stop = { command = ["sh", "-c", "echo draining; exit 1"], required = true }
+            "#,
+        )
+        .unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 20, 16384);
+        process.spawn_npm_process().await.unwrap();
+
+        // A failing required pre_stop hook keeps the process running
+        let err = process.request_stop().await.unwrap_err();
+        assert!(format!("{:#}", err).contains("required pre_stop hook failed"), "{:#}", err);
+        assert_eq!(process.state().await, ProcessState::Running);
+
+        // ...unless it is retired
+        process.retire().await.unwrap();
+        assert_eq!(process.state().await, ProcessState::Stopped);
+
+        sleep(Duration::from_millis(100)).await;
+        let tail = process.log_tail(-1, 10).await;
+        assert!(tail.contains(&"[hook] warm".to_string()), "{:?}", tail);
+        assert!(tail.contains(&"[hook] draining".to_string()), "{:?}", tail);
+
+        let hooks: Vec<_> = process
+            .events
+            .read()
+            .await
+            .iter()
+            .filter_map(|e| match e {
+                ProcessEvent::HookRan { hook, error, .. } => Some((*hook, error.is_some())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(hooks, [("post_start", false), ("pre_stop", true), ("pre_stop", true)]);
+    }
+
+    /// Spawns `script` under a wrapper shell that doesn't exec it, like
+    /// direnv or npm, and returns the PID of the script.
+    #[cfg(unix)]
+    async fn spawn_wrapped(script: &str) -> (ProcessManager, i32) {
+        let command = format!("sh -c '{}' & echo child $!; wait", script);
+        let config = ProcessConfig {
+            command: vec!["sh".to_string(), "-c".to_string(), command],
+            ..toml::from_str("type = \"npm\"").unwrap()
+        };
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+        process.spawn_npm_process().await.unwrap();
+
+        for _ in 0..50 {
+            let tail = process.log_tail(-1, 5).await;
+            if let Some(pid) = tail.iter().find_map(|l| l.strip_prefix("child ")) {
+                return (process, pid.parse().unwrap());
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        panic!("wrapped script didn't start");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stop_reaches_wrapped_process() {
+        let (process, pid) = spawn_wrapped("sleep 60").await;
+        let start = std::time::Instant::now();
+        process.request_stop().await.unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(3), "{:?}", start.elapsed());
+        assert_eq!(process.state().await, ProcessState::Stopped);
+        assert!(!pid_alive(pid));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stop_kills_process_ignoring_sigterm() {
+        let (process, pid) = spawn_wrapped("trap \"\" TERM; while true; do sleep 0.1; done").await;
+        let start = std::time::Instant::now();
+        process.request_stop().await.unwrap();
+
+        assert!(start.elapsed() >= Duration::from_secs(5), "{:?}", start.elapsed());
+        assert_eq!(process.state().await, ProcessState::Stopped);
+        assert!(!pid_alive(pid));
+        // The exited child isn't signaled again
+        assert!(!process.signal_stop(nix::sys::signal::Signal::SIGKILL).await);
+    }
+
+    #[tokio::test]
+    async fn test_log_tail() {
+        let config: ProcessConfig = toml::from_str("type = \"rust\"").unwrap();
+        let process = ProcessManager::new("main".to_string(), config, PathBuf::from("."), no_direnv(), false, 10, 16384);
+        assert!(process.log_tail(-1, 20).await.is_empty());
+
+        {
+            let mut logs = process.logs.write().await;
+            logs.new_instance();
+            for i in 0..30 {
+                logs.append(format!("line {}", i));
+            }
+            logs.new_instance();
+        }
+
+        let tail = process.log_tail(-2, 20).await;
+        assert_eq!(tail.len(), 20);
+        assert_eq!(tail[0], "line 10");
+        assert_eq!(tail[19], "line 29");
+        assert!(process.log_tail(-1, 20).await.is_empty());
     }
 }
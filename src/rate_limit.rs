@@ -0,0 +1,132 @@
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Sliding-window rate limiter for MCP tool calls, keyed by limit name.
+pub struct RateLimiter {
+    /// Allowed calls per minute, by tool name or "search" for all search tools.
+    limits: HashMap<String, u32>,
+    calls: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: HashMap<String, u32>) -> Self {
+        Self {
+            limits,
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The limit that applies to a tool: its own entry, or "search" for the
+    /// search tools.
+    fn limit_for(&self, tool: &str) -> Option<(&str, u32)> {
+        if let Some((key, limit)) = self.limits.get_key_value(tool) {
+            return Some((key, *limit));
+        }
+        if tool.starts_with("search_") {
+            if let Some((key, limit)) = self.limits.get_key_value("search") {
+                return Some((key, *limit));
+            }
+        }
+        None
+    }
+
+    /// Records a call to `tool`. Returns how long to wait if it exceeds the limit.
+    pub fn check(&self, tool: &str) -> Result<(), Duration> {
+        self.check_at(tool, Instant::now())
+    }
+
+    fn check_at(&self, tool: &str, now: Instant) -> Result<(), Duration> {
+        let Some((key, limit)) = self.limit_for(tool) else {
+            return Ok(());
+        };
+
+        let mut calls = self.calls.lock().unwrap();
+        let window = calls.entry(key.to_string()).or_default();
+        while window
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= WINDOW)
+        {
+            window.pop_front();
+        }
+
+        if window.len() >= limit as usize {
+            let oldest = *window.front().expect("limit is reached, so window is non-empty");
+            return Err(WINDOW - now.duration_since(oldest));
+        }
+
+        window.push_back(now);
+        Ok(())
+    }
+
+    /// Current usage of each limit, for get_manager_info.
+    pub fn state(&self) -> Value {
+        let now = Instant::now();
+        let calls = self.calls.lock().unwrap();
+        let mut keys: Vec<_> = self.limits.keys().collect();
+        keys.sort();
+
+        let limits: Vec<Value> = keys
+            .into_iter()
+            .map(|key| {
+                let used = calls
+                    .get(key)
+                    .map(|w| w.iter().filter(|t| now.duration_since(**t) < WINDOW).count())
+                    .unwrap_or(0);
+                json!({
+                    "name": key,
+                    "per_minute": self.limits[key],
+                    "used_last_minute": used,
+                })
+            })
+            .collect();
+        Value::Array(limits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(HashMap::from([
+            ("restart".to_string(), 2),
+            ("search".to_string(), 3),
+        ]))
+    }
+
+    #[test]
+    fn test_limit_and_retry_after() {
+        let limiter = limiter();
+        let start = Instant::now();
+
+        assert!(limiter.check_at("restart", start).is_ok());
+        assert!(limiter.check_at("restart", start + Duration::from_secs(10)).is_ok());
+
+        let retry = limiter
+            .check_at("restart", start + Duration::from_secs(20))
+            .unwrap_err();
+        assert_eq!(retry, Duration::from_secs(40));
+
+        // Once the first call leaves the window, there's room again
+        assert!(limiter.check_at("restart", start + Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn test_search_group_and_unlimited_tools() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        assert!(limiter.check_at("search_logs", now).is_ok());
+        assert!(limiter.check_at("search_build_log", now).is_ok());
+        assert!(limiter.check_at("search_manager_logs", now).is_ok());
+        assert!(limiter.check_at("search_logs", now).is_err());
+
+        for _ in 0..100 {
+            assert!(limiter.check_at("get_status", now).is_ok());
+        }
+    }
+}
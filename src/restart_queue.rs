@@ -0,0 +1,115 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Bounds how many processes bulk restarts rebuild and restart at once:
+/// restarting all, by tag and cascades, and the switch to release mode.
+/// Processes beyond `max_concurrent_restarts` wait their turn in order.
+pub struct RestartQueue {
+    permits: Semaphore,
+    /// Processes waiting for a permit, first in line first.
+    waiting: Mutex<VecDeque<String>>,
+}
+
+/// Takes a process out of the line once it stops waiting, because it got a
+/// permit or because the restart was cancelled.
+struct Waiting<'a> {
+    queue: &'a RestartQueue,
+    name: &'a str,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut waiting = self.queue.waiting.lock().unwrap();
+        if let Some(index) = waiting.iter().position(|name| name == self.name) {
+            waiting.remove(index);
+        }
+    }
+}
+
+impl RestartQueue {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            permits: Semaphore::new(max_concurrent),
+            waiting: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Waits until `name` may restart. The semaphore is fair, so permits go
+    /// out in the order of `waiting`.
+    pub async fn acquire(&self, name: &str) -> SemaphorePermit<'_> {
+        self.waiting.lock().unwrap().push_back(name.to_string());
+        let _waiting = Waiting { queue: self, name };
+        self.permits.acquire().await.expect("the restart queue is never closed")
+    }
+
+    /// Where `name` is in line, counting from 1, if it is waiting.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.waiting.lock().unwrap().iter().position(|waiting| waiting == name).map(|index| index + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_concurrency_ceiling() {
+        let queue = Arc::new(RestartQueue::new(2));
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        // Fake slow restarts, more than fit at once
+        let restarts: Vec<_> = (0..6)
+            .map(|i| {
+                let (queue, running, peak) = (queue.clone(), running.clone(), peak.clone());
+                tokio::spawn(async move {
+                    let _permit = queue.acquire(&format!("process{}", i)).await;
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for restart in restarts {
+            restart.await.unwrap();
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(running.load(Ordering::SeqCst), 0);
+        assert_eq!(queue.position("process5"), None);
+    }
+
+    #[tokio::test]
+    async fn test_queue_positions() {
+        let queue = Arc::new(RestartQueue::new(1));
+        let held = queue.acquire("api").await;
+        assert_eq!(queue.position("api"), None);
+
+        let spawn = |name: &'static str| {
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                let _permit = queue.acquire(name).await;
+            })
+        };
+        let worker = spawn("worker");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let frontend = spawn("frontend");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(queue.position("worker"), Some(1));
+        assert_eq!(queue.position("frontend"), Some(2));
+
+        // Giving up leaves the line
+        frontend.abort();
+        let _ = frontend.await;
+        assert_eq!(queue.position("frontend"), None);
+
+        drop(held);
+        worker.await.unwrap();
+        assert_eq!(queue.position("worker"), None);
+    }
+}
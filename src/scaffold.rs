@@ -0,0 +1,306 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// A process proposed for the generated config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DetectedProcess {
+    /// A binary package, in `project_dir` if it is a workspace member.
+    Rust { name: String, project_dir: Option<String> },
+    Npm { name: String, script: String },
+}
+
+/// Everything `init` found in a project directory.
+#[derive(Debug, Default)]
+pub struct Detection {
+    pub processes: Vec<DetectedProcess>,
+    /// Workspace members without a binary to run, e.g. libraries.
+    pub library_members: Vec<String>,
+    /// Scripts from package.json that weren't picked as the npm process.
+    pub other_scripts: Vec<String>,
+}
+
+/// Inspects `project_dir` for Cargo.toml and package.json.
+pub fn detect(project_dir: &Path) -> Result<Detection> {
+    let mut detection = Detection::default();
+
+    let cargo_toml_path = project_dir.join("Cargo.toml");
+    if cargo_toml_path.exists() {
+        let content = std::fs::read_to_string(&cargo_toml_path)
+            .context("Failed to read Cargo.toml")?;
+        let cargo_toml: toml::Value =
+            toml::from_str(&content).context("Failed to parse Cargo.toml")?;
+
+        if let Some(name) = cargo_toml
+            .get("package")
+            .and_then(|p| p.get("name"))
+            .and_then(|n| n.as_str())
+        {
+            detection.processes.push(DetectedProcess::Rust {
+                name: sanitize_name(name),
+                project_dir: None,
+            });
+        }
+
+        // Each member with a binary is run from its own directory
+        let members: Vec<&str> = cargo_toml
+            .get("workspace")
+            .and_then(|w| w.get("members"))
+            .and_then(|m| m.as_array())
+            .map(|members| members.iter().filter_map(|m| m.as_str()).collect())
+            .unwrap_or_default();
+        for member in expand_members(project_dir, &members) {
+            match member_binary(&project_dir.join(&member)) {
+                Some(name) => {
+                    let mut name = sanitize_name(&name);
+                    if detection.processes.iter().any(|p| p.name() == name) {
+                        name = sanitize_name(&member.replace('/', "-"));
+                    }
+                    detection.processes.push(DetectedProcess::Rust {
+                        name,
+                        project_dir: Some(member),
+                    });
+                }
+                None => detection.library_members.push(member),
+            }
+        }
+    }
+
+    let package_json_path = project_dir.join("package.json");
+    if package_json_path.exists() {
+        let content = std::fs::read_to_string(&package_json_path)
+            .context("Failed to read package.json")?;
+        let package_json: serde_json::Value =
+            serde_json::from_str(&content).context("Failed to parse package.json")?;
+
+        let scripts: Vec<String> = package_json["scripts"]
+            .as_object()
+            .map(|s| s.keys().cloned().collect())
+            .unwrap_or_default();
+
+        // Prefer a dev server, then a plain start script.
+        let script = ["dev", "start", "serve"]
+            .iter()
+            .find(|s| scripts.iter().any(|script| script == *s))
+            .map(|s| s.to_string());
+
+        if let Some(script) = script {
+            let mut name = package_json["name"]
+                .as_str()
+                .map(sanitize_name)
+                .filter(|n| !n.is_empty())
+                .unwrap_or_else(|| "frontend".to_string());
+            if detection.processes.iter().any(|p| p.name() == name) {
+                name.push_str("-npm");
+            }
+
+            detection.other_scripts = scripts.into_iter().filter(|s| *s != script).collect();
+            detection.processes.push(DetectedProcess::Npm { name, script });
+        } else {
+            detection.other_scripts = scripts;
+        }
+    }
+
+    Ok(detection)
+}
+
+impl DetectedProcess {
+    fn name(&self) -> &str {
+        match self {
+            DetectedProcess::Rust { name, .. } | DetectedProcess::Npm { name, .. } => name,
+        }
+    }
+}
+
+/// Workspace member directories, relative to `project_dir`, with `dir/*`
+/// entries expanded to the packages in `dir`. The root itself is left out.
+fn expand_members(project_dir: &Path, members: &[&str]) -> Vec<String> {
+    let mut expanded = Vec::new();
+    for member in members {
+        let member = member.trim_end_matches('/');
+        if let Some(parent) = member.strip_suffix("/*") {
+            let mut found: Vec<String> = std::fs::read_dir(project_dir.join(parent))
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter(|entry| entry.path().join("Cargo.toml").is_file())
+                .map(|entry| format!("{}/{}", parent, entry.file_name().to_string_lossy()))
+                .collect();
+            found.sort();
+            expanded.extend(found);
+        } else if member != "." && !member.is_empty() {
+            expanded.push(member.to_string());
+        }
+    }
+    expanded
+}
+
+/// The name of the package in `dir`, if it has a binary the builder can
+/// run: `src/main.rs`, or a `[[bin]]` of the package's name.
+fn member_binary(dir: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    let cargo_toml: toml::Value = toml::from_str(&content).ok()?;
+    let name = cargo_toml.get("package")?.get("name")?.as_str()?.to_string();
+    let named_bin = cargo_toml
+        .get("bin")
+        .and_then(|bins| bins.as_array())
+        .is_some_and(|bins| bins.iter().any(|bin| bin.get("name").and_then(|n| n.as_str()) == Some(&name)));
+    (dir.join("src/main.rs").is_file() || named_bin).then_some(name)
+}
+
+/// Renders a commented `.mcp-run` for the detected processes.
+pub fn render(detection: &Detection) -> String {
+    let mut out = String::new();
+    out.push_str("# Generated by `background-process-manager init`. Review before use.\n\n");
+    out.push_str("# Port for the MCP server to listen on\n");
+    out.push_str("mcp_port = 3001\n\n");
+    out.push_str("# Time in hours before switching to release mode\n");
+    out.push_str("dev_timeout_hours = 3\n\n");
+    out.push_str("# Wait time in seconds after a crash in dev mode\n");
+    out.push_str("dev_crash_wait_seconds = 120\n\n");
+    out.push_str("# Backoff bounds in seconds for crash recovery in release mode\n");
+    out.push_str("release_crash_backoff_initial_seconds = 1\n");
+    out.push_str("release_crash_backoff_max_seconds = 300\n");
+
+    for process in &detection.processes {
+        out.push('\n');
+        match process {
+            DetectedProcess::Rust { name, project_dir } => {
+                out.push_str(&format!("[process.{}]\n", name));
+                out.push_str("type = \"rust\"\n");
+                if let Some(project_dir) = project_dir {
+                    out.push_str("# Workspace member, built and run from its own directory\n");
+                    out.push_str(&format!("project_dir = \"{}\"\n", project_dir));
+                }
+                out.push_str("# Arguments to pass to the binary\n");
+                out.push_str("args = []\n");
+            }
+            DetectedProcess::Npm { name, script } => {
+                out.push_str(&format!("[process.{}]\n", name));
+                out.push_str("type = \"npm\"\n");
+                out.push_str(&format!("command = [\"npm\", \"run\", \"{}\"]\n", script));
+            }
+        }
+    }
+
+    if detection.processes.is_empty() {
+        out.push_str("\n# No Cargo.toml package or package.json script found; define processes here.\n");
+        out.push_str("# [process.main]\n");
+        out.push_str("# type = \"rust\"\n");
+        out.push_str("# args = []\n");
+    }
+
+    if !detection.library_members.is_empty() {
+        out.push_str("\n# Workspace members without a binary to run:\n");
+        for member in &detection.library_members {
+            out.push_str(&format!("#   {}\n", member));
+        }
+    }
+
+    if !detection.other_scripts.is_empty() {
+        out.push_str("\n# Other package.json scripts that could be run as npm processes:\n");
+        for script in &detection.other_scripts {
+            out.push_str(&format!("#   npm run {}\n", script));
+        }
+    }
+
+    out
+}
+
+/// Restricts a name to characters usable as a bare TOML key.
+fn sanitize_name(name: &str) -> String {
+    name.rsplit('/')
+        .next()
+        .unwrap_or(name)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ProcessType};
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/scaffold")
+            .join(name)
+    }
+
+    #[test]
+    fn test_rust_package() {
+        let detection = detect(&fixture("rust")).unwrap();
+        assert_eq!(
+            detection.processes,
+            vec![DetectedProcess::Rust {
+                name: "my-server".to_string(),
+                project_dir: None
+            }]
+        );
+
+        let config = Config::parse(&render(&detection)).unwrap();
+        assert_eq!(config.process["my-server"].process_type, ProcessType::Rust);
+    }
+
+    #[test]
+    fn test_workspace_with_npm() {
+        let detection = detect(&fixture("workspace_npm")).unwrap();
+        assert_eq!(detection.library_members, vec!["crates/core"]);
+        assert_eq!(
+            detection.processes,
+            vec![
+                DetectedProcess::Rust {
+                    name: "app".to_string(),
+                    project_dir: None
+                },
+                DetectedProcess::Rust {
+                    name: "crates-cli".to_string(),
+                    project_dir: Some("crates/cli".to_string())
+                },
+                DetectedProcess::Rust {
+                    name: "worker".to_string(),
+                    project_dir: Some("services/worker".to_string())
+                },
+                DetectedProcess::Npm {
+                    name: "app-npm".to_string(),
+                    script: "dev".to_string()
+                },
+            ]
+        );
+        assert_eq!(detection.other_scripts, vec!["build", "test"]);
+
+        let rendered = render(&detection);
+        assert!(rendered.contains("#   crates/core"));
+        assert!(rendered.contains("#   npm run build"));
+
+        let config = Config::parse(&rendered).unwrap();
+        assert_eq!(config.process["app-npm"].command, vec!["npm", "run", "dev"]);
+        assert_eq!(config.process["crates-cli"].project_dir, Some(PathBuf::from("crates/cli")));
+        assert_eq!(config.process["worker"].project_dir, Some(PathBuf::from("services/worker")));
+    }
+
+    #[test]
+    fn test_npm_only() {
+        let detection = detect(&fixture("npm")).unwrap();
+        assert_eq!(
+            detection.processes,
+            vec![DetectedProcess::Npm {
+                name: "frontend".to_string(),
+                script: "start".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("@scope/web.app"), "web-app");
+        assert_eq!(sanitize_name("my_crate"), "my_crate");
+    }
+}
@@ -0,0 +1,182 @@
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Shown instead of a process's status when its locks couldn't be taken in
+/// time, e.g. because a stuck operation holds one.
+pub const UNAVAILABLE: &str = "status unavailable (internal lock timeout)";
+
+/// What the compact form of get_status and list_processes show about a
+/// process.
+pub struct ProcessSummary<'a> {
+    pub name: &'a str,
+    pub state: &'a str,
+    pub process_type: &'a str,
+    /// Set while the process is running.
+    pub uptime: Option<Duration>,
+    /// Crashes since the last manual restart.
+    pub crashes: usize,
+    /// Time left before an automatic restart after a crash.
+    pub backoff: Option<Duration>,
+    /// A manual restart or rebuild is in progress.
+    pub restarting: bool,
+}
+
+impl<'a> ProcessSummary<'a> {
+    /// The summary of a process whose status is `UNAVAILABLE`.
+    pub fn unavailable(name: &'a str, process_type: &'a str) -> Self {
+        ProcessSummary {
+            name,
+            state: "unavailable",
+            process_type,
+            uptime: None,
+            crashes: 0,
+            backoff: None,
+            restarting: false,
+        }
+    }
+
+    /// E.g. "api: running 2h14m" or "worker: crashed x3, backing off 45s".
+    /// Agents are prompted with this format, so keep it stable.
+    pub fn line(&self) -> String {
+        if self.state == "unavailable" {
+            return format!("{}: {}", self.name, UNAVAILABLE);
+        }
+        let mut line = format!("{}: {}", self.name, self.state);
+        if let Some(uptime) = self.uptime {
+            line.push_str(&format!(" {}", compact_duration(uptime)));
+        }
+        if self.state == "crashed" {
+            if self.crashes > 1 {
+                line.push_str(&format!(" x{}", self.crashes));
+            }
+        } else if self.crashes > 0 {
+            line.push_str(&format!(", {} crashes", self.crashes));
+        }
+        if let Some(backoff) = self.backoff {
+            line.push_str(&format!(", backing off {}", compact_duration(backoff)));
+        }
+        if self.restarting {
+            line.push_str(", restarting");
+        }
+        line
+    }
+
+    /// The list_processes entry.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "state": self.state,
+            "uptime_seconds": self.uptime.map(|u| u.as_secs()),
+            "crash_count": self.crashes,
+            "type": self.process_type,
+        })
+    }
+}
+
+/// "mode: dev, release in 2h59m", or "mode: release".
+pub fn mode_line(mode: &str, time_until_release: Option<Duration>) -> String {
+    match time_until_release {
+        Some(time) => format!("mode: {}, release in {}", mode, compact_duration(time)),
+        None => format!("mode: {}", mode),
+    }
+}
+
+/// The mode line, then all processes on one line.
+pub fn format(mode_line: &str, processes: &[ProcessSummary]) -> String {
+    format!("{}\n{}", mode_line, processes_line(processes))
+}
+
+/// All processes on one line.
+pub fn processes_line(processes: &[ProcessSummary]) -> String {
+    let processes: Vec<String> = processes.iter().map(ProcessSummary::line).collect();
+    processes.join(" | ")
+}
+
+/// "45s", "14m", "2h14m" or "3d2h", rounded down.
+fn compact_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h{}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d{}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process<'a>(name: &'a str, state: &'a str) -> ProcessSummary<'a> {
+        ProcessSummary {
+            name,
+            state,
+            process_type: "rust",
+            uptime: None,
+            crashes: 0,
+            backoff: None,
+            restarting: false,
+        }
+    }
+
+    #[test]
+    fn test_compact_format() {
+        let processes = [
+            ProcessSummary {
+                uptime: Some(Duration::from_secs(2 * 3600 + 14 * 60 + 59)),
+                ..process("api", "running")
+            },
+            ProcessSummary {
+                crashes: 3,
+                backoff: Some(Duration::from_secs(45)),
+                ..process("worker", "crashed")
+            },
+            ProcessSummary {
+                uptime: Some(Duration::from_secs(2 * 3600 + 13 * 60)),
+                ..process("web", "running")
+            },
+        ];
+        assert_eq!(
+            format(&mode_line("dev", Some(Duration::from_secs(10742))), &processes),
+            "mode: dev, release in 2h59m\napi: running 2h14m | worker: crashed x3, backing off 45s | web: running 2h13m"
+        );
+        assert_eq!(mode_line("release", None), "mode: release");
+    }
+
+    #[test]
+    fn test_process_lines() {
+        assert_eq!(process("db", "stopped").line(), "db: stopped");
+        assert_eq!(
+            ProcessSummary::unavailable("db", "rust").line(),
+            "db: status unavailable (internal lock timeout)"
+        );
+        assert_eq!(ProcessSummary { crashes: 1, ..process("db", "crashed") }.line(), "db: crashed");
+        assert_eq!(
+            ProcessSummary {
+                uptime: Some(Duration::from_secs(3 * 86400 + 7200)),
+                crashes: 2,
+                ..process("db", "running")
+            }
+            .line(),
+            "db: running 3d2h, 2 crashes"
+        );
+        assert_eq!(
+            ProcessSummary { restarting: true, ..process("db", "building") }.line(),
+            "db: building, restarting"
+        );
+        assert_eq!(compact_duration(Duration::from_secs(600)), "10m");
+    }
+
+    #[test]
+    fn test_json() {
+        let summary = ProcessSummary {
+            uptime: Some(Duration::from_secs(90)),
+            crashes: 2,
+            ..process("api", "running")
+        };
+        assert_eq!(
+            summary.to_json(),
+            json!({"name": "api", "state": "running", "uptime_seconds": 90, "crash_count": 2, "type": "rust"})
+        );
+    }
+}
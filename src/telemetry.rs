@@ -0,0 +1,109 @@
+use crate::config::TelemetryConfig;
+use anyhow::{Context, Result};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+use opentelemetry_sdk::Resource;
+use std::time::Instant;
+use tracing::{warn, Span};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::{reload, Registry};
+
+/// The layer exporting spans, innermost so that it can be swapped in once
+/// the config is loaded, after tracing is initialized.
+pub type TelemetryLayer = OpenTelemetryLayer<Registry, Tracer>;
+
+/// Swaps the OTLP layer in; until then, and without `[telemetry]`, the
+/// reloadable layer is empty.
+pub type TelemetryHandle = reload::Handle<Option<TelemetryLayer>, Registry>;
+
+/// An empty layer to initialize tracing with, and the handle to fill it.
+pub fn reloadable() -> (reload::Layer<Option<TelemetryLayer>, Registry>, TelemetryHandle) {
+    reload::Layer::new(None)
+}
+
+/// Shuts the provider down when dropped, flushing the spans still batched,
+/// so that they are exported however `run` ends, errors included.
+pub struct TelemetryGuard(Option<SdkTracerProvider>);
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        let Some(provider) = self.0.take() else {
+            return;
+        };
+        // The exporter's blocking HTTP client can't be used on a runtime
+        // thread
+        match std::thread::spawn(move || provider.shutdown()).join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Failed to flush traces: {}", e),
+            Err(_) => warn!("Failed to flush traces: the shutdown panicked"),
+        }
+    }
+}
+
+/// Starts exporting spans to the configured OTLP endpoint, until the
+/// returned guard is dropped.
+pub fn enable(handle: &TelemetryHandle, config: &TelemetryConfig) -> Result<TelemetryGuard> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&config.endpoint)
+        .build()
+        .context("Failed to create the OTLP span exporter")?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(config.service_name.clone()).build())
+        .build();
+    handle
+        .reload(Some(layer(&provider)))
+        .context("Failed to enable the telemetry layer")?;
+    Ok(TelemetryGuard(Some(provider)))
+}
+
+fn layer(provider: &SdkTracerProvider) -> TelemetryLayer {
+    tracing_opentelemetry::layer().with_tracer(provider.tracer("background-process-manager"))
+}
+
+/// Records the `outcome` and `duration_ms` fields of a span covering an
+/// operation that began at `started`.
+pub fn record_outcome<T>(span: &Span, started: Instant, result: &Result<T>) {
+    span.record("outcome", if result.is_ok() { "succeeded" } else { "failed" });
+    span.record("duration_ms", started.elapsed().as_millis() as u64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_sdk::trace::InMemorySpanExporter;
+    use tracing::field::Empty;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_spans_exported() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+        let (reloadable, handle) = reloadable();
+        let subscriber = tracing_subscriber::registry().with(reloadable);
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("ignored").in_scope(|| {});
+            handle.reload(Some(layer(&provider))).unwrap();
+
+            let restart = tracing::info_span!("restart", process = "main", trigger = "manual");
+            restart.in_scope(|| {
+                let build =
+                    tracing::info_span!("build", process = "main", profile = "dev", outcome = Empty, duration_ms = Empty);
+                let result: Result<()> = Ok(());
+                record_outcome(&build, Instant::now(), &result);
+            });
+        });
+        provider.force_flush().unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let names: Vec<_> = spans.iter().map(|s| s.name.as_ref()).collect();
+        assert_eq!(names, ["build", "restart"]);
+        let (build, restart) = (&spans[0], &spans[1]);
+        assert_eq!(build.parent_span_id, restart.span_context.span_id());
+        let outcome = build.attributes.iter().find(|kv| kv.key.as_str() == "outcome").unwrap();
+        assert_eq!(outcome.value.as_str(), "succeeded");
+    }
+}
@@ -1,13 +1,29 @@
 use anyhow::Result;
 use chrono::Local;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
-use super::mcp_client::{McpClient, ProcessInfo, ServerStatus};
+use super::mcp_client::{ActivityEntry, McpClient, ProcessInfo, ServerStatus, StateKind, TimedOut};
+
+/// Activity entries kept client-side, beyond what the server still has.
+const MAX_ACTIVITY: usize = 200;
+/// Activity entries fetched on each refresh.
+const ACTIVITY_FETCH: usize = 50;
+
+/// An action on every process, asked to be confirmed first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BulkAction {
+    RestartAll,
+    StopAll,
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ConnectionState {
     Disconnected,
     Connecting,
     Connected,
+    /// The last request got no answer in time; the server may just be busy.
+    TimedOut,
     Error,
 }
 
@@ -18,8 +34,25 @@ pub struct App {
     pub server_status: Option<ServerStatus>,
     pub selected_process_index: Option<usize>,
     pub logs: String,
+    /// Set when `logs` holds the build log of this process instead of its
+    /// output, because its last build failed.
+    pub build_log_shown_for: Option<String>,
+    /// Which log instance `logs` came from, when the server said: always for
+    /// build logs and for instances before the current one.
+    pub log_header: Option<String>,
+    /// Whether the activity feed is shown in place of the process details.
+    pub show_activity: bool,
+    /// Recent activity, newest first.
+    pub activity: VecDeque<ActivityEntry>,
     pub status_message: String,
     pub last_update: Option<chrono::DateTime<Local>>,
+    /// How long the last successful `get_status` round trip took.
+    pub last_latency: Option<Duration>,
+    /// When the connection last failed and why. Unlike `status_message`,
+    /// this outlives reconnecting, so a flaky connection can be diagnosed.
+    pub last_error: Option<(chrono::DateTime<Local>, String)>,
+    /// A bulk action waiting for y/n.
+    pub pending_action: Option<BulkAction>,
 }
 
 impl App {
@@ -31,8 +64,15 @@ impl App {
             server_status: None,
             selected_process_index: None,
             logs: String::new(),
+            build_log_shown_for: None,
+            log_header: None,
+            show_activity: false,
+            activity: VecDeque::new(),
             status_message: String::new(),
             last_update: None,
+            last_latency: None,
+            last_error: None,
+            pending_action: None,
         }
     }
 
@@ -85,13 +125,24 @@ impl App {
 
     pub fn clear_logs(&mut self) {
         self.logs.clear();
+        self.build_log_shown_for = None;
+        self.log_header = None;
+    }
+
+    /// Whether the selected process's build just failed and its build log
+    /// isn't shown yet, so the Output pane should switch to it on its own.
+    pub fn should_show_build_log(&self) -> bool {
+        self.get_selected_process()
+            .is_some_and(|p| p.build_failed() && self.build_log_shown_for.as_ref() != Some(&p.name))
     }
 
     pub async fn update_status(&mut self, client: &mut McpClient) -> Result<()> {
         self.connection_state = ConnectionState::Connecting;
 
+        let started = Instant::now();
         match client.get_status().await {
             Ok(status) => {
+                self.last_latency = Some(started.elapsed());
                 // Adjust selected index if needed
                 if let Some(index) = self.selected_process_index {
                     if index >= status.processes.len() {
@@ -109,18 +160,42 @@ impl App {
                 Ok(())
             }
             Err(e) => {
-                self.connection_state = ConnectionState::Error;
+                self.connection_state = if e.is::<TimedOut>() {
+                    ConnectionState::TimedOut
+                } else {
+                    ConnectionState::Error
+                };
                 self.status_message = format!("Error: {}", e);
+                self.last_error = Some((Local::now(), format!("{:#}", e)));
                 Err(e)
             }
         }
     }
 
+    /// Fetches the selected process's output, or its build log if it is down
+    /// because the build failed: its runtime log has nothing about that.
     pub async fn refresh_logs(&mut self, client: &mut McpClient) -> Result<()> {
         if let Some(process) = self.get_selected_process() {
-            match client.search_logs(&process.name, Some(100)).await {
+            let name = process.name.clone();
+            let build_failed = process.build_failed();
+            let result = if build_failed {
+                client.search_build_log(&name, Some(100)).await
+            } else {
+                client.search_logs(&name, Some(100)).await
+            };
+            match result {
                 Ok(logs) => {
-                    self.logs = logs;
+                    // The header identifying the instance goes in the title
+                    let (first, rest) = logs.split_once('\n').unwrap_or((&logs, ""));
+                    let header = first
+                        .strip_prefix("[Log instance ")
+                        .and_then(|header| header.strip_suffix(']'))
+                        .map(|header| (format!("log instance {}", header), rest.to_string()));
+                    (self.log_header, self.logs) = match header {
+                        Some((header, rest)) => (Some(header), rest),
+                        None => (None, logs),
+                    };
+                    self.build_log_shown_for = build_failed.then_some(name);
                     Ok(())
                 }
                 Err(e) => {
@@ -133,6 +208,33 @@ impl App {
         }
     }
 
+    pub fn toggle_activity(&mut self) {
+        self.show_activity = !self.show_activity;
+    }
+
+    /// Fetches the newest activity and adds what's new to the scrollback.
+    pub async fn refresh_activity(&mut self, client: &mut McpClient) -> Result<()> {
+        match client.get_recent_activity(ACTIVITY_FETCH).await {
+            Ok(entries) => {
+                // Entries come newest first, so add them to the front oldest first
+                for entry in entries.into_iter().rev() {
+                    if self.activity.front().is_some_and(|newest| entry.timestamp < newest.timestamp)
+                        || self.activity.contains(&entry)
+                    {
+                        continue;
+                    }
+                    self.activity.push_front(entry);
+                }
+                self.activity.truncate(MAX_ACTIVITY);
+                Ok(())
+            }
+            Err(e) => {
+                self.status_message = format!("Error fetching activity: {}", e);
+                Err(e)
+            }
+        }
+    }
+
     pub async fn restart_selected_process(&mut self, client: &mut McpClient) -> Result<()> {
         if let Some(process) = self.get_selected_process() {
             let process_name = process.name.clone();
@@ -156,29 +258,133 @@ impl App {
         }
     }
 
+    /// Asks for confirmation before restarting every process.
+    pub fn request_restart_all(&mut self) {
+        self.pending_action = Some(BulkAction::RestartAll);
+        self.status_message = "Restart all processes? (y/n)".to_string();
+    }
+
+    /// Asks for confirmation before stopping every process.
+    pub fn request_stop_all(&mut self) {
+        self.pending_action = Some(BulkAction::StopAll);
+        self.status_message = "Stop all processes? (y/n)".to_string();
+    }
+
+    /// Runs the pending bulk action if `confirmed`, else drops it.
+    pub async fn resolve_pending_action(&mut self, client: &mut McpClient, confirmed: bool) -> Result<()> {
+        let Some(action) = self.pending_action.take() else {
+            return Ok(());
+        };
+        if !confirmed {
+            self.status_message = "Cancelled".to_string();
+            return Ok(());
+        }
+
+        match action {
+            BulkAction::RestartAll => self.restart_all(client).await,
+            BulkAction::StopAll => self.stop_all(client).await,
+        }
+    }
+
+    async fn restart_all(&mut self, client: &mut McpClient) -> Result<()> {
+        self.status_message = "Restarting all processes...".to_string();
+
+        match client.restart_all().await {
+            Ok(result) => {
+                self.status_message = summarize_bulk(&result, "restarted");
+                let _ = self.update_status(client).await;
+                Ok(())
+            }
+            Err(e) => {
+                self.status_message = format!("Error restarting all processes: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    async fn stop_all(&mut self, client: &mut McpClient) -> Result<()> {
+        self.status_message = "Stopping all processes...".to_string();
+
+        match client.stop_all().await {
+            Ok(result) => {
+                self.status_message = summarize_bulk(&result, "stopped");
+                let _ = self.update_status(client).await;
+                Ok(())
+            }
+            Err(e) => {
+                self.status_message = format!("Error stopping all processes: {}", e);
+                Err(e)
+            }
+        }
+    }
+
     pub fn get_process_counts(&self) -> (usize, usize, usize) {
         if let Some(ref status) = self.server_status {
-            let running = status
-                .processes
-                .iter()
-                .filter(|p| p.state.to_lowercase().contains("running"))
-                .count();
-            let stopped = status
-                .processes
-                .iter()
-                .filter(|p| {
-                    let state = p.state.to_lowercase();
-                    state.contains("stopped") || state.contains("idle")
-                })
-                .count();
-            let errored = status
-                .processes
-                .iter()
-                .filter(|p| p.state.to_lowercase().contains("crashed"))
-                .count();
-            (running, stopped, errored)
+            let count = |kind| status.processes.iter().filter(|p| StateKind::of(&p.state) == kind).count();
+            (
+                count(StateKind::Running),
+                count(StateKind::Stopped),
+                count(StateKind::Errored),
+            )
         } else {
             (0, 0, 0)
         }
     }
 }
+
+/// One line out of the restart or stop tool's "  - name: outcome" lines,
+/// where `done` is the outcome of a process that went well, e.g.
+/// "Restarted 2 of 3 processes; failed: web".
+fn summarize_bulk(result: &str, done: &str) -> String {
+    let mut succeeded = 0;
+    let mut failed = Vec::new();
+    let mut skipped = Vec::new();
+    for line in result.lines() {
+        let Some((name, outcome)) = line.trim_start().strip_prefix("- ").and_then(|l| l.split_once(": ")) else {
+            continue;
+        };
+        if outcome == done {
+            succeeded += 1;
+        } else if outcome.starts_with("failed") {
+            failed.push(name);
+        } else {
+            skipped.push(name);
+        }
+    }
+
+    let mut verb = done.to_string();
+    verb[..1].make_ascii_uppercase();
+    let mut summary = format!(
+        "{} {} of {} processes",
+        verb,
+        succeeded,
+        succeeded + failed.len() + skipped.len()
+    );
+    if !failed.is_empty() {
+        summary.push_str(&format!("; failed: {}", failed.join(", ")));
+    }
+    if !skipped.is_empty() {
+        summary.push_str(&format!("; skipped: {}", skipped.join(", ")));
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize_bulk() {
+        let restarts = "Restarting all processes in dev mode:\n  - api: restarted\n  - db: failed: Build failed\n  \
+                        - web: skipped (stopped)\n  - worker: restarted";
+        assert_eq!(
+            summarize_bulk(restarts, "restarted"),
+            "Restarted 2 of 4 processes; failed: db; skipped: web"
+        );
+
+        let stops = "Stopping all processes:\n  - web: stopped\n  - api: already stopped";
+        assert_eq!(summarize_bulk(stops, "stopped"), "Stopped 1 of 2 processes; skipped: api");
+
+        assert_eq!(summarize_bulk("Restarting all processes in dev mode:", "restarted"), "Restarted 0 of 0 processes");
+    }
+}
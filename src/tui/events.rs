@@ -26,10 +26,8 @@ impl EventHandler {
                 // Wait for crossterm event with timeout
                 if event::poll(tick_rate).unwrap() {
                     match event::read().unwrap() {
-                        CrosstermEvent::Key(key) => {
-                            if key.kind == KeyEventKind::Press {
-                                event_sender.send(Event::Key(key)).ok();
-                            }
+                        CrosstermEvent::Key(key) if key.kind == KeyEventKind::Press => {
+                            event_sender.send(Event::Key(key)).ok();
                         }
                         CrosstermEvent::Resize(width, height) => {
                             event_sender.send(Event::Resize(width, height)).ok();
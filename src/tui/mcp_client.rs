@@ -1,6 +1,47 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::fmt;
+use std::time::Duration;
+
+/// How long a request may take unless `--timeout` says otherwise. The server
+/// can take a while to answer while a process is being restarted.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tools that only read, so calling them twice is harmless. Only these, and
+/// `initialize`, are retried; retrying `restart` could restart twice.
+const READ_ONLY_TOOLS: &[&str] = &["get_status", "search_logs", "search_build_log", "get_recent_activity"];
+
+/// How often a read-only request is retried after a connection failure,
+/// e.g. while the server restarts, and how long to wait before the first
+/// retry. The wait doubles with each retry, up to `MAX_RETRY_DELAY`.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            delay: Duration::from_millis(200),
+        }
+    }
+}
+
+/// The longest a doubled retry wait grows to. A longer `delay` is kept as is.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+impl RetryPolicy {
+    fn delay_before(&self, retry: u32) -> Duration {
+        let cap = MAX_RETRY_DELAY.max(self.delay);
+        self.delay
+            .checked_mul(2u32.saturating_pow(retry))
+            .map_or(cap, |delay| delay.min(cap))
+    }
+}
 
 #[derive(Debug, Deserialize)]
 pub struct ServerStatus {
@@ -14,8 +55,75 @@ pub struct ProcessInfo {
     pub name: String,
     pub state: String,
     pub uptime: Option<String>,
+    pub version: Option<String>,
+    pub stale: bool,
+    pub restarting: bool,
     pub events: Vec<String>,
     pub crash_count: u32,
+    pub restart_count: u32,
+}
+
+/// An entry of the get_recent_activity feed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivityEntry {
+    pub timestamp: DateTime<Utc>,
+    /// None for mode changes, which aren't about one process.
+    pub process: Option<String>,
+    pub summary: String,
+}
+
+/// How the TUI groups the states get_status reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateKind {
+    /// A manual restart or rebuild is in progress, whatever the state says.
+    Restarting,
+    /// Building, starting or stopping.
+    Transitional,
+    Running,
+    /// Not started yet, or stopped on purpose.
+    Stopped,
+    /// Crashed, or failed to start.
+    Errored,
+    Unknown,
+}
+
+impl StateKind {
+    /// Classifies a state as the server spells it (`ProcessState::as_str`).
+    pub fn of(state: &str) -> Self {
+        match state {
+            "building" | "starting" | "stopping" => StateKind::Transitional,
+            "running" => StateKind::Running,
+            "not_started" | "stopped" => StateKind::Stopped,
+            "crashed" | "failed" => StateKind::Errored,
+            _ => StateKind::Unknown,
+        }
+    }
+}
+
+impl ProcessInfo {
+    pub fn state_kind(&self) -> StateKind {
+        if self.restarting {
+            StateKind::Restarting
+        } else {
+            StateKind::of(&self.state)
+        }
+    }
+
+    /// Whether the process is down because its last build failed, rather
+    /// than because it crashed at runtime. Events are newest first.
+    pub fn build_failed(&self) -> bool {
+        StateKind::of(&self.state) == StateKind::Errored
+            && self.events.first().is_some_and(|e| e.starts_with("Build failed"))
+    }
+
+    /// State to show to the user, which hides the transient state during a restart.
+    pub fn display_state(&self) -> String {
+        if self.restarting {
+            "restarting…".to_string()
+        } else {
+            self.state.clone()
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -41,18 +149,54 @@ struct JsonRpcError {
     message: String,
 }
 
+/// A request that got no response within the client's timeout.
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request timed out after {}s", self.0.as_secs_f64())
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
 pub struct McpClient {
     url: String,
     client: reqwest::Client,
+    timeout: Duration,
+    retry: RetryPolicy,
     next_id: u64,
 }
 
 impl McpClient {
-    pub fn new(url: String) -> Self {
-        Self {
+    /// A client whose requests give up after `timeout`, failing with
+    /// `TimedOut`, so that a busy server can't freeze the TUI.
+    pub fn new(url: String, timeout: Duration) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .context("Failed to create the HTTP client")?;
+        Ok(Self {
             url,
-            client: reqwest::Client::new(),
+            client,
+            timeout,
+            retry: RetryPolicy::default(),
             next_id: 1,
+        })
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Turns a timeout into `TimedOut`, and adds `context` to other errors.
+    fn request_error(&self, e: reqwest::Error, context: &'static str) -> anyhow::Error {
+        if e.is_timeout() {
+            TimedOut(self.timeout).into()
+        } else {
+            anyhow::Error::new(e).context(context)
         }
     }
 
@@ -62,7 +206,31 @@ impl McpClient {
         id
     }
 
+    /// Sends a request, retrying read-only ones that failed to connect or
+    /// lost the connection. Timeouts and MCP errors aren't retried: the
+    /// server is up, and asking again wouldn't help.
     async fn send_request(&mut self, method: &str, params: Option<Value>) -> Result<Value> {
+        let read_only = method == "initialize"
+            || (method == "tools/call"
+                && params
+                    .as_ref()
+                    .and_then(|params| params["name"].as_str())
+                    .is_some_and(|tool| READ_ONLY_TOOLS.contains(&tool)));
+        let retries = if read_only { self.retry.retries } else { 0 };
+
+        let mut retry = 0;
+        loop {
+            match self.send_once(method, params.clone()).await {
+                Err(e) if retry < retries && e.downcast_ref::<reqwest::Error>().is_some() => {
+                    tokio::time::sleep(self.retry.delay_before(retry)).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn send_once(&mut self, method: &str, params: Option<Value>) -> Result<Value> {
         let id = self.get_next_id();
         let request = JsonRpcRequest {
             jsonrpc: "2.0".to_string(),
@@ -77,12 +245,12 @@ impl McpClient {
             .json(&request)
             .send()
             .await
-            .context("Failed to send request")?;
+            .map_err(|e| self.request_error(e, "Failed to send request"))?;
 
         let json_response: JsonRpcResponse = response
             .json()
             .await
-            .context("Failed to parse response")?;
+            .map_err(|e| self.request_error(e, "Failed to parse response"))?;
 
         if let Some(error) = json_response.error {
             anyhow::bail!("MCP error {}: {}", error.code, error.message);
@@ -127,7 +295,7 @@ impl McpClient {
     }
 
     fn parse_status_text(&self, text: &str) -> Result<ServerStatus> {
-        let mut lines = text.lines();
+        let mut lines = text.lines().peekable();
         let mut mode = String::new();
         let mut time_until_release = None;
         let mut processes = Vec::new();
@@ -144,15 +312,19 @@ impl McpClient {
                         .trim()
                         .to_string(),
                 );
-            } else if line.starts_with("  ") && line.contains(':') && !line.contains("Uptime") && !line.contains("Recent events") && !line.contains("Crash count") {
+            } else if line.starts_with("  ") && line.contains(':') && !line.contains("Uptime") && !line.contains("Recent events") && !line.contains("Crash count") && !line.contains("Restart count") {
                 // This is a process line
                 let parts: Vec<&str> = line.trim().splitn(2, ':').collect();
                 if parts.len() == 2 {
                     let name = parts[0].trim().to_string();
                     let state = parts[1].trim().to_string();
                     let mut uptime = None;
+                    let mut version = None;
+                    let mut stale = false;
+                    let mut restarting = false;
                     let mut events = Vec::new();
                     let mut crash_count = 0;
+                    let mut restart_count = 0;
 
                     // Read additional process info
                     while let Some(info_line) = lines.next() {
@@ -171,24 +343,36 @@ impl McpClient {
                                     .trim()
                                     .to_string(),
                             );
+                        } else if info_line.contains("Restarting:") {
+                            restarting = info_line.trim().trim_start_matches("Restarting:").trim() == "true";
+                        } else if info_line.contains("Version:") {
+                            let text = info_line
+                                .trim()
+                                .trim_start_matches("Version:")
+                                .trim()
+                                .trim_start_matches("running build of ");
+                            stale = text.contains("[stale:");
+                            version = Some(
+                                text.split(" [stale:").next().unwrap_or(text).to_string(),
+                            );
                         } else if info_line.contains("Recent events:") {
-                            // Continue reading events
-                            while let Some(event_line) = lines.next() {
-                                if event_line.starts_with("      - ") {
-                                    events.push(
-                                        event_line
-                                            .trim()
-                                            .trim_start_matches("- ")
-                                            .to_string(),
-                                    );
-                                } else {
-                                    break;
-                                }
+                            // Continue reading events, leaving the line after them
+                            while let Some(event_line) = lines.next_if(|l| l.starts_with("      - ")) {
+                                events.push(
+                                    event_line
+                                        .trim()
+                                        .trim_start_matches("- ")
+                                        .to_string(),
+                                );
                             }
                         } else if info_line.contains("Crash count:") {
-                            if let Some(count_str) = info_line.trim().trim_start_matches("Crash count:").trim().parse().ok() {
+                            if let Ok(count_str) = info_line.trim().trim_start_matches("Crash count:").trim().parse() {
                                 crash_count = count_str;
                             }
+                        } else if info_line.contains("Restart count:") {
+                            if let Ok(count) = info_line.trim().trim_start_matches("Restart count:").trim().parse() {
+                                restart_count = count;
+                            }
                         }
                     }
 
@@ -196,8 +380,12 @@ impl McpClient {
                         name,
                         state,
                         uptime,
+                        version,
+                        stale,
+                        restarting,
                         events,
                         crash_count,
+                        restart_count,
                     });
                 }
             }
@@ -211,6 +399,14 @@ impl McpClient {
     }
 
     pub async fn search_logs(&mut self, process: &str, tail: Option<usize>) -> Result<String> {
+        self.search("search_logs", process, tail).await
+    }
+
+    pub async fn search_build_log(&mut self, process: &str, tail: Option<usize>) -> Result<String> {
+        self.search("search_build_log", process, tail).await
+    }
+
+    async fn search(&mut self, tool: &str, process: &str, tail: Option<usize>) -> Result<String> {
         let mut args = json!({
             "process": process
         });
@@ -223,7 +419,7 @@ impl McpClient {
             .send_request(
                 "tools/call",
                 Some(json!({
-                    "name": "search_logs",
+                    "name": tool,
                     "arguments": args
                 })),
             )
@@ -236,6 +432,81 @@ impl McpClient {
         Ok(text.to_string())
     }
 
+    /// The newest `limit` entries of the activity feed, newest first.
+    pub async fn get_recent_activity(&mut self, limit: usize) -> Result<Vec<ActivityEntry>> {
+        let result = self
+            .send_request(
+                "tools/call",
+                Some(json!({
+                    "name": "get_recent_activity",
+                    "arguments": {
+                        "limit": limit
+                    }
+                })),
+            )
+            .await?;
+
+        let text = result["content"][0]["text"]
+            .as_str()
+            .context("Invalid response format")?;
+        let feed: Value = serde_json::from_str(text).context("Invalid activity feed")?;
+
+        feed["activity"]
+            .as_array()
+            .context("Invalid activity feed")?
+            .iter()
+            .map(|entry| {
+                let timestamp = entry["timestamp"].as_str().context("Activity entry without timestamp")?;
+                Ok(ActivityEntry {
+                    timestamp: DateTime::parse_from_rfc3339(timestamp)?.with_timezone(&Utc),
+                    process: entry["process"].as_str().map(str::to_string),
+                    summary: entry["summary"].as_str().unwrap_or_default().to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// Restarts every process, returning the server's line per process.
+    pub async fn restart_all(&mut self) -> Result<String> {
+        let result = self
+            .send_request(
+                "tools/call",
+                Some(json!({
+                    "name": "restart",
+                    "arguments": {
+                        "all": true
+                    }
+                })),
+            )
+            .await?;
+
+        let text = result["content"][0]["text"]
+            .as_str()
+            .context("Invalid response format")?;
+
+        Ok(text.to_string())
+    }
+
+    pub async fn stop_all(&mut self) -> Result<String> {
+        let result = self
+            .send_request(
+                "tools/call",
+                Some(json!({
+                    "name": "stop",
+                    "arguments": {
+                        "all": true
+                    }
+                })),
+            )
+            .await?;
+
+        let text = result["content"][0]["text"]
+            .as_str()
+            .context("Invalid response format")?;
+
+        Ok(text.to_string())
+    }
+
     pub async fn restart_process(&mut self, process: &str) -> Result<String> {
         let result = self
             .send_request(
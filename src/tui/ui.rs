@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style, Stylize},
@@ -7,6 +8,7 @@ use ratatui::{
 };
 
 use super::app::{App, ConnectionState};
+use super::mcp_client::StateKind;
 
 pub fn render(frame: &mut Frame, app: &App) {
     let chunks = Layout::default()
@@ -29,7 +31,11 @@ pub fn render(frame: &mut Frame, app: &App) {
         .split(chunks[1]);
 
     render_server_status(frame, app, top_chunks[0]);
-    render_process_details(frame, app, top_chunks[1]);
+    if app.show_activity {
+        render_activity(frame, app, top_chunks[1]);
+    } else {
+        render_process_details(frame, app, top_chunks[1]);
+    }
     render_processes(frame, app, bottom_chunks[0]);
     render_output(frame, app, bottom_chunks[1]);
     render_keyboard_shortcuts(frame, chunks[2]);
@@ -45,6 +51,7 @@ fn render_server_status(frame: &mut Frame, app: &App, area: Rect) {
         ConnectionState::Connected => ("● Connected", Color::Green),
         ConnectionState::Connecting => ("● Connecting", Color::Yellow),
         ConnectionState::Disconnected => ("● Disconnected", Color::Red),
+        ConnectionState::TimedOut => ("● Timed out", Color::Yellow),
         ConnectionState::Error => ("● Error", Color::Red),
     };
 
@@ -53,7 +60,7 @@ fn render_server_status(frame: &mut Frame, app: &App, area: Rect) {
             Span::styled(connection_indicator, Style::default().fg(connection_color).bold()),
             Span::raw(" | Port: "),
             Span::styled(
-                app.mcp_url.split(':').last().unwrap_or("3001").trim_end_matches("/mcp"),
+                app.mcp_url.split(':').next_back().unwrap_or("3001").trim_end_matches("/mcp"),
                 Style::default().fg(Color::White),
             ),
         ]),
@@ -70,6 +77,16 @@ fn render_server_status(frame: &mut Frame, app: &App, area: Rect) {
             ]));
         }
 
+        if let Some(latency) = app.last_latency {
+            lines.push(Line::from(vec![
+                Span::raw("Latency: "),
+                Span::styled(
+                    format!("{} ms", latency.as_millis()),
+                    Style::default().fg(Color::Gray),
+                ),
+            ]));
+        }
+
         lines.push(Line::from(vec![
             Span::raw("Mode: "),
             Span::styled(
@@ -119,6 +136,13 @@ fn render_server_status(frame: &mut Frame, app: &App, area: Rect) {
         )));
     }
 
+    if let Some((at, ref error)) = app.last_error {
+        lines.push(Line::from(vec![
+            Span::raw(format!("Last error ({}): ", at.format("%H:%M:%S"))),
+            Span::styled(error.as_str(), Style::default().fg(Color::Red)),
+        ]));
+    }
+
     if !app.status_message.is_empty() {
         lines.push(Line::from(""));
         lines.push(Line::from(Span::styled(
@@ -134,6 +158,15 @@ fn render_server_status(frame: &mut Frame, app: &App, area: Rect) {
     frame.render_widget(paragraph, area);
 }
 
+fn state_color(kind: StateKind) -> Color {
+    match kind {
+        StateKind::Restarting | StateKind::Transitional | StateKind::Stopped => Color::Yellow,
+        StateKind::Running => Color::Green,
+        StateKind::Errored => Color::Red,
+        StateKind::Unknown => Color::Gray,
+    }
+}
+
 fn render_process_details(frame: &mut Frame, app: &App, area: Rect) {
     let block = Block::default()
         .title("Process Details")
@@ -141,12 +174,7 @@ fn render_process_details(frame: &mut Frame, app: &App, area: Rect) {
         .borders(Borders::ALL);
 
     let content = if let Some(process) = app.get_selected_process() {
-        let state_color = match process.state.to_lowercase().as_str() {
-            s if s.contains("running") => Color::Green,
-            s if s.contains("stopped") || s.contains("idle") => Color::Yellow,
-            s if s.contains("crashed") => Color::Red,
-            _ => Color::Gray,
-        };
+        let state_color = state_color(process.state_kind());
 
         let mut lines = vec![
             Line::from(vec![
@@ -159,7 +187,7 @@ fn render_process_details(frame: &mut Frame, app: &App, area: Rect) {
             Line::from(vec![
                 Span::raw("State: "),
                 Span::styled(
-                    process.state.clone(),
+                    process.display_state(),
                     Style::default().fg(state_color).bold(),
                 ),
             ]),
@@ -175,6 +203,17 @@ fn render_process_details(frame: &mut Frame, app: &App, area: Rect) {
             ]));
         }
 
+        if let Some(ref version) = process.version {
+            let mut spans = vec![
+                Span::raw("Version: "),
+                Span::styled(version.clone(), Style::default().fg(Color::White)),
+            ];
+            if process.stale {
+                spans.push(Span::styled(" (stale)", Style::default().fg(Color::Yellow).bold()));
+            }
+            lines.push(Line::from(spans));
+        }
+
         if process.crash_count > 0 {
             lines.push(Line::from(vec![
                 Span::raw("Crash count: "),
@@ -185,6 +224,16 @@ fn render_process_details(frame: &mut Frame, app: &App, area: Rect) {
             ]));
         }
 
+        if process.restart_count > 0 {
+            lines.push(Line::from(vec![
+                Span::raw("Restart count: "),
+                Span::styled(
+                    format!("{}", process.restart_count),
+                    Style::default().fg(Color::White),
+                ),
+            ]));
+        }
+
         if !process.events.is_empty() {
             lines.push(Line::from(""));
             lines.push(Line::from(Span::styled(
@@ -217,6 +266,57 @@ fn render_process_details(frame: &mut Frame, app: &App, area: Rect) {
     frame.render_widget(paragraph, area);
 }
 
+/// "2m ago" style age of a timestamp.
+fn time_ago(timestamp: DateTime<Utc>) -> String {
+    let secs = (Utc::now() - timestamp).num_seconds().max(0);
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+fn render_activity(frame: &mut Frame, app: &App, area: Rect) {
+    let block = Block::default()
+        .title("Activity")
+        .title_style(Style::default().fg(Color::Cyan))
+        .borders(Borders::ALL);
+
+    let selected = app.get_selected_process().map(|p| p.name.as_str());
+    let items: Vec<ListItem> = if app.activity.is_empty() {
+        vec![ListItem::new(Span::styled(
+            "No activity yet.",
+            Style::default().fg(Color::Gray).italic(),
+        ))]
+    } else {
+        app.activity
+            .iter()
+            .take(area.height.saturating_sub(2) as usize)
+            .map(|entry| {
+                let text = match entry.process {
+                    Some(ref process) => format!("{} {}", process, entry.summary),
+                    None => entry.summary.clone(),
+                };
+                let style = if selected.is_some() && entry.process.as_deref() == selected {
+                    Style::default().fg(Color::White).add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default().fg(Color::Gray)
+                };
+
+                ListItem::new(Line::from(vec![
+                    Span::styled(format!("{}: ", time_ago(entry.timestamp)), Style::default().fg(Color::DarkGray)),
+                    Span::styled(text, style),
+                ]))
+            })
+            .collect()
+    };
+
+    let list = List::new(items).block(block);
+
+    frame.render_widget(list, area);
+}
+
 fn render_processes(frame: &mut Frame, app: &App, area: Rect) {
     let block = Block::default()
         .title("Processes")
@@ -235,18 +335,15 @@ fn render_processes(frame: &mut Frame, app: &App, area: Rect) {
                 .iter()
                 .enumerate()
                 .map(|(i, process)| {
-                    let state_color = match process.state.to_lowercase().as_str() {
-                        s if s.contains("running") => Color::Green,
-                        s if s.contains("stopped") || s.contains("idle") => Color::Yellow,
-                        s if s.contains("crashed") => Color::Red,
-                        _ => Color::Gray,
-                    };
-
-                    let icon = match process.state.to_lowercase().as_str() {
-                        s if s.contains("running") => "▶",
-                        s if s.contains("stopped") || s.contains("idle") => "■",
-                        s if s.contains("crashed") => "✗",
-                        _ => "?",
+                    let state = process.display_state();
+                    let state_color = state_color(process.state_kind());
+                    let icon = match process.state_kind() {
+                        StateKind::Restarting => "↻",
+                        StateKind::Transitional => "…",
+                        StateKind::Running => "▶",
+                        StateKind::Stopped => "■",
+                        StateKind::Errored => "✗",
+                        StateKind::Unknown => "?",
                     };
 
                     let mut style = Style::default();
@@ -261,7 +358,7 @@ fn render_processes(frame: &mut Frame, app: &App, area: Rect) {
                             style.fg(Color::White),
                         ),
                         Span::styled(
-                            format!("({})", process.state),
+                            format!("({})", state),
                             style.fg(state_color),
                         ),
                     ]))
@@ -282,9 +379,18 @@ fn render_processes(frame: &mut Frame, app: &App, area: Rect) {
 }
 
 fn render_output(frame: &mut Frame, app: &App, area: Rect) {
+    let title = match app.build_log_shown_for {
+        Some(ref name) => format!("Output: build log of {}, whose last build failed", name),
+        None => "Output".to_string(),
+    };
+    let title = match app.log_header {
+        Some(ref header) => format!("{} — {}", title, header),
+        None => title,
+    };
+    let color = if app.build_log_shown_for.is_some() { Color::Yellow } else { Color::Cyan };
     let block = Block::default()
-        .title("Output")
-        .title_style(Style::default().fg(Color::Cyan))
+        .title(title)
+        .title_style(Style::default().fg(color))
         .borders(Borders::ALL);
 
     let content = if app.logs.is_empty() {
@@ -312,11 +418,14 @@ fn render_output(frame: &mut Frame, app: &App, area: Rect) {
 }
 
 fn render_keyboard_shortcuts(frame: &mut Frame, area: Rect) {
-    let shortcuts = vec![
+    let shortcuts = [
         ("▲▼", "Navigate"),
         ("⏎", "View Output"),
         ("r", "Restart"),
+        ("R", "Restart All"),
+        ("X", "Stop All"),
         ("c", "Clear"),
+        ("t", "Activity"),
         ("q", "Quit"),
     ];
 
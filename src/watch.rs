@@ -0,0 +1,204 @@
+use crate::config::WatchConfig;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::WalkBuilder;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::warn;
+
+/// Directories at a process's root that never hold sources: dependencies
+/// and git's own files.
+const BUILTIN_EXCLUDES: &[&str] = &["node_modules", ".git"];
+
+/// Decides which files under a process's directory are its sources: those
+/// under `watch.paths` (all of it by default), minus its target directory,
+/// the built-in excludes, whatever the directory's `.gitignore` ignores and
+/// `watch.ignore`.
+#[derive(Clone)]
+pub struct PathFilter {
+    root: PathBuf,
+    paths: Vec<PathBuf>,
+    /// The target directory relative to `root`, if it is inside it.
+    target_dir: Option<PathBuf>,
+    gitignore: Gitignore,
+    ignore: Gitignore,
+}
+
+impl PathFilter {
+    /// Reads `.gitignore` in `root`, if there is one. Patterns that don't
+    /// parse are skipped; `watch.ignore` was validated with the config.
+    /// Cargo writing into `target_dir` mustn't look like a change, wherever
+    /// it is.
+    pub fn new(root: &Path, config: &WatchConfig, target_dir: &Path) -> Self {
+        let gitignore_path = root.join(".gitignore");
+        let (gitignore, error) = Gitignore::new(&gitignore_path);
+        if let Some(e) = error.filter(|_| gitignore_path.exists()) {
+            warn!("Some of {} was not understood: {}", gitignore_path.display(), e);
+        }
+
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in &config.ignore {
+            let _ = builder.add_line(None, pattern);
+        }
+        let ignore = builder.build().unwrap_or_else(|_| Gitignore::empty());
+
+        let paths = if config.paths.is_empty() {
+            vec![root.to_path_buf()]
+        } else {
+            config.paths.iter().map(|path| root.join(path)).collect()
+        };
+
+        // Cargo reports the target directory canonicalized, which the root
+        // may not be
+        let target_dir = match (root.canonicalize(), target_dir.canonicalize()) {
+            (Ok(root), Ok(target_dir)) => target_dir.strip_prefix(root).ok().map(Path::to_path_buf),
+            _ => target_dir.strip_prefix(root).ok().map(Path::to_path_buf),
+        }
+        .filter(|relative| !relative.as_os_str().is_empty());
+
+        Self { root: root.to_path_buf(), paths, target_dir, gitignore, ignore }
+    }
+
+    /// Whether a change to `path`, as reported by a file watcher, concerns
+    /// the sources.
+    pub fn is_relevant(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        if relative
+            .components()
+            .next()
+            .is_some_and(|c| BUILTIN_EXCLUDES.iter().any(|exclude| c.as_os_str() == *exclude))
+        {
+            return false;
+        }
+        if self.target_dir.as_ref().is_some_and(|target_dir| relative.starts_with(target_dir)) {
+            return false;
+        }
+        if !self.paths.iter().any(|p| path.starts_with(p)) {
+            return false;
+        }
+        !self.gitignore.matched_path_or_any_parents(path, is_dir).is_ignore()
+            && !self.ignore.matched_path_or_any_parents(path, is_dir).is_ignore()
+    }
+
+    /// The newest modification time among the sources. Blocks while it walks
+    /// them; ignored directories aren't descended into. The walk also honours
+    /// `.gitignore` files in subdirectories, and the git repository's above
+    /// the root, whether or not the project is in git.
+    pub fn newest_mtime(&self) -> Option<SystemTime> {
+        let (first, rest) = self.paths.split_first()?;
+        let mut walk = WalkBuilder::new(first);
+        for path in rest {
+            walk.add(path);
+        }
+        let filter = self.clone();
+        walk.hidden(false)
+            .ignore(false)
+            .git_global(false)
+            .git_exclude(false)
+            .require_git(false)
+            .filter_entry(move |entry| {
+                filter.is_relevant(entry.path(), entry.file_type().is_some_and(|t| t.is_dir()))
+            });
+
+        walk.build()
+            .flatten()
+            .filter(|entry| entry.file_type().is_some_and(|t| !t.is_dir()))
+            .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+            .max()
+    }
+
+    /// Whether any source changed after `built`, e.g. the binary's mtime.
+    pub fn changed_since(&self, built: SystemTime) -> bool {
+        self.newest_mtime().is_some_and(|newest| newest > built)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    fn touch(path: &Path, mtime: SystemTime) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let file = fs::File::options().create(true).append(true).open(path).unwrap();
+        file.set_modified(mtime).unwrap();
+    }
+
+    #[test]
+    fn test_path_filter() {
+        let root = std::env::temp_dir().join(format!("bpm-test-path-filter-{}", std::process::id()));
+        let built = SystemTime::now() - Duration::from_secs(3600);
+        let before = built - Duration::from_secs(60);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join(".gitignore"), "*.log\n/generated/\n").unwrap();
+        touch(&root.join("src/main.rs"), before);
+        touch(&root.join("Cargo.toml"), before);
+        touch(&root.join(".gitignore"), before);
+
+        let config = WatchConfig { paths: Vec::new(), ignore: vec!["docs/".to_string()] };
+        let filter = PathFilter::new(&root, &config, &root.join("target"));
+        assert!(!filter.changed_since(built));
+
+        // Churn the build produces, or that is ignored, doesn't count
+        let now = SystemTime::now();
+        for path in ["target/debug/app", "target/debug/.fingerprint/x", "node_modules/a/index.js", "server.log"] {
+            touch(&root.join(path), now);
+        }
+        touch(&root.join("generated/schema.rs"), now);
+        touch(&root.join("docs/notes.md"), now);
+        assert!(!filter.changed_since(built));
+
+        // So is what a .gitignore further down ignores
+        fs::create_dir_all(root.join("web")).unwrap();
+        fs::write(root.join("web/.gitignore"), "dist/\n").unwrap();
+        touch(&root.join("web/.gitignore"), before);
+        touch(&root.join("web/dist/bundle.js"), now);
+        assert!(!filter.changed_since(built));
+        assert!(!filter.is_relevant(&root.join("target/debug/app"), false));
+        assert!(!filter.is_relevant(&root.join("generated"), true));
+        assert!(!filter.is_relevant(Path::new("/elsewhere/src/main.rs"), false));
+
+        // Editing a source does
+        assert!(filter.is_relevant(&root.join("src/main.rs"), false));
+        touch(&root.join("src/main.rs"), now);
+        assert!(filter.changed_since(built));
+
+        // watch.paths narrows it down
+        let config = WatchConfig { paths: vec![PathBuf::from("assets")], ignore: Vec::new() };
+        let filter = PathFilter::new(&root, &config, &root.join("target"));
+        assert!(!filter.is_relevant(&root.join("src/main.rs"), false));
+        assert!(!filter.changed_since(built));
+        touch(&root.join("assets/logo.svg"), now);
+        assert!(filter.changed_since(built));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_path_filter_target_dir() {
+        let root = std::env::temp_dir().join(format!("bpm-test-path-filter-target-{}", std::process::id()));
+        let built = SystemTime::now() - Duration::from_secs(3600);
+        touch(&root.join("src/main.rs"), built - Duration::from_secs(60));
+
+        // Build output goes wherever cargo was told to put it
+        let filter = PathFilter::new(&root, &WatchConfig::default(), &root.join("out"));
+        touch(&root.join("out/debug/app"), SystemTime::now());
+        assert!(!filter.changed_since(built));
+        assert!(!filter.is_relevant(&root.join("out/debug/app"), false));
+
+        // And the built-in excludes only apply at the root
+        assert!(filter.is_relevant(&root.join("src/target/mod.rs"), false));
+        assert!(filter.is_relevant(&root.join("target/notes.rs"), false));
+        assert!(!filter.is_relevant(&root.join("node_modules/a/index.js"), false));
+        touch(&root.join("src/target/mod.rs"), SystemTime::now());
+        assert!(filter.changed_since(built));
+
+        // A target directory outside the root excludes nothing
+        let filter = PathFilter::new(&root, &WatchConfig::default(), Path::new("/elsewhere/target"));
+        assert!(filter.is_relevant(&root.join("src/main.rs"), false));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}